@@ -0,0 +1,80 @@
+//! Guard for the operator-only `/api/admin` endpoints.
+
+use std::future::{ready, Ready};
+
+use actix_web::dev::Payload;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{web, FromRequest, HttpRequest};
+
+use crate::error::{ApiError, FieldError};
+use crate::state::AppState;
+
+/// Actor recorded for actions taken with the shared admin token.
+pub const ADMIN_ACTOR: &str = "admin";
+/// Longest name a request may give for the person acting.
+pub const MAX_ACTOR_LEN: usize = 100;
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Extractor that admits a request only if it carries
+/// `Authorization: Bearer <admin.token>`. Without a configured token the
+/// admin API is disabled.
+#[derive(Debug)]
+pub struct Admin;
+
+impl Admin {
+    /// The actor to record for a request naming `claimed` as the person
+    /// acting: `admin (alice)`. The token is shared, so the name is only the
+    /// caller's word, and is kept next to the credential that was checked.
+    pub fn actor(&self, claimed: &str) -> Result<String, ApiError> {
+        let name = claimed.trim();
+        let problem = if name.is_empty() {
+            Some("is required".to_string())
+        } else if name.chars().count() > MAX_ACTOR_LEN {
+            Some(format!("must be at most {MAX_ACTOR_LEN} characters"))
+        } else if name.chars().any(char::is_control) {
+            Some("must not contain control characters".to_string())
+        } else {
+            None
+        };
+        match problem {
+            Some(message) => Err(ApiError::Validation(vec![FieldError::new(
+                None, "actor", message,
+            )])),
+            None => Ok(format!("{ADMIN_ACTOR} ({name})")),
+        }
+    }
+
+    fn check(req: &HttpRequest) -> Result<Self, ApiError> {
+        let expected = req
+            .app_data::<web::Data<AppState>>()
+            .and_then(|state| state.admin_token.clone());
+        let Some(expected) = expected else {
+            return Err(ApiError::Forbidden(
+                "Admin API is disabled; set admin.token or BETTERGOV_ADMIN_TOKEN".to_string(),
+            ));
+        };
+        let token = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+            .unwrap_or_default();
+        if !constant_time_eq(expected.as_bytes(), token.as_bytes()) {
+            return Err(ApiError::Unauthorized("Invalid admin token".to_string()));
+        }
+        Ok(Admin)
+    }
+}
+
+impl FromRequest for Admin {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Admin::check(req))
+    }
+}
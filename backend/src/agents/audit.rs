@@ -0,0 +1,76 @@
+//! Audit trail of agent credential changes: keys issued and revoked,
+//! enrollment codes minted and redeemed, and failed redemptions.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgExecutor;
+
+use crate::admin::Admin;
+use crate::agents::registry;
+use crate::db::agent_audit::{self, NewAuditEntry};
+use crate::error::{ApiError, FieldError};
+use crate::state::AppState;
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Where a request came from, for the record only. Forwarded addresses
+/// can be forged, so the connecting peer is kept next to them.
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr().map(|addr| addr.ip().to_string());
+    let info = req.connection_info();
+    let forwarded = info.realip_remote_addr().map(|addr| {
+        addr.parse::<std::net::SocketAddr>()
+            .map(|a| a.ip().to_string())
+            .unwrap_or_else(|_| addr.to_string())
+    });
+    match (forwarded, peer) {
+        (Some(forwarded), Some(peer)) if forwarded != peer => {
+            Some(format!("{forwarded} via {peer}"))
+        }
+        (forwarded, peer) => forwarded.or(peer),
+    }
+}
+
+/// Writes `entry`, stamped with the client address of `req`.
+pub async fn record(
+    db: impl PgExecutor<'_>,
+    req: &HttpRequest,
+    entry: NewAuditEntry<'_>,
+) -> sqlx::Result<()> {
+    let client_ip = client_ip(req);
+    agent_audit::insert(
+        db,
+        &NewAuditEntry {
+            client_ip: client_ip.as_deref(),
+            ..entry
+        },
+    )
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub location: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// `GET /api/admin/audit`: newest first, optionally for one `location`.
+pub async fn list_audit(
+    _: Admin,
+    state: web::Data<AppState>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            None,
+            "limit",
+            format!("must be between 1 and {MAX_LIMIT}"),
+        )]));
+    }
+    let location = query.location.as_deref().map(registry::normalize_location);
+    let entries = agent_audit::list(&state.pool, location.as_deref(), limit).await?;
+    Ok(HttpResponse::Ok().json(json!({ "entries": entries })))
+}
@@ -0,0 +1,277 @@
+//! Signed geo-reports. Each agent holds a key issued through the admin API
+//! and signs every report with HMAC-SHA256 over
+//!
+//! ```text
+//! {timestamp}\n{nonce}\n{body}
+//! ```
+//!
+//! keyed with the secret as given (its hex text, not the decoded bytes), so
+//! the agent can sign with `openssl dgst -sha256 -hmac "$AGENT_SECRET"`.
+//! Keys live in the database and survive API restarts; a stale timestamp or
+//! a nonce seen before is rejected as a replay.
+
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+
+use crate::db::agent_keys::{self, AgentKey};
+use crate::db::{agent_heartbeats, agents};
+use crate::error::ApiError;
+use crate::state::AppState;
+
+pub const KEY_HEADER: &str = "X-Agent-Key";
+pub const TIMESTAMP_HEADER: &str = "X-Agent-Timestamp";
+pub const NONCE_HEADER: &str = "X-Agent-Nonce";
+pub const SIGNATURE_HEADER: &str = "X-Agent-Signature";
+
+const NONCE_LEN: std::ops::RangeInclusive<usize> = 16..=64;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(secret: &str, timestamp: &str, nonce: &str, body: &[u8]) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b"\n");
+    mac.update(nonce.as_bytes());
+    mac.update(b"\n");
+    mac.update(body);
+    mac
+}
+
+/// Hex signature of a report, as the agent computes it.
+pub fn sign(secret: &str, timestamp: &str, nonce: &str, body: &[u8]) -> String {
+    hex::encode(mac(secret, timestamp, nonce, body).finalize().into_bytes())
+}
+
+/// Checks `signature` (hex, any case) in constant time.
+pub fn verify(secret: &str, timestamp: &str, nonce: &str, body: &[u8], signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    mac(secret, timestamp, nonce, body)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+/// A fresh `(key_id, secret)` pair for `location`.
+pub fn generate_key(location: &str) -> (String, String) {
+    let mut rng = rand::thread_rng();
+    let key_id = format!(
+        "{}-{}",
+        location.to_ascii_lowercase(),
+        hex::encode(rng.gen::<[u8; 6]>())
+    );
+    (key_id, hex::encode(rng.gen::<[u8; 32]>()))
+}
+
+/// The signing headers of a report.
+#[derive(Debug)]
+pub struct Signed<'a> {
+    pub key_id: &'a str,
+    pub timestamp: &'a str,
+    pub nonce: &'a str,
+    pub signature: &'a str,
+}
+
+impl<'a> Signed<'a> {
+    pub fn from_request(req: &'a HttpRequest) -> Result<Self, ApiError> {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| ApiError::Unauthorized(format!("Missing {name} header")))
+        };
+        let signed = Signed {
+            key_id: header(KEY_HEADER)?,
+            timestamp: header(TIMESTAMP_HEADER)?,
+            nonce: header(NONCE_HEADER)?,
+            signature: header(SIGNATURE_HEADER)?,
+        };
+        let nonce_ok = NONCE_LEN.contains(&signed.nonce.len())
+            && signed
+                .nonce
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !nonce_ok {
+            return Err(ApiError::BadRequest(format!(
+                "{NONCE_HEADER} must be {} to {} characters of [A-Za-z0-9_-]",
+                NONCE_LEN.start(),
+                NONCE_LEN.end()
+            )));
+        }
+        Ok(signed)
+    }
+
+    /// The signed timestamp, by the agent's clock; `None` unless it is Unix
+    /// seconds, which `authenticate` has already checked.
+    pub fn sent_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp(self.timestamp.parse().ok()?, 0)
+    }
+}
+
+/// Rejects timestamps further than `max_skew_secs` from `now` either way.
+fn check_fresh(timestamp: &str, now: DateTime<Utc>, max_skew_secs: u64) -> Result<(), ApiError> {
+    let timestamp: i64 = timestamp
+        .parse()
+        .map_err(|_| ApiError::BadRequest(format!("{TIMESTAMP_HEADER} must be Unix seconds")))?;
+    if now.timestamp().abs_diff(timestamp) > max_skew_secs {
+        return Err(ApiError::Unauthorized(format!(
+            "Stale report: timestamp {timestamp} is more than {max_skew_secs}s from server time"
+        )));
+    }
+    Ok(())
+}
+
+/// Authenticates a signed report body and returns the key that signed it.
+///
+/// A key that was rotated out or revoked marks its agent out of sync, so
+/// the dashboard shows it as needing redeployment. The nonce is claimed
+/// separately, inside the transaction that stores the report.
+pub async fn authenticate(
+    state: &AppState,
+    signed: &Signed<'_>,
+    body: &[u8],
+) -> Result<AgentKey, ApiError> {
+    let now = Utc::now();
+    check_fresh(signed.timestamp, now, state.agents.max_clock_skew_secs)?;
+
+    let Some(key) = agent_keys::get(&state.pool, signed.key_id).await? else {
+        return Err(ApiError::Unauthorized(format!(
+            "Unknown agent key {}",
+            signed.key_id
+        )));
+    };
+    if !key.is_usable(now) {
+        agent_heartbeats::mark_out_of_sync(&state.pool, &key.location).await?;
+        return Err(ApiError::Unauthorized(format!(
+            "Key {} for {} is no longer valid. Agent needs redeployment.",
+            key.key_id, key.location
+        )));
+    }
+    match agents::get(&state.pool, &key.location).await? {
+        Some(agent) if agent.enabled => {}
+        Some(_) => {
+            return Err(ApiError::Forbidden(format!(
+                "Agent {} is disabled",
+                key.location
+            )))
+        }
+        None => {
+            return Err(ApiError::Forbidden(format!(
+                "Unauthorized location: {}",
+                key.location
+            )))
+        }
+    }
+    if !verify(
+        &key.secret,
+        signed.timestamp,
+        signed.nonce,
+        body,
+        signed.signature,
+    ) {
+        return Err(ApiError::Unauthorized(format!(
+            "Invalid signature for key {}",
+            key.key_id
+        )));
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "5f0c1b8e2d7a4c3e9b6f1a0d8c7e2b4a";
+    const BODY: &[u8] = br#"{"location":"PH","results":[]}"#;
+
+    #[test]
+    fn matches_openssl_hmac() {
+        // printf '%s\n%s\n%s' 1760500000 0123456789abcdef '{"location":"PH","results":[]}' \
+        //   | openssl dgst -sha256 -hmac 5f0c1b8e2d7a4c3e9b6f1a0d8c7e2b4a
+        assert_eq!(
+            sign(SECRET, "1760500000", "0123456789abcdef", BODY),
+            "896b46017a5eabcf85a6392df198a3c15af7e661ae6e93e02d398f4e121ebd66"
+        );
+    }
+
+    #[test]
+    fn verifies_only_the_signed_request() {
+        let signature = sign(SECRET, "1760500000", "0123456789abcdef", BODY);
+        assert!(verify(
+            SECRET,
+            "1760500000",
+            "0123456789abcdef",
+            BODY,
+            &signature
+        ));
+        assert!(verify(
+            SECRET,
+            "1760500000",
+            "0123456789abcdef",
+            BODY,
+            &signature.to_uppercase()
+        ));
+        assert!(!verify(
+            SECRET,
+            "1760500001",
+            "0123456789abcdef",
+            BODY,
+            &signature
+        ));
+        assert!(!verify(
+            SECRET,
+            "1760500000",
+            "0123456789abcdeg",
+            BODY,
+            &signature
+        ));
+        assert!(!verify(
+            SECRET,
+            "1760500000",
+            "0123456789abcdef",
+            b"{}",
+            &signature
+        ));
+        assert!(!verify(
+            "other",
+            "1760500000",
+            "0123456789abcdef",
+            BODY,
+            &signature
+        ));
+        assert!(!verify(
+            SECRET,
+            "1760500000",
+            "0123456789abcdef",
+            BODY,
+            "not hex"
+        ));
+    }
+
+    #[test]
+    fn rejects_stale_and_future_timestamps() {
+        let now = Utc::now();
+        let at = |offset: i64| (now.timestamp() + offset).to_string();
+        assert!(check_fresh(&at(0), now, 300).is_ok());
+        assert!(check_fresh(&at(-300), now, 300).is_ok());
+        assert!(check_fresh(&at(-301), now, 300).is_err());
+        assert!(check_fresh(&at(301), now, 300).is_err());
+        assert!(check_fresh("yesterday", now, 300).is_err());
+    }
+
+    #[test]
+    fn generated_keys_are_distinct() {
+        let (id_a, secret_a) = generate_key("PH");
+        let (id_b, secret_b) = generate_key("PH");
+        assert!(id_a.starts_with("ph-"));
+        assert_ne!(id_a, id_b);
+        assert_ne!(secret_a, secret_b);
+        assert_eq!(secret_a.len(), 64);
+    }
+}
@@ -0,0 +1,519 @@
+//! Agent enrollment. An admin mints a short-lived, one-time code for a
+//! registered agent; the agent redeems it at `/api/agents/enroll` for a
+//! signing key. The code is the only thing that has to travel to the
+//! agent's host, and trust never depends on the caller's address.
+
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::admin::Admin;
+use crate::agents::{audit, keys, registry};
+use crate::db::agent_audit::NewAuditEntry;
+use crate::db::{agents, enrollment_codes};
+use crate::error::{ApiError, FieldError};
+use crate::state::AppState;
+
+const MIN_TTL_SECS: u64 = 60;
+const MAX_TTL_SECS: u64 = 86400;
+
+fn generate_code() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 16]>())
+}
+
+/// Codes are stored hashed, like passwords, so a database dump cannot be
+/// used to enroll.
+fn hash_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.trim().to_ascii_lowercase().as_bytes()))
+}
+
+/// Body of the mint endpoint. `actor` names who is minting the code and is
+/// recorded with the admin token it came with; `ttl_secs` is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MintRequest {
+    #[serde(default)]
+    pub actor: String,
+    pub ttl_secs: Option<u64>,
+}
+
+/// `POST /api/admin/agents/{location}/enrollment-codes`: mints a code. It
+/// is returned once and never stored in the clear.
+pub async fn mint_code(
+    admin: Admin,
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    location: web::Path<String>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let location = registry::normalize_location(&location);
+    let body: MintRequest = if body.iter().all(u8::is_ascii_whitespace) {
+        MintRequest::default()
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|e| ApiError::BadRequest(format!("Invalid JSON body: {e}")))?
+    };
+    let ttl = body
+        .ttl_secs
+        .unwrap_or(state.agents.enrollment_code_ttl_secs);
+    if !(MIN_TTL_SECS..=MAX_TTL_SECS).contains(&ttl) {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            None,
+            "ttl_secs",
+            format!("must be between {MIN_TTL_SECS} and {MAX_TTL_SECS}"),
+        )]));
+    }
+    let actor = admin.actor(&body.actor)?;
+    let actor = actor.as_str();
+    let agent = registry::require(&state, &location).await?;
+    if !agent.enabled {
+        return Err(ApiError::Conflict(format!("Agent {location} is disabled")));
+    }
+
+    let code = generate_code();
+    let expires_at = Utc::now() + Duration::from_secs(ttl);
+    let mut tx = state.pool.begin().await?;
+    let minted =
+        enrollment_codes::insert(&mut *tx, &location, &hash_code(&code), actor, expires_at).await?;
+    audit::record(
+        &mut *tx,
+        &req,
+        NewAuditEntry {
+            location: Some(&location),
+            event: "enrollment_code_issued",
+            actor,
+            detail: Some(&format!("expires at {expires_at}")),
+            ..Default::default()
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    println!("Enrollment code issued for {location} by {actor}, expires at {expires_at}");
+    Ok(HttpResponse::Created().json(json!({
+        "location": location,
+        "code": code,
+        "expires_at": minted.expires_at,
+    })))
+}
+
+/// `GET /api/admin/agents/{location}/enrollment-codes`: newest first,
+/// without the codes.
+pub async fn list_codes(
+    _: Admin,
+    state: web::Data<AppState>,
+    location: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let location = registry::normalize_location(&location);
+    registry::require(&state, &location).await?;
+    let codes = enrollment_codes::list(&state.pool, &location).await?;
+    Ok(HttpResponse::Ok().json(json!({ "codes": codes })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnrollRequest {
+    #[serde(default)]
+    pub code: String,
+}
+
+/// `POST /api/agents/enroll`: redeems a code for a signing key, rotating
+/// out the agent's previous keys as `POST .../keys` does. Every attempt,
+/// failed or not, is audit logged.
+pub async fn enroll(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    body: web::Json<EnrollRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let mut tx = state.pool.begin().await?;
+    let code = enrollment_codes::get_for_update(&mut *tx, &hash_code(&body.code)).await?;
+
+    let now = Utc::now();
+    let checked = match code {
+        None => Err((
+            None,
+            ApiError::Unauthorized("Invalid enrollment code".to_string()),
+        )),
+        Some(code) if code.used_at.is_some() => Err((
+            Some(code.location),
+            ApiError::Unauthorized("Enrollment code was already used".to_string()),
+        )),
+        Some(code) if code.expires_at <= now => Err((
+            Some(code.location),
+            ApiError::Unauthorized("Enrollment code has expired".to_string()),
+        )),
+        Some(code) => match agents::get(&mut *tx, &code.location).await? {
+            Some(agent) if agent.enabled => Ok(code),
+            _ => {
+                let e = ApiError::Forbidden(format!("Agent {} is disabled", code.location));
+                Err((Some(code.location), e))
+            }
+        },
+    };
+    let code = match checked {
+        Ok(code) => code,
+        Err((location, e)) => {
+            drop(tx);
+            let location = location.as_deref();
+            eprintln!(
+                "Rejected enrollment for {}: {e}",
+                location.unwrap_or("unknown agent")
+            );
+            audit::record(
+                &state.pool,
+                &req,
+                NewAuditEntry {
+                    location,
+                    event: "enrollment_rejected",
+                    actor: location.unwrap_or("unknown"),
+                    detail: Some(&e.to_string()),
+                    ..Default::default()
+                },
+            )
+            .await?;
+            return Err(e);
+        }
+    };
+
+    let overlap = Duration::from_secs(state.agents.key_overlap_secs);
+    let issued = keys::issue(&mut tx, &code.location, overlap).await?;
+    enrollment_codes::mark_used(&mut *tx, code.id, &issued.key.key_id).await?;
+    audit::record(
+        &mut *tx,
+        &req,
+        NewAuditEntry {
+            location: Some(&code.location),
+            event: "enrolled",
+            actor: &code.location,
+            key_id: Some(&issued.key.key_id),
+            detail: Some(&format!("code #{} minted by {}", code.id, code.created_by)),
+            ..Default::default()
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    println!(
+        "Agent {} enrolled with key {}",
+        code.location, issued.key.key_id
+    );
+    Ok(HttpResponse::Created().json(issued))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::{test as actix_test, App};
+    use serde_json::Value;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::db::agent_audit::{self, AuditEntry};
+    use crate::db::{self, agent_keys};
+
+    const TOKEN: &str = "enrollment-test-token";
+
+    /// A registered agent under a name no other test uses, with the admin
+    /// API enabled.
+    struct Fixture {
+        state: web::Data<AppState>,
+        location: String,
+    }
+
+    impl Fixture {
+        async fn new(pool: PgPool) -> Self {
+            let tag = hex::encode(rand::thread_rng().gen::<[u8; 4]>());
+            let location = format!("T{}", tag.to_ascii_uppercase());
+            agents::insert(&pool, &location, &Default::default())
+                .await
+                .unwrap();
+            let state = web::Data::new(AppState {
+                admin_token: Some(TOKEN.to_string()),
+                ..AppState::for_tests(pool)
+            });
+            Fixture { state, location }
+        }
+
+        fn pool(&self) -> &PgPool {
+            &self.state.pool
+        }
+
+        async fn call(&self, request: actix_test::TestRequest) -> (StatusCode, Value) {
+            let app = actix_test::init_service(
+                App::new()
+                    .app_data(self.state.clone())
+                    .route(
+                        "/api/admin/agents/{location}/enrollment-codes",
+                        web::post().to(mint_code),
+                    )
+                    .route("/api/agents/enroll", web::post().to(enroll)),
+            )
+            .await;
+            let response = actix_test::call_service(&app, request.to_request()).await;
+            let status = response.status();
+            (status, actix_test::read_body_json(response).await)
+        }
+
+        async fn mint_for(&self, location: &str, body: Value) -> (StatusCode, Value) {
+            self.call(
+                actix_test::TestRequest::post()
+                    .uri(&format!("/api/admin/agents/{location}/enrollment-codes"))
+                    .insert_header((AUTHORIZATION, format!("Bearer {TOKEN}")))
+                    .set_json(body),
+            )
+            .await
+        }
+
+        /// Mints a code for the fixture's agent as `alice`.
+        async fn mint(&self) -> String {
+            let (status, body) = self
+                .mint_for(&self.location, json!({ "actor": "alice" }))
+                .await;
+            assert_eq!(status, StatusCode::CREATED);
+            body["code"].as_str().unwrap().to_string()
+        }
+
+        /// Redeems `code` from `client_ip`, as a proxy would forward it.
+        async fn enroll_from(&self, code: &str, client_ip: &str) -> (StatusCode, Value) {
+            self.call(
+                actix_test::TestRequest::post()
+                    .uri("/api/agents/enroll")
+                    .insert_header(("X-Forwarded-For", client_ip))
+                    .set_json(json!({ "code": code })),
+            )
+            .await
+        }
+
+        async fn enroll(&self, code: &str) -> (StatusCode, Value) {
+            self.enroll_from(code, "192.0.2.1").await
+        }
+
+        /// The agent's audit trail, oldest first.
+        async fn audit(&self) -> Vec<AuditEntry> {
+            let mut entries = agent_audit::list(self.pool(), Some(&self.location), 100)
+                .await
+                .unwrap();
+            entries.reverse();
+            entries
+        }
+
+        async fn clean_up(self) {
+            for table in [
+                "agent_audit_log",
+                "agent_heartbeats",
+                "agent_keys",
+                "agents",
+            ] {
+                sqlx::query(&format!(
+                    "DELETE FROM monitoring.{table} WHERE location = $1"
+                ))
+                .bind(&self.location)
+                .execute(self.pool())
+                .await
+                .unwrap();
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn mints_codes_for_enabled_agents_only() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool).await;
+        let (status, body) = fixture
+            .mint_for(
+                &fixture.location.to_ascii_lowercase(),
+                json!({ "actor": " alice ", "ttl_secs": 900 }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["location"], fixture.location.as_str());
+        let code = body["code"].as_str().unwrap();
+        assert_eq!(code.len(), 32);
+
+        let stored = enrollment_codes::list(fixture.pool(), &fixture.location)
+            .await
+            .unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].code_hash, hash_code(code));
+        assert_eq!(stored[0].created_by, "admin (alice)");
+        assert!(stored[0].used_at.is_none());
+        let lifetime = stored[0].expires_at - stored[0].created_at;
+        assert!((lifetime.num_seconds() - 900).abs() <= 5, "{lifetime}");
+
+        let audit = fixture.audit().await;
+        assert_eq!(audit.len(), 1);
+        assert_eq!(audit[0].event, "enrollment_code_issued");
+        assert_eq!(audit[0].actor, "admin (alice)");
+
+        let (status, _) = fixture
+            .mint_for(
+                &fixture.location,
+                json!({ "actor": "alice", "ttl_secs": 59 }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = fixture.mint_for("TNONE", json!({ "actor": "alice" })).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        agents::set_enabled(fixture.pool(), &fixture.location, false)
+            .await
+            .unwrap();
+        let (status, _) = fixture
+            .mint_for(&fixture.location, json!({ "actor": "alice" }))
+            .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(fixture.audit().await.len(), 1);
+        fixture.clean_up().await;
+    }
+
+    #[actix_web::test]
+    async fn codes_enroll_once() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool).await;
+        let code = fixture.mint().await;
+
+        let (status, issued) = fixture.enroll(&format!(" {} ", code.to_uppercase())).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let key_id = issued["key"]["key_id"].as_str().unwrap();
+        assert_eq!(issued["key"]["location"], fixture.location.as_str());
+        assert!(agent_keys::get(fixture.pool(), key_id)
+            .await
+            .unwrap()
+            .is_some_and(|key| key.is_usable(Utc::now())));
+        let stored = enrollment_codes::list(fixture.pool(), &fixture.location)
+            .await
+            .unwrap();
+        assert!(stored[0].used_at.is_some());
+        assert_eq!(stored[0].key_id.as_deref(), Some(key_id));
+
+        let (status, body) = fixture.enroll(&code).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["message"], "Enrollment code was already used");
+        assert_eq!(
+            agent_keys::list(fixture.pool(), &fixture.location)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let audit = fixture.audit().await;
+        let events: Vec<_> = audit.iter().map(|e| e.event.as_str()).collect();
+        assert_eq!(
+            events,
+            ["enrollment_code_issued", "enrolled", "enrollment_rejected"]
+        );
+        assert_eq!(audit[1].actor, fixture.location);
+        assert_eq!(audit[1].key_id.as_deref(), Some(key_id));
+        assert_eq!(
+            audit[1].detail.as_deref(),
+            Some(format!("code #{} minted by admin (alice)", stored[0].id).as_str())
+        );
+        assert_eq!(audit[1].client_ip.as_deref(), Some("192.0.2.1"));
+        assert_eq!(audit[2].actor, fixture.location);
+        assert_eq!(
+            audit[2].detail.as_deref(),
+            Some("Enrollment code was already used")
+        );
+        fixture.clean_up().await;
+    }
+
+    #[actix_web::test]
+    async fn expired_codes_are_rejected() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool).await;
+        let code = generate_code();
+        enrollment_codes::insert(
+            fixture.pool(),
+            &fixture.location,
+            &hash_code(&code),
+            "admin (alice)",
+            Utc::now() - Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+
+        let (status, body) = fixture.enroll(&code).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["message"], "Enrollment code has expired");
+        assert!(agent_keys::list(fixture.pool(), &fixture.location)
+            .await
+            .unwrap()
+            .is_empty());
+        let audit = fixture.audit().await;
+        assert_eq!(audit.len(), 1);
+        assert_eq!(audit[0].event, "enrollment_rejected");
+        assert_eq!(
+            audit[0].detail.as_deref(),
+            Some("Enrollment code has expired")
+        );
+        fixture.clean_up().await;
+    }
+
+    #[actix_web::test]
+    async fn disabled_and_unknown_agents_cannot_enroll() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool).await;
+        let code = fixture.mint().await;
+        agents::set_enabled(fixture.pool(), &fixture.location, false)
+            .await
+            .unwrap();
+        let (status, body) = fixture.enroll(&code).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            body["message"],
+            format!("Agent {} is disabled", fixture.location)
+        );
+        let audit = fixture.audit().await;
+        assert_eq!(audit.last().unwrap().event, "enrollment_rejected");
+        assert_eq!(audit.last().unwrap().actor, fixture.location);
+
+        // Deleting the agent takes its codes with it, so the code is no
+        // longer known at all.
+        agents::delete(fixture.pool(), &fixture.location)
+            .await
+            .unwrap();
+        let client_ip = format!(
+            "2001:db8::{}",
+            hex::encode(rand::thread_rng().gen::<[u8; 2]>())
+        );
+        let (status, body) = fixture.enroll_from(&code, &client_ip).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["message"], "Invalid enrollment code");
+        let (location, actor, detail): (Option<String>, String, Option<String>) = sqlx::query_as(
+            "DELETE FROM monitoring.agent_audit_log WHERE client_ip = $1
+             RETURNING location, actor, detail",
+        )
+        .bind(&client_ip)
+        .fetch_one(fixture.pool())
+        .await
+        .unwrap();
+        assert_eq!(location, None);
+        assert_eq!(actor, "unknown");
+        assert_eq!(detail.as_deref(), Some("Invalid enrollment code"));
+        fixture.clean_up().await;
+    }
+
+    #[test]
+    fn codes_hash_the_same_however_they_are_typed() {
+        let code = generate_code();
+        assert_eq!(code.len(), 32);
+        assert_eq!(
+            hash_code(&code),
+            hash_code(&format!(" {} ", code.to_uppercase()))
+        );
+        assert_ne!(hash_code(&code), hash_code(&generate_code()));
+    }
+}
@@ -0,0 +1,314 @@
+//! Admin endpoints that issue, list and revoke agent signing keys.
+
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgConnection;
+
+use crate::admin::{Admin, ADMIN_ACTOR};
+use crate::agents::{audit, auth, registry};
+use crate::db::agent_audit::NewAuditEntry;
+use crate::db::agent_keys::{self, AgentKey};
+use crate::error::ApiError;
+use crate::state::AppState;
+
+/// A freshly issued key. The secret is only ever returned here.
+#[derive(Debug, Serialize)]
+pub struct Issued {
+    pub key: AgentKey,
+    pub secret: String,
+    /// End of the overlap window of the keys this one replaces, if any.
+    pub previous_keys_expire_at: Option<DateTime<Utc>>,
+}
+
+/// Issues a new key for `location` and starts the overlap window of its
+/// current ones, which keep working until `overlap` has passed.
+pub async fn issue(
+    conn: &mut PgConnection,
+    location: &str,
+    overlap: Duration,
+) -> sqlx::Result<Issued> {
+    let expires_at = Utc::now() + overlap;
+    let (key_id, secret) = auth::generate_key(location);
+    let expiring = agent_keys::expire_current(&mut *conn, location, expires_at).await?;
+    let key = agent_keys::insert(&mut *conn, location, &key_id, &secret).await?;
+
+    println!("Issued agent key {key_id} for {location}");
+    if expiring > 0 {
+        println!("{expiring} older key(s) for {location} expire at {expires_at}");
+    }
+    Ok(Issued {
+        key,
+        secret,
+        previous_keys_expire_at: (expiring > 0).then_some(expires_at),
+    })
+}
+
+async fn known_location(state: &AppState, location: &str) -> Result<String, ApiError> {
+    let location = registry::normalize_location(location);
+    registry::require(state, &location).await?;
+    Ok(location)
+}
+
+/// `POST /api/admin/agents/{location}/keys`: issues a new key, see
+/// [`issue`].
+pub async fn rotate_key(
+    _: Admin,
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    location: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let location = known_location(&state, &location).await?;
+    let overlap = Duration::from_secs(state.agents.key_overlap_secs);
+
+    let mut tx = state.pool.begin().await?;
+    let issued = issue(&mut tx, &location, overlap).await?;
+    audit::record(
+        &mut *tx,
+        &req,
+        NewAuditEntry {
+            location: Some(&location),
+            event: "key_issued",
+            actor: ADMIN_ACTOR,
+            key_id: Some(&issued.key.key_id),
+            ..Default::default()
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(HttpResponse::Created().json(issued))
+}
+
+/// `GET /api/admin/agents/{location}/keys`: newest first, without secrets.
+pub async fn list_keys(
+    _: Admin,
+    state: web::Data<AppState>,
+    location: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let location = known_location(&state, &location).await?;
+    let keys = agent_keys::list(&state.pool, &location).await?;
+    Ok(HttpResponse::Ok().json(json!({ "keys": keys })))
+}
+
+/// `DELETE /api/admin/agents/{location}/keys/{key_id}`: revokes a key at
+/// once, e.g. when it leaked.
+pub async fn revoke_key(
+    _: Admin,
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (location, key_id) = path.into_inner();
+    let location = known_location(&state, &location).await?;
+    let mut tx = state.pool.begin().await?;
+    let Some(key) = agent_keys::revoke(&mut *tx, &location, &key_id).await? else {
+        return Err(ApiError::NotFound(format!(
+            "Key {key_id} not found for {location}"
+        )));
+    };
+    audit::record(
+        &mut *tx,
+        &req,
+        NewAuditEntry {
+            location: Some(&location),
+            event: "key_revoked",
+            actor: ADMIN_ACTOR,
+            key_id: Some(&key_id),
+            ..Default::default()
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    println!("Revoked agent key {key_id} for {location}");
+    Ok(HttpResponse::Ok().json(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use rand::Rng;
+    use serde_json::Value;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::config::AgentsConfig;
+    use crate::db::{self, agents};
+
+    const TOKEN: &str = "keys-test-token";
+
+    /// A registered agent under a name no other test uses, with the admin
+    /// API enabled and `overlap_secs` of key overlap.
+    struct Fixture {
+        state: web::Data<AppState>,
+        location: String,
+    }
+
+    impl Fixture {
+        async fn new(pool: PgPool, overlap_secs: u64) -> Self {
+            let tag = hex::encode(rand::thread_rng().gen::<[u8; 4]>());
+            let location = format!("T{}", tag.to_ascii_uppercase());
+            agents::insert(&pool, &location, &Default::default())
+                .await
+                .unwrap();
+            let state = web::Data::new(AppState {
+                admin_token: Some(TOKEN.to_string()),
+                agents: AgentsConfig {
+                    key_overlap_secs: overlap_secs,
+                    ..Default::default()
+                },
+                ..AppState::for_tests(pool)
+            });
+            Fixture { state, location }
+        }
+
+        async fn call(&self, request: test::TestRequest) -> (StatusCode, Value) {
+            let app = test::init_service(
+                App::new()
+                    .app_data(self.state.clone())
+                    .route(
+                        "/api/admin/agents/{location}/keys",
+                        web::post().to(rotate_key),
+                    )
+                    .route(
+                        "/api/admin/agents/{location}/keys/{key_id}",
+                        web::delete().to(revoke_key),
+                    ),
+            )
+            .await;
+            let request = request
+                .insert_header((AUTHORIZATION, format!("Bearer {TOKEN}")))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            let status = response.status();
+            (status, test::read_body_json(response).await)
+        }
+
+        /// Rotates through the admin API and returns `(key_id, secret)`.
+        async fn rotate(&self) -> (String, String) {
+            let uri = format!("/api/admin/agents/{}/keys", self.location);
+            let (status, body) = self.call(test::TestRequest::post().uri(&uri)).await;
+            assert_eq!(status, StatusCode::CREATED);
+            (
+                body["key"]["key_id"].as_str().unwrap().to_string(),
+                body["secret"].as_str().unwrap().to_string(),
+            )
+        }
+
+        async fn revoke(&self, key_id: &str) {
+            let uri = format!("/api/admin/agents/{}/keys/{key_id}", self.location);
+            let (status, body) = self.call(test::TestRequest::delete().uri(&uri)).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["revoked"], true);
+        }
+
+        /// Authenticates a report signed now with `key`.
+        async fn report_with(&self, (key_id, secret): &(String, String)) -> Result<(), ApiError> {
+            let body = br#"{"results":[]}"#;
+            let timestamp = Utc::now().timestamp().to_string();
+            let nonce = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+            let signature = auth::sign(secret, &timestamp, &nonce, body);
+            let signed = auth::Signed {
+                key_id,
+                timestamp: &timestamp,
+                nonce: &nonce,
+                signature: &signature,
+            };
+            auth::authenticate(&self.state, &signed, body)
+                .await
+                .map(|_| ())
+        }
+
+        async fn clean_up(self) {
+            for table in [
+                "agent_audit_log",
+                "agent_heartbeats",
+                "agent_keys",
+                "agents",
+            ] {
+                sqlx::query(&format!(
+                    "DELETE FROM monitoring.{table} WHERE location = $1"
+                ))
+                .bind(&self.location)
+                .execute(&self.state.pool)
+                .await
+                .unwrap();
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn rotated_out_keys_work_until_the_overlap_ends() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool, 2).await;
+        let old = fixture.rotate().await;
+        let new = fixture.rotate().await;
+
+        assert!(fixture.report_with(&old).await.is_ok());
+        assert!(fixture.report_with(&new).await.is_ok());
+
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        let rejected = fixture.report_with(&old).await;
+        assert!(
+            matches!(&rejected, Err(ApiError::Unauthorized(m)) if m.contains("no longer valid")),
+            "{rejected:?}"
+        );
+        assert!(fixture.report_with(&new).await.is_ok());
+        fixture.clean_up().await;
+    }
+
+    #[actix_web::test]
+    async fn revoked_keys_are_rejected_at_once() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool, 86_400).await;
+        let old = fixture.rotate().await;
+        let new = fixture.rotate().await;
+        assert!(fixture.report_with(&old).await.is_ok());
+
+        fixture.revoke(&old.0).await;
+        let rejected = fixture.report_with(&old).await;
+        assert!(
+            matches!(&rejected, Err(ApiError::Unauthorized(m)) if m.contains("no longer valid")),
+            "{rejected:?}"
+        );
+        assert!(fixture.report_with(&new).await.is_ok());
+        fixture.clean_up().await;
+    }
+
+    #[actix_web::test]
+    async fn disabled_agents_cannot_use_their_keys() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool, 86_400).await;
+        let old = fixture.rotate().await;
+        let new = fixture.rotate().await;
+
+        agents::set_enabled(&fixture.state.pool, &fixture.location, false)
+            .await
+            .unwrap();
+        for key in [&old, &new] {
+            let rejected = fixture.report_with(key).await;
+            assert!(
+                matches!(&rejected, Err(ApiError::Forbidden(m)) if m.contains("is disabled")),
+                "{rejected:?}"
+            );
+        }
+
+        agents::set_enabled(&fixture.state.pool, &fixture.location, true)
+            .await
+            .unwrap();
+        assert!(fixture.report_with(&new).await.is_ok());
+        fixture.clean_up().await;
+    }
+}
@@ -0,0 +1,402 @@
+//! Agent liveness. Each agent's report cadence is learned from the gaps
+//! between its recent reports, and a background task moves it through
+//! online, late, offline and outdated, alerting on every change.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::alerts;
+use crate::db::agent_heartbeats;
+use crate::db::agent_reports::{self, ReportStats};
+use crate::db::agents;
+use crate::error::ApiError;
+use crate::notify::Dispatcher;
+use crate::state::AppState;
+
+/// Gaps shorter than this separate reports within one round of checks
+/// (the agent reports each result as soon as it has it), not rounds.
+pub const MIN_GAP_SECS: f64 = 30.0;
+/// Gaps needed before the learned cadence replaces the configured one.
+const MIN_SAMPLES: usize = 3;
+const MAX_SAMPLES: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liveness {
+    Online,
+    Late,
+    Offline,
+    /// Reporting with a revoked or expired key, or an old agent version.
+    Outdated,
+}
+
+impl Liveness {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Liveness::Online => "online",
+            Liveness::Late => "late",
+            Liveness::Offline => "offline",
+            Liveness::Outdated => "outdated",
+        }
+    }
+
+    /// Parses the `liveness` column; `None` for anything unrecognised.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "online" => Some(Liveness::Online),
+            "late" => Some(Liveness::Late),
+            "offline" => Some(Liveness::Offline),
+            "outdated" => Some(Liveness::Outdated),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Liveness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A change of liveness, as `on_agent_transition` receives it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LivenessTransition {
+    pub from: Liveness,
+    pub to: Liveness,
+}
+
+#[derive(Debug, Clone)]
+pub struct LivenessSettings {
+    /// Cadence assumed until enough reports have been seen.
+    pub default_interval: Duration,
+    /// Late after this many intervals of silence...
+    pub late_after: f64,
+    /// ...and offline after this many.
+    pub offline_after: f64,
+    pub min_version: Option<String>,
+}
+
+impl Default for LivenessSettings {
+    fn default() -> Self {
+        LivenessSettings {
+            default_interval: Duration::from_secs(300),
+            late_after: 1.5,
+            offline_after: 3.0,
+            min_version: None,
+        }
+    }
+}
+
+/// What is known about an agent at one instant.
+#[derive(Debug, Clone, Copy)]
+pub struct Observation<'a> {
+    /// Time since the last accepted report; `None` if it never reported.
+    pub silent: Option<Duration>,
+    pub interval: Duration,
+    /// Its last report was signed with a key that is no longer valid.
+    pub out_of_sync: bool,
+    pub version: Option<&'a str>,
+}
+
+impl LivenessSettings {
+    pub fn classify(&self, obs: &Observation<'_>) -> Liveness {
+        if obs.out_of_sync || self.is_outdated_version(obs.version) {
+            return Liveness::Outdated;
+        }
+        let Some(silent) = obs.silent else {
+            return Liveness::Offline;
+        };
+        let intervals = silent.as_secs_f64() / obs.interval.as_secs_f64();
+        if intervals > self.offline_after {
+            Liveness::Offline
+        } else if intervals > self.late_after {
+            Liveness::Late
+        } else {
+            Liveness::Online
+        }
+    }
+
+    fn is_outdated_version(&self, version: Option<&str>) -> bool {
+        match (version, &self.min_version) {
+            (Some(version), Some(min)) => version_older(version, min),
+            _ => false,
+        }
+    }
+}
+
+/// Median of the most recent gaps (newest first), or `default` until there
+/// are enough of them.
+pub fn expected_interval(gaps_secs: &[f64], default: Duration) -> Duration {
+    let mut gaps: Vec<f64> = gaps_secs
+        .iter()
+        .copied()
+        .filter(|g| g.is_finite())
+        .take(MAX_SAMPLES)
+        .collect();
+    if gaps.len() < MIN_SAMPLES {
+        return default;
+    }
+    gaps.sort_by(f64::total_cmp);
+    let mid = gaps.len() / 2;
+    let median = if gaps.len().is_multiple_of(2) {
+        (gaps[mid - 1] + gaps[mid]) / 2.0
+    } else {
+        gaps[mid]
+    };
+    Duration::from_secs_f64(median)
+}
+
+/// Compares dotted versions numerically ("2.10" is newer than "2.9"). A
+/// missing or non-numeric component counts as 0.
+pub fn version_older(version: &str, min: &str) -> bool {
+    let parts = |v: &str| -> Vec<u64> {
+        v.trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|p| {
+                let digits: String = p.chars().take_while(char::is_ascii_digit).collect();
+                digits.parse().unwrap_or(0)
+            })
+            .collect()
+    };
+    let (a, b) = (parts(version), parts(min));
+    let len = a.len().max(b.len());
+    let at = |v: &[u64], i: usize| v.get(i).copied().unwrap_or(0);
+    (0..len)
+        .map(|i| at(&a, i).cmp(&at(&b, i)))
+        .find(|o| o.is_ne())
+        .is_some_and(|o| o.is_lt())
+}
+
+/// One row of `/api/agent-status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentStatus {
+    pub location: String,
+    pub name: Option<String>,
+    pub region: Option<String>,
+    pub enabled: bool,
+    /// A liveness state, or "disabled".
+    pub status: &'static str,
+    /// When the tracker last moved the agent into its stored state.
+    pub status_since: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub minutes_since_last_seen: Option<f64>,
+    pub expected_interval_secs: f64,
+    pub reports_last_hour: i64,
+    pub version: Option<String>,
+    pub clock_skew_secs: Option<f64>,
+    pub out_of_sync: bool,
+    #[serde(skip)]
+    pub liveness: Option<Liveness>,
+    #[serde(skip)]
+    pub stored: Option<Liveness>,
+    #[serde(skip)]
+    pub detail: String,
+}
+
+fn minutes(d: Duration) -> u64 {
+    d.as_secs() / 60
+}
+
+/// Classifies every registered agent as of now.
+pub async fn snapshot(
+    pool: &PgPool,
+    settings: &LivenessSettings,
+) -> sqlx::Result<Vec<AgentStatus>> {
+    let now = Utc::now();
+    let heartbeats: HashMap<String, _> = agent_heartbeats::list(pool)
+        .await?
+        .into_iter()
+        .map(|h| (h.location.clone(), h))
+        .collect();
+    let stats: HashMap<String, ReportStats> = agent_reports::stats(pool, MIN_GAP_SECS)
+        .await?
+        .into_iter()
+        .map(|s| (s.location.clone(), s))
+        .collect();
+
+    let mut statuses = Vec::new();
+    for agent in agents::list(pool).await? {
+        let heartbeat = heartbeats.get(&agent.location);
+        let stats = stats.get(&agent.location);
+        let silent = heartbeat.map(|h| (now - h.last_seen).to_std().unwrap_or_default());
+        let interval = expected_interval(
+            stats.map(|s| s.gaps_secs.as_slice()).unwrap_or_default(),
+            settings.default_interval,
+        );
+        let out_of_sync = heartbeat.is_some_and(|h| h.out_of_sync);
+        let observation = Observation {
+            silent,
+            interval,
+            out_of_sync,
+            version: agent.version.as_deref(),
+        };
+        let liveness = agent.enabled.then(|| settings.classify(&observation));
+        let detail = match liveness {
+            Some(Liveness::Outdated) if out_of_sync => {
+                "its key is no longer valid; redeploy it".to_string()
+            }
+            Some(Liveness::Outdated) => format!(
+                "version {} is older than {}",
+                agent.version.as_deref().unwrap_or_default(),
+                settings.min_version.as_deref().unwrap_or_default()
+            ),
+            _ => match silent {
+                Some(silent) => format!(
+                    "no report for {} minutes (expected every {})",
+                    minutes(silent),
+                    minutes(interval).max(1)
+                ),
+                None => "it has never reported".to_string(),
+            },
+        };
+
+        statuses.push(AgentStatus {
+            status: liveness.map_or("disabled", Liveness::as_str),
+            status_since: agent.liveness_since,
+            last_seen: heartbeat.map(|h| h.last_seen),
+            minutes_since_last_seen: silent.map(|s| (s.as_secs_f64() / 60.0 * 10.0).round() / 10.0),
+            expected_interval_secs: interval.as_secs_f64(),
+            reports_last_hour: stats.map_or(0, |s| s.reports_last_hour),
+            clock_skew_secs: stats.and_then(|s| s.clock_skew_secs),
+            out_of_sync,
+            stored: agent.liveness.as_deref().and_then(Liveness::parse),
+            liveness,
+            detail,
+            location: agent.location,
+            name: agent.name,
+            region: agent.region,
+            enabled: agent.enabled,
+            version: agent.version,
+        });
+    }
+    Ok(statuses)
+}
+
+/// Stores each agent's new liveness and alerts on changes. The first
+/// classification of an agent counts as a change from online, so an agent
+/// that is already down when tracking starts still raises an alert, unless
+/// it has never reported at all.
+pub async fn track(
+    pool: &PgPool,
+    settings: &LivenessSettings,
+    notifier: &Dispatcher,
+) -> sqlx::Result<()> {
+    for status in snapshot(pool, settings).await? {
+        if status.liveness == status.stored {
+            continue;
+        }
+        let mut tx = pool.begin().await?;
+        agents::set_liveness(
+            &mut *tx,
+            &status.location,
+            status.liveness.map(Liveness::as_str),
+        )
+        .await?;
+        let event = match status.liveness {
+            Some(to) if status.last_seen.is_some() || status.stored.is_some() => {
+                let transition = LivenessTransition {
+                    from: status.stored.unwrap_or(Liveness::Online),
+                    to,
+                };
+                println!(
+                    "Agent {} is now {to} (was {})",
+                    status.location, transition.from
+                );
+                alerts::on_agent_transition(&mut tx, &status.location, transition, &status.detail)
+                    .await?
+            }
+            Some(_) => None,
+            None => alerts::on_agent_disabled(&mut tx, &status.location).await?,
+        };
+        tx.commit().await?;
+        notifier.dispatch(event);
+    }
+    Ok(())
+}
+
+/// `GET /api/agent-status`: every registered agent with its liveness, last
+/// report, learned cadence, report rate, version and clock skew.
+pub async fn agent_status(state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let agents = snapshot(&state.pool, &state.liveness).await?;
+    Ok(HttpResponse::Ok().json(json!({ "agents": agents })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observe(silent_secs: Option<u64>) -> Observation<'static> {
+        Observation {
+            silent: silent_secs.map(Duration::from_secs),
+            interval: Duration::from_secs(300),
+            out_of_sync: false,
+            version: None,
+        }
+    }
+
+    #[test]
+    fn silence_moves_through_late_and_offline() {
+        let settings = LivenessSettings::default();
+        assert_eq!(settings.classify(&observe(Some(0))), Liveness::Online);
+        assert_eq!(settings.classify(&observe(Some(450))), Liveness::Online);
+        assert_eq!(settings.classify(&observe(Some(451))), Liveness::Late);
+        assert_eq!(settings.classify(&observe(Some(900))), Liveness::Late);
+        assert_eq!(settings.classify(&observe(Some(901))), Liveness::Offline);
+        assert_eq!(settings.classify(&observe(None)), Liveness::Offline);
+    }
+
+    #[test]
+    fn stale_keys_and_old_versions_are_outdated() {
+        let settings = LivenessSettings {
+            min_version: Some("2.1.0".to_string()),
+            ..Default::default()
+        };
+        let out_of_sync = Observation {
+            out_of_sync: true,
+            ..observe(Some(10))
+        };
+        assert_eq!(settings.classify(&out_of_sync), Liveness::Outdated);
+        let old = Observation {
+            version: Some("2.0.9"),
+            ..observe(Some(10))
+        };
+        assert_eq!(settings.classify(&old), Liveness::Outdated);
+        let current = Observation {
+            version: Some("2.1"),
+            ..observe(Some(10))
+        };
+        assert_eq!(settings.classify(&current), Liveness::Online);
+    }
+
+    #[test]
+    fn learns_the_cadence_from_recent_gaps() {
+        let default = Duration::from_secs(300);
+        assert_eq!(expected_interval(&[], default), default);
+        assert_eq!(expected_interval(&[60.0, 60.0], default), default);
+        assert_eq!(
+            expected_interval(&[60.0, 61.0, 3600.0, 59.0], default),
+            Duration::from_secs_f64(60.5)
+        );
+        assert_eq!(
+            expected_interval(&[120.0, 118.0, 900.0], default),
+            Duration::from_secs(120)
+        );
+    }
+
+    #[test]
+    fn compares_versions_numerically() {
+        assert!(version_older("2.9", "2.10"));
+        assert!(version_older("v1.2.3", "1.3"));
+        assert!(version_older("2.0", "2.0.1"));
+        assert!(!version_older("2.0.0", "2"));
+        assert!(!version_older("2.10", "2.9"));
+        assert!(!version_older("3.0-beta", "2.9"));
+    }
+}
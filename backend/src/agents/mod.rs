@@ -0,0 +1,7 @@
+pub mod audit;
+pub mod auth;
+pub mod enrollment;
+pub mod keys;
+pub mod liveness;
+pub mod registry;
+pub mod report;
@@ -0,0 +1,575 @@
+//! Admin endpoints for the geo agent registry. A location may report only
+//! while it is registered and enabled; see add_agent_registry.sql.
+
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::admin::Admin;
+use crate::alerts::{AGENT_SERVICE, SYSTEM_ACTOR};
+use crate::db::agents::{self, Agent, AgentMetadata};
+use crate::db::{agent_heartbeats, agent_keys, alerts};
+use crate::error::{ApiError, FieldError};
+use crate::notify::AlertEvent;
+use crate::state::AppState;
+
+const MAX_LOCATION_LEN: usize = 16;
+const MAX_TEXT_LEN: usize = 100;
+
+/// Locations are stored upper-cased, so `ph` and `PH` name the same agent.
+pub fn normalize_location(location: &str) -> String {
+    location.trim().to_ascii_uppercase()
+}
+
+/// The registered agent at `location`, or 404.
+pub async fn require(state: &AppState, location: &str) -> Result<Agent, ApiError> {
+    agents::get(&state.pool, location)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Agent {location} is not registered")))
+}
+
+/// Body of the register and update endpoints. Update replaces every
+/// metadata field, so omitted fields are cleared.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentBody {
+    pub location: Option<String>,
+    pub name: Option<String>,
+    pub region: Option<String>,
+    pub isp: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub version: Option<String>,
+}
+
+impl AgentBody {
+    fn metadata(&self, errors: &mut Vec<FieldError>) -> AgentMetadata<'_> {
+        let meta = AgentMetadata {
+            name: optional_text("name", &self.name, errors),
+            region: optional_text("region", &self.region, errors),
+            isp: optional_text("isp", &self.isp, errors),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            version: optional_text("version", &self.version, errors),
+        };
+        match (meta.latitude, meta.longitude) {
+            (Some(lat), Some(lon)) => {
+                if !(-90.0..=90.0).contains(&lat) {
+                    errors.push(FieldError::new(
+                        None,
+                        "latitude",
+                        "must be between -90 and 90",
+                    ));
+                }
+                if !(-180.0..=180.0).contains(&lon) {
+                    errors.push(FieldError::new(
+                        None,
+                        "longitude",
+                        "must be between -180 and 180",
+                    ));
+                }
+            }
+            (None, None) => {}
+            (None, Some(_)) => errors.push(FieldError::new(
+                None,
+                "latitude",
+                "is required with longitude",
+            )),
+            (Some(_), None) => errors.push(FieldError::new(
+                None,
+                "longitude",
+                "is required with latitude",
+            )),
+        }
+        meta
+    }
+}
+
+/// Trims a free-text field; blank means unset.
+fn optional_text<'a>(
+    field: &str,
+    value: &'a Option<String>,
+    errors: &mut Vec<FieldError>,
+) -> Option<&'a str> {
+    let value = value.as_deref().map(str::trim).filter(|v| !v.is_empty());
+    if value.is_some_and(|v| v.chars().count() > MAX_TEXT_LEN) {
+        errors.push(FieldError::new(
+            None,
+            field,
+            format!("must be at most {MAX_TEXT_LEN} characters"),
+        ));
+    }
+    value
+}
+
+fn validate_location(location: &str, errors: &mut Vec<FieldError>) {
+    let valid = (1..=MAX_LOCATION_LEN).contains(&location.len())
+        && location
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if !valid {
+        errors.push(FieldError::new(
+            None,
+            "location",
+            format!("must be 1 to {MAX_LOCATION_LEN} characters of [A-Z0-9_-]"),
+        ));
+    }
+}
+
+/// `GET /api/admin/agents`
+pub async fn list_agents(_: Admin, state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let agents = agents::list(&state.pool).await?;
+    Ok(HttpResponse::Ok().json(json!({ "agents": agents })))
+}
+
+/// `POST /api/admin/agents`: registers a new vantage point. It can report
+/// as soon as it has a key.
+pub async fn register_agent(
+    _: Admin,
+    state: web::Data<AppState>,
+    body: web::Json<AgentBody>,
+) -> Result<HttpResponse, ApiError> {
+    let mut errors = Vec::new();
+    let location = normalize_location(body.location.as_deref().unwrap_or_default());
+    validate_location(&location, &mut errors);
+    let meta = body.metadata(&mut errors);
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+    match agents::insert(&state.pool, &location, &meta).await? {
+        Some(agent) => {
+            println!("Registered agent {location}");
+            Ok(HttpResponse::Created().json(agent))
+        }
+        None => Err(ApiError::Conflict(format!(
+            "Agent {location} is already registered"
+        ))),
+    }
+}
+
+/// `GET /api/admin/agents/{location}`
+pub async fn get_agent(
+    _: Admin,
+    state: web::Data<AppState>,
+    location: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let agent = require(&state, &normalize_location(&location)).await?;
+    Ok(HttpResponse::Ok().json(agent))
+}
+
+/// `PUT /api/admin/agents/{location}`: replaces the agent's metadata.
+pub async fn update_agent(
+    _: Admin,
+    state: web::Data<AppState>,
+    location: web::Path<String>,
+    body: web::Json<AgentBody>,
+) -> Result<HttpResponse, ApiError> {
+    let location = normalize_location(&location);
+    let mut errors = Vec::new();
+    if body
+        .location
+        .as_deref()
+        .is_some_and(|l| normalize_location(l) != location)
+    {
+        errors.push(FieldError::new(None, "location", "cannot be changed"));
+    }
+    let meta = body.metadata(&mut errors);
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+    match agents::update_metadata(&state.pool, &location, &meta).await? {
+        Some(agent) => Ok(HttpResponse::Ok().json(agent)),
+        None => Err(ApiError::NotFound(format!(
+            "Agent {location} is not registered"
+        ))),
+    }
+}
+
+async fn set_enabled(
+    state: &AppState,
+    location: &str,
+    enabled: bool,
+) -> Result<HttpResponse, ApiError> {
+    let location = normalize_location(location);
+    match agents::set_enabled(&state.pool, &location, enabled).await? {
+        Some(agent) => {
+            println!(
+                "Agent {location} {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+            Ok(HttpResponse::Ok().json(agent))
+        }
+        None => Err(ApiError::NotFound(format!(
+            "Agent {location} is not registered"
+        ))),
+    }
+}
+
+/// `POST /api/admin/agents/{location}/disable`: its reports are refused
+/// and it is no longer watched for liveness, but its keys and history stay.
+pub async fn disable_agent(
+    _: Admin,
+    state: web::Data<AppState>,
+    location: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    set_enabled(&state, &location, false).await
+}
+
+/// `POST /api/admin/agents/{location}/enable`
+pub async fn enable_agent(
+    _: Admin,
+    state: web::Data<AppState>,
+    location: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    set_enabled(&state, &location, true).await
+}
+
+/// `DELETE /api/admin/agents/{location}`: removes the agent with its keys,
+/// enrollment codes (by cascade) and heartbeat, and resolves its offline
+/// alert. Past checks are kept.
+pub async fn delete_agent(
+    _: Admin,
+    state: web::Data<AppState>,
+    location: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let location = normalize_location(&location);
+    let mut tx = state.pool.begin().await?;
+    if !agents::delete(&mut *tx, &location).await? {
+        return Err(ApiError::NotFound(format!(
+            "Agent {location} is not registered"
+        )));
+    }
+    let keys = agent_keys::delete_for_location(&mut *tx, &location).await?;
+    agent_heartbeats::delete(&mut *tx, &location).await?;
+    let resolved = alerts::resolve_open(
+        &mut *tx,
+        &location,
+        AGENT_SERVICE,
+        SYSTEM_ACTOR,
+        &format!("Agent {location} was deleted"),
+    )
+    .await?;
+    tx.commit().await?;
+
+    println!("Deleted agent {location} and {keys} key(s)");
+    state.notifier.dispatch(resolved.map(AlertEvent::resolved));
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "deleted",
+        "location": location,
+        "keys_deleted": keys,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use rand::Rng;
+    use serde_json::Value;
+    use sqlx::postgres::PgPoolOptions;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::agents::auth;
+    use crate::db::{self, enrollment_codes};
+
+    const TOKEN: &str = "registry-test-token";
+
+    fn state(pool: PgPool) -> web::Data<AppState> {
+        web::Data::new(AppState {
+            admin_token: Some(TOKEN.to_string()),
+            ..AppState::for_tests(pool)
+        })
+    }
+
+    /// A location no other test uses.
+    fn location() -> String {
+        let tag = hex::encode(rand::thread_rng().gen::<[u8; 4]>());
+        format!("T{}", tag.to_ascii_uppercase())
+    }
+
+    async fn call(state: &web::Data<AppState>, request: test::TestRequest) -> (StatusCode, Value) {
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .app_data(web::JsonConfig::default().error_handler(|e, _| {
+                    ApiError::BadRequest(format!("Invalid JSON body: {e}")).into()
+                }))
+                .route("/api/admin/agents", web::post().to(register_agent))
+                .route("/api/admin/agents/{location}", web::get().to(get_agent))
+                .route("/api/admin/agents/{location}", web::put().to(update_agent))
+                .route(
+                    "/api/admin/agents/{location}",
+                    web::delete().to(delete_agent),
+                )
+                .route(
+                    "/api/admin/agents/{location}/disable",
+                    web::post().to(disable_agent),
+                )
+                .route(
+                    "/api/admin/agents/{location}/enable",
+                    web::post().to(enable_agent),
+                ),
+        )
+        .await;
+        let request = request
+            .insert_header((AUTHORIZATION, format!("Bearer {TOKEN}")))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        let status = response.status();
+        (status, test::read_body_json(response).await)
+    }
+
+    async fn register(state: &web::Data<AppState>, body: Value) -> (StatusCode, Value) {
+        call(
+            state,
+            test::TestRequest::post()
+                .uri("/api/admin/agents")
+                .set_json(body),
+        )
+        .await
+    }
+
+    async fn count(pool: &PgPool, table: &str, location: &str) -> i64 {
+        sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM monitoring.{table} WHERE location = $1"
+        ))
+        .bind(location)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn clean_up(pool: &PgPool, location: &str) {
+        for table in ["agent_heartbeats", "agent_keys", "agents"] {
+            sqlx::query(&format!(
+                "DELETE FROM monitoring.{table} WHERE location = $1"
+            ))
+            .bind(location)
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+        sqlx::query("DELETE FROM monitoring.alerts WHERE host = $1")
+            .bind(location)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[actix_web::test]
+    async fn registers_each_location_once() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let state = state(pool.clone());
+        let location = location();
+
+        let (status, agent) = register(
+            &state,
+            json!({
+                "location": format!(" {} ", location.to_ascii_lowercase()),
+                "name": " Manila ",
+                "region": "NCR",
+                "isp": "",
+                "latitude": 90.0,
+                "longitude": -180.0,
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(agent["location"], location.as_str());
+        assert_eq!(agent["name"], "Manila");
+        assert_eq!(agent["region"], "NCR");
+        assert_eq!(agent["isp"], Value::Null);
+        assert_eq!(agent["latitude"], 90.0);
+        assert_eq!(agent["longitude"], -180.0);
+        assert_eq!(agent["enabled"], true);
+
+        let uri = format!("/api/admin/agents/{}", location.to_ascii_lowercase());
+        let (status, fetched) = call(&state, test::TestRequest::get().uri(&uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(fetched["name"], "Manila");
+
+        let (status, body) = register(&state, json!({ "location": location })).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            body["message"],
+            format!("Agent {location} is already registered")
+        );
+        assert_eq!(count(&pool, "agents", &location).await, 1);
+
+        let (status, _) = call(
+            &state,
+            test::TestRequest::get().uri("/api/admin/agents/TNONE"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        clean_up(&pool, &location).await;
+    }
+
+    #[actix_web::test]
+    async fn rejects_metadata_out_of_bounds() {
+        // Every body is refused before the database is reached.
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(200))
+            .connect_lazy("postgres://monitor@127.0.0.1:1/monitoring")
+            .unwrap();
+        let state = state(pool);
+        let long = "x".repeat(MAX_TEXT_LEN + 1);
+        for (body, field, message) in [
+            (
+                json!({}),
+                "location",
+                "must be 1 to 16 characters of [A-Z0-9_-]",
+            ),
+            (
+                json!({ "location": "PH MANILA" }),
+                "location",
+                "must be 1 to 16 characters of [A-Z0-9_-]",
+            ),
+            (
+                json!({ "location": "A".repeat(MAX_LOCATION_LEN + 1) }),
+                "location",
+                "must be 1 to 16 characters of [A-Z0-9_-]",
+            ),
+            (
+                json!({ "location": "PH", "latitude": 90.5, "longitude": 0.0 }),
+                "latitude",
+                "must be between -90 and 90",
+            ),
+            (
+                json!({ "location": "PH", "latitude": 0.0, "longitude": -180.5 }),
+                "longitude",
+                "must be between -180 and 180",
+            ),
+            (
+                json!({ "location": "PH", "longitude": 121.0 }),
+                "latitude",
+                "is required with longitude",
+            ),
+            (
+                json!({ "location": "PH", "latitude": 14.6 }),
+                "longitude",
+                "is required with latitude",
+            ),
+            (
+                json!({ "location": "PH", "region": long }),
+                "region",
+                "must be at most 100 characters",
+            ),
+            (
+                json!({ "location": "PH", "name": long }),
+                "name",
+                "must be at most 100 characters",
+            ),
+        ] {
+            let (status, response) = register(&state, body.clone()).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+            assert_eq!(response["errors"][0]["field"], field, "{body}");
+            assert_eq!(response["errors"][0]["message"], message, "{body}");
+        }
+
+        let (status, _) = register(&state, json!({ "location": "PH", "city": "Manila" })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, response) = call(
+            &state,
+            test::TestRequest::put()
+                .uri("/api/admin/agents/PH")
+                .set_json(json!({ "location": "SG", "latitude": -91.0, "longitude": 0.0 })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response["errors"][0]["field"], "location");
+        assert_eq!(response["errors"][0]["message"], "cannot be changed");
+        assert_eq!(response["errors"][1]["field"], "latitude");
+    }
+
+    #[actix_web::test]
+    async fn disabling_keeps_the_agent_and_its_keys() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let state = state(pool.clone());
+        let location = location();
+        register(&state, json!({ "location": location })).await;
+        let (key_id, secret) = auth::generate_key(&location);
+        agent_keys::insert(&pool, &location, &key_id, &secret)
+            .await
+            .unwrap();
+
+        let uri = format!("/api/admin/agents/{location}/disable");
+        let (status, agent) = call(&state, test::TestRequest::post().uri(&uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(agent["enabled"], false);
+        let stored = agents::get(&pool, &location).await.unwrap().unwrap();
+        assert!(!stored.enabled);
+        assert_eq!(count(&pool, "agent_keys", &location).await, 1);
+
+        let uri = format!("/api/admin/agents/{location}/enable");
+        let (status, agent) = call(&state, test::TestRequest::post().uri(&uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(agent["enabled"], true);
+
+        let (status, _) = call(
+            &state,
+            test::TestRequest::post().uri("/api/admin/agents/TNONE/disable"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        clean_up(&pool, &location).await;
+    }
+
+    #[actix_web::test]
+    async fn deleting_drops_keys_codes_and_heartbeat() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let state = state(pool.clone());
+        let location = location();
+        register(&state, json!({ "location": location })).await;
+        let (key_id, secret) = auth::generate_key(&location);
+        agent_keys::insert(&pool, &location, &key_id, &secret)
+            .await
+            .unwrap();
+        enrollment_codes::insert(
+            &pool,
+            &location,
+            &format!("hash-{location}"),
+            "admin (test)",
+            chrono::Utc::now() + chrono::Duration::minutes(15),
+        )
+        .await
+        .unwrap();
+        agent_heartbeats::touch(&pool, &location, &key_id)
+            .await
+            .unwrap();
+        alerts::open(&pool, &location, AGENT_SERVICE, "critical", "offline")
+            .await
+            .unwrap();
+
+        let uri = format!("/api/admin/agents/{location}");
+        let (status, body) = call(&state, test::TestRequest::delete().uri(&uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "deleted");
+        assert_eq!(body["keys_deleted"], 1);
+        for table in [
+            "agents",
+            "agent_keys",
+            "agent_enrollment_codes",
+            "agent_heartbeats",
+        ] {
+            assert_eq!(count(&pool, table, &location).await, 0, "{table}");
+        }
+        assert!(alerts::find_open(&pool, &location, AGENT_SERVICE)
+            .await
+            .unwrap()
+            .is_none());
+
+        let (status, _) = call(&state, test::TestRequest::delete().uri(&uri)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        clean_up(&pool, &location).await;
+    }
+}
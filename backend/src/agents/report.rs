@@ -0,0 +1,617 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::agents::auth::{self, Signed};
+use crate::agents::registry;
+use crate::alerts;
+use crate::db::agent_reports::{self, NewAgentReport};
+use crate::db::uptime_checks::{self, NewUptimeCheck};
+use crate::db::{agent_heartbeats, agents, report_nonces, subdomains};
+use crate::error::ApiError;
+use crate::state::AppState;
+use crate::status;
+
+/// How far ahead of the server clock a result timestamp may be.
+const MAX_CLOCK_AHEAD: Duration = Duration::minutes(5);
+const MAX_VERSION_LEN: usize = 100;
+
+/// `POST /api/geo-report` body. Results stay untyped until each one is
+/// validated on its own, so one bad entry cannot sink the whole batch.
+/// `location` may be omitted; the signing key already identifies it.
+#[derive(Debug, Deserialize)]
+pub struct GeoReport {
+    #[serde(default)]
+    pub location: String,
+    /// Version of the agent script, kept on its registry entry.
+    pub version: Option<String>,
+    #[serde(default)]
+    pub results: Vec<Value>,
+}
+
+/// One probe result, as emitted by deployment/geo_monitor.sh.
+#[derive(Debug, Deserialize)]
+struct GeoResult {
+    subdomain: String,
+    status_code: Option<i32>,
+    response_time_ms: Option<f64>,
+    up: bool,
+    timestamp: DateTime<Utc>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResultRef {
+    pub index: usize,
+    pub subdomain: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Malformed {
+    pub index: usize,
+    pub reason: String,
+}
+
+/// Per-result outcome of a report. `index` is the position in `results`.
+#[derive(Debug, Serialize)]
+pub struct ReportOutcome {
+    pub status: &'static str,
+    pub received: usize,
+    pub accepted: Vec<ResultRef>,
+    pub rejected_malformed: Vec<Malformed>,
+    pub rejected_unknown_subdomain: Vec<ResultRef>,
+    /// Results for TCP and UDP targets, which agents only probe over HTTP
+    /// and so would report as down.
+    pub rejected_not_http: Vec<ResultRef>,
+}
+
+fn parse_result(value: Value, now: DateTime<Utc>) -> Result<GeoResult, String> {
+    let mut result: GeoResult = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    result.subdomain = result.subdomain.trim().to_ascii_lowercase();
+    if result.subdomain.is_empty() {
+        return Err("subdomain is empty".to_string());
+    }
+    if let Some(code) = result.status_code {
+        if !(100..=599).contains(&code) {
+            return Err(format!("status_code {code} is not an HTTP status"));
+        }
+    }
+    if let Some(ms) = result.response_time_ms {
+        if !ms.is_finite() || ms < 0.0 {
+            return Err(format!("response_time_ms {ms} is not a duration"));
+        }
+    }
+    if result.timestamp > now + MAX_CLOCK_AHEAD {
+        return Err(format!("timestamp {} is in the future", result.timestamp));
+    }
+    Ok(result)
+}
+
+fn result_refs(results: Vec<(usize, GeoResult)>) -> Vec<ResultRef> {
+    results
+        .into_iter()
+        .map(|(index, r)| ResultRef {
+            index,
+            subdomain: r.subdomain,
+        })
+        .collect()
+}
+
+/// `POST /api/geo-report`: accepts a signed batch of results from a geo
+/// agent (see [`auth`] for the signature scheme).
+///
+/// Every result is classified individually. The nonce, the heartbeat, the
+/// report's entry in the liveness history, the accepted checks and the
+/// resulting status updates and alerts are then written in a single
+/// transaction, so a failure leaves no partial report
+/// behind and a replayed report is refused as a whole.
+pub async fn receive_geo_report(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let signed = Signed::from_request(&req)?;
+    let key = match auth::authenticate(&state, &signed, &body).await {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Rejected geo-report signed with {}: {e}", signed.key_id);
+            return Err(e);
+        }
+    };
+    let location = key.location;
+
+    let GeoReport {
+        location: claimed,
+        version,
+        results,
+    } = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid JSON body: {e}")))?;
+    let claimed = registry::normalize_location(&claimed);
+    if !claimed.is_empty() && claimed != location {
+        return Err(ApiError::Forbidden(format!(
+            "Key {} is not valid for location {claimed}",
+            key.key_id
+        )));
+    }
+    let version = version
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+    if version.as_ref().is_some_and(|v| v.len() > MAX_VERSION_LEN) {
+        return Err(ApiError::BadRequest(format!(
+            "version must be at most {MAX_VERSION_LEN} characters"
+        )));
+    }
+
+    let now = Utc::now();
+    let received = results.len();
+    let mut rejected_malformed = Vec::new();
+    let mut parsed = Vec::new();
+    for (index, value) in results.into_iter().enumerate() {
+        match parse_result(value, now) {
+            Ok(result) => parsed.push((index, result)),
+            Err(reason) => rejected_malformed.push(Malformed { index, reason }),
+        }
+    }
+
+    let names: Vec<&str> = parsed.iter().map(|(_, r)| r.subdomain.as_str()).collect();
+    let kinds = subdomains::check_kinds(&state.pool, &names).await?;
+    let mut valid = Vec::new();
+    let mut unknown = Vec::new();
+    let mut not_http = Vec::new();
+    for (index, result) in parsed {
+        match kinds.get(&result.subdomain).map(String::as_str) {
+            Some("http") => valid.push((index, result)),
+            Some(_) => not_http.push((index, result)),
+            None => unknown.push((index, result)),
+        }
+    }
+    // `record_check` locks each subdomain's row until commit. Taking the
+    // locks in name order keeps two agents reporting the same hosts from
+    // deadlocking; per host, results still apply oldest first.
+    valid.sort_by(|(_, a), (_, b)| (&a.subdomain, a.timestamp).cmp(&(&b.subdomain, b.timestamp)));
+
+    let checks: Vec<NewUptimeCheck> = valid
+        .iter()
+        .map(|(_, r)| NewUptimeCheck {
+            time: r.timestamp,
+            subdomain: r.subdomain.clone(),
+            status_code: r.status_code,
+            response_time_ms: r.response_time_ms,
+            up: r.up,
+            error_message: r.error.clone(),
+            location: Some(location.clone()),
+            ..Default::default()
+        })
+        .collect();
+
+    let mut events = Vec::new();
+    let mut tx = state.pool.begin().await?;
+    if !report_nonces::claim(&mut *tx, &location, signed.nonce).await? {
+        eprintln!(
+            "Rejected replayed geo-report from {location} (nonce {})",
+            signed.nonce
+        );
+        return Err(ApiError::Unauthorized(format!(
+            "Replayed report: nonce {} was already used",
+            signed.nonce
+        )));
+    }
+    agent_heartbeats::touch(&mut *tx, &location, &key.key_id).await?;
+    agent_reports::insert(
+        &mut *tx,
+        &NewAgentReport {
+            location: &location,
+            sent_at: signed.sent_at().unwrap_or(now),
+            results: received as i32,
+            accepted: valid.len() as i32,
+            version: version.as_deref(),
+        },
+    )
+    .await?;
+    if let Some(version) = &version {
+        agents::set_version(&mut *tx, &location, version).await?;
+    }
+    uptime_checks::insert_many(&mut *tx, &checks).await?;
+    for check in &checks {
+        if let Some(change) = status::record_check(
+            &mut tx,
+            &check.subdomain,
+            &location,
+            check.up,
+            &state.thresholds,
+        )
+        .await?
+        {
+            events.extend(alerts::on_status_change(&mut tx, &check.subdomain, &change).await?);
+        }
+    }
+    tx.commit().await?;
+    for check in &checks {
+        state.subdomain_cache.record(check);
+    }
+    state.notifier.dispatch(events);
+
+    let outcome = ReportOutcome {
+        status: if valid.len() == received {
+            "success"
+        } else {
+            "partial"
+        },
+        received,
+        accepted: result_refs(valid),
+        rejected_malformed,
+        rejected_unknown_subdomain: result_refs(unknown),
+        rejected_not_http: result_refs(not_http),
+    };
+    println!(
+        "Received {received} geo-reports from {location}: {} accepted, {} malformed, {} unknown, {} not HTTP",
+        outcome.accepted.len(),
+        outcome.rejected_malformed.len(),
+        outcome.rejected_unknown_subdomain.len(),
+        outcome.rejected_not_http.len()
+    );
+    Ok(HttpResponse::Ok().json(outcome))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use rand::Rng;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::db::{self, agent_keys};
+
+    /// A registered agent with a key, and a known subdomain, under names no
+    /// other test uses.
+    struct Fixture {
+        pool: PgPool,
+        location: String,
+        key_id: String,
+        secret: String,
+        subdomain: String,
+    }
+
+    impl Fixture {
+        async fn new(pool: PgPool) -> Self {
+            let tag = hex::encode(rand::thread_rng().gen::<[u8; 4]>());
+            let location = format!("T{}", tag.to_ascii_uppercase());
+            let subdomain = format!("report-{tag}.bettergov.test");
+            agents::insert(&pool, &location, &Default::default())
+                .await
+                .unwrap();
+            let (key_id, secret) = auth::generate_key(&location);
+            agent_keys::insert(&pool, &location, &key_id, &secret)
+                .await
+                .unwrap();
+            subdomains::upsert_discovered(&pool, "bettergov.test", &subdomain)
+                .await
+                .unwrap();
+            Fixture {
+                pool,
+                location,
+                key_id,
+                secret,
+                subdomain,
+            }
+        }
+
+        /// Posts `body` signed at `timestamp` with `nonce`.
+        async fn post(&self, body: &Value, timestamp: i64, nonce: &str) -> (StatusCode, Value) {
+            let body = body.to_string();
+            let timestamp = timestamp.to_string();
+            let signature = auth::sign(&self.secret, &timestamp, nonce, body.as_bytes());
+            self.send(body, &timestamp, nonce, signature).await
+        }
+
+        /// Posts `body` with the given headers, signed or not.
+        async fn send(
+            &self,
+            body: String,
+            timestamp: &str,
+            nonce: &str,
+            signature: String,
+        ) -> (StatusCode, Value) {
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(AppState::for_tests(self.pool.clone())))
+                    .route("/api/geo-report", web::post().to(receive_geo_report)),
+            )
+            .await;
+            let request = test::TestRequest::post()
+                .uri("/api/geo-report")
+                .insert_header((auth::KEY_HEADER, self.key_id.as_str()))
+                .insert_header((auth::TIMESTAMP_HEADER, timestamp))
+                .insert_header((auth::NONCE_HEADER, nonce))
+                .insert_header((auth::SIGNATURE_HEADER, signature))
+                .set_payload(body)
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            let status = response.status();
+            (status, test::read_body_json(response).await)
+        }
+
+        async fn stored_checks(&self) -> Vec<(DateTime<Utc>, Option<i32>, bool)> {
+            sqlx::query_as(
+                "SELECT time, status_code, up FROM monitoring.uptime_checks
+                 WHERE subdomain = $1 AND location = $2
+                 ORDER BY time",
+            )
+            .bind(&self.subdomain)
+            .bind(&self.location)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+        }
+
+        async fn clean_up(self) {
+            for table in ["uptime_checks", "subdomain_location_status"] {
+                sqlx::query(&format!(
+                    "DELETE FROM monitoring.{table} WHERE subdomain = $1"
+                ))
+                .bind(&self.subdomain)
+                .execute(&self.pool)
+                .await
+                .unwrap();
+            }
+            sqlx::query("DELETE FROM monitoring.subdomains WHERE subdomain = $1")
+                .bind(&self.subdomain)
+                .execute(&self.pool)
+                .await
+                .unwrap();
+            for table in [
+                "agent_report_nonces",
+                "agent_reports",
+                "agent_heartbeats",
+                "agent_keys",
+                "agents",
+            ] {
+                sqlx::query(&format!(
+                    "DELETE FROM monitoring.{table} WHERE location = $1"
+                ))
+                .bind(&self.location)
+                .execute(&self.pool)
+                .await
+                .unwrap();
+            }
+        }
+    }
+
+    fn nonce() -> String {
+        hex::encode(rand::thread_rng().gen::<[u8; 16]>())
+    }
+
+    #[actix_web::test]
+    async fn classifies_each_result_and_stores_the_valid_ones() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool).await;
+        let now = Utc::now();
+        let at = |minutes: i64| (now - Duration::minutes(minutes)).to_rfc3339();
+        let subdomain = fixture.subdomain.to_ascii_uppercase();
+        let body = json!({
+            "location": fixture.location,
+            "version": "2.1.0",
+            "results": [
+                {"subdomain": subdomain, "status_code": 200, "response_time_ms": 120.5, "up": true, "timestamp": at(1)},
+                {"subdomain": subdomain, "status_code": 700, "up": true, "timestamp": at(1)},
+                {"subdomain": "unknown.bettergov.test", "status_code": 200, "up": true, "timestamp": at(1)},
+                {"subdomain": subdomain, "up": true},
+                {"subdomain": subdomain, "status_code": 503, "up": false, "timestamp": at(3), "error": "Service Unavailable"},
+            ],
+        });
+
+        let (status, outcome) = fixture.post(&body, now.timestamp(), &nonce()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(outcome["status"], "partial");
+        assert_eq!(outcome["received"], 5);
+        let indexes = |list: &Value| -> Vec<u64> {
+            list.as_array()
+                .unwrap()
+                .iter()
+                .map(|r| r["index"].as_u64().unwrap())
+                .collect()
+        };
+        // Accepted results are stored, and listed, oldest first.
+        assert_eq!(indexes(&outcome["accepted"]), [4, 0]);
+        assert_eq!(outcome["accepted"][0]["subdomain"], fixture.subdomain);
+        assert_eq!(indexes(&outcome["rejected_malformed"]), [1, 3]);
+        assert_eq!(
+            outcome["rejected_malformed"][0]["reason"],
+            "status_code 700 is not an HTTP status"
+        );
+        assert_eq!(indexes(&outcome["rejected_unknown_subdomain"]), [2]);
+        assert_eq!(outcome["rejected_not_http"], json!([]));
+
+        let stored = fixture.stored_checks().await;
+        assert_eq!(stored.len(), 2);
+        assert_eq!((stored[0].1, stored[0].2), (Some(503), false));
+        assert_eq!((stored[1].1, stored[1].2), (Some(200), true));
+        let agent = agents::get(&fixture.pool, &fixture.location)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(agent.version.as_deref(), Some("2.1.0"));
+
+        let clean = json!({
+            "results": [
+                {"subdomain": fixture.subdomain, "status_code": 200, "up": true, "timestamp": at(0)},
+            ],
+        });
+        let (status, outcome) = fixture.post(&clean, now.timestamp(), &nonce()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(outcome["status"], "success");
+        assert_eq!(fixture.stored_checks().await.len(), 3);
+
+        fixture.clean_up().await;
+    }
+
+    fn one_result(fixture: &Fixture) -> Value {
+        json!({
+            "results": [
+                {"subdomain": fixture.subdomain, "status_code": 200, "up": true, "timestamp": Utc::now().to_rfc3339()},
+            ],
+        })
+    }
+
+    #[actix_web::test]
+    async fn concurrent_reports_on_the_same_hosts_do_not_deadlock() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let a = Fixture::new(pool.clone()).await;
+        let b = Fixture::new(pool).await;
+        // By time alone, `a` would lock its own host first and `b` the
+        // other way round.
+        let batch = |first: &str, second: &str| {
+            let now = Utc::now();
+            json!({
+                "results": [
+                    {"subdomain": first, "status_code": 200, "up": true, "timestamp": (now - Duration::seconds(2)).to_rfc3339()},
+                    {"subdomain": second, "status_code": 200, "up": true, "timestamp": (now - Duration::seconds(1)).to_rfc3339()},
+                ],
+            })
+        };
+        for _ in 0..5 {
+            let now = Utc::now().timestamp();
+            let (batch_a, batch_b) = (
+                batch(&a.subdomain, &b.subdomain),
+                batch(&b.subdomain, &a.subdomain),
+            );
+            let (nonce_a, nonce_b) = (nonce(), nonce());
+            let (from_a, from_b) = tokio::join!(
+                a.post(&batch_a, now, &nonce_a),
+                b.post(&batch_b, now, &nonce_b),
+            );
+            assert_eq!(from_a.0, StatusCode::OK, "{}", from_a.1);
+            assert_eq!(from_b.0, StatusCode::OK, "{}", from_b.1);
+        }
+        assert_eq!(a.stored_checks().await.len(), 5);
+        assert_eq!(b.stored_checks().await.len(), 5);
+        a.clean_up().await;
+        b.clean_up().await;
+    }
+
+    #[actix_web::test]
+    async fn rejects_a_tampered_body() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool).await;
+        let signed = one_result(&fixture).to_string();
+        let timestamp = Utc::now().timestamp().to_string();
+        let nonce = nonce();
+        let signature = auth::sign(&fixture.secret, &timestamp, &nonce, signed.as_bytes());
+        let tampered = signed.replace("200", "201");
+
+        let (status, body) = fixture
+            .send(tampered, &timestamp, &nonce, signature.clone())
+            .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["status"], "rejected");
+        assert_eq!(
+            body["message"],
+            format!("Invalid signature for key {}", fixture.key_id)
+        );
+        assert!(fixture.stored_checks().await.is_empty());
+
+        // The nonce was not spent by the rejected report.
+        let (status, _) = fixture.send(signed, &timestamp, &nonce, signature).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(fixture.stored_checks().await.len(), 1);
+
+        fixture.clean_up().await;
+    }
+
+    #[actix_web::test]
+    async fn rejects_a_replayed_nonce() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool).await;
+        let body = one_result(&fixture);
+        let timestamp = Utc::now().timestamp();
+        let nonce = nonce();
+
+        let (status, _) = fixture.post(&body, timestamp, &nonce).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, replay) = fixture.post(&body, timestamp, &nonce).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(replay["status"], "rejected");
+        assert_eq!(
+            replay["message"],
+            format!("Replayed report: nonce {nonce} was already used")
+        );
+        assert_eq!(fixture.stored_checks().await.len(), 1);
+        let (reports,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM monitoring.agent_reports WHERE location = $1")
+                .bind(&fixture.location)
+                .fetch_one(&fixture.pool)
+                .await
+                .unwrap();
+        assert_eq!(reports, 1);
+
+        fixture.clean_up().await;
+    }
+
+    #[actix_web::test]
+    async fn rejects_stale_and_future_timestamps() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool).await;
+        let body = one_result(&fixture);
+        let skew = AppState::for_tests(fixture.pool.clone())
+            .agents
+            .max_clock_skew_secs as i64;
+        let now = Utc::now().timestamp();
+
+        for timestamp in [now - skew - 60, now + skew + 60] {
+            let (status, outcome) = fixture.post(&body, timestamp, &nonce()).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{timestamp}");
+            assert_eq!(outcome["status"], "rejected");
+            assert!(
+                outcome["message"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("Stale report"),
+                "{outcome}"
+            );
+        }
+        assert!(fixture.stored_checks().await.is_empty());
+
+        fixture.clean_up().await;
+    }
+
+    #[actix_web::test]
+    async fn rejects_results_for_targets_agents_cannot_probe() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let fixture = Fixture::new(pool).await;
+        sqlx::query("UPDATE monitoring.subdomains SET check_kind = 'tcp' WHERE subdomain = $1")
+            .bind(&fixture.subdomain)
+            .execute(&fixture.pool)
+            .await
+            .unwrap();
+
+        let (status, outcome) = fixture
+            .post(&one_result(&fixture), Utc::now().timestamp(), &nonce())
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(outcome["status"], "partial");
+        assert_eq!(outcome["accepted"], json!([]));
+        assert_eq!(
+            outcome["rejected_not_http"],
+            json!([{"index": 0, "subdomain": fixture.subdomain}])
+        );
+        assert!(fixture.stored_checks().await.is_empty());
+
+        fixture.clean_up().await;
+    }
+}
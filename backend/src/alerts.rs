@@ -0,0 +1,757 @@
+//! Alert lifecycle: stable status transitions, agent liveness changes,
+//! certificate problems and unexpected DNS or content changes open alerts,
+//! recovery resolves them, and operators acknowledge or resolve them
+//! through the API. Each `(host, service)` pair has at most one open alert.
+
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::admin::Admin;
+use crate::agents::liveness::{Liveness, LivenessTransition};
+use crate::checker::tls::{self, Handshake};
+use crate::db::alerts::{self, Alert, AlertFilter};
+use crate::db::notification_deliveries::{self, Delivery};
+use crate::error::{ApiError, FieldError};
+use crate::notify::AlertEvent;
+use crate::state::AppState;
+use crate::status::{Status, StatusChange};
+
+/// `service` of alerts about a subdomain's stable status.
+pub const UPTIME_SERVICE: &str = "uptime";
+/// `service` of alerts about a geo agent that is late, offline or outdated.
+pub const AGENT_SERVICE: &str = "agent";
+/// `service` of alerts about a subdomain's certificate nearing expiry.
+pub const TLS_EXPIRY_SERVICE: &str = "tls_expiry";
+/// `service` of alerts about a certificate that does not cover its host.
+pub const TLS_HOSTNAME_SERVICE: &str = "tls_hostname";
+/// `service` of alerts about a subdomain's DNS records changing
+/// unexpectedly.
+pub const DNS_CHANGE_SERVICE: &str = "dns_change";
+/// `service` of alerts about the body of a subdomain watched with a
+/// `body_hash` assertion changing.
+pub const CONTENT_CHANGE_SERVICE: &str = "content_change";
+/// Every service whose alerts are about one subdomain.
+pub const SUBDOMAIN_SERVICES: [&str; 5] = [
+    UPTIME_SERVICE,
+    TLS_EXPIRY_SERVICE,
+    TLS_HOSTNAME_SERVICE,
+    DNS_CHANGE_SERVICE,
+    CONTENT_CHANGE_SERVICE,
+];
+/// Actor recorded when an alert is resolved by recovery.
+pub const SYSTEM_ACTOR: &str = "system";
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Opens, escalates or resolves the uptime alert of `subdomain` after its
+/// global status changed. Returns the event to notify about once the
+/// caller's transaction commits.
+pub async fn on_status_change(
+    conn: &mut PgConnection,
+    subdomain: &str,
+    change: &StatusChange,
+) -> sqlx::Result<Option<AlertEvent>> {
+    let to = change.transition.to;
+    match to {
+        Status::Down | Status::Flapping | Status::RegionalOutage => {
+            let severity = if to == Status::Down {
+                "critical"
+            } else {
+                "warning"
+            };
+            let mut message = format!("{subdomain} is {to}");
+            if change.counted > 1 {
+                message.push_str(&format!(
+                    " (failing at {} of {} locations: {})",
+                    change.failing.len(),
+                    change.counted,
+                    change.failing.join(", ")
+                ));
+            }
+            let opened =
+                alerts::open(&mut *conn, subdomain, UPTIME_SERVICE, severity, &message).await?;
+            if opened.created {
+                println!("Alert opened: {message}");
+                return Ok(Some(AlertEvent::opened(opened.alert)));
+            }
+            println!("Alert updated: {message}");
+            if to == Status::Down {
+                return Ok(Some(AlertEvent::escalated(opened.alert)));
+            }
+        }
+        Status::Up => {
+            let comment = format!("Recovered: {subdomain} is UP");
+            if let Some(alert) = alerts::resolve_open(
+                &mut *conn,
+                subdomain,
+                UPTIME_SERVICE,
+                SYSTEM_ACTOR,
+                &comment,
+            )
+            .await?
+            {
+                println!("Alert resolved: {comment}");
+                return Ok(Some(AlertEvent::resolved(alert)));
+            }
+        }
+        Status::Unknown => {}
+    }
+    Ok(None)
+}
+
+/// Opens, escalates or resolves the alert of agent `location` after its
+/// liveness changed. `detail` says why, e.g. how long it has been silent.
+pub async fn on_agent_transition(
+    conn: &mut PgConnection,
+    location: &str,
+    transition: LivenessTransition,
+    detail: &str,
+) -> sqlx::Result<Option<AlertEvent>> {
+    let severity = match transition.to {
+        Liveness::Online => {
+            let comment = format!("Recovered: agent {location} is reporting again");
+            return resolve_agent(conn, location, &comment).await;
+        }
+        Liveness::Offline => "critical",
+        Liveness::Late | Liveness::Outdated => "warning",
+    };
+    let message = format!("Agent {location} is {}: {detail}", transition.to);
+    let opened = alerts::open(&mut *conn, location, AGENT_SERVICE, severity, &message).await?;
+    if opened.created {
+        println!("Alert opened: {message}");
+        return Ok(Some(AlertEvent::opened(opened.alert)));
+    }
+    println!("Alert updated: {message}");
+    if transition.to == Liveness::Offline && transition.from == Liveness::Late {
+        return Ok(Some(AlertEvent::escalated(opened.alert)));
+    }
+    Ok(None)
+}
+
+/// Resolves the alert of an agent that was disabled while it had one.
+pub async fn on_agent_disabled(
+    conn: &mut PgConnection,
+    location: &str,
+) -> sqlx::Result<Option<AlertEvent>> {
+    resolve_agent(conn, location, &format!("Agent {location} is disabled")).await
+}
+
+async fn resolve_agent(
+    conn: &mut PgConnection,
+    location: &str,
+    comment: &str,
+) -> sqlx::Result<Option<AlertEvent>> {
+    clear(conn, location, AGENT_SERVICE, comment).await
+}
+
+/// Orders severities from least to most urgent.
+fn severity_rank(severity: &str) -> u8 {
+    match severity {
+        "critical" => 2,
+        "warning" => 1,
+        _ => 0,
+    }
+}
+
+/// Opens the `service` alert of `host`, or updates the open one and
+/// reports an escalation if it became more severe.
+async fn raise(
+    conn: &mut PgConnection,
+    host: &str,
+    service: &str,
+    severity: &str,
+    message: &str,
+) -> sqlx::Result<Option<AlertEvent>> {
+    let previous = alerts::find_open(&mut *conn, host, service).await?;
+    let opened = alerts::open(&mut *conn, host, service, severity, message).await?;
+    if opened.created {
+        println!("Alert opened: {message}");
+        return Ok(Some(AlertEvent::opened(opened.alert)));
+    }
+    if previous.is_some_and(|p| severity_rank(&p.severity) < severity_rank(severity)) {
+        println!("Alert escalated: {message}");
+        return Ok(Some(AlertEvent::escalated(opened.alert)));
+    }
+    Ok(None)
+}
+
+async fn clear(
+    conn: &mut PgConnection,
+    host: &str,
+    service: &str,
+    comment: &str,
+) -> sqlx::Result<Option<AlertEvent>> {
+    let resolved = alerts::resolve_open(&mut *conn, host, service, SYSTEM_ACTOR, comment).await?;
+    Ok(resolved.map(|alert| {
+        println!("Alert resolved: {comment}");
+        AlertEvent::resolved(alert)
+    }))
+}
+
+/// Opens, escalates or resolves the certificate alerts of `subdomain`
+/// after a TLS handshake at `now`: one for expiry, tightening at each of
+/// [`tls::EXPIRY_TIERS`], and one for a certificate not covering it.
+pub async fn on_tls_check(
+    conn: &mut PgConnection,
+    subdomain: &str,
+    handshake: &Handshake,
+    now: DateTime<Utc>,
+) -> sqlx::Result<Vec<AlertEvent>> {
+    let cert = &handshake.certificate;
+    let mut events = Vec::new();
+    let expiry = match tls::expiry_severity(cert.not_after, now) {
+        Some(severity) => {
+            let date = cert.not_after.format("%Y-%m-%d %H:%M UTC");
+            let message = if cert.not_after <= now {
+                format!("Certificate of {subdomain} expired on {date}")
+            } else {
+                let days = (cert.not_after - now).num_days();
+                format!("Certificate of {subdomain} expires in {days} days, on {date}")
+            };
+            raise(conn, subdomain, TLS_EXPIRY_SERVICE, severity, &message).await?
+        }
+        None => {
+            let comment = format!("Recovered: certificate of {subdomain} was renewed");
+            clear(conn, subdomain, TLS_EXPIRY_SERVICE, &comment).await?
+        }
+    };
+    events.extend(expiry);
+    let hostname = if handshake.hostname_valid {
+        let comment = format!("Recovered: certificate of {subdomain} covers it");
+        clear(conn, subdomain, TLS_HOSTNAME_SERVICE, &comment).await?
+    } else {
+        let names = if cert.dns_names.is_empty() {
+            "none".to_string()
+        } else {
+            cert.dns_names.join(", ")
+        };
+        let message = format!("Certificate of {subdomain} does not cover it (names: {names})");
+        raise(conn, subdomain, TLS_HOSTNAME_SERVICE, "critical", &message).await?
+    };
+    events.extend(hostname);
+    Ok(events)
+}
+
+/// Opens or updates the DNS alert of `subdomain` for the `reasons` its
+/// records changed unexpectedly in one check. There is no recovery to
+/// resolve it: an operator confirms the change was intended.
+pub async fn on_dns_change(
+    conn: &mut PgConnection,
+    subdomain: &str,
+    reasons: &[String],
+) -> sqlx::Result<Option<AlertEvent>> {
+    if reasons.is_empty() {
+        return Ok(None);
+    }
+    let message = format!("DNS of {subdomain} changed: {}", reasons.join("; "));
+    raise(conn, subdomain, DNS_CHANGE_SERVICE, "warning", &message).await
+}
+
+/// Opens or updates the content alert of `subdomain` after the hash of its
+/// body changed from `previous` to `current`. Like DNS changes, it stays
+/// open until an operator confirms the change.
+pub async fn on_content_change(
+    conn: &mut PgConnection,
+    subdomain: &str,
+    previous: &str,
+    current: &str,
+) -> sqlx::Result<Option<AlertEvent>> {
+    let short = |hash: &str| hash.chars().take(12).collect::<String>();
+    let message = format!(
+        "Content of {subdomain} changed (SHA-256 {} -> {})",
+        short(previous),
+        short(current)
+    );
+    raise(conn, subdomain, CONTENT_CHANGE_SERVICE, "warning", &message).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlertsQuery {
+    #[serde(default)]
+    pub resolved: bool,
+    pub limit: Option<i64>,
+    pub severity: Option<String>,
+    pub host: Option<String>,
+}
+
+/// `GET /api/alerts`: newest first, open alerts unless `resolved=true`.
+pub async fn list_alerts(
+    state: web::Data<AppState>,
+    query: web::Query<AlertsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            None,
+            "limit",
+            format!("must be between 1 and {MAX_LIMIT}"),
+        )]));
+    }
+    let filter = AlertFilter {
+        resolved: query.resolved,
+        severity: query.severity.as_deref(),
+        host: query.host.as_deref(),
+    };
+    let alerts = alerts::list(&state.pool, &filter, limit).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "alerts": alerts })))
+}
+
+/// An alert with the notification attempts made for it.
+#[derive(Debug, Serialize)]
+pub struct AlertDetail {
+    #[serde(flatten)]
+    pub alert: Alert,
+    /// Oldest first.
+    pub deliveries: Vec<Delivery>,
+}
+
+/// `GET /api/alerts/{id}`
+pub async fn get_alert(
+    state: web::Data<AppState>,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+    let alert = alerts::get(&state.pool, id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("Alert {id} not found")))?;
+    let deliveries = notification_deliveries::list_for_alert(&state.pool, id).await?;
+    Ok(HttpResponse::Ok().json(AlertDetail { alert, deliveries }))
+}
+
+/// Body of the acknowledge and resolve endpoints. `actor` names who is
+/// handling the alert; it is recorded with the admin token it came with.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertAction {
+    #[serde(default)]
+    pub actor: String,
+    pub comment: Option<String>,
+}
+
+impl AlertAction {
+    fn comment(&self) -> Option<&str> {
+        self.comment
+            .as_deref()
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+    }
+}
+
+/// Explains why an acknowledge or resolve matched no open alert.
+async fn not_open(state: &AppState, id: i32) -> ApiError {
+    match alerts::get(&state.pool, id).await {
+        Ok(Some(_)) => ApiError::Conflict(format!("Alert {id} is already resolved")),
+        Ok(None) => ApiError::NotFound(format!("Alert {id} not found")),
+        Err(e) => e.into(),
+    }
+}
+
+/// `POST /api/alerts/{id}/acknowledge` (admin token)
+pub async fn acknowledge_alert(
+    admin: Admin,
+    state: web::Data<AppState>,
+    id: web::Path<i32>,
+    action: web::Json<AlertAction>,
+) -> Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+    let actor = admin.actor(&action.actor)?;
+    match alerts::acknowledge(&state.pool, id, &actor, action.comment()).await? {
+        Some(alert) => {
+            println!("Alert {id} acknowledged by {actor}");
+            Ok(HttpResponse::Ok().json(alert))
+        }
+        None => Err(not_open(&state, id).await),
+    }
+}
+
+/// `POST /api/alerts/{id}/resolve` (admin token)
+pub async fn resolve_alert(
+    admin: Admin,
+    state: web::Data<AppState>,
+    id: web::Path<i32>,
+    action: web::Json<AlertAction>,
+) -> Result<HttpResponse, ApiError> {
+    let id = id.into_inner();
+    let actor = admin.actor(&action.actor)?;
+    match alerts::resolve(&state.pool, id, &actor, action.comment()).await? {
+        Some(alert) => {
+            println!("Alert {id} resolved by {actor}");
+            state
+                .notifier
+                .dispatch([AlertEvent::resolved(alert.clone())]);
+            Ok(HttpResponse::Ok().json(alert))
+        }
+        None => Err(not_open(&state, id).await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::{test as actix_test, App};
+    use rand::Rng;
+    use serde_json::{json, Value};
+    use sqlx::postgres::PgPoolOptions;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::admin::ADMIN_ACTOR;
+    use crate::db;
+    use crate::notify::EventKind;
+    use crate::status::Transition;
+
+    const TOKEN: &str = "alerts-test-token";
+
+    fn host() -> String {
+        let tag = hex::encode(rand::thread_rng().gen::<[u8; 4]>());
+        format!("alerts-{tag}.bettergov.test")
+    }
+
+    fn change(from: Status, to: Status) -> StatusChange {
+        StatusChange {
+            transition: Transition { from, to },
+            failing: Vec::new(),
+            counted: 1,
+        }
+    }
+
+    async fn step(
+        conn: &mut PgConnection,
+        host: &str,
+        from: Status,
+        to: Status,
+    ) -> Option<AlertEvent> {
+        on_status_change(conn, host, &change(from, to))
+            .await
+            .unwrap()
+    }
+
+    async fn clean_up(pool: &PgPool, host: &str) {
+        sqlx::query("DELETE FROM monitoring.alerts WHERE host = $1")
+            .bind(host)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    fn action() -> Value {
+        json!({ "actor": " alice ", "comment": " on it " })
+    }
+
+    async fn post(
+        state: &web::Data<AppState>,
+        path: &str,
+        token: Option<&str>,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .app_data(web::JsonConfig::default().error_handler(|e, _| {
+                    ApiError::BadRequest(format!("Invalid JSON body: {e}")).into()
+                }))
+                .route(
+                    "/api/alerts/{id}/acknowledge",
+                    web::post().to(acknowledge_alert),
+                )
+                .route("/api/alerts/{id}/resolve", web::post().to(resolve_alert)),
+        )
+        .await;
+        let mut req = actix_test::TestRequest::post().uri(path).set_json(body);
+        if let Some(token) = token {
+            req = req.insert_header((AUTHORIZATION, format!("Bearer {token}")));
+        }
+        let resp = actix_test::call_service(&app, req.to_request()).await;
+        let status = resp.status();
+        (status, actix_test::read_body_json(resp).await)
+    }
+
+    #[actix_web::test]
+    async fn alert_actions_need_the_admin_token() {
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(200))
+            .connect_lazy("postgres://monitor@127.0.0.1:1/monitoring")
+            .unwrap();
+        let disabled = web::Data::new(AppState::for_tests(pool.clone()));
+        let (status, _) = post(
+            &disabled,
+            "/api/alerts/1/acknowledge",
+            Some(TOKEN),
+            action(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let state = web::Data::new(AppState {
+            admin_token: Some(TOKEN.to_string()),
+            ..AppState::for_tests(pool)
+        });
+        for path in ["/api/alerts/1/acknowledge", "/api/alerts/1/resolve"] {
+            let (status, body) = post(&state, path, None, action()).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{path}");
+            assert_eq!(body["status"], "rejected");
+            let (status, _) = post(&state, path, Some("wrong"), action()).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{path}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn alert_actions_need_a_named_actor() {
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(200))
+            .connect_lazy("postgres://monitor@127.0.0.1:1/monitoring")
+            .unwrap();
+        let state = web::Data::new(AppState {
+            admin_token: Some(TOKEN.to_string()),
+            ..AppState::for_tests(pool)
+        });
+        let path = "/api/alerts/1/acknowledge";
+        for (body, problem) in [
+            (json!({ "comment": "on it" }), "is required"),
+            (json!({ "actor": "  " }), "is required"),
+            (
+                json!({ "actor": "a".repeat(101) }),
+                "must be at most 100 characters",
+            ),
+            (
+                json!({ "actor": "alice\nbob" }),
+                "must not contain control characters",
+            ),
+        ] {
+            let (status, response) = post(&state, path, Some(TOKEN), body).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(response["errors"][0]["field"], "actor");
+            assert_eq!(response["errors"][0]["message"], problem);
+        }
+        let (status, response) = post(
+            &state,
+            path,
+            Some(TOKEN),
+            json!({ "actor": "alice", "who": "bob" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(response["message"]
+            .as_str()
+            .unwrap()
+            .contains("unknown field `who`"));
+    }
+
+    #[actix_web::test]
+    async fn alert_actions_record_the_actor_with_the_admin_token() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let host = host();
+        let opened = alerts::open(&pool, &host, UPTIME_SERVICE, "critical", "down")
+            .await
+            .unwrap();
+        let id = opened.alert.id;
+        let state = web::Data::new(AppState {
+            admin_token: Some(TOKEN.to_string()),
+            ..AppState::for_tests(pool.clone())
+        });
+
+        let (status, body) = post(
+            &state,
+            &format!("/api/alerts/{id}/acknowledge"),
+            Some(TOKEN),
+            action(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["acknowledged"], true);
+        assert_eq!(body["acknowledged_by"], "admin (alice)");
+        assert_eq!(body["acknowledge_comment"], "on it");
+
+        let path = format!("/api/alerts/{id}/resolve");
+        let (status, body) = post(&state, &path, Some(TOKEN), action()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["resolved_by"], "admin (alice)");
+        let (status, _) = post(&state, &path, Some(TOKEN), action()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = post(&state, "/api/alerts/0/resolve", Some(TOKEN), action()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        clean_up(&pool, &host).await;
+    }
+
+    #[actix_web::test]
+    async fn alert_detail_lists_its_deliveries() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let host = host();
+        let id = alerts::open(&pool, &host, UPTIME_SERVICE, "critical", "down")
+            .await
+            .unwrap()
+            .alert
+            .id;
+        for (attempt, error) in [(1, Some("endpoint answered 503: busy")), (2, None)] {
+            let delivery = notification_deliveries::NewDelivery {
+                alert_id: id,
+                channel: "oncall",
+                kind: "webhook",
+                event: "opened",
+                attempt,
+                error,
+            };
+            notification_deliveries::insert(&pool, &delivery)
+                .await
+                .unwrap();
+        }
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(pool.clone())))
+                .route("/api/alerts/{id}", web::get().to(get_alert)),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::get()
+            .uri(&format!("/api/alerts/{id}"))
+            .to_request();
+        let body: Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["id"], id);
+        assert_eq!(body["host"], host.as_str());
+        let deliveries = body["deliveries"].as_array().unwrap();
+        assert_eq!(deliveries.len(), 2);
+        assert_eq!(deliveries[0]["attempt"], 1);
+        assert_eq!(deliveries[0]["success"], false);
+        assert_eq!(deliveries[1]["success"], true);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/api/alerts/0")
+            .to_request();
+        let resp = actix_test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        clean_up(&pool, &host).await;
+    }
+
+    #[actix_web::test]
+    async fn open_updates_the_one_unresolved_alert() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let host = host();
+        let first = alerts::open(&pool, &host, UPTIME_SERVICE, "warning", "flapping")
+            .await
+            .unwrap();
+        assert!(first.created);
+        let second = alerts::open(&pool, &host, UPTIME_SERVICE, "critical", "down")
+            .await
+            .unwrap();
+        assert!(!second.created);
+        assert_eq!(second.alert.id, first.alert.id);
+        assert_eq!(second.alert.severity, "critical");
+        assert_eq!(second.alert.message.as_deref(), Some("down"));
+
+        // Another service of the same host is a separate alert.
+        let other = alerts::open(&pool, &host, TLS_EXPIRY_SERVICE, "warning", "expiring")
+            .await
+            .unwrap();
+        assert!(other.created);
+        assert_ne!(other.alert.id, first.alert.id);
+
+        // Once resolved, the partial index admits a new open alert.
+        alerts::resolve(&pool, first.alert.id, ADMIN_ACTOR, None)
+            .await
+            .unwrap()
+            .unwrap();
+        let reopened = alerts::open(&pool, &host, UPTIME_SERVICE, "critical", "down")
+            .await
+            .unwrap();
+        assert!(reopened.created);
+        assert_ne!(reopened.alert.id, first.alert.id);
+
+        let (open,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM monitoring.alerts
+             WHERE host = $1 AND service = $2 AND NOT resolved",
+        )
+        .bind(&host)
+        .bind(UPTIME_SERVICE)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(open, 1);
+
+        clean_up(&pool, &host).await;
+    }
+
+    #[actix_web::test]
+    async fn status_changes_open_escalate_and_resolve_one_alert() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let host = host();
+        let mut conn = pool.acquire().await.unwrap();
+        let conn = &mut *conn;
+
+        let opened = step(conn, &host, Status::Up, Status::Flapping)
+            .await
+            .unwrap();
+        assert_eq!(opened.kind, EventKind::Opened);
+        assert_eq!(opened.alert.severity, "warning");
+        let id = opened.alert.id;
+
+        // A warning that stays a warning updates quietly.
+        assert!(step(conn, &host, Status::Flapping, Status::RegionalOutage)
+            .await
+            .is_none());
+
+        let escalated = step(conn, &host, Status::RegionalOutage, Status::Down)
+            .await
+            .unwrap();
+        assert_eq!(escalated.kind, EventKind::Escalated);
+        assert_eq!(escalated.alert.id, id);
+        assert_eq!(escalated.alert.severity, "critical");
+
+        assert!(step(conn, &host, Status::Down, Status::Unknown)
+            .await
+            .is_none());
+
+        let resolved = step(conn, &host, Status::Down, Status::Up).await.unwrap();
+        assert_eq!(resolved.kind, EventKind::Resolved);
+        assert_eq!(resolved.alert.id, id);
+        assert_eq!(resolved.alert.resolved_by.as_deref(), Some(SYSTEM_ACTOR));
+        assert!(step(conn, &host, Status::Up, Status::Up).await.is_none());
+
+        let reopened = step(conn, &host, Status::Up, Status::Down).await.unwrap();
+        assert_eq!(reopened.kind, EventKind::Opened);
+        assert_ne!(reopened.alert.id, id);
+
+        clean_up(&pool, &host).await;
+    }
+
+    #[actix_web::test]
+    async fn status_messages_name_the_failing_locations() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let host = host();
+        let mut conn = pool.acquire().await.unwrap();
+        let change = StatusChange {
+            transition: Transition {
+                from: Status::Up,
+                to: Status::RegionalOutage,
+            },
+            failing: vec!["SG".to_string()],
+            counted: 3,
+        };
+        let event = on_status_change(&mut conn, &host, &change)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event.alert.message.as_deref(),
+            Some(format!("{host} is REGIONAL_OUTAGE (failing at 1 of 3 locations: SG)").as_str())
+        );
+        drop(conn);
+
+        clean_up(&pool, &host).await;
+    }
+}
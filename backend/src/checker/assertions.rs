@@ -0,0 +1,396 @@
+//! Content assertions: conditions an HTTP response must meet, beyond its
+//! status code, for its target to count as up. They are set per target
+//! through the inventory API and stored in `subdomains.check_assertions`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, OnceLock};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How much of the body is read when a target has assertions.
+pub const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+const MAX_VALUE_LEN: usize = 256;
+const MAX_PATTERN_LEN: usize = 512;
+/// Compiled patterns kept; the cache starts over when it fills, which only
+/// happens if patterns keep changing.
+const MAX_CACHED_PATTERNS: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Assertion {
+    /// The body contains `value`.
+    Contains { value: String },
+    /// The body does not contain `value`, e.g. `Fatal error`.
+    NotContains { value: String },
+    /// The body matches `pattern`.
+    Regex { pattern: String },
+    /// The body is JSON with a value at `path` (`$.status`,
+    /// `$.checks[0]['db']`), equal to `equals` if given.
+    JsonPath {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        equals: Option<Value>,
+    },
+    /// The body is at most `value` bytes.
+    MaxBodyBytes { value: usize },
+    /// The response has header `name`, containing `contains` if given.
+    Header {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        contains: Option<String>,
+    },
+    /// Never fails; the body's hash is recorded and a change raises a
+    /// `content_change` alert, to catch defacement.
+    BodyHash,
+}
+
+/// A response as the assertions see it. Header names must be lowercase.
+#[derive(Debug, Clone, Copy)]
+pub struct Response<'a> {
+    pub headers: &'a BTreeMap<String, String>,
+    /// At most [`MAX_BODY_BYTES`].
+    pub body: &'a [u8],
+    /// Whether more of the body arrived than was read.
+    pub truncated: bool,
+}
+
+impl Assertion {
+    /// Whether it looks at the body, which a HEAD request does not get.
+    pub fn needs_body(&self) -> bool {
+        !matches!(self, Assertion::Header { .. })
+    }
+
+    /// Why it could never be checked, if it could not.
+    pub fn validate(&self) -> Result<(), String> {
+        let bounded = |what: &str, value: &str, max: usize| {
+            if value.is_empty() || value.len() > max {
+                Err(format!("{what} must be 1 to {max} characters"))
+            } else {
+                Ok(())
+            }
+        };
+        match self {
+            Assertion::Contains { value } | Assertion::NotContains { value } => {
+                bounded("value", value, MAX_VALUE_LEN)
+            }
+            Assertion::Regex { pattern } => {
+                bounded("pattern", pattern, MAX_PATTERN_LEN)?;
+                compiled(pattern).map(|_| ())
+            }
+            Assertion::JsonPath { path, .. } => parse_path(path).map(|_| ()),
+            Assertion::MaxBodyBytes { value } => {
+                if (1..=MAX_BODY_BYTES).contains(value) {
+                    Ok(())
+                } else {
+                    Err(format!("value must be between 1 and {MAX_BODY_BYTES}"))
+                }
+            }
+            Assertion::Header { name, contains } => {
+                bounded("name", name, MAX_VALUE_LEN)?;
+                if !name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_".contains(&b))
+                {
+                    return Err("name must be a header name".to_string());
+                }
+                contains
+                    .as_deref()
+                    .map_or(Ok(()), |c| bounded("contains", c, MAX_VALUE_LEN))
+            }
+            Assertion::BodyHash => Ok(()),
+        }
+    }
+
+    /// `Err` says how `response` fails it.
+    pub fn check(&self, response: &Response<'_>) -> Result<(), String> {
+        let text = || String::from_utf8_lossy(response.body);
+        match self {
+            Assertion::Contains { value } => {
+                if text().contains(value.as_str()) {
+                    Ok(())
+                } else {
+                    Err(format!("body does not contain {value:?}"))
+                }
+            }
+            Assertion::NotContains { value } => {
+                if text().contains(value.as_str()) {
+                    Err(format!("body contains {value:?}"))
+                } else {
+                    Ok(())
+                }
+            }
+            Assertion::Regex { pattern } => {
+                let regex = compiled(pattern)?;
+                if regex.is_match(&text()) {
+                    Ok(())
+                } else {
+                    Err(format!("body does not match /{pattern}/"))
+                }
+            }
+            Assertion::JsonPath { path, equals } => {
+                let steps = parse_path(path)?;
+                let json: Value = serde_json::from_slice(response.body).map_err(|_| {
+                    if response.truncated {
+                        format!("body is not JSON within its first {MAX_BODY_BYTES} bytes")
+                    } else {
+                        "body is not JSON".to_string()
+                    }
+                })?;
+                let found = steps
+                    .iter()
+                    .try_fold(&json, |value, step| step.select(value))
+                    .ok_or_else(|| format!("{path} is missing"))?;
+                match equals {
+                    Some(expected) if found != expected => {
+                        Err(format!("{path} is {found}, not {expected}"))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Assertion::MaxBodyBytes { value } => {
+                if response.truncated || response.body.len() > *value {
+                    let size = if response.truncated {
+                        format!("over {MAX_BODY_BYTES}")
+                    } else {
+                        response.body.len().to_string()
+                    };
+                    Err(format!("body is {size} bytes, more than {value}"))
+                } else {
+                    Ok(())
+                }
+            }
+            Assertion::Header { name, contains } => {
+                let name = name.to_ascii_lowercase();
+                let value = response
+                    .headers
+                    .get(&name)
+                    .ok_or_else(|| format!("header {name} is missing"))?;
+                match contains {
+                    Some(wanted) if !value.contains(wanted.as_str()) => {
+                        Err(format!("header {name} is {value:?}, without {wanted:?}"))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Assertion::BodyHash => Ok(()),
+        }
+    }
+}
+
+/// Compiled regex assertion patterns. Targets are reloaded every pass, so
+/// they are cached by pattern rather than on the assertion.
+fn pattern_cache() -> &'static Mutex<HashMap<String, Regex>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
+
+/// `pattern` compiled, once per pattern.
+fn compiled(pattern: &str) -> Result<Regex, String> {
+    let cache = pattern_cache();
+    if let Some(regex) = cache.lock().unwrap_or_else(|e| e.into_inner()).get(pattern) {
+        return Ok(regex.clone());
+    }
+    let regex = Regex::new(pattern).map_err(|e| format!("invalid pattern: {e}"))?;
+    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+    if cache.len() >= MAX_CACHED_PATTERNS {
+        cache.clear();
+    }
+    cache.insert(pattern.to_string(), regex.clone());
+    Ok(regex)
+}
+
+/// Checks `assertions` in order; `Err` names the first that fails.
+pub fn check_all(assertions: &[Assertion], response: &Response<'_>) -> Result<(), String> {
+    assertions
+        .iter()
+        .try_for_each(|a| a.check(response))
+        .map_err(|reason| format!("Assertion failed: {reason}"))
+}
+
+/// A step of a JSON path.
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+impl Step {
+    fn select<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        match self {
+            Step::Key(key) => value.get(key),
+            Step::Index(index) => value.get(index),
+        }
+    }
+}
+
+/// Parses the JSONPath subset of `$`, `.key`, `['key']` and `[index]`.
+fn parse_path(path: &str) -> Result<Vec<Step>, String> {
+    let invalid = || format!("invalid JSON path {path:?}; use e.g. $.checks[0].status");
+    let mut rest = path.strip_prefix('$').ok_or_else(invalid)?;
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(invalid());
+            }
+            steps.push(Step::Key(after[..end].to_string()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix("['") {
+            let end = after.find("']").ok_or_else(invalid)?;
+            steps.push(Step::Key(after[..end].to_string()));
+            rest = &after[end + 2..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(invalid)?;
+            let index = after[..end].parse().map_err(|_| invalid())?;
+            steps.push(Step::Index(index));
+            rest = &after[end + 1..];
+        } else {
+            return Err(invalid());
+        }
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn assertion(value: Value) -> Assertion {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn parses_json_paths() {
+        assert_eq!(parse_path("$").unwrap(), []);
+        assert_eq!(
+            parse_path("$.checks[1]['db.primary'].ok").unwrap(),
+            [
+                Step::Key("checks".to_string()),
+                Step::Index(1),
+                Step::Key("db.primary".to_string()),
+                Step::Key("ok".to_string()),
+            ]
+        );
+        for bad in ["status", "$.", "$..a", "$[x]", "$['a'", "$a"] {
+            assert!(parse_path(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn reads_the_stored_form() {
+        assert_eq!(
+            assertion(json!({"type": "json_path", "path": "$.status", "equals": "ok"})),
+            Assertion::JsonPath {
+                path: "$.status".to_string(),
+                equals: Some(json!("ok")),
+            }
+        );
+        assert_eq!(assertion(json!({"type": "body_hash"})), Assertion::BodyHash);
+        let unknown = json!({"type": "contains", "value": "ok", "case": "ignore"});
+        assert!(serde_json::from_value::<Assertion>(unknown).is_err());
+        assert!(assertion(json!({"type": "regex", "pattern": "("}))
+            .validate()
+            .is_err());
+        assert!(assertion(json!({"type": "max_body_bytes", "value": 0}))
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn compiles_each_pattern_once() {
+        let pattern = r"build-\d+ compiles_each_pattern_once";
+        let cached = || {
+            pattern_cache()
+                .lock()
+                .unwrap()
+                .get(pattern)
+                .map(|r| r.as_str().to_string())
+        };
+        assert_eq!(cached(), None);
+        let regex = assertion(json!({"type": "regex", "pattern": pattern}));
+        assert_eq!(regex.validate(), Ok(()));
+        assert_eq!(cached().as_deref(), Some(pattern));
+
+        let headers = BTreeMap::new();
+        let response = Response {
+            headers: &headers,
+            body: b"build-42 compiles_each_pattern_once",
+            truncated: false,
+        };
+        assert_eq!(regex.check(&response), Ok(()));
+        assert_eq!(regex.check(&response), Ok(()));
+        assert_eq!(cached().as_deref(), Some(pattern));
+
+        let bad = assertion(json!({"type": "regex", "pattern": "(unclosed"}));
+        assert!(bad.validate().unwrap_err().starts_with("invalid pattern"));
+        assert!(pattern_cache().lock().unwrap().get("(unclosed").is_none());
+    }
+
+    #[test]
+    fn names_the_failed_assertion() {
+        let headers = BTreeMap::from([(
+            "content-type".to_string(),
+            "application/json; charset=utf-8".to_string(),
+        )]);
+        let body = br#"{"status": "degraded", "checks": [{"db": true}], "version": "1.4.2"}"#;
+        let response = Response {
+            headers: &headers,
+            body,
+            truncated: false,
+        };
+        let check = |value: Value| assertion(value).check(&response);
+
+        assert!(check(json!({"type": "contains", "value": "version"})).is_ok());
+        assert!(check(json!({"type": "not_contains", "value": "Fatal error"})).is_ok());
+        assert!(check(json!({"type": "regex", "pattern": r#""version": "1\.\d+"#})).is_ok());
+        assert!(
+            check(json!({"type": "json_path", "path": "$.checks[0].db", "equals": true})).is_ok()
+        );
+        assert!(
+            check(json!({"type": "header", "name": "Content-Type", "contains": "json"})).is_ok()
+        );
+        assert!(check(json!({"type": "max_body_bytes", "value": 100})).is_ok());
+
+        assert_eq!(
+            check(json!({"type": "json_path", "path": "$.status", "equals": "ok"})),
+            Err(r#"$.status is "degraded", not "ok""#.to_string())
+        );
+        assert_eq!(
+            check(json!({"type": "json_path", "path": "$.uptime"})),
+            Err("$.uptime is missing".to_string())
+        );
+        assert_eq!(
+            check(json!({"type": "header", "name": "x-request-id"})),
+            Err("header x-request-id is missing".to_string())
+        );
+        assert_eq!(
+            check(json!({"type": "max_body_bytes", "value": 10})),
+            Err(format!("body is {} bytes, more than 10", body.len()))
+        );
+
+        let assertions = [
+            assertion(json!({"type": "contains", "value": "status"})),
+            assertion(json!({"type": "not_contains", "value": "degraded"})),
+            assertion(json!({"type": "contains", "value": "missing"})),
+        ];
+        assert_eq!(
+            check_all(&assertions, &response),
+            Err(r#"Assertion failed: body contains "degraded""#.to_string())
+        );
+
+        let html = Response {
+            headers: &headers,
+            body: b"<html>",
+            truncated: false,
+        };
+        assert_eq!(
+            assertion(json!({"type": "json_path", "path": "$"})).check(&html),
+            Err("body is not JSON".to_string())
+        );
+    }
+}
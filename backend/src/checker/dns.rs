@@ -0,0 +1,618 @@
+//! DNS checks: the watched records of every active subdomain are resolved
+//! through the configured resolvers and compared with the last answer.
+//! Every change is kept, and the ones that should not go unnoticed (a new
+//! name server, or addresses moving to another network) raise an alert.
+
+use std::collections::{BTreeSet, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use super::MAX_CONCURRENT_PROBES;
+use crate::alerts;
+use crate::db::dns_changes::{self, NewDnsChange};
+use crate::db::dns_records::{self, NewDnsRecordSet};
+use crate::db::subdomains;
+use crate::dns::{self, RecordType};
+use crate::notify::{AlertEvent, Dispatcher};
+
+/// Team Cymru's IP-to-ASN zones; a TXT query for the reversed address
+/// answers `13335 | 1.1.1.0/24 | AU | apnic | 2011-08-11`.
+const CYMRU_ORIGIN: &str = "origin.asn.cymru.com";
+const CYMRU_ORIGIN6: &str = "origin6.asn.cymru.com";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DnsConfig {
+    /// Seconds between checks of every subdomain; 0 turns them off.
+    pub interval_secs: u64,
+    /// Timeout of one query to one resolver.
+    pub timeout_secs: u64,
+    /// Recursive resolvers, asked in order until one answers.
+    pub resolvers: Vec<SocketAddr>,
+    pub record_types: Vec<RecordType>,
+    /// Types whose every change raises an alert. A and AAAA records alert
+    /// only when they move to a network they were not in before.
+    pub alert_types: Vec<RecordType>,
+    /// Looks up the origin ASN of every address, which is what notices
+    /// addresses moving to another network.
+    pub asn_lookup: bool,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        DnsConfig {
+            interval_secs: 300,
+            timeout_secs: 3,
+            resolvers: vec![
+                SocketAddr::from(([1, 1, 1, 1], 53)),
+                SocketAddr::from(([8, 8, 8, 8], 53)),
+            ],
+            record_types: RecordType::ALL.to_vec(),
+            alert_types: vec![
+                RecordType::Cname,
+                RecordType::Ns,
+                RecordType::Mx,
+                RecordType::Caa,
+            ],
+            asn_lookup: true,
+        }
+    }
+}
+
+impl DnsConfig {
+    pub fn resolver(&self) -> Resolver {
+        Resolver::new(
+            self.resolvers.clone(),
+            Duration::from_secs(self.timeout_secs),
+        )
+    }
+}
+
+/// The records of one type of a name, as one resolver gave them.
+#[derive(Debug, Clone)]
+pub struct Answer {
+    pub resolver: SocketAddr,
+    /// Sorted; empty if the name has none or does not exist.
+    pub records: Vec<String>,
+    pub elapsed: Duration,
+}
+
+pub struct Resolver {
+    servers: Vec<SocketAddr>,
+    timeout: Duration,
+}
+
+impl Resolver {
+    pub fn new(servers: Vec<SocketAddr>, timeout: Duration) -> Self {
+        Resolver { servers, timeout }
+    }
+
+    /// The `rtype` records of `name` from the first resolver that answers.
+    pub async fn resolve(&self, name: &str, rtype: RecordType) -> Result<Answer, String> {
+        let mut errors = Vec::new();
+        for &server in &self.servers {
+            match dns::query(server, name, rtype, self.timeout).await {
+                Ok(response) => {
+                    return Ok(Answer {
+                        resolver: server,
+                        records: response.values(rtype),
+                        elapsed: response.elapsed,
+                    })
+                }
+                Err(e) => errors.push(format!("{server}: {e}")),
+            }
+        }
+        Err(if errors.is_empty() {
+            "No resolvers configured".to_string()
+        } else {
+            errors.join("; ")
+        })
+    }
+
+    /// The ASNs announcing `ip`; empty if the lookup fails or it is not
+    /// routed.
+    pub async fn origin_asns(&self, ip: IpAddr) -> Vec<i32> {
+        let Ok(answer) = self.resolve(&cymru_name(ip), RecordType::Txt).await else {
+            return Vec::new();
+        };
+        let asns: BTreeSet<i32> = answer
+            .records
+            .iter()
+            .filter_map(|txt| txt.split('|').next())
+            .flat_map(|origins| origins.split_whitespace())
+            .filter_map(|asn| asn.parse().ok())
+            .collect();
+        asns.into_iter().collect()
+    }
+}
+
+/// The name to ask Cymru's zones about `ip`: its octets, or for IPv6 its
+/// nibbles, in reverse.
+pub fn cymru_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let o = v4.octets();
+            format!("{}.{}.{}.{}.{CYMRU_ORIGIN}", o[3], o[2], o[1], o[0])
+        }
+        IpAddr::V6(v6) => {
+            let mut name = String::with_capacity(64 + CYMRU_ORIGIN6.len());
+            for byte in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0xf, byte >> 4));
+            }
+            name + CYMRU_ORIGIN6
+        }
+    }
+}
+
+/// Records of one type with the ASNs of their addresses.
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot<'a> {
+    pub records: &'a [String],
+    pub asns: &'a [i32],
+}
+
+/// Why a change of `rtype` records from `previous` to `current` needs an
+/// operator's eye, or `None` if it is routine. Address records moving to
+/// an ASN are only noticed when both sides have ASNs to compare.
+pub fn unexpected_change(
+    alert_types: &[RecordType],
+    rtype: RecordType,
+    previous: Snapshot<'_>,
+    current: Snapshot<'_>,
+) -> Option<String> {
+    if previous.records == current.records {
+        return None;
+    }
+    let added: Vec<&str> = difference(current.records, previous.records);
+    let removed: Vec<&str> = difference(previous.records, current.records);
+    let mut detail = Vec::new();
+    if !added.is_empty() {
+        detail.push(format!("added {}", added.join(", ")));
+    }
+    if !removed.is_empty() {
+        detail.push(format!("removed {}", removed.join(", ")));
+    }
+    let detail = detail.join("; ");
+
+    let new_asns: Vec<i32> = current
+        .asns
+        .iter()
+        .copied()
+        .filter(|asn| !previous.asns.contains(asn))
+        .collect();
+    if !previous.asns.is_empty() && !new_asns.is_empty() {
+        return Some(format!(
+            "{rtype} records moved to {} (from {}): {detail}",
+            asn_list(&new_asns),
+            asn_list(previous.asns)
+        ));
+    }
+    alert_types
+        .contains(&rtype)
+        .then(|| format!("{rtype} records changed: {detail}"))
+}
+
+fn difference<'a>(of: &'a [String], without: &[String]) -> Vec<&'a str> {
+    of.iter()
+        .filter(|value| !without.contains(value))
+        .map(String::as_str)
+        .collect()
+}
+
+fn asn_list(asns: &[i32]) -> String {
+    asns.iter()
+        .map(|asn| format!("AS{asn}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A subdomain's host name without the port some carry.
+fn host_name(subdomain: &str) -> &str {
+    subdomain
+        .rsplit_once(':')
+        .filter(|(_, port)| port.parse::<u16>().is_ok())
+        .map_or(subdomain, |(name, _)| name)
+}
+
+struct Lookup {
+    rtype: RecordType,
+    result: Result<Answer, String>,
+    asns: Vec<i32>,
+}
+
+/// Resolves the watched records of every active subdomain once per run.
+pub struct DnsChecker {
+    pool: PgPool,
+    resolver: Resolver,
+    config: DnsConfig,
+    notifier: Arc<Dispatcher>,
+    /// ASNs looked up this run; subdomains often share addresses.
+    asns: Mutex<HashMap<IpAddr, Vec<i32>>>,
+}
+
+impl DnsChecker {
+    pub fn new(pool: PgPool, config: DnsConfig, notifier: Arc<Dispatcher>) -> Self {
+        DnsChecker {
+            pool,
+            resolver: config.resolver(),
+            config,
+            notifier,
+            asns: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn run_checks(&self) {
+        println!("[{}] Starting DNS checks...", Utc::now());
+        let targets = match subdomains::list_check_targets(&self.pool).await {
+            Ok(targets) => targets,
+            Err(e) => {
+                eprintln!("Failed to get subdomains: {e}");
+                return;
+            }
+        };
+        self.asns.lock().unwrap().clear();
+        let total = targets.len();
+        let resolved = stream::iter(targets)
+            .map(|target| self.check(target.subdomain))
+            .buffer_unordered(MAX_CONCURRENT_PROBES)
+            .filter(|ok| std::future::ready(*ok))
+            .count()
+            .await;
+        println!("Completed DNS checks: {resolved}/{total} subdomains fully resolved");
+    }
+
+    /// Whether every watched type got an answer.
+    async fn check(&self, subdomain: String) -> bool {
+        let host = host_name(&subdomain);
+        let mut lookups = Vec::with_capacity(self.config.record_types.len());
+        for &rtype in &self.config.record_types {
+            let result = self.resolver.resolve(host, rtype).await;
+            let asns = match &result {
+                Ok(answer) if self.config.asn_lookup => self.asns_of(&answer.records).await,
+                _ => Vec::new(),
+            };
+            lookups.push(Lookup {
+                rtype,
+                result,
+                asns,
+            });
+        }
+        let failures: Vec<String> = lookups
+            .iter()
+            .filter_map(|l| l.result.as_ref().err().map(|e| format!("{}: {e}", l.rtype)))
+            .collect();
+        if !failures.is_empty() {
+            println!("  DNS {subdomain} - {}", failures.join(" | "));
+        }
+        match self.save(&subdomain, &lookups).await {
+            Ok(events) => self.notifier.dispatch(events),
+            Err(e) => eprintln!("Error saving DNS check for {subdomain}: {e}"),
+        }
+        failures.is_empty()
+    }
+
+    /// The ASNs of the addresses among `records`, sorted.
+    async fn asns_of(&self, records: &[String]) -> Vec<i32> {
+        let mut asns = BTreeSet::new();
+        for ip in records.iter().filter_map(|r| r.parse::<IpAddr>().ok()) {
+            let cached = self.asns.lock().unwrap().get(&ip).cloned();
+            let origins = match cached {
+                Some(origins) => origins,
+                None => {
+                    let origins = self.resolver.origin_asns(ip).await;
+                    self.asns.lock().unwrap().insert(ip, origins.clone());
+                    origins
+                }
+            };
+            asns.extend(origins);
+        }
+        asns.into_iter().collect()
+    }
+
+    async fn save(&self, subdomain: &str, lookups: &[Lookup]) -> sqlx::Result<Vec<AlertEvent>> {
+        let previous: HashMap<String, dns_records::DnsRecordSet> =
+            dns_records::list_for(&self.pool, subdomain)
+                .await?
+                .into_iter()
+                .map(|set| (set.record_type.clone(), set))
+                .collect();
+        let mut tx = self.pool.begin().await?;
+        let mut reasons = Vec::new();
+        for lookup in lookups {
+            let rtype = lookup.rtype.as_str();
+            let answer = match &lookup.result {
+                Ok(answer) => answer,
+                Err(e) => {
+                    dns_records::record_failure(&mut *tx, subdomain, rtype, e).await?;
+                    continue;
+                }
+            };
+            // The first answer is the baseline.
+            let known = previous.get(rtype).filter(|set| set.resolver.is_some());
+            let changed = known.is_some_and(|set| set.records != answer.records);
+            if let Some(set) = known.filter(|_| changed) {
+                println!(
+                    "  DNS {subdomain} - {rtype} changed: [{}] -> [{}]",
+                    set.records.join(", "),
+                    answer.records.join(", ")
+                );
+                dns_changes::insert(
+                    &mut *tx,
+                    &NewDnsChange {
+                        subdomain,
+                        record_type: rtype,
+                        previous: &set.records,
+                        current: &answer.records,
+                        previous_asns: &set.asns,
+                        current_asns: &lookup.asns,
+                    },
+                )
+                .await?;
+                reasons.extend(unexpected_change(
+                    &self.config.alert_types,
+                    lookup.rtype,
+                    Snapshot {
+                        records: &set.records,
+                        asns: &set.asns,
+                    },
+                    Snapshot {
+                        records: &answer.records,
+                        asns: &lookup.asns,
+                    },
+                ));
+            }
+            dns_records::record(
+                &mut *tx,
+                &NewDnsRecordSet {
+                    subdomain,
+                    record_type: rtype,
+                    resolver: &answer.resolver.to_string(),
+                    resolution_ms: answer.elapsed.as_secs_f64() * 1000.0,
+                    records: &answer.records,
+                    asns: &lookup.asns,
+                    changed,
+                },
+            )
+            .await?;
+        }
+        let event = alerts::on_dns_change(&mut tx, subdomain, &reasons).await?;
+        tx.commit().await?;
+        Ok(event.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, UdpSocket};
+
+    use super::*;
+
+    /// Zone data of the stub server: `(name, type code)` to record data
+    /// in wire form. Names not in it get NXDOMAIN.
+    type Zone = HashMap<(String, u16), Vec<Vec<u8>>>;
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut wire = Vec::new();
+        for label in name.split('.') {
+            wire.push(label.len() as u8);
+            wire.extend(label.as_bytes());
+        }
+        wire.push(0);
+        wire
+    }
+
+    fn txt(text: &str) -> Vec<u8> {
+        let mut wire = vec![text.len() as u8];
+        wire.extend(text.as_bytes());
+        wire
+    }
+
+    /// Answers `query` from `zone`, with `rcode` instead if given, and
+    /// only the header and question if `truncate` (setting TC).
+    fn respond(zone: &Zone, query: &[u8], rcode: Option<u8>, truncate: bool) -> Vec<u8> {
+        let mut pos = 12;
+        let mut labels = Vec::new();
+        while query[pos] != 0 {
+            let len = usize::from(query[pos]);
+            labels.push(String::from_utf8_lossy(&query[pos + 1..pos + 1 + len]).to_lowercase());
+            pos += 1 + len;
+        }
+        let question_end = pos + 5;
+        let qtype = u16::from_be_bytes([query[pos + 1], query[pos + 2]]);
+        let records = zone.get(&(labels.join("."), qtype));
+        let rcode = rcode.unwrap_or(if records.is_some() { 0 } else { 3 });
+        let answers = records.filter(|_| !truncate).cloned().unwrap_or_default();
+
+        let mut message = query[..2].to_vec();
+        message.extend([0x81 | if truncate { 0x02 } else { 0 }, 0x80 | rcode]);
+        message.extend([0, 1, 0, answers.len() as u8, 0, 0, 0, 0]);
+        message.extend(&query[12..question_end]);
+        for rdata in answers {
+            message.extend([0xc0, 12]);
+            message.extend(qtype.to_be_bytes());
+            message.extend([0, 1, 0, 0, 1, 0x2c]);
+            message.extend((rdata.len() as u16).to_be_bytes());
+            message.extend(rdata);
+        }
+        message
+    }
+
+    /// Serves `zone` over UDP and TCP on one port, answering UDP queries
+    /// for names in `truncated` with TC set so that they retry over TCP.
+    async fn stub(zone: Zone, rcode: Option<u8>, truncated: &[&str]) -> SocketAddr {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).await.unwrap();
+        let zone = Arc::new(zone);
+        let truncated: Vec<Vec<u8>> = truncated.iter().map(|n| name(n)).collect();
+        let udp_zone = Arc::clone(&zone);
+        tokio::spawn(async move {
+            let mut buf = [0; 1500];
+            while let Ok((len, peer)) = udp.recv_from(&mut buf).await {
+                let query = &buf[..len];
+                let truncate = truncated.iter().any(|n| query[12..].starts_with(n));
+                let response = respond(&udp_zone, query, rcode, truncate);
+                udp.send_to(&response, peer).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = tcp.accept().await {
+                let len = stream.read_u16().await.unwrap();
+                let mut query = vec![0; usize::from(len)];
+                stream.read_exact(&mut query).await.unwrap();
+                let response = respond(&zone, &query, rcode, false);
+                stream.write_u16(response.len() as u16).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    fn zone() -> Zone {
+        let mut mx = vec![0, 10];
+        mx.extend(name("mail.bettergov.ph"));
+        let mut caa = vec![0, 5];
+        caa.extend(b"issueletsencrypt.org");
+        let big = (1..=120).map(|i| vec![198, 51, 100, i]).collect();
+        HashMap::from([
+            (
+                ("bettergov.ph".to_string(), 1),
+                vec![vec![192, 0, 2, 10], vec![192, 0, 2, 2]],
+            ),
+            (("bettergov.ph".to_string(), 15), vec![mx]),
+            (("bettergov.ph".to_string(), 257), vec![caa]),
+            (("big.bettergov.ph".to_string(), 1), big),
+            (
+                ("10.2.0.192.origin.asn.cymru.com".to_string(), 16),
+                vec![txt("64500 64501 | 192.0.2.0/24 | PH | apnic | 2020-01-01")],
+            ),
+        ])
+    }
+
+    #[tokio::test]
+    async fn resolves_through_the_first_resolver_that_answers() {
+        let failing = stub(Zone::new(), Some(2), &[]).await;
+        let good = stub(zone(), None, &["big.bettergov.ph"]).await;
+        let resolver = Resolver::new(vec![failing, good], Duration::from_secs(2));
+
+        let a = resolver
+            .resolve("BetterGov.ph", RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(a.resolver, good);
+        assert_eq!(a.records, ["192.0.2.10", "192.0.2.2"]);
+        let mx = resolver
+            .resolve("bettergov.ph", RecordType::Mx)
+            .await
+            .unwrap();
+        assert_eq!(mx.records, ["10 mail.bettergov.ph"]);
+        let caa = resolver
+            .resolve("bettergov.ph", RecordType::Caa)
+            .await
+            .unwrap();
+        assert_eq!(caa.records, ["0 issue \"letsencrypt.org\""]);
+        let missing = resolver.resolve("nope.bettergov.ph", RecordType::A).await;
+        assert!(missing.unwrap().records.is_empty());
+
+        // Too big for UDP: fetched again over TCP.
+        let big = resolver
+            .resolve("big.bettergov.ph", RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(big.records.len(), 120);
+
+        let only_failing = Resolver::new(vec![failing], Duration::from_secs(2));
+        let error = only_failing
+            .resolve("bettergov.ph", RecordType::A)
+            .await
+            .unwrap_err();
+        assert_eq!(error, format!("{failing}: SERVFAIL"));
+    }
+
+    #[tokio::test]
+    async fn looks_up_origin_asns() {
+        let resolver = Resolver::new(vec![stub(zone(), None, &[]).await], Duration::from_secs(2));
+        let ip: IpAddr = "192.0.2.10".parse().unwrap();
+        assert_eq!(resolver.origin_asns(ip).await, [64500, 64501]);
+        assert!(resolver
+            .origin_asns("192.0.2.2".parse().unwrap())
+            .await
+            .is_empty());
+    }
+
+    #[test]
+    fn names_addresses_in_cymru_zones() {
+        assert_eq!(
+            cymru_name("1.2.3.4".parse().unwrap()),
+            "4.3.2.1.origin.asn.cymru.com"
+        );
+        let v6 = cymru_name("2001:db8::1".parse().unwrap());
+        assert!(v6.starts_with("1.0.0.0.0.0.0.0."));
+        assert!(v6.ends_with(".0.0.0.0.8.b.d.0.1.0.0.2.origin6.asn.cymru.com"));
+        assert_eq!(host_name("bettergov.ph:8443"), "bettergov.ph");
+        assert_eq!(host_name("bettergov.ph"), "bettergov.ph");
+    }
+
+    #[test]
+    fn alerts_on_watched_types_and_new_networks() {
+        let alert_types = DnsConfig::default().alert_types;
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let old = strings(&["192.0.2.10"]);
+        let new = strings(&["192.0.2.11", "203.0.113.5"]);
+        fn snapshot<'a>(records: &'a [String], asns: &'a [i32]) -> Snapshot<'a> {
+            Snapshot { records, asns }
+        }
+
+        // Addresses moving within the same network are routine.
+        let same_network = unexpected_change(
+            &alert_types,
+            RecordType::A,
+            snapshot(&old, &[64500]),
+            snapshot(&new, &[64500]),
+        );
+        assert_eq!(same_network, None);
+        let moved = unexpected_change(
+            &alert_types,
+            RecordType::A,
+            snapshot(&old, &[64500]),
+            snapshot(&new, &[64500, 64511]),
+        );
+        assert_eq!(
+            moved.as_deref(),
+            Some("A records moved to AS64511 (from AS64500): added 192.0.2.11, 203.0.113.5; removed 192.0.2.10")
+        );
+        // Without ASNs to compare there is nothing to judge by.
+        let unknown = unexpected_change(
+            &alert_types,
+            RecordType::A,
+            snapshot(&old, &[]),
+            snapshot(&new, &[64511]),
+        );
+        assert_eq!(unknown, None);
+
+        let ns = unexpected_change(
+            &alert_types,
+            RecordType::Ns,
+            snapshot(&strings(&["ns1.dns.ph", "ns2.dns.ph"]), &[]),
+            snapshot(&strings(&["ns1.dns.ph", "ns9.evil.test"]), &[]),
+        );
+        assert_eq!(
+            ns.as_deref(),
+            Some("NS records changed: added ns9.evil.test; removed ns2.dns.ph")
+        );
+        let txt = unexpected_change(
+            &alert_types,
+            RecordType::Txt,
+            snapshot(&strings(&["v=spf1 -all"]), &[]),
+            snapshot(&strings(&[]), &[]),
+        );
+        assert_eq!(txt, None);
+    }
+}
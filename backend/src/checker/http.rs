@@ -0,0 +1,836 @@
+//! The HTTP probe of the uptime checker. It drives the connection itself,
+//! rather than through a pooled client, so every check pays for and times
+//! its own DNS lookup, TCP connect and TLS handshake. When a proxy is set in
+//! the environment, probes go through reqwest instead, which honours it and
+//! `NO_PROXY`, and are not timed.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use http_body_util::{BodyExt, Empty};
+use hyper::body::{Bytes, Incoming};
+use hyper::client::conn::http1;
+use hyper::{header, HeaderMap, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use url::{Host, Position, Url};
+
+use super::assertions::{self, Assertion};
+use crate::db::uptime_checks::Timing;
+
+/// How much of the body is kept for fingerprinting.
+const BODY_SAMPLE_BYTES: usize = 10 * 1024;
+const USER_AGENT: &str = "BetterGov Monitoring/1.0";
+const MAX_REDIRECTS: usize = 10;
+/// Head start of the first address family before the other is tried too.
+const FALLBACK_DELAY: Duration = Duration::from_millis(250);
+/// Any of these set sends probes through [`Client::proxied`].
+const PROXY_VARS: &[&str] = &[
+    "HTTP_PROXY",
+    "http_proxy",
+    "HTTPS_PROXY",
+    "https_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error(transparent)]
+    Tls(#[from] rustls::Error),
+    #[error("proxied HTTP client: {0}")]
+    Proxied(#[from] reqwest::Error),
+}
+
+/// What a single HTTP probe observed.
+#[derive(Debug, Clone, Default)]
+pub struct HttpProbe {
+    pub up: bool,
+    pub status_code: Option<i32>,
+    /// From the first request to the final response headers, redirects
+    /// included.
+    pub response_time_ms: Option<f64>,
+    pub error_message: Option<String>,
+    /// Lowercase header names; repeated headers are joined with ", ".
+    pub headers: BTreeMap<String, String>,
+    /// The first `BODY_SAMPLE_BYTES` of the body, lossily decoded.
+    pub body: String,
+    /// Whether a response arrived at all, as opposed to a network failure.
+    pub responded: bool,
+    /// Hex SHA-256 of the body, for targets asserting `body_hash` whose
+    /// status code counts as up.
+    pub body_hash: Option<String>,
+    /// Of the last request made, which failed if nothing `responded`.
+    /// Empty through a proxy.
+    pub timing: Timing,
+}
+
+/// Makes HTTP/1.1 requests on fresh connections.
+pub struct Client {
+    tls: TlsConnector,
+    timeout: Duration,
+    connect_timeout: Duration,
+    /// Set when the environment configures a proxy. Requests then go
+    /// through it, or directly where `NO_PROXY` says so, without timings.
+    proxied: Option<reqwest::Client>,
+}
+
+impl Client {
+    /// `timeout` bounds a whole probe, redirects and body included, and
+    /// `connect_timeout` the DNS lookup, connect and handshake of each
+    /// request.
+    pub fn new(
+        roots: RootCertStore,
+        timeout: Duration,
+        connect_timeout: Duration,
+    ) -> Result<Self, ClientError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let proxied = if proxy_configured() {
+            println!("A proxy is configured: HTTP checks go through it and are not timed");
+            Some(proxied_client(connect_timeout, None)?)
+        } else {
+            None
+        };
+        Ok(Client {
+            tls: TlsConnector::from(Arc::new(config)),
+            timeout,
+            connect_timeout,
+            proxied,
+        })
+    }
+
+    /// Requests `url`, following redirects. `timing` is of the latest
+    /// request, as far as it got.
+    async fn fetch(
+        &self,
+        url: &str,
+        method: &Method,
+        timing: &mut Timing,
+    ) -> Result<Fetched, String> {
+        if let Some(proxied) = &self.proxied {
+            return match proxied.request(method.clone(), url).send().await {
+                Ok(response) => Ok(Fetched::Proxied(response)),
+                Err(e) => Err(describe_error(&e)),
+            };
+        }
+        let mut url = Url::parse(url).map_err(|e| format!("Error: {e}"))?;
+        for _ in 0..=MAX_REDIRECTS {
+            *timing = Timing::default();
+            let (response, connection) = self.send(&url, method, timing).await?;
+            let next = is_redirect(response.status())
+                .then(|| response.headers().get(header::LOCATION))
+                .flatten()
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| url.join(location).ok())
+                .filter(|next| matches!(next.scheme(), "http" | "https"));
+            match next {
+                Some(next) => url = next,
+                None => return Ok(Fetched::Direct(response, connection)),
+            }
+        }
+        Err("Error: too many redirects".to_string())
+    }
+
+    /// Makes one request to `url` and waits for the response headers.
+    async fn send(
+        &self,
+        url: &Url,
+        method: &Method,
+        timing: &mut Timing,
+    ) -> Result<(Response<Incoming>, Connection), String> {
+        let host = url
+            .host()
+            .ok_or_else(|| format!("Error: {url} has no host"))?;
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| format!("Error: {url} has no port"))?;
+        let request = Request::builder()
+            .method(method.clone())
+            .uri(&url[Position::BeforePath..Position::AfterQuery])
+            .header(
+                header::HOST,
+                &url[Position::BeforeHost..Position::AfterPort],
+            )
+            .header(header::USER_AGENT, USER_AGENT)
+            .header(header::ACCEPT, "*/*")
+            .body(Empty::new())
+            .map_err(|e| format!("Error: {e}"))?;
+
+        let connect_deadline = tokio::time::Instant::now() + self.connect_timeout;
+        let tcp = tokio::time::timeout_at(
+            connect_deadline,
+            connect(&host, port, connect_deadline, timing),
+        )
+        .await
+        .map_err(|_| "Timeout".to_string())?
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::TimedOut => "Timeout".to_string(),
+            _ => format!("Connection failed: {e}"),
+        })?;
+        if url.scheme() != "https" {
+            return exchange(tcp, request, timing).await;
+        }
+
+        let name = match host {
+            Host::Domain(name) => ServerName::try_from(name.to_string())
+                .map_err(|e| format!("Error: invalid host name: {e}"))?,
+            Host::Ipv4(ip) => ServerName::from(IpAddr::V4(ip)),
+            Host::Ipv6(ip) => ServerName::from(IpAddr::V6(ip)),
+        };
+        let started = Instant::now();
+        let tls = tokio::time::timeout_at(connect_deadline, self.tls.connect(name, tcp))
+            .await
+            .map_err(|_| "Timeout".to_string())?
+            .map_err(|e| format!("Connection failed: {e}"))?;
+        timing.tls_ms = Some(elapsed_ms(started));
+        exchange(tls, request, timing).await
+    }
+}
+
+/// A client trusting the public web roots.
+pub fn client(timeout: Duration, connect_timeout: Duration) -> Result<Client, ClientError> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    Client::new(roots, timeout, connect_timeout)
+}
+
+fn proxy_configured() -> bool {
+    PROXY_VARS
+        .iter()
+        .any(|var| std::env::var_os(var).is_some_and(|value| !value.is_empty()))
+}
+
+/// A reqwest client using the proxies of the environment, or `proxy`
+/// ahead of them. Probes bound it with their own deadline.
+fn proxied_client(
+    connect_timeout: Duration,
+    proxy: Option<reqwest::Proxy>,
+) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(connect_timeout)
+        .user_agent(USER_AGENT)
+        .redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS));
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy);
+    }
+    builder.build()
+}
+
+fn describe_error(e: &reqwest::Error) -> String {
+    if e.is_timeout() {
+        "Timeout".to_string()
+    } else if e.is_connect() {
+        format!("Connection failed: {}", root_cause(e))
+    } else {
+        format!("Error: {}", root_cause(e))
+    }
+}
+
+/// Drives the HTTP connection of a response; dropping it closes the
+/// connection, however much of the body was read.
+struct Connection(tokio::task::JoinHandle<()>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// A response whose headers have arrived.
+enum Fetched {
+    /// Over a connection of our own, which the body is read from.
+    Direct(Response<Incoming>, Connection),
+    Proxied(reqwest::Response),
+}
+
+impl Fetched {
+    fn status(&self) -> StatusCode {
+        match self {
+            Fetched::Direct(response, _) => response.status(),
+            Fetched::Proxied(response) => response.status(),
+        }
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        match self {
+            Fetched::Direct(response, _) => response.headers(),
+            Fetched::Proxied(response) => response.headers(),
+        }
+    }
+
+    /// Reads up to `limit` bytes of the body, and whether there was more. A
+    /// body that fails midway or is still arriving at `deadline` yields
+    /// whatever arrived.
+    async fn read_body(self, limit: usize, deadline: tokio::time::Instant) -> (Vec<u8>, bool) {
+        let mut bytes = Vec::new();
+        match self {
+            Fetched::Direct(response, _connection) => {
+                let mut body = response.into_body();
+                while bytes.len() <= limit {
+                    match tokio::time::timeout_at(deadline, body.frame()).await {
+                        Ok(Some(Ok(frame))) => {
+                            if let Some(chunk) = frame.data_ref() {
+                                bytes.extend_from_slice(chunk);
+                            }
+                        }
+                        _ => break,
+                    }
+                }
+            }
+            Fetched::Proxied(mut response) => {
+                while bytes.len() <= limit {
+                    match tokio::time::timeout_at(deadline, response.chunk()).await {
+                        Ok(Ok(Some(chunk))) => bytes.extend_from_slice(&chunk),
+                        _ => break,
+                    }
+                }
+            }
+        }
+        let truncated = bytes.len() > limit;
+        bytes.truncate(limit);
+        (bytes, truncated)
+    }
+}
+
+/// Looks `host` up and connects to one of its addresses by `deadline`.
+async fn connect(
+    host: &Host<&str>,
+    port: u16,
+    deadline: tokio::time::Instant,
+    timing: &mut Timing,
+) -> io::Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = match *host {
+        Host::Domain(name) => {
+            let started = Instant::now();
+            let addrs = tokio::net::lookup_host((name, port)).await?.collect();
+            timing.dns_ms = Some(elapsed_ms(started));
+            addrs
+        }
+        Host::Ipv4(ip) => vec![(ip, port).into()],
+        Host::Ipv6(ip) => vec![(ip, port).into()],
+    };
+    let started = Instant::now();
+    let stream = connect_any(&addrs, deadline).await?;
+    timing.connect_ms = Some(elapsed_ms(started));
+    let _ = stream.set_nodelay(true);
+    Ok(stream)
+}
+
+/// Happy eyeballs (RFC 8305): tries the addresses of the first one's family
+/// in order, and those of the other family alongside them once the first
+/// attempt has had [`FALLBACK_DELAY`] or failed. The first connection wins,
+/// so an unreachable address family cannot use up the whole deadline.
+async fn connect_any(
+    addrs: &[SocketAddr],
+    deadline: tokio::time::Instant,
+) -> io::Result<TcpStream> {
+    let Some(first) = addrs.first() else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "could not resolve to any addresses",
+        ));
+    };
+    let (preferred, fallback): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs
+        .iter()
+        .partition(|addr| addr.is_ipv4() == first.is_ipv4());
+    let preferred = connect_in_turn(&preferred, deadline);
+    if fallback.is_empty() {
+        return preferred.await;
+    }
+    tokio::pin!(preferred);
+    tokio::select! {
+        result = &mut preferred => {
+            return match result {
+                Ok(stream) => Ok(stream),
+                Err(_) => connect_in_turn(&fallback, deadline).await,
+            };
+        }
+        () = tokio::time::sleep(FALLBACK_DELAY) => {}
+    }
+    let fallback = connect_in_turn(&fallback, deadline);
+    tokio::pin!(fallback);
+    tokio::select! {
+        result = &mut preferred => match result {
+            Ok(stream) => Ok(stream),
+            Err(_) => fallback.await,
+        },
+        result = &mut fallback => match result {
+            Ok(stream) => Ok(stream),
+            Err(_) => preferred.await,
+        },
+    }
+}
+
+/// Tries `addrs` one after another, giving each an equal share of the time
+/// left before `deadline`, so one that never answers cannot starve the
+/// rest.
+async fn connect_in_turn(
+    addrs: &[SocketAddr],
+    deadline: tokio::time::Instant,
+) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for (i, addr) in addrs.iter().enumerate() {
+        let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+        let share = left / (addrs.len() - i) as u32;
+        match tokio::time::timeout(share, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => last_error = Some(e),
+            Err(_) => {
+                last_error = Some(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("connecting to {addr} timed out"),
+                ))
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::NotFound)))
+}
+
+/// Sends `request` over `stream` and waits for the response headers.
+async fn exchange<S>(
+    stream: S,
+    request: Request<Empty<Bytes>>,
+    timing: &mut Timing,
+) -> Result<(Response<Incoming>, Connection), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let failed = |e: hyper::Error| format!("Error: {}", root_cause(&e));
+    let (mut sender, conn) = http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(failed)?;
+    let connection = Connection(tokio::spawn(async move {
+        let _ = conn.await;
+    }));
+    let started = Instant::now();
+    let response = sender.send_request(request).await.map_err(failed)?;
+    timing.ttfb_ms = Some(elapsed_ms(started));
+    Ok((response, connection))
+}
+
+fn is_redirect(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    )
+}
+
+fn elapsed_ms(started: Instant) -> f64 {
+    started.elapsed().as_secs_f64() * 1000.0
+}
+
+/// What to request and which answers count as up.
+#[derive(Debug, Clone)]
+pub struct ProbeSpec<'a> {
+    pub host: &'a str,
+    pub path: &'a str,
+    pub method: Method,
+    /// `None` counts anything below 500 as up.
+    pub expected_status: Option<&'a [i32]>,
+    /// Overrides the client's timeout.
+    pub timeout: Option<Duration>,
+    /// Checked once the status code counts as up.
+    pub assertions: &'a [Assertion],
+}
+
+fn is_up(status: u16, expected: Option<&[i32]>) -> bool {
+    match expected {
+        Some(codes) => codes.contains(&i32::from(status)),
+        None => status < 500,
+    }
+}
+
+/// Requests `spec.path` on `spec.host` over HTTPS, falling back to plain
+/// HTTP. Redirects are followed, so expected codes and assertions apply to
+/// the final response.
+pub async fn probe(client: &Client, spec: &ProbeSpec<'_>) -> HttpProbe {
+    let mut result = HttpProbe::default();
+
+    for scheme in ["https", "http"] {
+        let url = format!("{scheme}://{}{}", spec.host, spec.path);
+        let started = Instant::now();
+        let deadline = tokio::time::Instant::now() + spec.timeout.unwrap_or(client.timeout);
+        let mut timing = Timing::default();
+        let fetched =
+            tokio::time::timeout_at(deadline, client.fetch(&url, &spec.method, &mut timing))
+                .await
+                .unwrap_or_else(|_| Err("Timeout".to_string()));
+        result.timing = timing;
+        match fetched {
+            Ok(fetched) => {
+                let status = fetched.status().as_u16();
+                result.status_code = Some(i32::from(status));
+                result.response_time_ms = Some(elapsed_ms(started));
+                result.up = is_up(status, spec.expected_status);
+                result.responded = true;
+                result.headers = header_map(fetched.headers());
+                result.error_message = None;
+                let limit = if spec.assertions.is_empty() {
+                    BODY_SAMPLE_BYTES
+                } else {
+                    assertions::MAX_BODY_BYTES
+                };
+                let direct = matches!(fetched, Fetched::Direct(..));
+                let reading = Instant::now();
+                let (body, truncated) = fetched.read_body(limit, deadline).await;
+                if direct {
+                    result.timing.transfer_ms = Some(elapsed_ms(reading));
+                }
+                let sample = &body[..body.len().min(BODY_SAMPLE_BYTES)];
+                result.body = String::from_utf8_lossy(sample).into_owned();
+                if result.up && !spec.assertions.is_empty() {
+                    if spec.assertions.contains(&Assertion::BodyHash) {
+                        result.body_hash = Some(hex::encode(Sha256::digest(&body)));
+                    }
+                    let response = assertions::Response {
+                        headers: &result.headers,
+                        body: &body,
+                        truncated,
+                    };
+                    if let Err(e) = assertions::check_all(spec.assertions, &response) {
+                        result.up = false;
+                        result.error_message = Some(e);
+                    }
+                }
+                break;
+            }
+            Err(e) => result.error_message = Some(e),
+        }
+    }
+
+    result
+}
+
+fn header_map(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut map: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        map.entry(name.as_str().to_string())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+    map
+}
+
+/// hyper's and reqwest's own messages can be as vague as "connection
+/// error"; the innermost source says what went wrong.
+fn root_cause(e: &(dyn std::error::Error + 'static)) -> String {
+    let mut cause = e;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+    cause.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use rustls::ServerConfig;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpSocket};
+    use tokio_rustls::TlsAcceptor;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::checker::tls_fixture as fixture;
+
+    fn spec(host: &str) -> ProbeSpec<'_> {
+        ProbeSpec {
+            host,
+            path: "/",
+            method: Method::GET,
+            expected_status: None,
+            timeout: None,
+            assertions: &[],
+        }
+    }
+
+    /// A listener that never accepts, with its backlog already full, so
+    /// connection attempts to it hang as if the address were black-holed.
+    /// Keep both halves alive for as long as that should last.
+    async fn black_hole(ip: IpAddr) -> (TcpListener, TcpStream) {
+        let socket = if ip.is_ipv4() {
+            TcpSocket::new_v4()
+        } else {
+            TcpSocket::new_v6()
+        }
+        .unwrap();
+        socket.bind((ip, 0).into()).unwrap();
+        let listener = socket.listen(0).unwrap();
+        let queued = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        (listener, queued)
+    }
+
+    fn after(duration: Duration) -> tokio::time::Instant {
+        tokio::time::Instant::now() + duration
+    }
+
+    #[tokio::test]
+    async fn moves_on_from_an_address_that_never_answers() {
+        let (hole, _queued) = black_hole(Ipv4Addr::LOCALHOST.into()).await;
+        let open = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addrs = [hole.local_addr().unwrap(), open.local_addr().unwrap()];
+
+        let started = Instant::now();
+        let stream = connect_any(&addrs, after(Duration::from_secs(1)))
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addrs[1]);
+        // The black hole got half of the deadline, not all of it.
+        let waited = started.elapsed();
+        assert!(waited >= Duration::from_millis(450), "{waited:?}");
+        assert!(waited < Duration::from_millis(900), "{waited:?}");
+
+        let err = connect_any(&addrs[..1], after(Duration::from_millis(200)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn races_the_other_address_family() {
+        let Ok(open) = TcpListener::bind("[::1]:0").await else {
+            return; // No IPv6 loopback here.
+        };
+        let (hole, _queued) = black_hole(Ipv4Addr::LOCALHOST.into()).await;
+        let addrs = [hole.local_addr().unwrap(), open.local_addr().unwrap()];
+
+        let started = Instant::now();
+        let stream = connect_any(&addrs, after(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addrs[1]);
+        let waited = started.elapsed();
+        assert!(waited >= FALLBACK_DELAY, "{waited:?}");
+        assert!(waited < Duration::from_secs(2), "{waited:?}");
+
+        // A family that fails outright hands over without the delay.
+        let closed = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+        let started = Instant::now();
+        let stream = connect_any(&[closed, addrs[1]], after(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addrs[1]);
+        assert!(started.elapsed() < FALLBACK_DELAY);
+    }
+
+    #[test]
+    fn expected_codes_replace_the_default_rule() {
+        assert!(is_up(200, None));
+        assert!(is_up(404, None));
+        assert!(!is_up(503, None));
+        assert!(is_up(200, Some(&[200, 204])));
+        assert!(!is_up(404, Some(&[200, 204])));
+        assert!(is_up(503, Some(&[503])));
+    }
+
+    #[tokio::test]
+    async fn failed_assertions_take_the_target_down() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"status": "degraded"})))
+            .mount(&server)
+            .await;
+        let client = &client(Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+        let host = server.address().to_string();
+        let probe_with = |assertions: serde_json::Value| {
+            let assertions: Vec<Assertion> = serde_json::from_value(assertions).unwrap();
+            let host = host.clone();
+            async move {
+                let spec = ProbeSpec {
+                    host: &host,
+                    path: "/api/status",
+                    method: Method::GET,
+                    expected_status: None,
+                    timeout: None,
+                    assertions: &assertions,
+                };
+                probe(client, &spec).await
+            }
+        };
+
+        let passing = probe_with(json!([
+            {"type": "json_path", "path": "$.status"},
+            {"type": "header", "name": "content-type", "contains": "json"},
+            {"type": "body_hash"},
+        ]))
+        .await;
+        assert!(passing.up);
+        assert_eq!(passing.error_message, None);
+        assert_eq!(
+            passing.body_hash.as_deref(),
+            Some(hex::encode(Sha256::digest(br#"{"status":"degraded"}"#)).as_str())
+        );
+
+        let failing = probe_with(json!([
+            {"type": "json_path", "path": "$.status", "equals": "ok"},
+        ]))
+        .await;
+        assert!(!failing.up);
+        assert_eq!(failing.status_code, Some(200));
+        assert_eq!(
+            failing.error_message.as_deref(),
+            Some(r#"Assertion failed: $.status is "degraded", not "ok""#)
+        );
+        assert_eq!(failing.body_hash, None);
+    }
+
+    #[tokio::test]
+    async fn times_each_phase_of_the_final_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(301).insert_header("location", "/home"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/home"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("welcome")
+                    .set_delay(Duration::from_millis(100)),
+            )
+            .mount(&server)
+            .await;
+        // Direct whatever proxy the environment sets, as only that is timed.
+        let client = Client {
+            proxied: None,
+            ..client(Duration::from_secs(5), Duration::from_secs(5)).unwrap()
+        };
+
+        // HTTPS fails against a plain HTTP server, so this is the fallback.
+        let host = format!("localhost:{}", server.address().port());
+        let result = probe(&client, &spec(&host)).await;
+        assert!(result.up);
+        assert_eq!(result.status_code, Some(200));
+        assert_eq!(result.body, "welcome");
+        let timing = result.timing;
+        assert!(timing.dns_ms.is_some());
+        assert!(timing.connect_ms.is_some());
+        assert_eq!(timing.tls_ms, None);
+        assert!(timing.ttfb_ms.is_some_and(|ms| ms >= 100.0));
+        assert!(timing.transfer_ms.is_some());
+        assert!(result.response_time_ms.unwrap() >= timing.ttfb_ms.unwrap());
+
+        let by_address = probe(&client, &spec(&server.address().to_string())).await;
+        assert!(by_address.up);
+        assert_eq!(by_address.timing.dns_ms, None);
+
+        let closed = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        let refused = probe(&client, &spec(&closed)).await;
+        assert!(!refused.up);
+        assert!(refused
+            .error_message
+            .is_some_and(|e| e.starts_with("Connection failed")));
+        assert_eq!(refused.timing, Timing::default());
+    }
+
+    #[tokio::test]
+    async fn times_the_tls_handshake() {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(fixture("localhost.der"))],
+                PrivateKeyDer::try_from(fixture("localhost.key.der")).unwrap(),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut tls = acceptor.accept(tcp).await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = tls.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            tls.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+            tls.flush().await.unwrap();
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(fixture("ca.der"))).unwrap();
+        let client = Client::new(roots, Duration::from_secs(5), Duration::from_secs(5)).unwrap();
+        let result = probe(&client, &spec(&format!("localhost:{port}"))).await;
+        assert!(result.up, "{:?}", result.error_message);
+        assert_eq!(result.body, "ok");
+        assert!(result.timing.tls_ms.is_some());
+        assert!(result.timing.ttfb_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn goes_through_a_configured_proxy_untimed() {
+        // The proxy answers plain HTTP requests itself and refuses to
+        // tunnel HTTPS, so only the fallback gets through.
+        let proxy = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/status"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("via proxy"))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+        let client = Client {
+            proxied: Some(
+                proxied_client(
+                    Duration::from_secs(5),
+                    Some(reqwest::Proxy::all(proxy.uri()).unwrap()),
+                )
+                .unwrap(),
+            ),
+            ..client(Duration::from_secs(5), Duration::from_secs(5)).unwrap()
+        };
+
+        // Not resolvable, so a direct connection could not have answered.
+        let target = ProbeSpec {
+            path: "/status",
+            ..spec("unreachable.invalid")
+        };
+        let result = probe(&client, &target).await;
+        assert!(result.up, "{:?}", result.error_message);
+        assert_eq!(result.status_code, Some(200));
+        assert_eq!(result.body, "via proxy");
+        assert_eq!(result.timing, Timing::default());
+        assert!(result.response_time_ms.is_some());
+    }
+}
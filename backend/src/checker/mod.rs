@@ -0,0 +1,381 @@
+//! In-process uptime checker: probes every active subdomain once per tick,
+//! or less often where a subdomain has its own interval, over HTTP or as a
+//! TCP or UDP service, and records the outcome in `monitoring.uptime_checks`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use futures_util::{stream, StreamExt};
+use sqlx::PgPool;
+
+use crate::alerts;
+use crate::db::subdomains::{self, CheckTarget};
+use crate::db::uptime_checks::{self, NewUptimeCheck, Timing};
+use crate::fingerprint::{Observed, RuleSet};
+use crate::notify::{AlertEvent, Dispatcher};
+use crate::status::{self, Thresholds};
+use crate::subdomains::SubdomainCache;
+
+pub mod assertions;
+pub mod dns;
+pub mod http;
+pub mod socket;
+pub mod tls;
+
+/// Probes in flight at once, as with the aiohttp connector limit.
+const MAX_CONCURRENT_PROBES: usize = 10;
+const SAVE_ATTEMPTS: u32 = 3;
+
+/// How often and how patiently the checker probes.
+#[derive(Debug, Clone)]
+pub struct CheckerSettings {
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+}
+
+impl Default for CheckerSettings {
+    fn default() -> Self {
+        CheckerSettings {
+            interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+pub struct UptimeChecker {
+    pool: PgPool,
+    client: http::Client,
+    interval: Duration,
+    /// Of TCP and UDP probes without their own timeout.
+    timeout: Duration,
+    /// Start of the pass each target was last probed in.
+    last_probed: Mutex<HashMap<String, Instant>>,
+    fingerprints: RuleSet,
+    thresholds: Thresholds,
+    notifier: Arc<Dispatcher>,
+    cache: Arc<SubdomainCache>,
+}
+
+impl UptimeChecker {
+    pub fn new(
+        pool: PgPool,
+        fingerprints: RuleSet,
+        thresholds: Thresholds,
+        settings: &CheckerSettings,
+        notifier: Arc<Dispatcher>,
+        cache: Arc<SubdomainCache>,
+    ) -> Result<Self, http::ClientError> {
+        Ok(UptimeChecker {
+            pool,
+            client: http::client(settings.timeout, settings.connect_timeout)?,
+            interval: settings.interval,
+            timeout: settings.timeout,
+            last_probed: Mutex::default(),
+            fingerprints,
+            thresholds,
+            notifier,
+            cache,
+        })
+    }
+
+    /// One pass over every active subdomain.
+    pub async fn run_checks(&self) {
+        println!("[{}] Starting uptime checks...", Utc::now());
+
+        let targets = match subdomains::list_check_targets(&self.pool).await {
+            Ok(targets) => targets,
+            Err(e) => {
+                eprintln!("Failed to get subdomains: {e}");
+                return;
+            }
+        };
+        if targets.is_empty() {
+            println!("No active subdomains found. Run subdomain discovery first.");
+            return;
+        }
+        let listed = targets.len();
+        let targets = self.due(targets, Instant::now());
+        if targets.len() < listed {
+            println!(
+                "Checking {} subdomains ({} not due yet)...",
+                targets.len(),
+                listed - targets.len()
+            );
+        } else {
+            println!("Checking {} subdomains...", targets.len());
+        }
+
+        let total = targets.len();
+        let saved = stream::iter(targets)
+            .map(|target| self.check(target))
+            .buffer_unordered(MAX_CONCURRENT_PROBES)
+            .filter(|saved| std::future::ready(*saved))
+            .count()
+            .await;
+
+        println!("Completed {saved}/{total} checks");
+    }
+
+    /// The targets due in a pass starting at `now`, which are noted as
+    /// probed. Passes start on scheduler ticks, so a target is due half a
+    /// pass early rather than slipping a whole pass on timer jitter.
+    fn due(&self, targets: Vec<CheckTarget>, now: Instant) -> Vec<CheckTarget> {
+        let mut last_probed = self.last_probed.lock().unwrap_or_else(|e| e.into_inner());
+        last_probed.retain(|subdomain, _| targets.iter().any(|t| &t.subdomain == subdomain));
+        targets
+            .into_iter()
+            .filter(|target| {
+                let interval = target
+                    .check_interval_secs
+                    .and_then(|secs| u64::try_from(secs).ok())
+                    .map(Duration::from_secs);
+                let due = is_due(
+                    last_probed.get(&target.subdomain).copied(),
+                    now,
+                    interval,
+                    self.interval / 2,
+                );
+                if due {
+                    last_probed.insert(target.subdomain.clone(), now);
+                }
+                due
+            })
+            .collect()
+    }
+
+    async fn check(&self, target: CheckTarget) -> bool {
+        let timeout = target
+            .check_timeout_secs
+            .and_then(|secs| u64::try_from(secs).ok())
+            .map(Duration::from_secs);
+        let result = match target.check_kind.as_str() {
+            "tcp" | "udp" => self.probe_socket(&target, timeout).await,
+            _ => self.probe_http(&target, timeout).await,
+        };
+
+        let status = if result.up { "UP" } else { "DOWN" };
+        let shown = match &result.platform {
+            Some(platform) => platform.clone(),
+            None if target.check_kind == "http" => "Unknown".to_string(),
+            None => target.check_kind.to_ascii_uppercase(),
+        };
+        match result.response_time_ms {
+            Some(ms) => println!("  {status} {} ({shown}) - {ms:.1}ms", target.subdomain),
+            None => println!("  {status} {} ({shown}) - N/A", target.subdomain),
+        }
+
+        let check = NewUptimeCheck {
+            time: Utc::now(),
+            subdomain: target.subdomain,
+            status_code: result.status_code,
+            response_time_ms: result.response_time_ms,
+            up: result.up,
+            platform: result.platform,
+            error_message: result.error_message,
+            headers: result.headers,
+            location: Some(status::CENTRAL_LOCATION.to_string()),
+            timing: result.timing,
+        };
+        if !self.save(&check).await {
+            return false;
+        }
+        self.cache.record(&check);
+        match self.update_status(&check).await {
+            Ok(event) => self.notifier.dispatch(event),
+            Err(e) => eprintln!("Error updating status for {}: {e}", check.subdomain),
+        }
+        true
+    }
+
+    async fn probe_http(&self, target: &CheckTarget, timeout: Option<Duration>) -> Probe {
+        let spec = http::ProbeSpec {
+            host: &target.subdomain,
+            path: &target.check_path,
+            method: target.check_method.parse().unwrap_or(hyper::Method::GET),
+            expected_status: target.expected_status.as_deref(),
+            timeout,
+            assertions: &target.check_assertions,
+        };
+        let result = http::probe(&self.client, &spec).await;
+
+        // Only a real response says anything about the platform; keep the
+        // last known value while a site is unreachable.
+        let platform = result.responded.then(|| {
+            self.fingerprints
+                .detect(&Observed {
+                    host: &target.subdomain,
+                    headers: &result.headers,
+                    body: &result.body,
+                })
+                .to_string()
+        });
+        if let Some(platform) = &platform {
+            if let Err(e) =
+                subdomains::update_platform(&self.pool, &target.subdomain, platform).await
+            {
+                eprintln!("Error updating platform for {}: {e}", target.subdomain);
+            }
+        }
+
+        if let Some(hash) = &result.body_hash {
+            match self.record_body_hash(&target.subdomain, hash).await {
+                Ok(event) => self.notifier.dispatch(event),
+                Err(e) => eprintln!("Error saving body hash for {}: {e}", target.subdomain),
+            }
+        }
+
+        Probe {
+            up: result.up,
+            status_code: result.status_code,
+            response_time_ms: result.response_time_ms,
+            error_message: result.error_message,
+            headers: serde_json::to_value(&result.headers).ok(),
+            platform,
+            timing: result.timing,
+        }
+    }
+
+    /// Stored payloads were checked when they were set, so one that no
+    /// longer decodes fails the check rather than being sent half-read.
+    async fn probe_socket(&self, target: &CheckTarget, timeout: Option<Duration>) -> Probe {
+        let decode = |text: &Option<String>| text.as_deref().map(socket::unescape).transpose();
+        let (send, expect) = match (decode(&target.check_send), decode(&target.check_expect)) {
+            (Ok(send), Ok(expect)) => (send, expect),
+            (Err(e), _) | (_, Err(e)) => {
+                return Probe {
+                    error_message: Some(format!("Invalid check settings: {e}")),
+                    ..Probe::default()
+                }
+            }
+        };
+        let spec = socket::SocketSpec {
+            addr: &target.subdomain,
+            send: send.as_deref(),
+            expect: expect.as_deref(),
+            timeout: timeout.unwrap_or(self.timeout),
+        };
+        let result = if target.check_kind == "udp" {
+            socket::probe_udp(&spec).await
+        } else {
+            socket::probe_tcp(&spec).await
+        };
+        Probe {
+            up: result.up,
+            response_time_ms: result.response_time_ms,
+            error_message: result.error_message,
+            ..Probe::default()
+        }
+    }
+
+    /// Raises the content alert if `hash` differs from the last body's.
+    async fn record_body_hash(
+        &self,
+        subdomain: &str,
+        hash: &str,
+    ) -> sqlx::Result<Option<AlertEvent>> {
+        let mut tx = self.pool.begin().await?;
+        let mut event = None;
+        if let Some(previous) = subdomains::replace_body_hash(&mut *tx, subdomain, hash).await? {
+            if previous != hash {
+                println!("  Content of {subdomain} changed");
+                event = alerts::on_content_change(&mut tx, subdomain, &previous, hash).await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(event)
+    }
+
+    async fn update_status(&self, check: &NewUptimeCheck) -> sqlx::Result<Option<AlertEvent>> {
+        let mut tx = self.pool.begin().await?;
+        let mut event = None;
+        if let Some(change) = status::record_check(
+            &mut tx,
+            &check.subdomain,
+            status::CENTRAL_LOCATION,
+            check.up,
+            &self.thresholds,
+        )
+        .await?
+        {
+            event = alerts::on_status_change(&mut tx, &check.subdomain, &change).await?;
+        }
+        tx.commit().await?;
+        Ok(event)
+    }
+
+    /// Writes a result, retrying briefly so a dropped connection does not
+    /// lose the data point.
+    async fn save(&self, check: &NewUptimeCheck) -> bool {
+        for attempt in 1..=SAVE_ATTEMPTS {
+            match uptime_checks::insert(&self.pool, check).await {
+                Ok(()) => return true,
+                Err(e) if attempt == SAVE_ATTEMPTS => {
+                    eprintln!(
+                        "Failed to save result for {} after {SAVE_ATTEMPTS} attempts: {e}",
+                        check.subdomain
+                    );
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(500)).await,
+            }
+        }
+        false
+    }
+}
+
+/// A probe of any kind, as it is recorded.
+#[derive(Debug, Default)]
+struct Probe {
+    up: bool,
+    status_code: Option<i32>,
+    response_time_ms: Option<f64>,
+    error_message: Option<String>,
+    headers: Option<serde_json::Value>,
+    /// `None` keeps the last known platform.
+    platform: Option<String>,
+    timing: Timing,
+}
+
+fn is_due(
+    last: Option<Instant>,
+    now: Instant,
+    interval: Option<Duration>,
+    slack: Duration,
+) -> bool {
+    match (last, interval) {
+        (Some(last), Some(interval)) => now.duration_since(last) + slack >= interval,
+        _ => true,
+    }
+}
+
+/// A certificate or key from `tests/fixtures/tls`, for the TLS and HTTP
+/// probe tests.
+#[cfg(test)]
+pub fn tls_fixture(name: &str) -> Vec<u8> {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/tls")
+        .join(name);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_with_an_interval_skip_passes() {
+        let start = Instant::now();
+        let pass = Duration::from_secs(60);
+        let five_minutes = Some(Duration::from_secs(300));
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        assert!(is_due(None, start, five_minutes, pass / 2));
+        assert!(is_due(Some(start), at(60), None, pass / 2));
+        assert!(!is_due(Some(start), at(240), five_minutes, pass / 2));
+        // A tick that fires a little early still counts.
+        assert!(is_due(Some(start), at(299), five_minutes, pass / 2));
+        assert!(is_due(Some(start), at(300), five_minutes, pass / 2));
+    }
+}
@@ -0,0 +1,313 @@
+//! Probes of services that do not speak HTTP, such as databases and mail
+//! relays: a TCP connect, optionally sending a payload and reading until
+//! an expected string arrives, and a UDP datagram that must be answered.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// How much of a banner or reply is read looking for the expected text.
+const MAX_REPLY_BYTES: usize = 4096;
+/// How much of an unexpected reply is quoted in the error.
+const QUOTED_REPLY_BYTES: usize = 64;
+
+/// What a single TCP or UDP probe observed.
+#[derive(Debug, Clone, Default)]
+pub struct SocketProbe {
+    pub up: bool,
+    /// Until connected (TCP without an expected string) or until the
+    /// expected reply arrived.
+    pub response_time_ms: Option<f64>,
+    pub error_message: Option<String>,
+}
+
+/// Where to probe and what counts as up.
+#[derive(Debug, Clone)]
+pub struct SocketSpec<'a> {
+    /// `host:port`.
+    pub addr: &'a str,
+    pub send: Option<&'a [u8]>,
+    /// Must appear in the banner or reply; `None` accepts any.
+    pub expect: Option<&'a [u8]>,
+    pub timeout: Duration,
+}
+
+/// Decodes the `\r`, `\n`, `\t`, `\0`, `\\` and `\xHH` escapes of a
+/// stored payload or expected string.
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = (hex.len() == 2)
+                    .then(|| u8::from_str_radix(&hex, 16).ok())
+                    .flatten()
+                    .ok_or_else(|| format!("invalid escape \\x{hex}"))?;
+                bytes.push(byte);
+            }
+            Some(other) => return Err(format!("invalid escape \\{other}")),
+            None => return Err("ends with a lone \\".to_string()),
+        }
+    }
+    Ok(bytes)
+}
+
+/// `bytes` for an error message, escaped and cut short.
+fn quote(bytes: &[u8]) -> String {
+    let shown = &bytes[..bytes.len().min(QUOTED_REPLY_BYTES)];
+    let mut quoted: String = shown.escape_ascii().to_string();
+    if shown.len() < bytes.len() {
+        quoted.push_str("...");
+    }
+    format!("\"{quoted}\"")
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn elapsed_ms(started: Instant) -> f64 {
+    started.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Connects to `spec.addr`, sends `spec.send` if any, and with an
+/// expected string reads until it shows up, the server closes, or
+/// [`MAX_REPLY_BYTES`] have arrived.
+pub async fn probe_tcp(spec: &SocketSpec<'_>) -> SocketProbe {
+    let started = Instant::now();
+    let exchange = async {
+        let mut stream = TcpStream::connect(spec.addr)
+            .await
+            .map_err(|e| (format!("Connection failed: {e}"), None))?;
+        let connected = elapsed_ms(started);
+        if let Some(payload) = spec.send {
+            stream
+                .write_all(payload)
+                .await
+                .map_err(|e| (format!("Send failed: {e}"), Some(connected)))?;
+        }
+        let Some(expect) = spec.expect else {
+            return Ok(connected);
+        };
+        let mut reply = Vec::new();
+        let mut buf = [0; 1024];
+        while !contains(&reply, expect) {
+            if reply.len() >= MAX_REPLY_BYTES {
+                break;
+            }
+            match stream.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => reply.extend_from_slice(&buf[..n]),
+                Err(e) => return Err((format!("Read failed: {e}"), Some(connected))),
+            }
+        }
+        if contains(&reply, expect) {
+            Ok(elapsed_ms(started))
+        } else {
+            Err((
+                format!("Expected {} not in reply {}", quote(expect), quote(&reply)),
+                Some(elapsed_ms(started)),
+            ))
+        }
+    };
+    outcome(tokio::time::timeout(spec.timeout, exchange).await)
+}
+
+/// Sends `spec.send` (or an empty datagram) to `spec.addr` and waits for
+/// a reply containing `spec.expect`. A closed port usually shows up as a
+/// refused connection; a filtered one only as a timeout.
+pub async fn probe_udp(spec: &SocketSpec<'_>) -> SocketProbe {
+    let started = Instant::now();
+    let exchange = async {
+        let failed = |e: std::io::Error| (format!("Connection failed: {e}"), None);
+        let addr = tokio::net::lookup_host(spec.addr)
+            .await
+            .map_err(failed)?
+            .next()
+            .ok_or_else(|| (format!("No address for {}", spec.addr), None))?;
+        let local: SocketAddr = if addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local).await.map_err(failed)?;
+        socket.connect(addr).await.map_err(failed)?;
+        socket
+            .send(spec.send.unwrap_or_default())
+            .await
+            .map_err(failed)?;
+        let mut buf = vec![0; MAX_REPLY_BYTES];
+        let len = socket.recv(&mut buf).await.map_err(failed)?;
+        let reply = &buf[..len];
+        match spec.expect {
+            Some(expect) if !contains(reply, expect) => Err((
+                format!("Expected {} not in reply {}", quote(expect), quote(reply)),
+                Some(elapsed_ms(started)),
+            )),
+            _ => Ok(elapsed_ms(started)),
+        }
+    };
+    outcome(tokio::time::timeout(spec.timeout, exchange).await)
+}
+
+fn outcome(
+    result: Result<Result<f64, (String, Option<f64>)>, tokio::time::error::Elapsed>,
+) -> SocketProbe {
+    match result {
+        Ok(Ok(ms)) => SocketProbe {
+            up: true,
+            response_time_ms: Some(ms),
+            error_message: None,
+        },
+        Ok(Err((error, ms))) => SocketProbe {
+            up: false,
+            response_time_ms: ms,
+            error_message: Some(error),
+        },
+        Err(_) => SocketProbe {
+            up: false,
+            response_time_ms: None,
+            error_message: Some("Timeout".to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn spec<'a>(addr: &'a str, send: Option<&'a [u8]>, expect: Option<&'a [u8]>) -> SocketSpec<'a> {
+        SocketSpec {
+            addr,
+            send,
+            expect,
+            timeout: Duration::from_secs(2),
+        }
+    }
+
+    /// An SMTP-like server: greets, then answers one line per line read.
+    async fn smtp() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    stream
+                        .write_all(b"220 relay.bettergov.ph ESMTP\r\n")
+                        .await
+                        .unwrap();
+                    let mut buf = [0; 256];
+                    while let Ok(n) = stream.read(&mut buf).await {
+                        if n == 0
+                            || stream
+                                .write_all(b"250 relay.bettergov.ph\r\n")
+                                .await
+                                .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn unescapes_payloads() {
+        assert_eq!(unescape(r"EHLO x\r\n").unwrap(), b"EHLO x\r\n");
+        assert_eq!(unescape(r"\x00\x1b\\\t").unwrap(), b"\x00\x1b\\\t");
+        assert_eq!(unescape("ñ").unwrap(), "ñ".as_bytes());
+        assert!(unescape(r"\q").is_err());
+        assert!(unescape(r"\x4").is_err());
+        assert!(unescape(r"\xzz").is_err());
+        assert!(unescape("trailing\\").is_err());
+    }
+
+    #[tokio::test]
+    async fn tcp_reads_banners_and_replies() {
+        let addr = smtp().await;
+        let connected = probe_tcp(&spec(&addr, None, None)).await;
+        assert!(connected.up);
+        assert!(connected.response_time_ms.is_some());
+
+        assert!(probe_tcp(&spec(&addr, None, Some(b"220 "))).await.up);
+        let ehlo = spec(&addr, Some(b"EHLO monitor\r\n"), Some(b"250 "));
+        assert!(probe_tcp(&ehlo).await.up);
+
+        // The banner is not what was expected, and nothing else comes.
+        let mut pop3 = spec(&addr, None, Some(b"+OK"));
+        pop3.timeout = Duration::from_millis(200);
+        let wrong = probe_tcp(&pop3).await;
+        assert!(!wrong.up);
+        assert_eq!(wrong.error_message.as_deref(), Some("Timeout"));
+
+        let closed = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        let refused = probe_tcp(&spec(&closed, None, None)).await;
+        assert!(!refused.up);
+        assert!(refused
+            .error_message
+            .is_some_and(|e| e.starts_with("Connection failed")));
+    }
+
+    #[tokio::test]
+    async fn tcp_reports_what_arrived_instead() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"-ERR busy\r\n").await.unwrap();
+        });
+        let probe = probe_tcp(&spec(&addr, None, Some(b"+OK"))).await;
+        assert!(!probe.up);
+        assert_eq!(
+            probe.error_message.as_deref(),
+            Some(r#"Expected "+OK" not in reply "-ERR busy\r\n""#)
+        );
+    }
+
+    #[tokio::test]
+    async fn udp_waits_for_an_answer() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            while let Ok((n, peer)) = server.recv_from(&mut buf).await {
+                if &buf[..n] == b"ping" {
+                    server.send_to(b"pong", peer).await.unwrap();
+                }
+            }
+        });
+        assert!(
+            probe_udp(&spec(&addr, Some(b"ping"), Some(b"pong")))
+                .await
+                .up
+        );
+        assert!(probe_udp(&spec(&addr, Some(b"ping"), None)).await.up);
+
+        let mut unanswered = spec(&addr, Some(b"hello"), None);
+        unanswered.timeout = Duration::from_millis(200);
+        let probe = probe_udp(&unanswered).await;
+        assert!(!probe.up);
+        assert_eq!(probe.error_message.as_deref(), Some("Timeout"));
+    }
+}
@@ -0,0 +1,471 @@
+//! TLS certificate checks: a handshake with every HTTP subdomain records
+//! what its certificate says and whether it would be trusted, and raises
+//! alerts as expiry nears or when the certificate does not cover the host.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use super::MAX_CONCURRENT_PROBES;
+use crate::alerts;
+use crate::db::subdomains;
+use crate::db::tls_certificates::{self, NewTlsCertificate};
+use crate::notify::{AlertEvent, Dispatcher};
+use crate::x509::Certificate;
+
+const HTTPS_PORT: u16 = 443;
+
+/// Days left at which the expiry alert opens or escalates, and its
+/// severity from then on. Expired certificates are critical too.
+pub const EXPIRY_TIERS: [(i64, &str); 3] = [(3, "critical"), (14, "warning"), (30, "info")];
+
+/// Severity of the expiry alert for a certificate valid until `not_after`,
+/// or `None` while it has more than 30 days left.
+pub fn expiry_severity(not_after: DateTime<Utc>, now: DateTime<Utc>) -> Option<&'static str> {
+    let left = not_after - now;
+    EXPIRY_TIERS
+        .iter()
+        .find(|(days, _)| left <= chrono::Duration::days(*days))
+        .map(|(_, severity)| *severity)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("cannot read CA certificates from {path}: {reason}")]
+    CaFile { path: PathBuf, reason: String },
+    #[error("cannot set up TLS: {0}")]
+    Setup(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Seconds between checks of every subdomain; 0 turns them off.
+    pub interval_secs: u64,
+    /// Connect and handshake timeout.
+    pub timeout_secs: u64,
+    /// PEM bundle of CAs trusted besides the public web roots, e.g. a
+    /// government CA.
+    pub ca_file: Option<PathBuf>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            interval_secs: 21600,
+            timeout_secs: 10,
+            ca_file: None,
+        }
+    }
+}
+
+impl TlsConfig {
+    /// A prober trusting the public web roots and `ca_file`.
+    pub fn prober(&self) -> Result<TlsProber, TlsError> {
+        let mut roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        if let Some(path) = &self.ca_file {
+            let invalid = |reason: String| TlsError::CaFile {
+                path: path.clone(),
+                reason,
+            };
+            let certs = CertificateDer::pem_file_iter(path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| invalid(e.to_string()))?;
+            if certs.is_empty() {
+                return Err(invalid("no certificates found".to_string()));
+            }
+            for cert in certs {
+                roots.add(cert).map_err(|e| invalid(e.to_string()))?;
+            }
+        }
+        TlsProber::new(roots, Duration::from_secs(self.timeout_secs))
+    }
+}
+
+/// What a handshake showed.
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    pub certificate: Certificate,
+    /// E.g. `TLSv1.3`.
+    pub protocol: Option<String>,
+    /// E.g. `TLS13_AES_128_GCM_SHA256`.
+    pub cipher: Option<String>,
+    /// Why the chain would be rejected; `None` if it is trusted and
+    /// current. Name mismatches are left to `hostname_valid`.
+    pub chain_error: Option<String>,
+    pub hostname_valid: bool,
+}
+
+/// Lets every handshake finish so that broken certificates can be
+/// described; the chain is judged afterwards. Handshake signatures are
+/// still verified.
+#[derive(Debug)]
+struct AcceptAndInspect(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for AcceptAndInspect {
+    fn verify_server_cert(
+        &self,
+        _: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+pub struct TlsProber {
+    config: Arc<ClientConfig>,
+    verifier: Arc<WebPkiServerVerifier>,
+    timeout: Duration,
+}
+
+impl TlsProber {
+    pub fn new(roots: RootCertStore, timeout: Duration) -> Result<Self, TlsError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier =
+            WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::clone(&provider))
+                .build()
+                .map_err(|e| TlsError::Setup(e.to_string()))?;
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| TlsError::Setup(e.to_string()))?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAndInspect(Arc::clone(&verifier))))
+            .with_no_client_auth();
+        Ok(TlsProber {
+            config: Arc::new(config),
+            verifier,
+            timeout,
+        })
+    }
+
+    /// Shakes hands with `host`, on port 443 unless it names another.
+    pub async fn probe(&self, host: &str) -> Result<Handshake, String> {
+        let (name, port) = host
+            .rsplit_once(':')
+            .and_then(|(name, port)| Some((name, port.parse().ok()?)))
+            .unwrap_or((host, HTTPS_PORT));
+        self.probe_at(name, (name, port)).await
+    }
+
+    /// Shakes hands with `addr`, asking for and judging the certificate of
+    /// `name`.
+    pub async fn probe_at(
+        &self,
+        name: &str,
+        addr: impl tokio::net::ToSocketAddrs,
+    ) -> Result<Handshake, String> {
+        tokio::time::timeout(self.timeout, self.handshake(name, addr))
+            .await
+            .unwrap_or_else(|_| Err("Timeout".to_string()))
+    }
+
+    async fn handshake(
+        &self,
+        name: &str,
+        addr: impl tokio::net::ToSocketAddrs,
+    ) -> Result<Handshake, String> {
+        let server_name = ServerName::try_from(name.to_string())
+            .map_err(|e| format!("Invalid host name: {e}"))?;
+        let tcp = TcpStream::connect(addr)
+            .await
+            .map_err(|e| format!("Connection failed: {e}"))?;
+        let tls = TlsConnector::from(Arc::clone(&self.config))
+            .connect(server_name.clone(), tcp)
+            .await
+            .map_err(|e| format!("Handshake failed: {e}"))?;
+        let (_, conn) = tls.get_ref();
+
+        let Some((end_entity, intermediates)) = conn
+            .peer_certificates()
+            .and_then(|chain| chain.split_first())
+        else {
+            return Err("No certificate presented".to_string());
+        };
+        let certificate =
+            Certificate::parse(end_entity).ok_or("Presented certificate cannot be read")?;
+        // The chain is checked before the name, so a name mismatch means
+        // the chain itself was fine.
+        let chain_error = match self.verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            &server_name,
+            &[],
+            UnixTime::now(),
+        ) {
+            Ok(_)
+            | Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => None,
+            Err(e) => Some(e.to_string()),
+        };
+        Ok(Handshake {
+            hostname_valid: certificate.matches(name),
+            protocol: conn
+                .protocol_version()
+                .map(|v| format!("{v:?}").replace('_', ".")),
+            cipher: conn
+                .negotiated_cipher_suite()
+                .map(|s| format!("{:?}", s.suite())),
+            chain_error,
+            certificate,
+        })
+    }
+}
+
+/// Checks the certificate of every active subdomain once per run.
+pub struct TlsChecker {
+    pool: PgPool,
+    prober: TlsProber,
+    notifier: Arc<Dispatcher>,
+}
+
+impl TlsChecker {
+    pub fn new(pool: PgPool, prober: TlsProber, notifier: Arc<Dispatcher>) -> Self {
+        TlsChecker {
+            pool,
+            prober,
+            notifier,
+        }
+    }
+
+    pub async fn run_checks(&self) {
+        println!("[{}] Starting TLS checks...", Utc::now());
+        let targets = match subdomains::list_check_targets(&self.pool).await {
+            Ok(targets) => targets,
+            Err(e) => {
+                eprintln!("Failed to get subdomains: {e}");
+                return;
+            }
+        };
+        // TCP and UDP targets need not speak TLS at all.
+        let targets: Vec<_> = targets
+            .into_iter()
+            .filter(|target| target.check_kind == "http")
+            .collect();
+        let total = targets.len();
+        let handshakes = stream::iter(targets)
+            .map(|target| self.check(target.subdomain))
+            .buffer_unordered(MAX_CONCURRENT_PROBES)
+            .filter(|ok| std::future::ready(*ok))
+            .count()
+            .await;
+        println!("Completed {handshakes}/{total} TLS handshakes");
+    }
+
+    /// Whether the handshake succeeded.
+    async fn check(&self, subdomain: String) -> bool {
+        let result = self.prober.probe(&subdomain).await;
+        match &result {
+            Ok(handshake) => {
+                let cert = &handshake.certificate;
+                let mut problems = Vec::new();
+                if let Some(e) = &handshake.chain_error {
+                    problems.push(e.clone());
+                }
+                if !handshake.hostname_valid {
+                    problems.push("name mismatch".to_string());
+                }
+                println!(
+                    "  TLS {subdomain} - {}, expires {} ({} days){}",
+                    handshake.protocol.as_deref().unwrap_or("unknown protocol"),
+                    cert.not_after.format("%Y-%m-%d"),
+                    (cert.not_after - Utc::now()).num_days(),
+                    if problems.is_empty() {
+                        String::new()
+                    } else {
+                        format!(": {}", problems.join(", "))
+                    }
+                );
+            }
+            Err(e) => println!("  TLS {subdomain} - {e}"),
+        }
+        match self.save(&subdomain, &result).await {
+            Ok(events) => self.notifier.dispatch(events),
+            Err(e) => eprintln!("Error saving TLS check for {subdomain}: {e}"),
+        }
+        result.is_ok()
+    }
+
+    async fn save(
+        &self,
+        subdomain: &str,
+        result: &Result<Handshake, String>,
+    ) -> sqlx::Result<Vec<AlertEvent>> {
+        let handshake = match result {
+            Ok(handshake) => handshake,
+            Err(e) => {
+                tls_certificates::record_failure(&self.pool, subdomain, e).await?;
+                return Ok(Vec::new());
+            }
+        };
+        let mut tx = self.pool.begin().await?;
+        tls_certificates::record(
+            &mut *tx,
+            &NewTlsCertificate {
+                subdomain,
+                certificate: &handshake.certificate,
+                protocol: handshake.protocol.as_deref(),
+                cipher: handshake.cipher.as_deref(),
+                chain_error: handshake.chain_error.as_deref(),
+                hostname_valid: handshake.hostname_valid,
+            },
+        )
+        .await?;
+        let events = alerts::on_tls_check(&mut tx, subdomain, handshake, Utc::now()).await?;
+        tx.commit().await?;
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::TimeZone;
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::ServerConfig;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+    use crate::checker::tls_fixture as fixture;
+
+    /// Serves `cert` (issued by the test CA) for one handshake; returns the
+    /// address.
+    async fn serve(cert: &str, key: &str) -> std::net::SocketAddr {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(fixture(cert))],
+                PrivateKeyDer::try_from(fixture(key)).unwrap(),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let _ = acceptor.accept(tcp).await;
+        });
+        addr
+    }
+
+    fn prober(trust_test_ca: bool) -> TlsProber {
+        let mut roots = RootCertStore::empty();
+        if trust_test_ca {
+            roots.add(CertificateDer::from(fixture("ca.der"))).unwrap();
+        } else {
+            roots.roots = webpki_roots::TLS_SERVER_ROOTS.to_vec();
+        }
+        TlsProber::new(roots, Duration::from_secs(5)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn describes_a_trusted_certificate() {
+        let addr = serve("localhost.der", "localhost.key.der").await;
+        let handshake = prober(true).probe_at("localhost", addr).await.unwrap();
+        assert_eq!(handshake.chain_error, None);
+        assert!(handshake.hostname_valid);
+        assert_eq!(handshake.protocol.as_deref(), Some("TLSv1.3"));
+        assert!(handshake.cipher.is_some_and(|c| c.starts_with("TLS13_")));
+        let cert = handshake.certificate;
+        assert_eq!(cert.subject, "O=BetterGov Test, CN=localhost");
+        assert_eq!(cert.issuer, "C=PH, O=BetterGov Test, CN=BetterGov Test CA");
+        assert_eq!(cert.dns_names, ["localhost", "*.tls.bettergov.test"]);
+        assert_eq!(cert.key_type, "ECDSA P-256");
+        assert!(cert.not_before < Utc::now() && Utc::now() < cert.not_after);
+    }
+
+    #[tokio::test]
+    async fn reports_a_name_mismatch_apart_from_the_chain() {
+        let addr = serve("localhost.der", "localhost.key.der").await;
+        let handshake = prober(true)
+            .probe_at("portal.bettergov.test", addr)
+            .await
+            .unwrap();
+        assert_eq!(handshake.chain_error, None);
+        assert!(!handshake.hostname_valid);
+    }
+
+    #[tokio::test]
+    async fn rejects_untrusted_and_expired_chains() {
+        let addr = serve("localhost.der", "localhost.key.der").await;
+        let untrusted = prober(false).probe_at("localhost", addr).await.unwrap();
+        assert!(untrusted
+            .chain_error
+            .is_some_and(|e| e.contains("UnknownIssuer")));
+
+        let addr = serve("expired.der", "expired.key.der").await;
+        let expired = prober(true).probe_at("localhost", addr).await.unwrap();
+        assert!(expired.chain_error.is_some_and(|e| e.contains("expired")));
+        assert_eq!(expired.certificate.key_type, "RSA 2048");
+        assert_eq!(
+            expired.certificate.not_after,
+            Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_failures_are_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let error = prober(true).probe_at("localhost", addr).await.unwrap_err();
+        assert!(error.starts_with("Connection failed"), "{error}");
+    }
+
+    #[test]
+    fn expiry_alerts_tighten_at_30_14_and_3_days() {
+        let now = Utc.with_ymd_and_hms(2025, 6, 1, 0, 0, 0).unwrap();
+        let in_days = |days: i64| now + chrono::Duration::days(days);
+        assert_eq!(expiry_severity(in_days(31), now), None);
+        assert_eq!(expiry_severity(in_days(30), now), Some("info"));
+        assert_eq!(expiry_severity(in_days(15), now), Some("info"));
+        assert_eq!(expiry_severity(in_days(14), now), Some("warning"));
+        assert_eq!(expiry_severity(in_days(3), now), Some("critical"));
+        assert_eq!(expiry_severity(in_days(-1), now), Some("critical"));
+    }
+}
@@ -0,0 +1,599 @@
+//! Server configuration, layered from lowest to highest precedence: built-in
+//! defaults, an optional TOML file, environment variables, then command-line
+//! flags. See `config.example.toml` for every setting.
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::agents::liveness::LivenessSettings;
+use crate::checker::dns::DnsConfig;
+use crate::checker::tls::TlsConfig;
+use crate::checker::CheckerSettings;
+use crate::db::PoolSettings;
+use crate::discovery::DiscoveryConfig;
+use crate::notify::NotificationsConfig;
+use crate::status::Thresholds;
+use crate::subdomains::inventory;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("cannot read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid config file {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+}
+
+/// Command-line flags. Each one can also be given through the environment
+/// variable next to it; a flag wins over the variable, and both win over
+/// the config file.
+#[derive(Debug, Default, Parser)]
+#[command(
+    name = "bettergov-api",
+    version,
+    about = "BetterGov monitoring API server"
+)]
+pub struct Cli {
+    /// TOML config file.
+    #[arg(short, long, env = "BETTERGOV_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on; repeat or comma-separate for several.
+    #[arg(long, env = "BETTERGOV_LISTEN", value_delimiter = ',')]
+    pub listen: Vec<SocketAddr>,
+    /// HTTP worker threads (defaults to the number of CPU cores).
+    #[arg(long, env = "BETTERGOV_WORKERS")]
+    pub workers: Option<usize>,
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true)]
+    pub database_url: Option<String>,
+    #[arg(long, env = "BETTERGOV_DB_MIN_CONNECTIONS")]
+    pub db_min_connections: Option<u32>,
+    #[arg(long, env = "BETTERGOV_DB_MAX_CONNECTIONS")]
+    pub db_max_connections: Option<u32>,
+    /// Seconds between uptime check rounds.
+    #[arg(long, env = "BETTERGOV_CHECK_INTERVAL")]
+    pub check_interval: Option<u64>,
+    /// Overall per-probe timeout in seconds.
+    #[arg(long, env = "BETTERGOV_PROBE_TIMEOUT")]
+    pub probe_timeout: Option<u64>,
+    /// Per-probe connect timeout in seconds.
+    #[arg(long, env = "BETTERGOV_PROBE_CONNECT_TIMEOUT")]
+    pub probe_connect_timeout: Option<u64>,
+    /// Bearer token for the admin API (agent key management).
+    #[arg(long, env = "BETTERGOV_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: Vec<SocketAddr>,
+    pub workers: Option<usize>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: vec![SocketAddr::from(([0, 0, 0, 0], 8000))],
+            workers: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: Option<String>,
+    pub min_connections: u32,
+    pub max_connections: u32,
+    pub acquire_timeout_secs: u64,
+    pub connect_attempts: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        let pool = PoolSettings::default();
+        DatabaseConfig {
+            url: None,
+            min_connections: pool.min_connections,
+            max_connections: pool.max_connections,
+            acquire_timeout_secs: pool.acquire_timeout.as_secs(),
+            connect_attempts: pool.connect_attempts,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheckerConfig {
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+    pub connect_timeout_secs: u64,
+}
+
+impl Default for CheckerConfig {
+    fn default() -> Self {
+        let checker = CheckerSettings::default();
+        CheckerConfig {
+            interval_secs: checker.interval.as_secs(),
+            timeout_secs: checker.timeout.as_secs(),
+            connect_timeout_secs: checker.connect_timeout.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentsConfig {
+    /// How far a signed report's timestamp may be from the server clock.
+    pub max_clock_skew_secs: u64,
+    /// How long the previous keys of a location stay valid after rotation.
+    pub key_overlap_secs: u64,
+    /// Default lifetime of an enrollment code.
+    pub enrollment_code_ttl_secs: u64,
+    /// Report interval assumed until an agent's cadence has been learned.
+    pub report_interval_secs: u64,
+    /// An agent is late after this many intervals without a report...
+    pub late_after_intervals: f64,
+    /// ...and offline after this many.
+    pub offline_after_intervals: f64,
+    /// Agents reporting an older version are outdated.
+    pub min_version: Option<String>,
+}
+
+impl Default for AgentsConfig {
+    fn default() -> Self {
+        let liveness = LivenessSettings::default();
+        AgentsConfig {
+            max_clock_skew_secs: 300,
+            key_overlap_secs: 86400,
+            enrollment_code_ttl_secs: 900,
+            report_interval_secs: liveness.default_interval.as_secs(),
+            late_after_intervals: liveness.late_after,
+            offline_after_intervals: liveness.offline_after,
+            min_version: liveness.min_version,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubdomainsConfig {
+    /// Domains whose hosts may be added to the inventory through the API.
+    pub domains: Vec<String>,
+}
+
+impl Default for SubdomainsConfig {
+    fn default() -> Self {
+        SubdomainsConfig {
+            domains: vec!["bettergov.ph".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Bearer token for `/api/admin`; the admin API is disabled without one.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub checker: CheckerConfig,
+    pub tls: TlsConfig,
+    pub dns: DnsConfig,
+    pub status: Thresholds,
+    pub agents: AgentsConfig,
+    pub subdomains: SubdomainsConfig,
+    pub discovery: DiscoveryConfig,
+    pub notifications: NotificationsConfig,
+    pub admin: AdminConfig,
+}
+
+impl Config {
+    /// Resolves the configuration for `cli` and checks that it is usable.
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => Config::default(),
+        };
+        config.apply(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let source = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        toml::from_str(&source).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn apply(&mut self, cli: &Cli) {
+        if !cli.listen.is_empty() {
+            self.server.listen = cli.listen.clone();
+        }
+        if cli.workers.is_some() {
+            self.server.workers = cli.workers;
+        }
+        if cli.database_url.is_some() {
+            self.database.url = cli.database_url.clone();
+        }
+        if let Some(n) = cli.db_min_connections {
+            self.database.min_connections = n;
+        }
+        if let Some(n) = cli.db_max_connections {
+            self.database.max_connections = n;
+        }
+        if let Some(secs) = cli.check_interval {
+            self.checker.interval_secs = secs;
+        }
+        if let Some(secs) = cli.probe_timeout {
+            self.checker.timeout_secs = secs;
+        }
+        if let Some(secs) = cli.probe_connect_timeout {
+            self.checker.connect_timeout_secs = secs;
+        }
+        if cli.admin_token.is_some() {
+            self.admin.token = cli.admin_token.clone();
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+
+        if self.server.listen.is_empty() {
+            return invalid("server.listen needs at least one address".into());
+        }
+        if self.server.workers == Some(0) {
+            return invalid("server.workers must be at least 1".into());
+        }
+        if self.database_url().is_empty() {
+            return invalid(
+                "no database URL; set DATABASE_URL, --database-url or database.url".into(),
+            );
+        }
+        let db = &self.database;
+        if db.max_connections == 0 || db.min_connections > db.max_connections {
+            return invalid(format!(
+                "database pool sizes must satisfy 0 <= min ({}) <= max ({}) and max >= 1",
+                db.min_connections, db.max_connections
+            ));
+        }
+        if db.acquire_timeout_secs == 0 || db.connect_attempts == 0 {
+            return invalid(
+                "database.acquire_timeout_secs and database.connect_attempts must be at least 1"
+                    .into(),
+            );
+        }
+        let checker = &self.checker;
+        if checker.interval_secs == 0
+            || checker.timeout_secs == 0
+            || checker.connect_timeout_secs == 0
+        {
+            return invalid("checker interval and timeouts must be at least 1 second".into());
+        }
+        if checker.connect_timeout_secs > checker.timeout_secs {
+            return invalid(format!(
+                "checker.connect_timeout_secs ({}) exceeds checker.timeout_secs ({})",
+                checker.connect_timeout_secs, checker.timeout_secs
+            ));
+        }
+        let status = &self.status;
+        if status.up_strikes == 0 || status.down_strikes == 0 {
+            return invalid("status strike thresholds must be at least 1".into());
+        }
+        if status.flap_window < 2 {
+            return invalid("status.flap_window must be at least 2".into());
+        }
+        if !(0.0..=100.0).contains(&status.flap_low_percent)
+            || !(0.0..=100.0).contains(&status.flap_high_percent)
+            || status.flap_low_percent > status.flap_high_percent
+        {
+            return invalid(format!(
+                "status flap thresholds must satisfy 0 <= low ({}) <= high ({}) <= 100",
+                status.flap_low_percent, status.flap_high_percent
+            ));
+        }
+        if status.down_quorum == 0 || status.location_stale_secs == 0 {
+            return invalid(
+                "status.down_quorum and status.location_stale_secs must be at least 1".into(),
+            );
+        }
+        let notifications = &self.notifications;
+        if notifications.attempts == 0 || notifications.timeout_secs == 0 {
+            return invalid(
+                "notifications.attempts and notifications.timeout_secs must be at least 1".into(),
+            );
+        }
+        if self.agents.max_clock_skew_secs == 0 {
+            return invalid("agents.max_clock_skew_secs must be at least 1".into());
+        }
+        if !(60..=86400).contains(&self.agents.enrollment_code_ttl_secs) {
+            return invalid("agents.enrollment_code_ttl_secs must be between 60 and 86400".into());
+        }
+        let agents = &self.agents;
+        if agents.report_interval_secs == 0 {
+            return invalid("agents.report_interval_secs must be at least 1".into());
+        }
+        if !(agents.late_after_intervals >= 1.0
+            && agents.offline_after_intervals > agents.late_after_intervals)
+        {
+            return invalid(format!(
+                "agent liveness thresholds must satisfy 1 <= late_after_intervals ({}) < offline_after_intervals ({})",
+                agents.late_after_intervals, agents.offline_after_intervals
+            ));
+        }
+        if self.tls.timeout_secs == 0 {
+            return invalid("tls.timeout_secs must be at least 1".into());
+        }
+        let dns = &self.dns;
+        if dns.timeout_secs == 0 {
+            return invalid("dns.timeout_secs must be at least 1".into());
+        }
+        if dns.resolvers.is_empty() {
+            return invalid("dns.resolvers needs at least one resolver".into());
+        }
+        if let Some(rtype) = dns
+            .alert_types
+            .iter()
+            .find(|rtype| !dns.record_types.contains(rtype))
+        {
+            return invalid(format!(
+                "dns.alert_types includes {rtype}, which is not in dns.record_types"
+            ));
+        }
+        if self.subdomains.domains.is_empty() {
+            return invalid("subdomains.domains needs at least one domain".into());
+        }
+        if let Some(domain) = self
+            .subdomains
+            .domains
+            .iter()
+            .find(|d| !inventory::is_hostname(d))
+        {
+            return invalid(format!(
+                "subdomains.domains entry {domain:?} is not a lowercase hostname"
+            ));
+        }
+        let discovery = &self.discovery;
+        if discovery.timeout_secs == 0 || discovery.concurrency == 0 {
+            return invalid(
+                "discovery.timeout_secs and discovery.concurrency must be at least 1".into(),
+            );
+        }
+        if discovery.crawl_start_urls().len() != discovery.crawl_start.len() {
+            return invalid("discovery.crawl_start entries must be absolute URLs".into());
+        }
+        if let Some(prefix) = discovery
+            .wordlist
+            .iter()
+            .find(|p| !inventory::is_hostname(p))
+        {
+            return invalid(format!(
+                "discovery.wordlist entry {prefix:?} is not a lowercase DNS label"
+            ));
+        }
+        if !discovery.ct.iter().all(|ct| ct.is_valid()) {
+            return invalid(
+                "each discovery.ct entry needs either a path or an absolute url".into(),
+            );
+        }
+        if self
+            .admin
+            .token
+            .as_deref()
+            .is_some_and(|t| t.trim().len() < 16)
+        {
+            return invalid("admin.token must be at least 16 characters".into());
+        }
+        Ok(())
+    }
+
+    pub fn database_url(&self) -> &str {
+        self.database.url.as_deref().unwrap_or_default()
+    }
+
+    /// Admin bearer token, if the admin API is enabled.
+    pub fn admin_token(&self) -> Option<String> {
+        self.admin.token.as_deref().map(|t| t.trim().to_string())
+    }
+
+    pub fn pool_settings(&self) -> PoolSettings {
+        PoolSettings {
+            min_connections: self.database.min_connections,
+            max_connections: self.database.max_connections,
+            acquire_timeout: Duration::from_secs(self.database.acquire_timeout_secs),
+            connect_attempts: self.database.connect_attempts,
+        }
+    }
+
+    pub fn liveness_settings(&self) -> LivenessSettings {
+        LivenessSettings {
+            default_interval: Duration::from_secs(self.agents.report_interval_secs),
+            late_after: self.agents.late_after_intervals,
+            offline_after: self.agents.offline_after_intervals,
+            min_version: self.agents.min_version.clone(),
+        }
+    }
+
+    pub fn checker_settings(&self) -> CheckerSettings {
+        CheckerSettings {
+            interval: Duration::from_secs(self.checker.interval_secs),
+            timeout: Duration::from_secs(self.checker.timeout_secs),
+            connect_timeout: Duration::from_secs(self.checker.connect_timeout_secs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid() -> Config {
+        let mut config = Config::default();
+        config.database.url = Some("postgres://monitor@localhost/monitoring".to_string());
+        config
+    }
+
+    #[test]
+    fn flags_beat_environment_beats_file_beats_defaults() {
+        let path = std::env::temp_dir().join(format!("bettergov-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            r#"
+                [server]
+                workers = 2
+
+                [database]
+                url = "postgres://file/monitoring"
+                max_connections = 9
+
+                [checker]
+                interval_secs = 30
+                timeout_secs = 20
+            "#,
+        )
+        .unwrap();
+        // The only test that sets these, so no other test sees them.
+        std::env::set_var("BETTERGOV_CHECK_INTERVAL", "45");
+        std::env::set_var("BETTERGOV_PROBE_TIMEOUT", "25");
+        let cli = Cli::try_parse_from([
+            "bettergov-api",
+            "--config",
+            path.to_str().unwrap(),
+            "--probe-timeout",
+            "15",
+            "--database-url",
+            "postgres://flag/monitoring",
+        ]);
+        std::env::remove_var("BETTERGOV_CHECK_INTERVAL");
+        std::env::remove_var("BETTERGOV_PROBE_TIMEOUT");
+        let config = Config::load(&cli.unwrap());
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(config.server.workers, Some(2));
+        assert_eq!(config.database.max_connections, 9);
+        assert_eq!(config.checker.interval_secs, 45);
+        assert_eq!(config.checker.timeout_secs, 15);
+        assert_eq!(config.database_url(), "postgres://flag/monitoring");
+        assert_eq!(config.database.min_connections, 5);
+        assert_eq!(config.checker.connect_timeout_secs, 5);
+        assert_eq!(config.server.listen, ServerConfig::default().listen);
+    }
+
+    #[test]
+    fn unreadable_and_unknown_settings_are_errors() {
+        let missing = Config::from_file(Path::new("/nonexistent/bettergov.toml"));
+        assert!(matches!(missing, Err(ConfigError::Read { .. })));
+
+        let path = std::env::temp_dir().join(format!("bettergov-typo-{}.toml", std::process::id()));
+        fs::write(&path, "[checker]\ninterval = 30\n").unwrap();
+        let typo = Config::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(typo, Err(ConfigError::Parse { .. })));
+    }
+
+    #[test]
+    fn validation_names_the_offending_setting() {
+        assert!(valid().validate().is_ok());
+
+        type Mutation = fn(&mut Config);
+        let cases: &[(Mutation, &str)] = &[
+            (|c| c.server.listen.clear(), "server.listen"),
+            (|c| c.server.workers = Some(0), "server.workers"),
+            (|c| c.database.url = None, "no database URL"),
+            (|c| c.database.max_connections = 0, "pool sizes"),
+            (|c| c.database.min_connections = 21, "pool sizes"),
+            (
+                |c| c.database.acquire_timeout_secs = 0,
+                "acquire_timeout_secs",
+            ),
+            (|c| c.database.connect_attempts = 0, "connect_attempts"),
+            (|c| c.checker.interval_secs = 0, "checker interval"),
+            (|c| c.checker.connect_timeout_secs = 30, "exceeds"),
+            (|c| c.status.down_strikes = 0, "strike thresholds"),
+            (|c| c.status.flap_window = 1, "flap_window"),
+            (|c| c.status.flap_low_percent = 90.0, "flap thresholds"),
+            (|c| c.status.flap_high_percent = 101.0, "flap thresholds"),
+            (|c| c.status.down_quorum = 0, "down_quorum"),
+            (|c| c.notifications.attempts = 0, "notifications.attempts"),
+            (|c| c.agents.max_clock_skew_secs = 0, "max_clock_skew_secs"),
+            (
+                |c| c.agents.enrollment_code_ttl_secs = 30,
+                "enrollment_code_ttl_secs",
+            ),
+            (
+                |c| c.agents.report_interval_secs = 0,
+                "report_interval_secs",
+            ),
+            (
+                |c| c.agents.late_after_intervals = 0.5,
+                "liveness thresholds",
+            ),
+            (
+                |c| c.agents.offline_after_intervals = 1.0,
+                "liveness thresholds",
+            ),
+            (|c| c.tls.timeout_secs = 0, "tls.timeout_secs"),
+            (|c| c.dns.timeout_secs = 0, "dns.timeout_secs"),
+            (|c| c.dns.resolvers.clear(), "dns.resolvers"),
+            (|c| c.dns.record_types.truncate(1), "dns.alert_types"),
+            (|c| c.subdomains.domains.clear(), "subdomains.domains needs"),
+            (
+                |c| c.subdomains.domains = vec!["BetterGov.PH".to_string()],
+                "not a lowercase hostname",
+            ),
+            (|c| c.discovery.concurrency = 0, "discovery.timeout_secs"),
+            (
+                |c| c.discovery.crawl_start = vec!["bettergov.ph".to_string()],
+                "absolute URLs",
+            ),
+            (
+                |c| c.discovery.wordlist = vec!["not_a_label".to_string()],
+                "discovery.wordlist",
+            ),
+            (
+                |c| {
+                    c.discovery.ct = vec![crate::discovery::ct::CtConfig {
+                        path: None,
+                        url: None,
+                        format: Default::default(),
+                    }]
+                },
+                "discovery.ct",
+            ),
+            (|c| c.admin.token = Some("short".to_string()), "admin.token"),
+        ];
+        for (mutate, expected) in cases {
+            let mut config = valid();
+            mutate(&mut config);
+            match config.validate() {
+                Err(ConfigError::Invalid(message)) => {
+                    assert!(message.contains(expected), "{message:?} lacks {expected:?}")
+                }
+                other => panic!("expected an error naming {expected:?}, got {other:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor};
+
+/// A row of `monitoring.agent_audit_log` (created by add_agent_enrollment.sql).
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub time: DateTime<Utc>,
+    pub location: Option<String>,
+    pub event: String,
+    pub actor: String,
+    pub key_id: Option<String>,
+    pub client_ip: Option<String>,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct NewAuditEntry<'a> {
+    pub location: Option<&'a str>,
+    pub event: &'a str,
+    pub actor: &'a str,
+    pub key_id: Option<&'a str>,
+    pub client_ip: Option<&'a str>,
+    pub detail: Option<&'a str>,
+}
+
+pub async fn insert(db: impl PgExecutor<'_>, entry: &NewAuditEntry<'_>) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO monitoring.agent_audit_log (location, event, actor, key_id, client_ip, detail)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(entry.location)
+    .bind(entry.event)
+    .bind(entry.actor)
+    .bind(entry.key_id)
+    .bind(entry.client_ip)
+    .bind(entry.detail)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// Newest first, optionally for one location.
+pub async fn list(
+    db: impl PgExecutor<'_>,
+    location: Option<&str>,
+    limit: i64,
+) -> sqlx::Result<Vec<AuditEntry>> {
+    sqlx::query_as(
+        "SELECT id, time, location, event, actor, key_id, client_ip, detail
+         FROM monitoring.agent_audit_log
+         WHERE ($1::text IS NULL OR location = $1)
+         ORDER BY time DESC, id DESC
+         LIMIT $2",
+    )
+    .bind(location)
+    .bind(limit)
+    .fetch_all(db)
+    .await
+}
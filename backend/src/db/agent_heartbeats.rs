@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor};
+
+/// A row of `monitoring.agent_heartbeats` (created by full_migration.sql).
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AgentHeartbeat {
+    pub location: String,
+    pub last_seen: DateTime<Utc>,
+    pub status: String,
+    #[serde(skip_serializing)]
+    pub agent_token: Option<String>,
+    #[serde(skip_serializing)]
+    pub expected_token: Option<String>,
+    pub token_generated_at: Option<DateTime<Utc>>,
+    pub out_of_sync: bool,
+}
+
+const COLUMNS: &str = "
+    location, last_seen,
+    COALESCE(status, 'active') AS status,
+    agent_token, expected_token, token_generated_at,
+    COALESCE(out_of_sync, false) AS out_of_sync";
+
+pub async fn get(db: impl PgExecutor<'_>, location: &str) -> sqlx::Result<Option<AgentHeartbeat>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.agent_heartbeats WHERE location = $1"
+    ))
+    .bind(location)
+    .fetch_optional(db)
+    .await
+}
+
+/// All agents, most recently seen first.
+pub async fn list(db: impl PgExecutor<'_>) -> sqlx::Result<Vec<AgentHeartbeat>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.agent_heartbeats ORDER BY last_seen DESC"
+    ))
+    .fetch_all(db)
+    .await
+}
+
+/// Records a report from `location`, creating its row on first contact.
+/// `agent_token` keeps the id of the key the report was signed with.
+pub async fn touch(db: impl PgExecutor<'_>, location: &str, key_id: &str) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO monitoring.agent_heartbeats (location, last_seen, status, agent_token, out_of_sync)
+         VALUES ($1, NOW(), 'active', $2, FALSE)
+         ON CONFLICT (location) DO UPDATE SET
+             last_seen = NOW(),
+             status = 'active',
+             agent_token = $2,
+             out_of_sync = FALSE",
+    )
+    .bind(location)
+    .bind(key_id)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// Flags an agent whose key is no longer valid, so the dashboard shows it
+/// as needing redeployment.
+pub async fn mark_out_of_sync(db: impl PgExecutor<'_>, location: &str) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE monitoring.agent_heartbeats
+         SET out_of_sync = TRUE, status = 'outdated'
+         WHERE location = $1",
+    )
+    .bind(location)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+pub async fn delete(db: impl PgExecutor<'_>, location: &str) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM monitoring.agent_heartbeats WHERE location = $1")
+        .bind(location)
+        .execute(db)
+        .await
+        .map(|_| ())
+}
@@ -0,0 +1,108 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor};
+
+/// A row of `monitoring.agent_keys` (created by add_agent_keys.sql).
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AgentKey {
+    pub id: i32,
+    pub location: String,
+    pub key_id: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+}
+
+impl AgentKey {
+    /// Whether the key may still sign reports at `now`.
+    pub fn is_usable(&self, now: DateTime<Utc>) -> bool {
+        !self.revoked && self.expires_at.is_none_or(|at| at > now)
+    }
+}
+
+const COLUMNS: &str = "id, location, key_id, secret, created_at, expires_at, revoked";
+
+pub async fn insert(
+    db: impl PgExecutor<'_>,
+    location: &str,
+    key_id: &str,
+    secret: &str,
+) -> sqlx::Result<AgentKey> {
+    sqlx::query_as(&format!(
+        "INSERT INTO monitoring.agent_keys (location, key_id, secret)
+         VALUES ($1, $2, $3)
+         RETURNING {COLUMNS}"
+    ))
+    .bind(location)
+    .bind(key_id)
+    .bind(secret)
+    .fetch_one(db)
+    .await
+}
+
+/// Schedules every current key of `location` to expire at `at`.
+pub async fn expire_current(
+    db: impl PgExecutor<'_>,
+    location: &str,
+    at: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    sqlx::query(
+        "UPDATE monitoring.agent_keys
+         SET expires_at = $2
+         WHERE location = $1 AND expires_at IS NULL AND NOT revoked",
+    )
+    .bind(location)
+    .bind(at)
+    .execute(db)
+    .await
+    .map(|r| r.rows_affected())
+}
+
+pub async fn get(db: impl PgExecutor<'_>, key_id: &str) -> sqlx::Result<Option<AgentKey>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.agent_keys WHERE key_id = $1"
+    ))
+    .bind(key_id)
+    .fetch_optional(db)
+    .await
+}
+
+/// Keys of `location`, newest first.
+pub async fn list(db: impl PgExecutor<'_>, location: &str) -> sqlx::Result<Vec<AgentKey>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.agent_keys
+         WHERE location = $1
+         ORDER BY created_at DESC, id DESC"
+    ))
+    .bind(location)
+    .fetch_all(db)
+    .await
+}
+
+/// Revokes a key immediately. `None` if `location` has no such key.
+pub async fn revoke(
+    db: impl PgExecutor<'_>,
+    location: &str,
+    key_id: &str,
+) -> sqlx::Result<Option<AgentKey>> {
+    sqlx::query_as(&format!(
+        "UPDATE monitoring.agent_keys SET revoked = TRUE
+         WHERE location = $1 AND key_id = $2
+         RETURNING {COLUMNS}"
+    ))
+    .bind(location)
+    .bind(key_id)
+    .fetch_optional(db)
+    .await
+}
+
+/// Drops every key of `location`, when its agent is deleted.
+pub async fn delete_for_location(db: impl PgExecutor<'_>, location: &str) -> sqlx::Result<u64> {
+    sqlx::query("DELETE FROM monitoring.agent_keys WHERE location = $1")
+        .bind(location)
+        .execute(db)
+        .await
+        .map(|r| r.rows_affected())
+}
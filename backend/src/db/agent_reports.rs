@@ -0,0 +1,78 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgExecutor};
+
+/// An accepted geo-report, for `monitoring.agent_reports` (created by
+/// add_agent_liveness.sql).
+pub struct NewAgentReport<'a> {
+    pub location: &'a str,
+    /// The signed timestamp of the report, by the agent's clock.
+    pub sent_at: DateTime<Utc>,
+    pub results: i32,
+    pub accepted: i32,
+    pub version: Option<&'a str>,
+}
+
+pub async fn insert(db: impl PgExecutor<'_>, report: &NewAgentReport<'_>) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO monitoring.agent_reports (location, sent_at, results, accepted, version)
+         VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(report.location)
+    .bind(report.sent_at)
+    .bind(report.results)
+    .bind(report.accepted)
+    .bind(report.version)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// Recent reporting behaviour of one agent.
+#[derive(Debug, Clone, FromRow)]
+pub struct ReportStats {
+    pub location: String,
+    pub reports_last_hour: i64,
+    /// Median of `sent_at - received_at` over the last 20 reports; positive
+    /// when the agent's clock is ahead.
+    pub clock_skew_secs: Option<f64>,
+    /// Gaps of at least `min_gap_secs` between consecutive reports over
+    /// the last six hours, newest first.
+    pub gaps_secs: Vec<f64>,
+}
+
+pub async fn stats(db: impl PgExecutor<'_>, min_gap_secs: f64) -> sqlx::Result<Vec<ReportStats>> {
+    sqlx::query_as(
+        "WITH recent AS (
+             SELECT location, received_at, sent_at,
+                    EXTRACT(EPOCH FROM received_at - LAG(received_at) OVER (
+                        PARTITION BY location ORDER BY received_at
+                    ))::float8 AS gap,
+                    ROW_NUMBER() OVER (PARTITION BY location ORDER BY received_at DESC) AS newest
+             FROM monitoring.agent_reports
+             WHERE received_at > NOW() - INTERVAL '6 hours'
+         )
+         SELECT location,
+                COUNT(*) FILTER (WHERE received_at > NOW() - INTERVAL '1 hour') AS reports_last_hour,
+                (percentile_cont(0.5) WITHIN GROUP (
+                    ORDER BY EXTRACT(EPOCH FROM sent_at - received_at)
+                ) FILTER (WHERE newest <= 20))::float8 AS clock_skew_secs,
+                COALESCE(
+                    array_agg(gap ORDER BY received_at DESC) FILTER (WHERE gap >= $1),
+                    '{}'
+                ) AS gaps_secs
+         FROM recent
+         GROUP BY location",
+    )
+    .bind(min_gap_secs)
+    .fetch_all(db)
+    .await
+}
+
+/// Drops reports received before `before`.
+pub async fn purge(db: impl PgExecutor<'_>, before: DateTime<Utc>) -> sqlx::Result<u64> {
+    sqlx::query("DELETE FROM monitoring.agent_reports WHERE received_at < $1")
+        .bind(before)
+        .execute(db)
+        .await
+        .map(|r| r.rows_affected())
+}
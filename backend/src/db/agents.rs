@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor};
+
+/// A row of `monitoring.agents` (created by add_agent_registry.sql).
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Agent {
+    pub location: String,
+    pub name: Option<String>,
+    pub region: Option<String>,
+    pub isp: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub version: Option<String>,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Last state set by the liveness tracker (add_agent_liveness.sql).
+    pub liveness: Option<String>,
+    pub liveness_since: Option<DateTime<Utc>>,
+}
+
+/// Operator-maintained description of an agent.
+#[derive(Debug, Clone, Default)]
+pub struct AgentMetadata<'a> {
+    pub name: Option<&'a str>,
+    pub region: Option<&'a str>,
+    pub isp: Option<&'a str>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub version: Option<&'a str>,
+}
+
+const COLUMNS: &str = "
+    location, name, region, isp, latitude, longitude, version,
+    enabled, created_at, updated_at, liveness, liveness_since";
+
+/// Registers an agent. `None` if `location` is already taken.
+pub async fn insert(
+    db: impl PgExecutor<'_>,
+    location: &str,
+    meta: &AgentMetadata<'_>,
+) -> sqlx::Result<Option<Agent>> {
+    sqlx::query_as(&format!(
+        "INSERT INTO monitoring.agents (location, name, region, isp, latitude, longitude, version)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)
+         ON CONFLICT (location) DO NOTHING
+         RETURNING {COLUMNS}"
+    ))
+    .bind(location)
+    .bind(meta.name)
+    .bind(meta.region)
+    .bind(meta.isp)
+    .bind(meta.latitude)
+    .bind(meta.longitude)
+    .bind(meta.version)
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn get(db: impl PgExecutor<'_>, location: &str) -> sqlx::Result<Option<Agent>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.agents WHERE location = $1"
+    ))
+    .bind(location)
+    .fetch_optional(db)
+    .await
+}
+
+/// All agents by location.
+pub async fn list(db: impl PgExecutor<'_>) -> sqlx::Result<Vec<Agent>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.agents ORDER BY location"
+    ))
+    .fetch_all(db)
+    .await
+}
+
+/// Replaces the metadata of an agent. `None` if it is not registered.
+pub async fn update_metadata(
+    db: impl PgExecutor<'_>,
+    location: &str,
+    meta: &AgentMetadata<'_>,
+) -> sqlx::Result<Option<Agent>> {
+    sqlx::query_as(&format!(
+        "UPDATE monitoring.agents
+         SET name = $2, region = $3, isp = $4, latitude = $5, longitude = $6, version = $7,
+             updated_at = NOW()
+         WHERE location = $1
+         RETURNING {COLUMNS}"
+    ))
+    .bind(location)
+    .bind(meta.name)
+    .bind(meta.region)
+    .bind(meta.isp)
+    .bind(meta.latitude)
+    .bind(meta.longitude)
+    .bind(meta.version)
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn set_enabled(
+    db: impl PgExecutor<'_>,
+    location: &str,
+    enabled: bool,
+) -> sqlx::Result<Option<Agent>> {
+    sqlx::query_as(&format!(
+        "UPDATE monitoring.agents SET enabled = $2, updated_at = NOW()
+         WHERE location = $1
+         RETURNING {COLUMNS}"
+    ))
+    .bind(location)
+    .bind(enabled)
+    .fetch_optional(db)
+    .await
+}
+
+/// Records the version an agent reported, if it changed.
+pub async fn set_version(
+    db: impl PgExecutor<'_>,
+    location: &str,
+    version: &str,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE monitoring.agents SET version = $2, updated_at = NOW()
+         WHERE location = $1 AND version IS DISTINCT FROM $2",
+    )
+    .bind(location)
+    .bind(version)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// Stores the liveness state of an agent; `None` clears it.
+pub async fn set_liveness(
+    db: impl PgExecutor<'_>,
+    location: &str,
+    liveness: Option<&str>,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE monitoring.agents SET liveness = $2, liveness_since = NOW()
+         WHERE location = $1",
+    )
+    .bind(location)
+    .bind(liveness)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// `false` if the agent was not registered.
+pub async fn delete(db: impl PgExecutor<'_>, location: &str) -> sqlx::Result<bool> {
+    sqlx::query("DELETE FROM monitoring.agents WHERE location = $1")
+        .bind(location)
+        .execute(db)
+        .await
+        .map(|r| r.rows_affected() == 1)
+}
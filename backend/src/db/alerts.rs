@@ -0,0 +1,182 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor};
+
+/// A row of `monitoring.alerts` (lifecycle columns from add_alert_lifecycle.sql).
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Alert {
+    pub id: i32,
+    pub time: DateTime<Utc>,
+    pub host: String,
+    pub service: String,
+    pub severity: String,
+    pub message: Option<String>,
+    pub acknowledged: bool,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    pub acknowledged_by: Option<String>,
+    pub acknowledge_comment: Option<String>,
+    pub resolved: bool,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolved_by: Option<String>,
+    pub resolve_comment: Option<String>,
+}
+
+const COLUMNS: &str = "
+    id, time, host, service, severity, message,
+    COALESCE(acknowledged, false) AS acknowledged,
+    acknowledged_at, acknowledged_by, acknowledge_comment,
+    COALESCE(resolved, false) AS resolved,
+    resolved_at, resolved_by, resolve_comment";
+
+/// Which alerts to list.
+#[derive(Debug, Clone, Default)]
+pub struct AlertFilter<'a> {
+    pub resolved: bool,
+    pub severity: Option<&'a str>,
+    pub host: Option<&'a str>,
+}
+
+/// Result of [`open`]: the open alert and whether this call created it.
+#[derive(Debug, FromRow)]
+pub struct Opened {
+    #[sqlx(flatten)]
+    pub alert: Alert,
+    pub created: bool,
+}
+
+/// Opens an alert for `host`/`service`, or updates the severity and message
+/// of the one already open.
+pub async fn open(
+    db: impl PgExecutor<'_>,
+    host: &str,
+    service: &str,
+    severity: &str,
+    message: &str,
+) -> sqlx::Result<Opened> {
+    sqlx::query_as(&format!(
+        "INSERT INTO monitoring.alerts (host, service, severity, message, acknowledged, resolved)
+         VALUES ($1, $2, $3, $4, FALSE, FALSE)
+         ON CONFLICT (host, service) WHERE NOT resolved DO UPDATE SET
+             severity = EXCLUDED.severity,
+             message = EXCLUDED.message
+         RETURNING {COLUMNS}, (xmax = 0) AS created"
+    ))
+    .bind(host)
+    .bind(service)
+    .bind(severity)
+    .bind(message)
+    .fetch_one(db)
+    .await
+}
+
+/// Resolves the open alert for `host`/`service`, if there is one.
+pub async fn resolve_open(
+    db: impl PgExecutor<'_>,
+    host: &str,
+    service: &str,
+    actor: &str,
+    comment: &str,
+) -> sqlx::Result<Option<Alert>> {
+    sqlx::query_as(&format!(
+        "UPDATE monitoring.alerts
+         SET resolved = TRUE, resolved_at = NOW(), resolved_by = $3, resolve_comment = $4
+         WHERE host = $1 AND service = $2 AND NOT resolved
+         RETURNING {COLUMNS}"
+    ))
+    .bind(host)
+    .bind(service)
+    .bind(actor)
+    .bind(comment)
+    .fetch_optional(db)
+    .await
+}
+
+/// The open alert for `host`/`service`, if there is one.
+pub async fn find_open(
+    db: impl PgExecutor<'_>,
+    host: &str,
+    service: &str,
+) -> sqlx::Result<Option<Alert>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.alerts
+         WHERE host = $1 AND service = $2 AND NOT resolved"
+    ))
+    .bind(host)
+    .bind(service)
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn get(db: impl PgExecutor<'_>, id: i32) -> sqlx::Result<Option<Alert>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.alerts WHERE id = $1"
+    ))
+    .bind(id)
+    .fetch_optional(db)
+    .await
+}
+
+/// Acknowledges an unresolved alert. `None` if it does not exist or is
+/// already resolved.
+pub async fn acknowledge(
+    db: impl PgExecutor<'_>,
+    id: i32,
+    actor: &str,
+    comment: Option<&str>,
+) -> sqlx::Result<Option<Alert>> {
+    sqlx::query_as(&format!(
+        "UPDATE monitoring.alerts
+         SET acknowledged = TRUE, acknowledged_at = NOW(),
+             acknowledged_by = $2, acknowledge_comment = $3
+         WHERE id = $1 AND NOT resolved
+         RETURNING {COLUMNS}"
+    ))
+    .bind(id)
+    .bind(actor)
+    .bind(comment)
+    .fetch_optional(db)
+    .await
+}
+
+/// Resolves an alert. `None` if it does not exist or is already resolved.
+pub async fn resolve(
+    db: impl PgExecutor<'_>,
+    id: i32,
+    actor: &str,
+    comment: Option<&str>,
+) -> sqlx::Result<Option<Alert>> {
+    sqlx::query_as(&format!(
+        "UPDATE monitoring.alerts
+         SET resolved = TRUE, resolved_at = NOW(), resolved_by = $2, resolve_comment = $3
+         WHERE id = $1 AND NOT resolved
+         RETURNING {COLUMNS}"
+    ))
+    .bind(id)
+    .bind(actor)
+    .bind(comment)
+    .fetch_optional(db)
+    .await
+}
+
+/// Alerts matching `filter`, newest first.
+pub async fn list(
+    db: impl PgExecutor<'_>,
+    filter: &AlertFilter<'_>,
+    limit: i64,
+) -> sqlx::Result<Vec<Alert>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS}
+         FROM monitoring.alerts
+         WHERE COALESCE(resolved, false) = $1
+           AND ($2::text IS NULL OR severity = $2)
+           AND ($3::text IS NULL OR host = $3)
+         ORDER BY time DESC
+         LIMIT $4"
+    ))
+    .bind(filter.resolved)
+    .bind(filter.severity)
+    .bind(filter.host)
+    .bind(limit)
+    .fetch_all(db)
+    .await
+}
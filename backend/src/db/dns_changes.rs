@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor};
+
+/// A row of `monitoring.dns_changes` (see add_dns_records.sql).
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DnsChange {
+    pub id: i32,
+    pub time: DateTime<Utc>,
+    pub subdomain: String,
+    pub record_type: String,
+    pub previous: Vec<String>,
+    pub current: Vec<String>,
+    pub previous_asns: Vec<i32>,
+    pub current_asns: Vec<i32>,
+}
+
+const COLUMNS: &str =
+    "id, time, subdomain, record_type, previous, current, previous_asns, current_asns";
+
+#[derive(Debug, Clone)]
+pub struct NewDnsChange<'a> {
+    pub subdomain: &'a str,
+    pub record_type: &'a str,
+    pub previous: &'a [String],
+    pub current: &'a [String],
+    pub previous_asns: &'a [i32],
+    pub current_asns: &'a [i32],
+}
+
+pub async fn insert(db: impl PgExecutor<'_>, change: &NewDnsChange<'_>) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO monitoring.dns_changes
+         (subdomain, record_type, previous, current, previous_asns, current_asns)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(change.subdomain)
+    .bind(change.record_type)
+    .bind(change.previous)
+    .bind(change.current)
+    .bind(change.previous_asns)
+    .bind(change.current_asns)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// The latest `limit` changes to the records of `subdomain`, newest first.
+pub async fn list_for(
+    db: impl PgExecutor<'_>,
+    subdomain: &str,
+    limit: i64,
+) -> sqlx::Result<Vec<DnsChange>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.dns_changes
+         WHERE subdomain = $1
+         ORDER BY time DESC, id DESC
+         LIMIT $2"
+    ))
+    .bind(subdomain)
+    .bind(limit)
+    .fetch_all(db)
+    .await
+}
@@ -0,0 +1,105 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor};
+
+/// A row of `monitoring.dns_records` (see add_dns_records.sql): the latest
+/// answer for one record type of a subdomain.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DnsRecordSet {
+    pub subdomain: String,
+    pub record_type: String,
+    pub checked_at: DateTime<Utc>,
+    /// Resolver of the last answer; `None` if there has been none.
+    pub resolver: Option<String>,
+    pub resolution_ms: Option<f64>,
+    /// Why the last lookup failed; the rest is from the last answer.
+    pub error: Option<String>,
+    pub records: Vec<String>,
+    pub asns: Vec<i32>,
+    pub changed_at: Option<DateTime<Utc>>,
+}
+
+const COLUMNS: &str = "
+    subdomain, record_type, checked_at, resolver, resolution_ms, error,
+    records, asns, changed_at";
+
+/// A successful lookup.
+#[derive(Debug, Clone)]
+pub struct NewDnsRecordSet<'a> {
+    pub subdomain: &'a str,
+    pub record_type: &'a str,
+    pub resolver: &'a str,
+    pub resolution_ms: f64,
+    pub records: &'a [String],
+    pub asns: &'a [i32],
+    /// Whether `records` differ from the previous answer.
+    pub changed: bool,
+}
+
+pub async fn record(db: impl PgExecutor<'_>, set: &NewDnsRecordSet<'_>) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO monitoring.dns_records
+         (subdomain, record_type, checked_at, resolver, resolution_ms, error, records, asns, changed_at)
+         VALUES ($1, $2, NOW(), $3, $4, NULL, $5, $6, CASE WHEN $7 THEN NOW() END)
+         ON CONFLICT (subdomain, record_type) DO UPDATE SET
+             checked_at = EXCLUDED.checked_at,
+             resolver = EXCLUDED.resolver,
+             resolution_ms = EXCLUDED.resolution_ms,
+             error = NULL,
+             records = EXCLUDED.records,
+             asns = EXCLUDED.asns,
+             changed_at = COALESCE(EXCLUDED.changed_at, dns_records.changed_at)",
+    )
+    .bind(set.subdomain)
+    .bind(set.record_type)
+    .bind(set.resolver)
+    .bind(set.resolution_ms)
+    .bind(set.records)
+    .bind(set.asns)
+    .bind(set.changed)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// Notes a lookup that got no answer, keeping the last one.
+pub async fn record_failure(
+    db: impl PgExecutor<'_>,
+    subdomain: &str,
+    record_type: &str,
+    error: &str,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO monitoring.dns_records (subdomain, record_type, checked_at, error)
+         VALUES ($1, $2, NOW(), $3)
+         ON CONFLICT (subdomain, record_type) DO UPDATE SET
+             checked_at = EXCLUDED.checked_at,
+             error = EXCLUDED.error",
+    )
+    .bind(subdomain)
+    .bind(record_type)
+    .bind(error)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// The record sets of `subdomain`, by type.
+pub async fn list_for(db: impl PgExecutor<'_>, subdomain: &str) -> sqlx::Result<Vec<DnsRecordSet>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.dns_records
+         WHERE subdomain = $1
+         ORDER BY record_type"
+    ))
+    .bind(subdomain)
+    .fetch_all(db)
+    .await
+}
+
+pub async fn delete_for_subdomain(db: impl PgExecutor<'_>, subdomain: &str) -> sqlx::Result<u64> {
+    sqlx::query("DELETE FROM monitoring.dns_records WHERE subdomain = $1")
+        .bind(subdomain)
+        .execute(db)
+        .await
+        .map(|r| r.rows_affected())
+}
@@ -0,0 +1,81 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor};
+
+/// A row of `monitoring.agent_enrollment_codes` (created by
+/// add_agent_enrollment.sql).
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct EnrollmentCode {
+    pub id: i32,
+    pub location: String,
+    #[serde(skip_serializing)]
+    pub code_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub created_by: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub key_id: Option<String>,
+}
+
+const COLUMNS: &str =
+    "id, location, code_hash, created_at, created_by, expires_at, used_at, key_id";
+
+pub async fn insert(
+    db: impl PgExecutor<'_>,
+    location: &str,
+    code_hash: &str,
+    created_by: &str,
+    expires_at: DateTime<Utc>,
+) -> sqlx::Result<EnrollmentCode> {
+    sqlx::query_as(&format!(
+        "INSERT INTO monitoring.agent_enrollment_codes (location, code_hash, created_by, expires_at)
+         VALUES ($1, $2, $3, $4)
+         RETURNING {COLUMNS}"
+    ))
+    .bind(location)
+    .bind(code_hash)
+    .bind(created_by)
+    .bind(expires_at)
+    .fetch_one(db)
+    .await
+}
+
+/// Looks a code up by hash and locks it, so two agents cannot redeem it at
+/// once.
+pub async fn get_for_update(
+    db: impl PgExecutor<'_>,
+    code_hash: &str,
+) -> sqlx::Result<Option<EnrollmentCode>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.agent_enrollment_codes
+         WHERE code_hash = $1
+         FOR UPDATE"
+    ))
+    .bind(code_hash)
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn mark_used(db: impl PgExecutor<'_>, id: i32, key_id: &str) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE monitoring.agent_enrollment_codes SET used_at = NOW(), key_id = $2
+         WHERE id = $1",
+    )
+    .bind(id)
+    .bind(key_id)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// Codes minted for `location`, newest first.
+pub async fn list(db: impl PgExecutor<'_>, location: &str) -> sqlx::Result<Vec<EnrollmentCode>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.agent_enrollment_codes
+         WHERE location = $1
+         ORDER BY created_at DESC, id DESC"
+    ))
+    .bind(location)
+    .fetch_all(db)
+    .await
+}
@@ -0,0 +1,137 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgExecutor};
+
+/// A row of the `monitoring.metrics` hypertable.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Metric {
+    /// Assigned on insert (see add_metric_ids.sql); `None` until stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub time: DateTime<Utc>,
+    pub host: String,
+    pub service: String,
+    pub metric_name: String,
+    pub value: Option<f64>,
+    pub status: Option<String>,
+    pub metadata: Option<Value>,
+}
+
+impl Metric {
+    /// Position of a stored row in the listing order, used as a page
+    /// cursor.
+    pub fn key(&self) -> Option<MetricKey> {
+        Some(MetricKey {
+            time: self.time,
+            id: self.id?,
+        })
+    }
+}
+
+/// Sort key of a metric row. Listings are ordered by this pair, newest
+/// first, so a page can resume strictly after the last row it returned.
+/// The id tells apart metrics sharing a timestamp, as those of a batch do.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricKey {
+    pub time: DateTime<Utc>,
+    pub id: i64,
+}
+
+/// Which metrics to list. `from` is inclusive, `to` exclusive.
+#[derive(Debug, Clone, Default)]
+pub struct MetricFilter<'a> {
+    pub host: Option<&'a str>,
+    pub service: Option<&'a str>,
+    pub metric_name: Option<&'a str>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+pub async fn insert(db: impl PgExecutor<'_>, metric: &Metric) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO monitoring.metrics (time, host, service, metric_name, value, status, metadata)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(metric.time)
+    .bind(&metric.host)
+    .bind(&metric.service)
+    .bind(&metric.metric_name)
+    .bind(metric.value)
+    .bind(&metric.status)
+    .bind(&metric.metadata)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// Writes a batch of metrics in a single statement.
+pub async fn insert_many(db: impl PgExecutor<'_>, metrics: &[Metric]) -> sqlx::Result<()> {
+    if metrics.is_empty() {
+        return Ok(());
+    }
+    let mut time = Vec::with_capacity(metrics.len());
+    let mut host = Vec::with_capacity(metrics.len());
+    let mut service = Vec::with_capacity(metrics.len());
+    let mut metric_name = Vec::with_capacity(metrics.len());
+    let mut value = Vec::with_capacity(metrics.len());
+    let mut status = Vec::with_capacity(metrics.len());
+    let mut metadata = Vec::with_capacity(metrics.len());
+    for metric in metrics {
+        time.push(metric.time);
+        host.push(metric.host.as_str());
+        service.push(metric.service.as_str());
+        metric_name.push(metric.metric_name.as_str());
+        value.push(metric.value);
+        status.push(metric.status.as_deref());
+        metadata.push(metric.metadata.clone());
+    }
+
+    sqlx::query(
+        "INSERT INTO monitoring.metrics (time, host, service, metric_name, value, status, metadata)
+         SELECT * FROM UNNEST($1::timestamptz[], $2::text[], $3::text[], $4::text[],
+                              $5::float8[], $6::text[], $7::jsonb[])",
+    )
+    .bind(time)
+    .bind(host)
+    .bind(service)
+    .bind(metric_name)
+    .bind(value)
+    .bind(status)
+    .bind(metadata)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// One page of metrics matching `filter`, newest first, starting strictly
+/// after `after` when given.
+pub async fn list(
+    db: impl PgExecutor<'_>,
+    filter: &MetricFilter<'_>,
+    after: Option<&MetricKey>,
+    limit: i64,
+) -> sqlx::Result<Vec<Metric>> {
+    sqlx::query_as(
+        "SELECT id, time, host, service, metric_name, value, status, metadata
+         FROM monitoring.metrics
+         WHERE ($1::timestamptz IS NULL OR time >= $1)
+           AND ($2::timestamptz IS NULL OR time < $2)
+           AND ($3::text IS NULL OR host = $3)
+           AND ($4::text IS NULL OR service = $4)
+           AND ($5::text IS NULL OR metric_name = $5)
+           AND ($6::timestamptz IS NULL OR (time, id) < ($6, $7::int8))
+         ORDER BY time DESC, id DESC
+         LIMIT $8",
+    )
+    .bind(filter.from)
+    .bind(filter.to)
+    .bind(filter.host)
+    .bind(filter.service)
+    .bind(filter.metric_name)
+    .bind(after.map(|k| k.time))
+    .bind(after.map(|k| k.id))
+    .bind(limit)
+    .fetch_all(db)
+    .await
+}
@@ -0,0 +1,143 @@
+//! Postgres access for the `monitoring` schema. Each submodule owns one table:
+//! its row struct and the queries the server runs against it.
+
+use std::time::Duration;
+
+use sqlx::postgres::{PgPool, PgPoolOptions};
+
+pub mod agent_audit;
+pub mod agent_heartbeats;
+pub mod agent_keys;
+pub mod agent_reports;
+pub mod agents;
+pub mod alerts;
+pub mod dns_changes;
+pub mod dns_records;
+pub mod enrollment_codes;
+pub mod metrics;
+pub mod notification_deliveries;
+pub mod report_nonces;
+pub mod subdomain_sources;
+pub mod subdomains;
+pub mod tls_certificates;
+pub mod uptime_checks;
+
+/// Pool sizing, matching the asyncpg pools the Python services used.
+#[derive(Debug, Clone)]
+pub struct PoolSettings {
+    pub min_connections: u32,
+    pub max_connections: u32,
+    pub acquire_timeout: Duration,
+    /// Connection attempts made at startup before giving up and serving
+    /// with a lazily connecting pool.
+    pub connect_attempts: u32,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        PoolSettings {
+            min_connections: 5,
+            max_connections: 20,
+            acquire_timeout: Duration::from_secs(5),
+            connect_attempts: 5,
+        }
+    }
+}
+
+impl PoolSettings {
+    fn options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .min_connections(self.min_connections)
+            .max_connections(self.max_connections)
+            .acquire_timeout(self.acquire_timeout)
+    }
+}
+
+/// Opens the pool, retrying with exponential backoff (1s, 2s, 4s, ...).
+///
+/// If the database is still unreachable after the last attempt the server
+/// starts anyway with a lazy pool, so `/api/health` can report "degraded"
+/// and the pool recovers on its own once the database comes back. Only a
+/// malformed `DATABASE_URL` is an error.
+pub async fn connect(database_url: &str, settings: &PoolSettings) -> Result<PgPool, sqlx::Error> {
+    for attempt in 1..=settings.connect_attempts {
+        match settings.options().connect(database_url).await {
+            Ok(pool) => return Ok(pool),
+            Err(sqlx::Error::Configuration(e)) => return Err(sqlx::Error::Configuration(e)),
+            Err(e) if attempt < settings.connect_attempts => {
+                let delay = retry_delay(attempt);
+                eprintln!(
+                    "Database connection attempt {attempt} failed ({e}), retrying in {}s...",
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => {
+                eprintln!(
+                    "Database unreachable after {attempt} attempts ({e}), continuing degraded"
+                );
+            }
+        }
+    }
+    settings.options().connect_lazy(database_url)
+}
+
+/// The wait after failed connection attempt `attempt` (from 1).
+fn retry_delay(attempt: u32) -> Duration {
+    Duration::from_secs(1 << (attempt - 1).min(16))
+}
+
+/// Round-trips a trivial query to prove the pool can reach the database.
+pub async fn ping(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query_scalar::<_, i32>("SELECT 1")
+        .fetch_one(pool)
+        .await
+        .map(|_| ())
+}
+
+/// A pool on `TEST_DATABASE_URL`, a database with the schema of
+/// `database/` applied, for tests that need Postgres. They are skipped
+/// when it is not set.
+#[cfg(test)]
+pub async fn test_pool() -> Option<PgPool> {
+    let url = std::env::var("TEST_DATABASE_URL").ok()?;
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&url)
+        .await
+        .unwrap_or_else(|e| panic!("TEST_DATABASE_URL: {e}"));
+    Some(pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially() {
+        let delays: Vec<u64> = (1..=5).map(|a| retry_delay(a).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16]);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_a_lazy_pool() {
+        let settings = PoolSettings {
+            min_connections: 0,
+            acquire_timeout: Duration::from_millis(200),
+            connect_attempts: 1,
+            ..PoolSettings::default()
+        };
+        // Nothing listens on port 1.
+        let pool = connect("postgres://monitor@127.0.0.1:1/monitoring", &settings)
+            .await
+            .unwrap();
+        assert!(ping(&pool).await.is_err());
+
+        let malformed = connect(
+            "postgres://monitor@127.0.0.1/monitoring?sslmode=maybe",
+            &settings,
+        )
+        .await;
+        assert!(matches!(malformed, Err(sqlx::Error::Configuration(_))));
+    }
+}
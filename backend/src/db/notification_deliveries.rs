@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor};
+
+/// A row of `monitoring.notification_deliveries`: one attempt to notify a
+/// channel about an alert.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Delivery {
+    pub id: i64,
+    pub time: DateTime<Utc>,
+    pub alert_id: i32,
+    pub channel: String,
+    pub kind: String,
+    pub event: String,
+    pub attempt: i32,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+pub struct NewDelivery<'a> {
+    pub alert_id: i32,
+    pub channel: &'a str,
+    pub kind: &'a str,
+    pub event: &'a str,
+    pub attempt: i32,
+    /// `None` when the attempt succeeded.
+    pub error: Option<&'a str>,
+}
+
+pub async fn insert(db: impl PgExecutor<'_>, delivery: &NewDelivery<'_>) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO monitoring.notification_deliveries
+         (alert_id, channel, kind, event, attempt, success, error)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(delivery.alert_id)
+    .bind(delivery.channel)
+    .bind(delivery.kind)
+    .bind(delivery.event)
+    .bind(delivery.attempt)
+    .bind(delivery.error.is_none())
+    .bind(delivery.error)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// Attempts made for one alert, oldest first.
+pub async fn list_for_alert(db: impl PgExecutor<'_>, alert_id: i32) -> sqlx::Result<Vec<Delivery>> {
+    sqlx::query_as(
+        "SELECT id, time, alert_id, channel, kind, event, attempt, success, error
+         FROM monitoring.notification_deliveries
+         WHERE alert_id = $1
+         ORDER BY time, id",
+    )
+    .bind(alert_id)
+    .fetch_all(db)
+    .await
+}
@@ -0,0 +1,26 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
+/// Records a report nonce. `false` if `location` already used it.
+pub async fn claim(db: impl PgExecutor<'_>, location: &str, nonce: &str) -> sqlx::Result<bool> {
+    sqlx::query(
+        "INSERT INTO monitoring.agent_report_nonces (location, nonce)
+         VALUES ($1, $2)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(location)
+    .bind(nonce)
+    .execute(db)
+    .await
+    .map(|r| r.rows_affected() == 1)
+}
+
+/// Forgets nonces received before `before`; reports that old are rejected
+/// by their timestamp anyway.
+pub async fn purge(db: impl PgExecutor<'_>, before: DateTime<Utc>) -> sqlx::Result<u64> {
+    sqlx::query("DELETE FROM monitoring.agent_report_nonces WHERE received_at < $1")
+        .bind(before)
+        .execute(db)
+        .await
+        .map(|r| r.rows_affected())
+}
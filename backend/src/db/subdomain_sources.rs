@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor};
+
+/// A row of `monitoring.subdomain_sources` (see add_subdomain_sources.sql):
+/// one discovery source that has found a subdomain.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SubdomainSource {
+    pub subdomain: String,
+    pub source: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub detail: Option<String>,
+}
+
+const COLUMNS: &str = "subdomain, source, first_seen, last_seen, detail";
+
+/// Notes that `source` found `subdomain` just now. `detail` replaces the
+/// previous one only when given.
+pub async fn record(
+    db: impl PgExecutor<'_>,
+    subdomain: &str,
+    source: &str,
+    detail: Option<&str>,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO monitoring.subdomain_sources (subdomain, source, detail)
+         VALUES ($1, $2, $3)
+         ON CONFLICT (subdomain, source) DO UPDATE SET
+             last_seen = NOW(),
+             detail = COALESCE(EXCLUDED.detail, subdomain_sources.detail)",
+    )
+    .bind(subdomain)
+    .bind(source)
+    .bind(detail)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// The sources that found `subdomain`, earliest first.
+pub async fn list(db: impl PgExecutor<'_>, subdomain: &str) -> sqlx::Result<Vec<SubdomainSource>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.subdomain_sources
+         WHERE subdomain = $1
+         ORDER BY first_seen, source"
+    ))
+    .bind(subdomain)
+    .fetch_all(db)
+    .await
+}
+
+pub async fn delete_for_subdomain(db: impl PgExecutor<'_>, subdomain: &str) -> sqlx::Result<u64> {
+    sqlx::query("DELETE FROM monitoring.subdomain_sources WHERE subdomain = $1")
+        .bind(subdomain)
+        .execute(db)
+        .await
+        .map(|r| r.rows_affected())
+}
@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::types::Json;
+use sqlx::{FromRow, PgExecutor};
+
+use crate::checker::assertions::Assertion;
+
+/// A row of `monitoring.subdomains`, with the nullable tracking columns from
+/// add_status_tracking.sql and add_check_path.sql resolved to their defaults.
+/// The check settings of add_check_settings.sql, add_check_kinds.sql and
+/// add_check_assertions.sql stay `None` where the checker's defaults apply.
+/// `sources` names the discovery sources that found it, earliest first (see
+/// `db::subdomain_sources`).
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Subdomain {
+    pub id: i32,
+    pub domain: String,
+    pub subdomain: String,
+    pub discovered_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub active: bool,
+    pub sources: Vec<String>,
+    pub platform: Option<String>,
+    pub last_platform_check: Option<DateTime<Utc>>,
+    pub check_path: String,
+    pub current_status: String,
+    pub consecutive_up_count: i32,
+    pub consecutive_down_count: i32,
+    pub last_status_change: Option<DateTime<Utc>>,
+    pub is_flapping: bool,
+    pub check_method: String,
+    pub expected_status: Option<Vec<i32>>,
+    pub check_timeout_secs: Option<i32>,
+    pub check_interval_secs: Option<i32>,
+    pub tags: Vec<String>,
+    pub check_kind: String,
+    pub check_send: Option<String>,
+    pub check_expect: Option<String>,
+    pub check_assertions: Json<Vec<Assertion>>,
+}
+
+const COLUMNS: &str = "
+    id, domain, subdomain, discovered_at, last_seen,
+    COALESCE(active, false) AS active,
+    ARRAY(
+        SELECT s.source FROM monitoring.subdomain_sources s
+        WHERE s.subdomain = subdomains.subdomain
+        ORDER BY s.first_seen, s.source
+    ) AS sources,
+    platform, last_platform_check,
+    COALESCE(check_path, '/') AS check_path,
+    COALESCE(current_status, 'UNKNOWN') AS current_status,
+    COALESCE(consecutive_up_count, 0) AS consecutive_up_count,
+    COALESCE(consecutive_down_count, 0) AS consecutive_down_count,
+    last_status_change,
+    COALESCE(is_flapping, false) AS is_flapping,
+    check_method, expected_status, check_timeout_secs, check_interval_secs, tags,
+    check_kind, check_send, check_expect, check_assertions";
+
+/// What the uptime checker needs to probe a subdomain.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct CheckTarget {
+    pub subdomain: String,
+    /// `http`, `tcp` or `udp`.
+    pub check_kind: String,
+    pub check_path: String,
+    pub check_method: String,
+    pub expected_status: Option<Vec<i32>>,
+    pub check_timeout_secs: Option<i32>,
+    pub check_interval_secs: Option<i32>,
+    /// Escaped as stored; see add_check_kinds.sql.
+    pub check_send: Option<String>,
+    pub check_expect: Option<String>,
+    pub check_assertions: Json<Vec<Assertion>>,
+}
+
+/// How a subdomain is checked, as set through the inventory API.
+#[derive(Debug)]
+pub struct CheckSettings<'a> {
+    pub check_kind: &'a str,
+    pub check_path: &'a str,
+    pub check_method: &'a str,
+    /// `None` counts anything below 500 as up.
+    pub expected_status: Option<&'a [i32]>,
+    pub timeout_secs: Option<i32>,
+    pub interval_secs: Option<i32>,
+    pub tags: &'a [String],
+    pub check_send: Option<&'a str>,
+    pub check_expect: Option<&'a str>,
+    pub check_assertions: &'a [Assertion],
+}
+
+pub async fn get(db: impl PgExecutor<'_>, subdomain: &str) -> sqlx::Result<Option<Subdomain>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.subdomains WHERE subdomain = $1"
+    ))
+    .bind(subdomain)
+    .fetch_optional(db)
+    .await
+}
+
+/// Filters of [`list_filtered`]; `None` matches anything.
+#[derive(Debug, Default)]
+pub struct SubdomainFilter<'a> {
+    /// A global status such as `DOWN`.
+    pub status: Option<&'a str>,
+    /// The whole platform or one of its layers, e.g. `nginx` or
+    /// `Nginx 1.24` for `Cloudflare → Nginx 1.24 → Next.js`, matched
+    /// case-insensitively with or without the layer's version.
+    pub platform: Option<&'a str>,
+    /// Subdomains found by this discovery source.
+    pub source: Option<&'a str>,
+    pub active: Option<bool>,
+    /// Subdomains carrying this tag.
+    pub tag: Option<&'a str>,
+    /// `http`, `tcp` or `udp`.
+    pub check_kind: Option<&'a str>,
+}
+
+const FILTER: &str = "
+    ($1::text IS NULL OR COALESCE(current_status, 'UNKNOWN') = $1)
+    AND ($2::text IS NULL OR LOWER(platform) = LOWER(TRIM($2)) OR EXISTS (
+        SELECT 1 FROM UNNEST(STRING_TO_ARRAY(platform, ' → ')) AS layer
+        WHERE LOWER(TRIM($2)) IN (
+            LOWER(layer),
+            LOWER(REGEXP_REPLACE(layer, ' [0-9]+([.][0-9]+)*$', ''))
+        )
+    ))
+    AND ($3::text IS NULL OR EXISTS (
+        SELECT 1 FROM monitoring.subdomain_sources s
+        WHERE s.subdomain = subdomains.subdomain AND s.source = $3
+    ))
+    AND ($4::boolean IS NULL OR COALESCE(active, false) = $4)
+    AND ($5::text IS NULL OR $5 = ANY(tags))
+    AND ($6::text IS NULL OR check_kind = $6)";
+
+/// One page of the subdomains matching `filter`, in the order of [`list`].
+pub async fn list_filtered(
+    db: impl PgExecutor<'_>,
+    filter: &SubdomainFilter<'_>,
+    limit: i64,
+    offset: i64,
+) -> sqlx::Result<Vec<Subdomain>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.subdomains
+         WHERE {FILTER}
+         ORDER BY active DESC, last_seen DESC, id
+         LIMIT $7 OFFSET $8"
+    ))
+    .bind(filter.status)
+    .bind(filter.platform)
+    .bind(filter.source)
+    .bind(filter.active)
+    .bind(filter.tag)
+    .bind(filter.check_kind)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(db)
+    .await
+}
+
+pub async fn count_filtered(
+    db: impl PgExecutor<'_>,
+    filter: &SubdomainFilter<'_>,
+) -> sqlx::Result<i64> {
+    sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM monitoring.subdomains WHERE {FILTER}"
+    ))
+    .bind(filter.status)
+    .bind(filter.platform)
+    .bind(filter.source)
+    .bind(filter.active)
+    .bind(filter.tag)
+    .bind(filter.check_kind)
+    .fetch_one(db)
+    .await
+}
+
+/// Every subdomain, active ones first, most recently seen first.
+pub async fn list(db: impl PgExecutor<'_>) -> sqlx::Result<Vec<Subdomain>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.subdomains ORDER BY active DESC, last_seen DESC"
+    ))
+    .fetch_all(db)
+    .await
+}
+
+pub async fn list_check_targets(db: impl PgExecutor<'_>) -> sqlx::Result<Vec<CheckTarget>> {
+    sqlx::query_as(
+        "SELECT subdomain, check_kind, COALESCE(check_path, '/') AS check_path, check_method,
+                expected_status, check_timeout_secs, check_interval_secs,
+                check_send, check_expect, check_assertions
+         FROM monitoring.subdomains
+         WHERE active = true
+         ORDER BY subdomain",
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Adds a subdomain with the given settings, active and due for its first
+/// check. `None` if it is already in the inventory. The caller records its
+/// provenance.
+pub async fn insert(
+    db: impl PgExecutor<'_>,
+    domain: &str,
+    subdomain: &str,
+    settings: &CheckSettings<'_>,
+) -> sqlx::Result<Option<Subdomain>> {
+    sqlx::query_as(&format!(
+        "INSERT INTO monitoring.subdomains
+             (domain, subdomain, discovery_method, check_path, check_method,
+              expected_status, check_timeout_secs, check_interval_secs, tags,
+              check_kind, check_send, check_expect, check_assertions)
+         VALUES ($1, $2, 'Manual', $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+         ON CONFLICT (subdomain) DO NOTHING
+         RETURNING {COLUMNS}"
+    ))
+    .bind(domain)
+    .bind(subdomain)
+    .bind(settings.check_path)
+    .bind(settings.check_method)
+    .bind(settings.expected_status)
+    .bind(settings.timeout_secs)
+    .bind(settings.interval_secs)
+    .bind(settings.tags)
+    .bind(settings.check_kind)
+    .bind(settings.check_send)
+    .bind(settings.check_expect)
+    .bind(Json(settings.check_assertions))
+    .fetch_optional(db)
+    .await
+}
+
+/// Replaces the check settings of `subdomain`, which makes its next body
+/// hash a new baseline; `None` if it is unknown.
+pub async fn update_settings(
+    db: impl PgExecutor<'_>,
+    subdomain: &str,
+    settings: &CheckSettings<'_>,
+) -> sqlx::Result<Option<Subdomain>> {
+    sqlx::query_as(&format!(
+        "UPDATE monitoring.subdomains
+         SET check_path = $2, check_method = $3, expected_status = $4,
+             check_timeout_secs = $5, check_interval_secs = $6, tags = $7,
+             check_kind = $8, check_send = $9, check_expect = $10,
+             check_assertions = $11, body_hash = NULL
+         WHERE subdomain = $1
+         RETURNING {COLUMNS}"
+    ))
+    .bind(subdomain)
+    .bind(settings.check_path)
+    .bind(settings.check_method)
+    .bind(settings.expected_status)
+    .bind(settings.timeout_secs)
+    .bind(settings.interval_secs)
+    .bind(settings.tags)
+    .bind(settings.check_kind)
+    .bind(settings.check_send)
+    .bind(settings.check_expect)
+    .bind(Json(settings.check_assertions))
+    .fetch_optional(db)
+    .await
+}
+
+/// Stores the latest body hash of `subdomain` and returns the one before.
+pub async fn replace_body_hash(
+    db: impl PgExecutor<'_>,
+    subdomain: &str,
+    hash: &str,
+) -> sqlx::Result<Option<String>> {
+    // The joined row is read before the update, so it has the old hash.
+    sqlx::query_scalar(
+        "UPDATE monitoring.subdomains s SET body_hash = $2
+         FROM monitoring.subdomains old
+         WHERE s.subdomain = $1 AND old.subdomain = $1
+         RETURNING old.body_hash",
+    )
+    .bind(subdomain)
+    .bind(hash)
+    .fetch_optional(db)
+    .await
+    .map(Option::flatten)
+}
+
+pub async fn set_active(
+    db: impl PgExecutor<'_>,
+    subdomain: &str,
+    active: bool,
+) -> sqlx::Result<Option<Subdomain>> {
+    sqlx::query_as(&format!(
+        "UPDATE monitoring.subdomains SET active = $2 WHERE subdomain = $1 RETURNING {COLUMNS}"
+    ))
+    .bind(subdomain)
+    .bind(active)
+    .fetch_optional(db)
+    .await
+}
+
+/// Removes `subdomain` from the inventory; its check history is kept.
+/// `false` if it was not there.
+pub async fn delete(db: impl PgExecutor<'_>, subdomain: &str) -> sqlx::Result<bool> {
+    sqlx::query("DELETE FROM monitoring.subdomains WHERE subdomain = $1")
+        .bind(subdomain)
+        .execute(db)
+        .await
+        .map(|r| r.rows_affected() == 1)
+}
+
+pub async fn count_active(db: impl PgExecutor<'_>) -> sqlx::Result<i64> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM monitoring.subdomains WHERE active = true")
+        .fetch_one(db)
+        .await
+}
+
+/// Adds a discovered subdomain with default settings, or marks a known one
+/// as seen; deactivated subdomains stay inactive. `true` if it was new. The
+/// caller records its sources; the legacy `discovery_method` keeps its
+/// `DNS Enumeration` default, which the views of full_migration.sql count.
+pub async fn upsert_discovered(
+    db: impl PgExecutor<'_>,
+    domain: &str,
+    subdomain: &str,
+) -> sqlx::Result<bool> {
+    sqlx::query_scalar(
+        "INSERT INTO monitoring.subdomains (domain, subdomain)
+         VALUES ($1, $2)
+         ON CONFLICT (subdomain) DO UPDATE SET last_seen = NOW()
+         RETURNING xmax = 0",
+    )
+    .bind(domain)
+    .bind(subdomain)
+    .fetch_one(db)
+    .await
+}
+
+pub async fn update_platform(
+    db: impl PgExecutor<'_>,
+    subdomain: &str,
+    platform: &str,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE monitoring.subdomains
+         SET platform = $2, last_platform_check = NOW()
+         WHERE subdomain = $1",
+    )
+    .bind(subdomain)
+    .bind(platform)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// The `check_kind` of each of `names` present in the inventory.
+pub async fn check_kinds(
+    db: impl PgExecutor<'_>,
+    names: &[&str],
+) -> sqlx::Result<HashMap<String, String>> {
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        "SELECT subdomain, check_kind FROM monitoring.subdomains WHERE subdomain = ANY($1)",
+    )
+    .bind(names)
+    .fetch_all(db)
+    .await?;
+    Ok(rows.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    #[tokio::test]
+    async fn discovered_subdomains_keep_the_legacy_discovery_method() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let subdomain = format!("legacy-{}.bettergov.test", std::process::id());
+        assert!(upsert_discovered(&pool, "bettergov.test", &subdomain)
+            .await
+            .unwrap());
+        assert!(!upsert_discovered(&pool, "bettergov.test", &subdomain)
+            .await
+            .unwrap());
+        let method: String = sqlx::query_scalar(
+            "DELETE FROM monitoring.subdomains WHERE subdomain = $1 RETURNING discovery_method",
+        )
+        .bind(&subdomain)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(method, "DNS Enumeration");
+    }
+
+    #[tokio::test]
+    async fn platform_filter_matches_any_layer() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let subdomain = format!("platform-{}.bettergov.test", std::process::id());
+        upsert_discovered(&pool, "bettergov.test", &subdomain)
+            .await
+            .unwrap();
+        update_platform(&pool, &subdomain, "Cloudflare → Nginx 1.24 → Next.js")
+            .await
+            .unwrap();
+
+        let matches = |platform: &'static str| {
+            let pool = pool.clone();
+            let subdomain = subdomain.clone();
+            async move {
+                let filter = SubdomainFilter {
+                    platform: Some(platform),
+                    ..Default::default()
+                };
+                let rows = list_filtered(&pool, &filter, 100_000, 0).await.unwrap();
+                rows.iter().any(|s| s.subdomain == subdomain)
+            }
+        };
+        for platform in [
+            "cloudflare",
+            "nginx",
+            " NGINX ",
+            "Nginx 1.24",
+            "next.js",
+            "Cloudflare → Nginx 1.24 → Next.js",
+        ] {
+            assert!(matches(platform).await, "{platform}");
+        }
+        for platform in ["Nginx 1.25", "ngin", "Next", "Cloudflare → Nginx 1.24"] {
+            assert!(!matches(platform).await, "{platform}");
+        }
+
+        sqlx::query("DELETE FROM monitoring.subdomain_sources WHERE subdomain = $1")
+            .bind(&subdomain)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM monitoring.subdomains WHERE subdomain = $1")
+            .bind(&subdomain)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor};
+
+use crate::x509::Certificate;
+
+/// A row of `monitoring.tls_certificates` (see add_tls_certificates.sql):
+/// the latest TLS check of a subdomain.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct TlsCertificate {
+    pub subdomain: String,
+    pub checked_at: DateTime<Utc>,
+    /// Why the last check failed; the rest is from the last handshake.
+    pub error: Option<String>,
+    pub protocol: Option<String>,
+    pub cipher: Option<String>,
+    pub issuer: Option<String>,
+    pub subject: Option<String>,
+    pub sans: Vec<String>,
+    pub not_before: Option<DateTime<Utc>>,
+    pub not_after: Option<DateTime<Utc>>,
+    /// Whole days until `not_after`; negative once it has passed.
+    pub days_left: Option<i32>,
+    pub key_type: Option<String>,
+    pub chain_valid: Option<bool>,
+    pub chain_error: Option<String>,
+    pub hostname_valid: Option<bool>,
+}
+
+const COLUMNS: &str = "
+    subdomain, checked_at, error, protocol, cipher, issuer, subject, sans,
+    not_before, not_after,
+    FLOOR(EXTRACT(EPOCH FROM not_after - NOW()) / 86400)::int AS days_left,
+    key_type, chain_valid, chain_error, hostname_valid";
+
+/// A completed handshake.
+#[derive(Debug, Clone)]
+pub struct NewTlsCertificate<'a> {
+    pub subdomain: &'a str,
+    pub certificate: &'a Certificate,
+    pub protocol: Option<&'a str>,
+    pub cipher: Option<&'a str>,
+    /// `None` if the chain is valid.
+    pub chain_error: Option<&'a str>,
+    pub hostname_valid: bool,
+}
+
+/// Replaces the latest check of the subdomain with a completed handshake.
+pub async fn record(db: impl PgExecutor<'_>, check: &NewTlsCertificate<'_>) -> sqlx::Result<()> {
+    let cert = check.certificate;
+    sqlx::query(
+        "INSERT INTO monitoring.tls_certificates
+         (subdomain, checked_at, error, protocol, cipher, issuer, subject, sans,
+          not_before, not_after, key_type, chain_valid, chain_error, hostname_valid)
+         VALUES ($1, NOW(), NULL, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+         ON CONFLICT (subdomain) DO UPDATE SET
+             checked_at = EXCLUDED.checked_at,
+             error = NULL,
+             protocol = EXCLUDED.protocol,
+             cipher = EXCLUDED.cipher,
+             issuer = EXCLUDED.issuer,
+             subject = EXCLUDED.subject,
+             sans = EXCLUDED.sans,
+             not_before = EXCLUDED.not_before,
+             not_after = EXCLUDED.not_after,
+             key_type = EXCLUDED.key_type,
+             chain_valid = EXCLUDED.chain_valid,
+             chain_error = EXCLUDED.chain_error,
+             hostname_valid = EXCLUDED.hostname_valid",
+    )
+    .bind(check.subdomain)
+    .bind(check.protocol)
+    .bind(check.cipher)
+    .bind(&cert.issuer)
+    .bind(&cert.subject)
+    .bind(&cert.dns_names)
+    .bind(cert.not_before)
+    .bind(cert.not_after)
+    .bind(&cert.key_type)
+    .bind(check.chain_error.is_none())
+    .bind(check.chain_error)
+    .bind(check.hostname_valid)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// Notes a check that got no handshake, keeping the last certificate seen.
+pub async fn record_failure(
+    db: impl PgExecutor<'_>,
+    subdomain: &str,
+    error: &str,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO monitoring.tls_certificates (subdomain, checked_at, error)
+         VALUES ($1, NOW(), $2)
+         ON CONFLICT (subdomain) DO UPDATE SET
+             checked_at = EXCLUDED.checked_at,
+             error = EXCLUDED.error",
+    )
+    .bind(subdomain)
+    .bind(error)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+pub async fn get(db: impl PgExecutor<'_>, subdomain: &str) -> sqlx::Result<Option<TlsCertificate>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.tls_certificates WHERE subdomain = $1"
+    ))
+    .bind(subdomain)
+    .fetch_optional(db)
+    .await
+}
+
+/// Every checked certificate, soonest to expire first; with `within_days`,
+/// only those expiring by then (expired ones included).
+pub async fn list(
+    db: impl PgExecutor<'_>,
+    within_days: Option<i32>,
+) -> sqlx::Result<Vec<TlsCertificate>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM monitoring.tls_certificates
+         WHERE $1::int IS NULL OR not_after <= NOW() + make_interval(days => $1)
+         ORDER BY not_after NULLS LAST, subdomain"
+    ))
+    .bind(within_days)
+    .fetch_all(db)
+    .await
+}
+
+pub async fn delete(db: impl PgExecutor<'_>, subdomain: &str) -> sqlx::Result<bool> {
+    sqlx::query("DELETE FROM monitoring.tls_certificates WHERE subdomain = $1")
+        .bind(subdomain)
+        .execute(db)
+        .await
+        .map(|r| r.rows_affected() == 1)
+}
@@ -0,0 +1,343 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{FromRow, PgExecutor};
+
+/// How long each phase of the request for the final response of an HTTP
+/// check took, in milliseconds, as `checker::http` measures it. A phase
+/// that did not happen, such as DNS for an IP address or TLS over plain
+/// HTTP, or that was never reached, is `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, FromRow)]
+pub struct Timing {
+    pub dns_ms: Option<f64>,
+    /// Across every address tried.
+    pub connect_ms: Option<f64>,
+    pub tls_ms: Option<f64>,
+    /// From sending the request to the response headers.
+    pub ttfb_ms: Option<f64>,
+    /// Reading the body, as much of it as the probe reads.
+    pub transfer_ms: Option<f64>,
+}
+
+/// A row of the `monitoring.uptime_checks` hypertable.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UptimeCheck {
+    pub time: DateTime<Utc>,
+    pub subdomain: String,
+    pub status_code: Option<i32>,
+    pub response_time_ms: Option<f64>,
+    pub up: bool,
+    pub platform: Option<String>,
+    pub error_message: Option<String>,
+    pub headers: Option<Value>,
+    pub location: Option<String>,
+    /// Phases of HTTP checks by the API's checker (see add_check_timings.sql).
+    #[sqlx(flatten)]
+    pub timing: Timing,
+}
+
+const COLUMNS: &str = "time, subdomain, status_code, response_time_ms, up, platform,
+    error_message, headers, location, dns_ms, connect_ms, tls_ms, ttfb_ms, transfer_ms";
+
+/// A check result about to be written. `location` is the agent location,
+/// or `status::CENTRAL_LOCATION` for the API's own checker.
+#[derive(Debug, Clone, Default)]
+pub struct NewUptimeCheck {
+    pub time: DateTime<Utc>,
+    pub subdomain: String,
+    pub status_code: Option<i32>,
+    pub response_time_ms: Option<f64>,
+    pub up: bool,
+    pub platform: Option<String>,
+    pub error_message: Option<String>,
+    pub headers: Option<Value>,
+    pub location: Option<String>,
+    pub timing: Timing,
+}
+
+pub async fn insert(db: impl PgExecutor<'_>, check: &NewUptimeCheck) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO monitoring.uptime_checks
+         (time, subdomain, status_code, response_time_ms, up, platform, error_message, headers, location,
+          dns_ms, connect_ms, tls_ms, ttfb_ms, transfer_ms)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+    )
+    .bind(check.time)
+    .bind(&check.subdomain)
+    .bind(check.status_code)
+    .bind(check.response_time_ms)
+    .bind(check.up)
+    .bind(&check.platform)
+    .bind(&check.error_message)
+    .bind(&check.headers)
+    .bind(&check.location)
+    .bind(check.timing.dns_ms)
+    .bind(check.timing.connect_ms)
+    .bind(check.timing.tls_ms)
+    .bind(check.timing.ttfb_ms)
+    .bind(check.timing.transfer_ms)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// One page of the checks of `subdomain` since `since`, newest first.
+pub async fn list_since(
+    db: impl PgExecutor<'_>,
+    subdomain: &str,
+    since: DateTime<Utc>,
+    limit: i64,
+    offset: i64,
+) -> sqlx::Result<Vec<UptimeCheck>> {
+    sqlx::query_as(&format!(
+        "SELECT {COLUMNS}
+         FROM monitoring.uptime_checks
+         WHERE subdomain = $1 AND time > $2
+         ORDER BY time DESC
+         LIMIT $3 OFFSET $4"
+    ))
+    .bind(subdomain)
+    .bind(since)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(db)
+    .await
+}
+
+pub async fn count_since(
+    db: impl PgExecutor<'_>,
+    subdomain: &str,
+    since: DateTime<Utc>,
+) -> sqlx::Result<i64> {
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM monitoring.uptime_checks WHERE subdomain = $1 AND time > $2",
+    )
+    .bind(subdomain)
+    .bind(since)
+    .fetch_one(db)
+    .await
+}
+
+/// The newest check of every subdomain checked since `since`.
+pub async fn latest_per_subdomain(
+    db: impl PgExecutor<'_>,
+    since: DateTime<Utc>,
+) -> sqlx::Result<Vec<UptimeCheck>> {
+    sqlx::query_as(&format!(
+        "SELECT DISTINCT ON (subdomain) {COLUMNS}
+         FROM monitoring.uptime_checks
+         WHERE time > $1
+         ORDER BY subdomain, time DESC"
+    ))
+    .bind(since)
+    .fetch_all(db)
+    .await
+}
+
+/// `(subdomain, bucket, checks, up checks)` for checks since `since`, in
+/// buckets of `bucket_secs` numbered from the Unix epoch.
+pub async fn bucket_counts(
+    db: impl PgExecutor<'_>,
+    since: DateTime<Utc>,
+    bucket_secs: i64,
+) -> sqlx::Result<Vec<(String, i64, i64, i64)>> {
+    sqlx::query_as(
+        "SELECT subdomain,
+                FLOOR(EXTRACT(EPOCH FROM time) / $2)::int8 AS bucket,
+                COUNT(*),
+                COUNT(*) FILTER (WHERE up)
+         FROM monitoring.uptime_checks
+         WHERE time > $1
+         GROUP BY 1, 2",
+    )
+    .bind(since)
+    .bind(bucket_secs)
+    .fetch_all(db)
+    .await
+}
+
+/// Uptime and latency of one subdomain from one location over a window,
+/// with the location's current status from add_location_status.sql.
+/// Latency percentiles only count successful checks.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct LocationStats {
+    pub subdomain: String,
+    /// `unknown` for checks stored without one.
+    pub location: String,
+    pub status: String,
+    pub status_since: Option<DateTime<Utc>>,
+    pub checks: i64,
+    pub up_checks: i64,
+    pub uptime_percent: f64,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub last_check: DateTime<Utc>,
+}
+
+/// Per-location statistics of `subdomain`, or of every subdomain, for
+/// checks in `from..=to`, by subdomain and then location.
+pub async fn location_stats(
+    db: impl PgExecutor<'_>,
+    subdomain: Option<&str>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> sqlx::Result<Vec<LocationStats>> {
+    sqlx::query_as(
+        "WITH stats AS (
+             SELECT subdomain, COALESCE(location, 'unknown') AS location,
+                    COUNT(*) AS checks,
+                    COUNT(*) FILTER (WHERE up) AS up_checks,
+                    percentile_cont(0.5) WITHIN GROUP (ORDER BY response_time_ms)
+                        FILTER (WHERE up) AS p50_ms,
+                    percentile_cont(0.95) WITHIN GROUP (ORDER BY response_time_ms)
+                        FILTER (WHERE up) AS p95_ms,
+                    percentile_cont(0.99) WITHIN GROUP (ORDER BY response_time_ms)
+                        FILTER (WHERE up) AS p99_ms,
+                    MAX(time) AS last_check
+             FROM monitoring.uptime_checks
+             WHERE time > $2 AND time <= $3
+               AND ($1::text IS NULL OR subdomain = $1)
+             GROUP BY 1, 2
+         )
+         SELECT stats.subdomain, stats.location,
+                COALESCE(s.current_status, 'UNKNOWN') AS status,
+                s.last_status_change AS status_since,
+                checks, up_checks,
+                (100.0 * up_checks / checks)::float8 AS uptime_percent,
+                p50_ms, p95_ms, p99_ms, stats.last_check
+         FROM stats
+         LEFT JOIN monitoring.subdomain_location_status s
+             ON s.subdomain = stats.subdomain AND s.location = stats.location
+         ORDER BY stats.subdomain, stats.location",
+    )
+    .bind(subdomain)
+    .bind(from)
+    .bind(to)
+    .fetch_all(db)
+    .await
+}
+
+/// Writes a batch of checks in a single statement, leaving out `timing`,
+/// which agents do not report.
+pub async fn insert_many(db: impl PgExecutor<'_>, checks: &[NewUptimeCheck]) -> sqlx::Result<()> {
+    if checks.is_empty() {
+        return Ok(());
+    }
+    let mut time = Vec::with_capacity(checks.len());
+    let mut subdomain = Vec::with_capacity(checks.len());
+    let mut status_code = Vec::with_capacity(checks.len());
+    let mut response_time_ms = Vec::with_capacity(checks.len());
+    let mut up = Vec::with_capacity(checks.len());
+    let mut platform = Vec::with_capacity(checks.len());
+    let mut error_message = Vec::with_capacity(checks.len());
+    let mut headers = Vec::with_capacity(checks.len());
+    let mut location = Vec::with_capacity(checks.len());
+    for check in checks {
+        time.push(check.time);
+        subdomain.push(check.subdomain.as_str());
+        status_code.push(check.status_code);
+        response_time_ms.push(check.response_time_ms);
+        up.push(check.up);
+        platform.push(check.platform.as_deref());
+        error_message.push(check.error_message.as_deref());
+        headers.push(check.headers.clone());
+        location.push(check.location.as_deref());
+    }
+
+    sqlx::query(
+        "INSERT INTO monitoring.uptime_checks
+         (time, subdomain, status_code, response_time_ms, up, platform, error_message, headers, location)
+         SELECT t, s, c, r, u, p, e, h, l
+         FROM UNNEST($1::timestamptz[], $2::text[], $3::int4[], $4::float8[], $5::bool[],
+                     $6::text[], $7::text[], $8::jsonb[], $9::text[])
+              AS batch(t, s, c, r, u, p, e, h, l)",
+    )
+    .bind(time)
+    .bind(subdomain)
+    .bind(status_code)
+    .bind(response_time_ms)
+    .bind(up)
+    .bind(platform)
+    .bind(error_message)
+    .bind(headers)
+    .bind(location)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::db;
+
+    fn check(
+        subdomain: &str,
+        location: &str,
+        minutes_ago: i64,
+        ms: f64,
+        up: bool,
+    ) -> NewUptimeCheck {
+        NewUptimeCheck {
+            time: Utc::now() - Duration::minutes(minutes_ago),
+            subdomain: subdomain.to_string(),
+            status_code: Some(if up { 200 } else { 503 }),
+            response_time_ms: Some(ms),
+            up,
+            location: Some(location.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn location_stats_take_percentiles_of_successful_checks() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let subdomain = format!("percentiles-{}.bettergov.test", std::process::id());
+        // 1..=100 ms at central, plus slow failures that must not count.
+        let mut checks: Vec<_> = (1..=100)
+            .map(|ms| check(&subdomain, "central", ms % 50, ms as f64, true))
+            .collect();
+        checks.extend((0..5).map(|i| check(&subdomain, "central", i, 30_000.0, false)));
+        checks.push(check(&subdomain, "SG", 1, 40.0, true));
+        checks.push(check(&subdomain, "SG", 2, 9_000.0, false));
+        // Outside the window.
+        checks.push(check(&subdomain, "SG", 120, 1.0, true));
+        insert_many(&pool, &checks).await.unwrap();
+
+        let now = Utc::now();
+        let stats = location_stats(&pool, Some(&subdomain), now - Duration::hours(1), now)
+            .await
+            .unwrap();
+        let locations: Vec<_> = stats.iter().map(|s| s.location.as_str()).collect();
+        assert_eq!(locations, ["SG", "central"]);
+
+        let close = |actual: Option<f64>, expected: f64| {
+            let actual = actual.unwrap();
+            assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+        };
+        let central = &stats[1];
+        assert_eq!((central.checks, central.up_checks), (105, 100));
+        close(Some(central.uptime_percent), 100.0 * 100.0 / 105.0);
+        // percentile_cont interpolates between the ranked values.
+        close(central.p50_ms, 50.5);
+        close(central.p95_ms, 95.05);
+        close(central.p99_ms, 99.01);
+
+        let sg = &stats[0];
+        assert_eq!((sg.checks, sg.up_checks), (2, 1));
+        close(sg.p50_ms, 40.0);
+        close(sg.p99_ms, 40.0);
+        assert_eq!(sg.status, "UNKNOWN");
+
+        sqlx::query("DELETE FROM monitoring.uptime_checks WHERE subdomain = $1")
+            .bind(&subdomain)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+//! Finds subdomains linked from the sites themselves, starting at each
+//! domain's home page and following links between pages under the
+//! configured domains.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::Url;
+
+use super::{DiscoveryError, DiscoverySource, Found};
+use crate::subdomains::inventory;
+
+/// Bytes of a page scanned for links.
+const MAX_PAGE_BYTES: usize = 1024 * 1024;
+
+pub fn client(timeout: Duration) -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .user_agent("BetterGov Monitoring/1.0")
+        .build()
+}
+
+/// The `href` and `src` targets in `html`, resolved against `base`. Only
+/// http(s) URLs are kept.
+pub fn extract_links(base: &Url, html: &str) -> Vec<Url> {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    let link = LINK.get_or_init(|| {
+        Regex::new(r#"(?i)\b(?:href|src)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#)
+            .expect("link pattern is valid")
+    });
+    link.captures_iter(html)
+        .filter_map(|c| c.get(1).or_else(|| c.get(2)).or_else(|| c.get(3)))
+        .filter_map(|m| base.join(m.as_str().trim()).ok())
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .collect()
+}
+
+/// The host of `url` with its port, if it is not the scheme's default.
+fn host_of(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    })
+}
+
+pub struct LinkCrawler {
+    client: reqwest::Client,
+    max_pages: usize,
+    /// Where to start instead of the domains' home pages.
+    start: Vec<Url>,
+}
+
+impl LinkCrawler {
+    pub fn new(client: reqwest::Client, max_pages: usize) -> Self {
+        LinkCrawler {
+            client,
+            max_pages,
+            start: Vec::new(),
+        }
+    }
+
+    pub fn starting_at(mut self, start: Vec<Url>) -> Self {
+        self.start = start;
+        self
+    }
+
+    async fn fetch(&self, url: &Url) -> Result<Option<String>, DiscoveryError> {
+        let mut response = self.client.get(url.clone()).send().await?;
+        let html = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_none_or(|v| v.contains("html"));
+        if !response.status().is_success() || !html {
+            return Ok(None);
+        }
+        let mut page = Vec::new();
+        while page.len() < MAX_PAGE_BYTES {
+            match response.chunk().await? {
+                Some(chunk) => page.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&page).into_owned()))
+    }
+}
+
+#[async_trait]
+impl DiscoverySource for LinkCrawler {
+    fn name(&self) -> &'static str {
+        "link_crawl"
+    }
+
+    /// Breadth first, up to `max_pages` pages; each host is reported with
+    /// the first page linking to it. A page that fails to load is skipped,
+    /// and the run fails only if nothing was found and the last page failed.
+    async fn discover(&self, domains: &[String]) -> Result<Vec<Found>, DiscoveryError> {
+        let mut queue: VecDeque<Url> = if self.start.is_empty() {
+            domains
+                .iter()
+                .filter_map(|d| Url::parse(&format!("https://{d}/")).ok())
+                .collect()
+        } else {
+            self.start.iter().cloned().collect()
+        };
+        let mut queued: HashSet<Url> = queue.iter().cloned().collect();
+        let mut found = Vec::new();
+        let mut reported = HashSet::new();
+        let mut fetched = 0;
+        let mut last_error = None;
+
+        while let Some(page) = queue.pop_front() {
+            if fetched >= self.max_pages {
+                break;
+            }
+            fetched += 1;
+            let html = match self.fetch(&page).await {
+                Ok(Some(html)) => html,
+                Ok(None) => continue,
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            last_error = None;
+            for mut link in extract_links(&page, &html) {
+                let Some(host) = host_of(&link) else {
+                    continue;
+                };
+                if inventory::domain_of(&host, domains).is_none() {
+                    continue;
+                }
+                if reported.insert(host.clone()) {
+                    found.push(Found::new(host, page.as_str()));
+                }
+                link.set_fragment(None);
+                if queued.insert(link.clone()) {
+                    queue.push_back(link);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) if found.is_empty() => Err(e),
+            _ => Ok(found),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    #[test]
+    fn extracts_absolute_relative_and_protocol_relative_links() {
+        let base = Url::parse("https://bettergov.ph/about/").unwrap();
+        let html = r#"
+            <a href="https://api.bettergov.ph/docs">API</a>
+            <A HREF='//data.bettergov.ph'>Data</A>
+            <a href=team.html>Team</a>
+            <img src="/logo.png">
+            <a href="mailto:hello@bettergov.ph">Mail</a>
+            <a href="javascript:void(0)">Menu</a>
+        "#;
+        let links: Vec<String> = extract_links(&base, html)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            links,
+            [
+                "https://api.bettergov.ph/docs",
+                "https://data.bettergov.ph/",
+                "https://bettergov.ph/about/team.html",
+                "https://bettergov.ph/logo.png",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_linked_subdomains_only() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                r#"<a href="https://visualizations.bettergov.ph/">Charts</a>
+                   <a href="https://github.com/bettergovph">Code</a>
+                   <a href="https://budget.bettergov.ph/2025#top">Budget</a>"#,
+                "text/html",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let crawler = LinkCrawler::new(client(Duration::from_secs(5)).unwrap(), 1)
+            .starting_at(vec![Url::parse(&server.uri()).unwrap()]);
+        let found = crawler
+            .discover(&["bettergov.ph".to_string()])
+            .await
+            .unwrap();
+        let hosts: Vec<&str> = found.iter().map(|f| f.host.as_str()).collect();
+        assert_eq!(
+            hosts,
+            ["visualizations.bettergov.ph", "budget.bettergov.ph"]
+        );
+        assert_eq!(
+            found[0].detail.as_deref(),
+            Some(format!("{}/", server.uri()).as_str())
+        );
+    }
+}
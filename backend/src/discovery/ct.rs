@@ -0,0 +1,447 @@
+//! Finds subdomains in the certificates logged to Certificate Transparency,
+//! read from offline dumps: crt.sh JSON exports, or the `get-entries`
+//! responses of an RFC 6962 log. Either can be a file or an endpoint.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use actix_web::{web, HttpResponse};
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{DiscoveryError, DiscoverySource, Found};
+use crate::admin::Admin;
+use crate::error::ApiError;
+use crate::state::AppState;
+use crate::subdomains::inventory;
+use crate::x509;
+
+/// Provenance of hosts found this way; the Python discovery called it
+/// "Certificate Transparency".
+pub const SOURCE: &str = "certificate_transparency";
+
+/// Largest dump [`import_ct`] accepts. A crt.sh export of a busy domain
+/// runs to tens of megabytes, far past actix's 256 KiB default.
+pub const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Replaced by each configured domain in a CT endpoint URL.
+const DOMAIN_PLACEHOLDER: &str = "{domain}";
+
+/// `MerkleTreeLeaf` entry types (RFC 6962, section 3.4).
+const X509_ENTRY: u16 = 0;
+const PRECERT_ENTRY: u16 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CtFormat {
+    /// `https://crt.sh/?q=...&output=json`: an array of rows whose
+    /// `name_value` holds the certificate's names, one per line.
+    #[default]
+    Crtsh,
+    /// A log's `/ct/v1/get-entries` response.
+    Rfc6962,
+}
+
+/// One `[[discovery.ct]]` entry: a dump file or an endpoint, not both.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CtConfig {
+    pub path: Option<PathBuf>,
+    /// Fetched once per domain if it contains `{domain}`, else once.
+    pub url: Option<String>,
+    #[serde(default)]
+    pub format: CtFormat,
+}
+
+impl CtConfig {
+    /// Whether exactly one of `path` and a valid absolute `url` is set.
+    pub fn is_valid(&self) -> bool {
+        match (&self.path, &self.url) {
+            (Some(_), None) => true,
+            (None, Some(url)) => reqwest::Url::parse(&url.replace(DOMAIN_PLACEHOLDER, "x")).is_ok(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CrtshRow {
+    id: Option<u64>,
+    #[serde(default)]
+    name_value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetEntries {
+    entries: Vec<LogEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LogEntry {
+    leaf_input: String,
+}
+
+/// Each name as a host, lower-cased and without a trailing dot. Wildcards
+/// and anything else that is not a plain hostname are dropped.
+fn hosts(names: impl IntoIterator<Item = impl AsRef<str>>) -> Vec<String> {
+    names
+        .into_iter()
+        .map(|name| inventory::normalize_host(name.as_ref()))
+        .filter(|host| inventory::is_hostname(host))
+        .collect()
+}
+
+/// The names in a crt.sh JSON export, each with the crt.sh entry it came
+/// from.
+pub fn parse_crtsh(text: &str) -> Result<Vec<Found>, String> {
+    let rows: Vec<CrtshRow> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    Ok(rows
+        .iter()
+        .flat_map(|row| {
+            let detail = match row.id {
+                Some(id) => format!("crt.sh entry {id}"),
+                None => "crt.sh".to_string(),
+            };
+            hosts(row.name_value.lines())
+                .into_iter()
+                .map(move |host| Found::new(host, detail.clone()))
+        })
+        .collect())
+}
+
+/// Big-endian `N`-byte integer at the front of `input`, and the rest.
+fn take<const N: usize>(input: &[u8]) -> Option<(usize, &[u8])> {
+    if input.len() < N {
+        return None;
+    }
+    let (bytes, rest) = input.split_at(N);
+    let n = bytes.iter().fold(0usize, |n, &b| (n << 8) | usize::from(b));
+    Some((n, rest))
+}
+
+/// A `MerkleTreeLeaf`'s entry type and the DER TBSCertificate it logs: of
+/// the certificate for an `x509_entry`, as is for a `precert_entry`.
+pub fn leaf_tbs(leaf: &[u8]) -> Option<(&'static str, &[u8])> {
+    // version v1 (0), leaf_type timestamped_entry (0), then the timestamp.
+    let rest = leaf.strip_prefix(&[0, 0])?.get(8..)?;
+    let (entry_type, rest) = take::<2>(rest)?;
+    match entry_type as u16 {
+        X509_ENTRY => {
+            let (len, rest) = take::<3>(rest)?;
+            Some(("x509", x509::tbs_of_cert(rest.get(..len)?)?))
+        }
+        PRECERT_ENTRY => {
+            // The issuer's key hash comes first.
+            let (len, rest) = take::<3>(rest.get(32..)?)?;
+            Some(("precert", rest.get(..len)?))
+        }
+        _ => None,
+    }
+}
+
+/// The names in a `get-entries` response, each with the index of the
+/// entry within it. Entries that do not parse are skipped.
+pub fn parse_get_entries(text: &str) -> Result<Vec<Found>, String> {
+    let response: GetEntries = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let mut found = Vec::new();
+    for (i, entry) in response.entries.iter().enumerate() {
+        let Ok(leaf) = STANDARD.decode(&entry.leaf_input) else {
+            continue;
+        };
+        let Some((kind, tbs)) = leaf_tbs(&leaf) else {
+            continue;
+        };
+        let detail = format!("CT log entry {i} ({kind})");
+        found.extend(
+            hosts(x509::dns_names(tbs))
+                .into_iter()
+                .map(|host| Found::new(host, detail.clone())),
+        );
+    }
+    Ok(found)
+}
+
+/// The names in a dump of `format`.
+pub fn parse(format: CtFormat, text: &str) -> Result<Vec<Found>, String> {
+    match format {
+        CtFormat::Crtsh => parse_crtsh(text),
+        CtFormat::Rfc6962 => parse_get_entries(text),
+    }
+}
+
+/// Keeps the first finding of each host under `domains`.
+fn under(domains: &[String], found: Vec<Found>) -> Vec<Found> {
+    let mut seen = HashSet::new();
+    found
+        .into_iter()
+        .filter(|f| inventory::domain_of(&f.host, domains).is_some() && seen.insert(f.host.clone()))
+        .collect()
+}
+
+pub fn client(timeout: Duration) -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .user_agent("BetterGov Monitoring/1.0")
+        .build()
+}
+
+/// Reads one CT dump or endpoint on every run.
+pub struct CtSource {
+    client: reqwest::Client,
+    config: CtConfig,
+}
+
+impl CtSource {
+    pub fn new(client: reqwest::Client, config: CtConfig) -> Self {
+        CtSource { client, config }
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Vec<Found>, DiscoveryError> {
+        let text = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        parse(self.config.format, &text).map_err(|reason| DiscoveryError::Parse {
+            from: url.to_string(),
+            reason,
+        })
+    }
+}
+
+#[async_trait]
+impl DiscoverySource for CtSource {
+    fn name(&self) -> &'static str {
+        SOURCE
+    }
+
+    async fn discover(&self, domains: &[String]) -> Result<Vec<Found>, DiscoveryError> {
+        let mut found = Vec::new();
+        if let Some(path) = &self.config.path {
+            let text =
+                tokio::fs::read_to_string(path)
+                    .await
+                    .map_err(|source| DiscoveryError::Read {
+                        path: path.clone(),
+                        source,
+                    })?;
+            found = parse(self.config.format, &text).map_err(|reason| DiscoveryError::Parse {
+                from: path.display().to_string(),
+                reason,
+            })?;
+        } else if let Some(url) = &self.config.url {
+            if url.contains(DOMAIN_PLACEHOLDER) {
+                for domain in domains {
+                    found.extend(self.fetch(&url.replace(DOMAIN_PLACEHOLDER, domain)).await?);
+                }
+            } else {
+                found = self.fetch(url).await?;
+            }
+        }
+        Ok(under(domains, found))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub format: CtFormat,
+}
+
+/// `POST /api/admin/discovery/ct?format=crtsh|rfc6962`: imports the CT dump
+/// in the body once, as a CT source would.
+pub async fn import_ct(
+    _: Admin,
+    state: web::Data<AppState>,
+    query: web::Query<ImportQuery>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let found = parse(query.format, &body)
+        .map_err(|reason| ApiError::BadRequest(format!("Invalid CT dump: {reason}")))?;
+    let discovered = super::merge(
+        &state.subdomains.domains,
+        under(&state.subdomains.domains, found)
+            .into_iter()
+            .map(|f| (SOURCE, f)),
+    );
+    let new = super::save(&state.pool, &discovered).await?;
+    println!(
+        "Imported {} hosts from an uploaded CT dump, {new} new",
+        discovered.len()
+    );
+    Ok(HttpResponse::Ok().json(json!({
+        "source": SOURCE,
+        "hosts": discovered.iter().map(|d| &d.host).collect::<Vec<_>>(),
+        "new": new,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::{test as actix_test, App};
+    use rand::Rng;
+    use serde_json::Value;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::config::SubdomainsConfig;
+    use crate::db::{self, subdomain_sources};
+
+    fn fixture(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/ct")
+            .join(name);
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+    }
+
+    fn domains() -> Vec<String> {
+        vec!["bettergov.ph".to_string()]
+    }
+
+    #[test]
+    fn crtsh_export_names() {
+        let found = under(&domains(), parse_crtsh(&fixture("crtsh.json")).unwrap());
+        let hosts: Vec<&str> = found.iter().map(|f| f.host.as_str()).collect();
+        assert_eq!(
+            hosts,
+            [
+                "bettergov.ph",
+                "procurement.bettergov.ph",
+                "procurement-api.bettergov.ph",
+            ]
+        );
+        assert_eq!(found[1].detail.as_deref(), Some("crt.sh entry 15123456790"));
+    }
+
+    #[test]
+    fn rfc6962_x509_and_precert_entries() {
+        let found = parse_get_entries(&fixture("get-entries.json")).unwrap();
+        let hosts: Vec<&str> = found.iter().map(|f| f.host.as_str()).collect();
+        assert_eq!(
+            hosts,
+            [
+                "bettergov.ph",
+                "www.bettergov.ph",
+                "transparency.bettergov.ph",
+                "elections.bettergov.ph",
+                "results.elections.bettergov.ph",
+                "example.org",
+            ]
+        );
+        assert_eq!(found[0].detail.as_deref(), Some("CT log entry 0 (x509)"));
+        assert_eq!(found[3].detail.as_deref(), Some("CT log entry 1 (precert)"));
+        assert_eq!(under(&domains(), found).len(), 5);
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_dump() {
+        assert!(parse_crtsh("<html>rate limited</html>").is_err());
+        assert!(parse_get_entries(r#"[{"name_value": "bettergov.ph"}]"#).is_err());
+        let garbage = r#"{"entries": [{"leaf_input": "AAAA", "extra_data": ""}]}"#;
+        assert!(parse_get_entries(garbage).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn queries_the_endpoint_per_domain() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .and(query_param("q", "%.bettergov.ph"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(fixture("crtsh.json")))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let source = CtSource::new(
+            client(Duration::from_secs(5)).unwrap(),
+            CtConfig {
+                path: None,
+                url: Some(format!("{}/?q=%25.{{domain}}&output=json", server.uri())),
+                format: CtFormat::Crtsh,
+            },
+        );
+        let found = source.discover(&domains()).await.unwrap();
+        assert_eq!(found.len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn imports_a_dump_larger_than_the_default_payload_limit() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let tag = hex::encode(rand::thread_rng().gen::<[u8; 4]>());
+        let domain = format!("ct-{tag}.bettergov.test");
+        // The fixture's rows for this domain, padded with certificates of
+        // other domains to the size of a real export.
+        let mut rows: Vec<Value> =
+            serde_json::from_str(&fixture("crtsh.json").replace("bettergov.ph", &domain)).unwrap();
+        let elsewhere = rows[2].clone();
+        rows.extend((0..4000).map(|_| elsewhere.clone()));
+        let body = serde_json::to_string(&rows).unwrap();
+        assert!(body.len() > 1024 * 1024);
+
+        let state = web::Data::new(AppState {
+            admin_token: Some("ct-test-token".to_string()),
+            subdomains: SubdomainsConfig {
+                domains: vec![domain.clone()],
+            },
+            ..AppState::for_tests(pool.clone())
+        });
+        let app = actix_test::init_service(
+            App::new().app_data(state).service(
+                web::resource("/api/admin/discovery/ct")
+                    .app_data(web::PayloadConfig::new(MAX_IMPORT_BYTES))
+                    .route(web::post().to(import_ct)),
+            ),
+        )
+        .await;
+        let request = actix_test::TestRequest::post()
+            .uri("/api/admin/discovery/ct?format=crtsh")
+            .insert_header((AUTHORIZATION, "Bearer ct-test-token"))
+            .set_payload(body)
+            .to_request();
+        let response = actix_test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let imported: Value = actix_test::read_body_json(response).await;
+        assert_eq!(imported["new"], 3);
+
+        let hosts = [
+            (domain.clone(), "crt.sh entry 15123456789"),
+            (format!("procurement.{domain}"), "crt.sh entry 15123456790"),
+            (
+                format!("procurement-api.{domain}"),
+                "crt.sh entry 15123456790",
+            ),
+        ];
+        for (host, detail) in &hosts {
+            let sources = subdomain_sources::list(&pool, host).await.unwrap();
+            assert_eq!(sources.len(), 1, "{host}");
+            assert_eq!(sources[0].source, SOURCE);
+            assert_eq!(sources[0].detail.as_deref(), Some(*detail), "{host}");
+        }
+        for (host, _) in &hosts {
+            subdomain_sources::delete_for_subdomain(&pool, host)
+                .await
+                .unwrap();
+            sqlx::query("DELETE FROM monitoring.subdomains WHERE subdomain = $1")
+                .bind(host)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+    }
+}
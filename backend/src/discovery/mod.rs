@@ -0,0 +1,346 @@
+//! Subdomain discovery, ported from subdomain_discovery.py.
+//!
+//! Each [`DiscoverySource`] looks for hosts under the configured domains in
+//! its own way: crawling links, probing a wordlist, or reading zone files
+//! and Certificate Transparency dumps.
+//! [`Discovery`] runs them all, merges what they found per host, and
+//! [`save`] adds new hosts to the inventory while recording which sources
+//! found each one in `monitoring.subdomain_sources`.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::db::{subdomain_sources, subdomains};
+use crate::subdomains::inventory;
+
+pub mod crawl;
+pub mod ct;
+pub mod wordlist;
+pub mod zone;
+
+pub use crawl::LinkCrawler;
+pub use ct::{CtConfig, CtFormat, CtSource};
+pub use wordlist::WordlistProber;
+pub use zone::{ZoneConfig, ZoneFormat, ZoneSource};
+
+/// Provenance of hosts added through `/api/admin/subdomains`.
+pub const MANUAL_SOURCE: &str = "manual";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DiscoveryError {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("cannot read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("cannot parse {from}: {reason}")]
+    Parse { from: String, reason: String },
+}
+
+/// A host as one source saw it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    pub host: String,
+    /// Where the source saw it, e.g. the page linking to it.
+    pub detail: Option<String>,
+}
+
+impl Found {
+    pub fn new(host: impl Into<String>, detail: impl Into<String>) -> Self {
+        Found {
+            host: host.into(),
+            detail: Some(detail.into()),
+        }
+    }
+}
+
+/// A way of finding subdomains.
+#[async_trait]
+pub trait DiscoverySource: Send + Sync {
+    /// Provenance recorded for what it finds, e.g. `wordlist`.
+    fn name(&self) -> &'static str;
+    /// Hosts under `domains`. Anything else it returns is dropped.
+    async fn discover(&self, domains: &[String]) -> Result<Vec<Found>, DiscoveryError>;
+}
+
+/// A host with every source that found it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discovered {
+    pub host: String,
+    /// The configured domain it belongs to.
+    pub domain: String,
+    /// Source name to the first detail it gave.
+    pub sources: BTreeMap<&'static str, Option<String>>,
+}
+
+/// Deduplicates `findings` by host, dropping hosts outside `domains`.
+/// Sorted by host.
+pub fn merge(
+    domains: &[String],
+    findings: impl IntoIterator<Item = (&'static str, Found)>,
+) -> Vec<Discovered> {
+    let mut merged: BTreeMap<String, Discovered> = BTreeMap::new();
+    for (source, found) in findings {
+        let host = inventory::normalize_host(&found.host);
+        let Some(domain) = inventory::domain_of(&host, domains) else {
+            continue;
+        };
+        let domain = domain.to_string();
+        merged
+            .entry(host.clone())
+            .or_insert_with(|| Discovered {
+                host,
+                domain,
+                sources: BTreeMap::new(),
+            })
+            .sources
+            .entry(source)
+            .or_insert(found.detail);
+    }
+    merged.into_values().collect()
+}
+
+/// Runs every configured source over the configured domains.
+pub struct Discovery {
+    domains: Vec<String>,
+    sources: Vec<Box<dyn DiscoverySource>>,
+}
+
+impl Discovery {
+    pub fn new(domains: Vec<String>, sources: Vec<Box<dyn DiscoverySource>>) -> Self {
+        Discovery { domains, sources }
+    }
+
+    /// Runs the sources concurrently. A failing source is logged and the
+    /// others' findings are still returned.
+    pub async fn run(&self) -> Vec<Discovered> {
+        let runs = self.sources.iter().map(|source| async move {
+            match source.discover(&self.domains).await {
+                Ok(found) => {
+                    println!(
+                        "Discovery source {} found {} hosts",
+                        source.name(),
+                        found.len()
+                    );
+                    found.into_iter().map(|f| (source.name(), f)).collect()
+                }
+                Err(e) => {
+                    eprintln!("Discovery source {} failed: {e}", source.name());
+                    Vec::new()
+                }
+            }
+        });
+        merge(&self.domains, join_all(runs).await.into_iter().flatten())
+    }
+
+    /// One scheduled run: discover, then save.
+    pub async fn run_and_save(&self, pool: &PgPool) {
+        println!(
+            "Starting subdomain discovery for {}...",
+            self.domains.join(", ")
+        );
+        let discovered = self.run().await;
+        match save(pool, &discovered).await {
+            Ok(new) => println!(
+                "Subdomain discovery complete: {} hosts, {new} new",
+                discovered.len()
+            ),
+            Err(e) => eprintln!("Failed to save discovered subdomains: {e}"),
+        }
+    }
+}
+
+/// Adds the hosts not yet in the inventory and records every source that
+/// found each one. Returns how many were new.
+pub async fn save(pool: &PgPool, discovered: &[Discovered]) -> sqlx::Result<usize> {
+    let mut new = 0;
+    for host in discovered {
+        if host.sources.is_empty() {
+            continue;
+        }
+        let mut tx = pool.begin().await?;
+        if subdomains::upsert_discovered(&mut *tx, &host.domain, &host.host).await? {
+            println!("Discovered new subdomain {}", host.host);
+            new += 1;
+        }
+        for (source, detail) in &host.sources {
+            subdomain_sources::record(&mut *tx, &host.host, source, detail.as_deref()).await?;
+        }
+        tx.commit().await?;
+    }
+    Ok(new)
+}
+
+/// Common subdomain prefixes, as subdomain_discovery.py tried them. `www`
+/// is left out: it only redirects to the apex.
+pub const COMMON_PREFIXES: &[&str] = &[
+    "api",
+    "admin",
+    "portal",
+    "dashboard",
+    "docs",
+    "dev",
+    "staging",
+    "test",
+    "app",
+    "web",
+    "service",
+    "services",
+    "data",
+    "db",
+    "database",
+    "auth",
+    "login",
+    "secure",
+    "ssl",
+    "mail",
+    "email",
+    "smtp",
+    "ftp",
+    "git",
+    "gitlab",
+    "github",
+    "jenkins",
+    "ci",
+    "cd",
+    "build",
+    "deploy",
+    "monitor",
+    "monitoring",
+    "metrics",
+    "logs",
+    "log",
+    "status",
+    "health",
+    "ping",
+    "check",
+    "probe",
+    "grafana",
+    "kibana",
+    "elasticsearch",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// Seconds between runs; 0 turns scheduled discovery off.
+    pub interval_secs: u64,
+    /// Per-request timeout of the crawler, the wordlist prober and CT
+    /// endpoints.
+    pub timeout_secs: u64,
+    /// Pages the link crawler fetches per run; 0 turns it off.
+    pub crawl_max_pages: usize,
+    /// Pages the crawler starts from; by default each domain's home page.
+    pub crawl_start: Vec<String>,
+    /// Prefixes probed under each domain; empty turns the prober off.
+    pub wordlist: Vec<String>,
+    /// Wordlist probes in flight at once.
+    pub concurrency: usize,
+    /// Zone files and AXFR dumps read on every run.
+    pub zones: Vec<ZoneConfig>,
+    /// Certificate Transparency dumps and endpoints read on every run.
+    pub ct: Vec<CtConfig>,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            interval_secs: 86400,
+            timeout_secs: 5,
+            crawl_max_pages: 20,
+            crawl_start: Vec::new(),
+            wordlist: COMMON_PREFIXES.iter().map(ToString::to_string).collect(),
+            concurrency: 10,
+            zones: Vec::new(),
+            ct: Vec::new(),
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    /// `crawl_start` as URLs; `Config::validate` rejects any that do not
+    /// parse.
+    pub fn crawl_start_urls(&self) -> Vec<reqwest::Url> {
+        self.crawl_start
+            .iter()
+            .filter_map(|url| reqwest::Url::parse(url).ok())
+            .collect()
+    }
+
+    /// The enabled sources.
+    pub fn build(&self) -> Result<Vec<Box<dyn DiscoverySource>>, DiscoveryError> {
+        let timeout = Duration::from_secs(self.timeout_secs);
+        let mut sources: Vec<Box<dyn DiscoverySource>> = Vec::new();
+        if self.crawl_max_pages > 0 {
+            sources.push(Box::new(
+                LinkCrawler::new(crawl::client(timeout)?, self.crawl_max_pages)
+                    .starting_at(self.crawl_start_urls()),
+            ));
+        }
+        if !self.wordlist.is_empty() {
+            sources.push(Box::new(WordlistProber::new(
+                wordlist::client(timeout)?,
+                self.wordlist.clone(),
+                self.concurrency,
+            )));
+        }
+        sources.extend(
+            self.zones
+                .iter()
+                .map(|zone| Box::new(ZoneSource::new(zone.clone())) as Box<dyn DiscoverySource>),
+        );
+        for ct in &self.ct {
+            sources.push(Box::new(CtSource::new(ct::client(timeout)?, ct.clone())));
+        }
+        Ok(sources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_findings_per_host_with_provenance() {
+        let domains = vec!["bettergov.ph".to_string()];
+        let merged = merge(
+            &domains,
+            [
+                ("wordlist", Found::new("api.bettergov.ph", "HEAD 200")),
+                (
+                    "link_crawl",
+                    Found::new("API.bettergov.ph.", "https://bettergov.ph/"),
+                ),
+                (
+                    "link_crawl",
+                    Found::new("api.bettergov.ph", "https://bettergov.ph/about"),
+                ),
+                (
+                    "link_crawl",
+                    Found::new("github.com", "https://bettergov.ph/"),
+                ),
+                ("zone_file", Found::new("bettergov.ph", "A")),
+            ],
+        );
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].host, "api.bettergov.ph");
+        assert_eq!(merged[0].domain, "bettergov.ph");
+        assert_eq!(
+            merged[0].sources.keys().copied().collect::<Vec<_>>(),
+            ["link_crawl", "wordlist"]
+        );
+        assert_eq!(
+            merged[0].sources["link_crawl"].as_deref(),
+            Some("https://bettergov.ph/")
+        );
+        assert_eq!(merged[1].host, "bettergov.ph");
+    }
+}
@@ -0,0 +1,107 @@
+//! Finds subdomains by probing common prefixes under each domain, as
+//! subdomain_discovery.py's `check_common_subdomains` did.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures_util::{stream, StreamExt};
+use rand::Rng;
+
+use super::{DiscoveryError, DiscoverySource, Found};
+
+/// Redirects are not followed: answering with one is proof enough that the
+/// host exists.
+pub fn client(timeout: Duration) -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent("BetterGov Monitoring/1.0")
+        .build()
+}
+
+/// The hosts probed under `domain`: the apex, then every prefix.
+pub fn candidates(domain: &str, prefixes: &[String]) -> Vec<String> {
+    std::iter::once(domain.to_string())
+        .chain(prefixes.iter().map(|p| format!("{p}.{domain}")))
+        .collect()
+}
+
+pub struct WordlistProber {
+    client: reqwest::Client,
+    prefixes: Vec<String>,
+    concurrency: usize,
+}
+
+impl WordlistProber {
+    pub fn new(client: reqwest::Client, prefixes: Vec<String>, concurrency: usize) -> Self {
+        WordlistProber {
+            client,
+            prefixes,
+            concurrency: concurrency.max(1),
+        }
+    }
+
+    /// HEADs `host` over HTTPS, then plain HTTP. Anything below 400 means it
+    /// exists; returns the URL and status that showed it.
+    async fn probe(&self, host: &str) -> Option<String> {
+        for scheme in ["https", "http"] {
+            let url = format!("{scheme}://{host}");
+            if let Ok(response) = self.client.head(&url).send().await {
+                let status = response.status().as_u16();
+                if status < 400 {
+                    return Some(format!("HEAD {url} {status}"));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[async_trait]
+impl DiscoverySource for WordlistProber {
+    fn name(&self) -> &'static str {
+        "wordlist"
+    }
+
+    /// Domains with wildcard DNS answer for any prefix, so a domain whose
+    /// random label answers too is skipped.
+    async fn discover(&self, domains: &[String]) -> Result<Vec<Found>, DiscoveryError> {
+        let mut found = Vec::new();
+        for domain in domains {
+            let canary = format!(
+                "{}.{domain}",
+                hex::encode(rand::thread_rng().gen::<[u8; 8]>())
+            );
+            if self.probe(&canary).await.is_some() {
+                eprintln!("Skipping wordlist for {domain}: it answers for any subdomain");
+                continue;
+            }
+            let hosts = candidates(domain, &self.prefixes);
+            let answered: Vec<Found> = stream::iter(hosts)
+                .map(|host| async move {
+                    let seen = self.probe(&host).await;
+                    seen.map(|detail| Found::new(host, detail))
+                })
+                .buffer_unordered(self.concurrency)
+                .filter_map(std::future::ready)
+                .collect()
+                .await;
+            found.extend(answered);
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes_the_apex_and_each_prefix() {
+        let prefixes = vec!["api".to_string(), "docs".to_string()];
+        assert_eq!(
+            candidates("bettergov.ph", &prefixes),
+            ["bettergov.ph", "api.bettergov.ph", "docs.bettergov.ph"]
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Finds subdomains in BIND-format zone files and AXFR dumps (the output
+//! of `dig axfr`, which is the same format with absolute names).
+
+use std::path::PathBuf;
+
+use actix_web::{web, HttpResponse};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{DiscoveryError, DiscoverySource, Found};
+use crate::admin::Admin;
+use crate::error::ApiError;
+use crate::state::AppState;
+use crate::subdomains::inventory;
+
+/// Record types whose owner is a host worth checking.
+const HOST_TYPES: [&str; 3] = ["A", "AAAA", "CNAME"];
+const CLASSES: [&str; 4] = ["IN", "CH", "HS", "CS"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoneFormat {
+    #[default]
+    Zone,
+    Axfr,
+}
+
+impl ZoneFormat {
+    /// Provenance recorded for hosts found this way.
+    pub fn source(self) -> &'static str {
+        match self {
+            ZoneFormat::Zone => "zone_file",
+            ZoneFormat::Axfr => "axfr",
+        }
+    }
+}
+
+/// One `[[discovery.zones]]` entry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub format: ZoneFormat,
+    /// Origin of relative names until the file sets `$ORIGIN`.
+    pub origin: Option<String>,
+}
+
+/// A resource record, reduced to what discovery needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Absolute, lower-cased, without the trailing dot.
+    pub owner: String,
+    /// Upper-cased, e.g. `CNAME`.
+    pub rtype: String,
+}
+
+/// Drops a `;` comment, unless the `;` is quoted.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Joins the lines of parenthesized records, keeping whether each entry
+/// started with blank space (which repeats the previous owner).
+fn entries(text: &str) -> Vec<(bool, String)> {
+    let mut entries = Vec::new();
+    let mut current: Option<(bool, String)> = None;
+    let mut depth = 0i32;
+    for line in text.lines() {
+        let line = strip_comment(line);
+        if current.is_none() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            current = Some((line.starts_with([' ', '\t']), String::new()));
+        }
+        let (_, entry) = current.as_mut().expect("entry was just started");
+        for c in line.chars() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => entry.push(c),
+            }
+        }
+        entry.push(' ');
+        if depth <= 0 {
+            depth = 0;
+            entries.extend(current.take());
+        }
+    }
+    entries.extend(current);
+    entries
+}
+
+/// Resolves `name` against `origin`; `None` if it is relative and there is
+/// no origin.
+fn absolute(name: &str, origin: Option<&str>) -> Option<String> {
+    let name = name.to_ascii_lowercase();
+    if name == "@" {
+        return origin.map(str::to_string);
+    }
+    if let Some(name) = name.strip_suffix('.') {
+        return Some(name.to_string());
+    }
+    origin.map(|origin| format!("{name}.{origin}"))
+}
+
+fn is_ttl(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_digit())
+        && token
+            .chars()
+            .all(|c| c.is_ascii_digit() || "smhdwSMHDW".contains(c))
+}
+
+/// The records of a zone file. `origin` applies to relative names until
+/// an `$ORIGIN` directive; records with unresolvable names are skipped.
+pub fn parse(text: &str, origin: Option<&str>) -> Vec<Record> {
+    let mut origin = origin.map(inventory::normalize_host);
+    let mut owner: Option<String> = None;
+    let mut records = Vec::new();
+    for (continued, entry) in entries(text) {
+        let mut tokens = entry.split_whitespace();
+        if !continued {
+            let Some(first) = tokens.next() else {
+                continue;
+            };
+            if first.eq_ignore_ascii_case("$ORIGIN") {
+                origin = tokens.next().and_then(|o| absolute(o, origin.as_deref()));
+                continue;
+            }
+            if first.starts_with('$') {
+                // $TTL, $INCLUDE and $GENERATE name no host of their own.
+                continue;
+            }
+            owner = absolute(first, origin.as_deref());
+        }
+        let rtype =
+            tokens.find(|t| !is_ttl(t) && !CLASSES.iter().any(|c| t.eq_ignore_ascii_case(c)));
+        if let (Some(owner), Some(rtype)) = (&owner, rtype) {
+            records.push(Record {
+                owner: owner.clone(),
+                rtype: rtype.to_ascii_uppercase(),
+            });
+        }
+    }
+    records
+}
+
+/// Owners of address and alias records that are plain hostnames, so
+/// wildcards and service names such as `_dmarc` are left out. In file
+/// order, without repeats.
+pub fn hosts(records: &[Record]) -> Vec<(&str, &str)> {
+    let mut hosts: Vec<(&str, &str)> = Vec::new();
+    for record in records {
+        if HOST_TYPES.contains(&record.rtype.as_str())
+            && inventory::is_hostname(&record.owner)
+            && !hosts.iter().any(|(owner, _)| *owner == record.owner)
+        {
+            hosts.push((&record.owner, &record.rtype));
+        }
+    }
+    hosts
+}
+
+/// Reads one zone file or AXFR dump on every run.
+pub struct ZoneSource {
+    config: ZoneConfig,
+}
+
+impl ZoneSource {
+    pub fn new(config: ZoneConfig) -> Self {
+        ZoneSource { config }
+    }
+}
+
+/// The hosts in zone `text`, each with its record type and `from`.
+pub fn found_in(text: &str, origin: Option<&str>, from: &str) -> Vec<Found> {
+    hosts(&parse(text, origin))
+        .into_iter()
+        .map(|(host, rtype)| Found::new(host, format!("{rtype} record in {from}")))
+        .collect()
+}
+
+#[async_trait]
+impl DiscoverySource for ZoneSource {
+    fn name(&self) -> &'static str {
+        self.config.format.source()
+    }
+
+    async fn discover(&self, _: &[String]) -> Result<Vec<Found>, DiscoveryError> {
+        let path = &self.config.path;
+        let text =
+            tokio::fs::read_to_string(path)
+                .await
+                .map_err(|source| DiscoveryError::Read {
+                    path: path.clone(),
+                    source,
+                })?;
+        Ok(found_in(
+            &text,
+            self.config.origin.as_deref(),
+            &path.display().to_string(),
+        ))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub format: ZoneFormat,
+    pub origin: Option<String>,
+}
+
+/// `POST /api/admin/discovery/zone?format=zone|axfr&origin=...`: imports
+/// the zone file or AXFR dump in the body once, as a zone source would.
+pub async fn import_zone(
+    _: Admin,
+    state: web::Data<AppState>,
+    query: web::Query<ImportQuery>,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let source = query.format.source();
+    let found = found_in(&body, query.origin.as_deref(), "upload");
+    if found.is_empty() {
+        return Err(ApiError::BadRequest(
+            "No A, AAAA or CNAME records found; relative names need an origin".to_string(),
+        ));
+    }
+    let discovered = super::merge(
+        &state.subdomains.domains,
+        found.into_iter().map(|f| (source, f)),
+    );
+    let new = super::save(&state.pool, &discovered).await?;
+    println!(
+        "Imported {} hosts from an uploaded {source}, {new} new",
+        discovered.len()
+    );
+    Ok(HttpResponse::Ok().json(json!({
+        "source": source,
+        "hosts": discovered.iter().map(|d| &d.host).collect::<Vec<_>>(),
+        "new": new,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/discovery")
+            .join(name);
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+    }
+
+    #[test]
+    fn zone_file_hosts() {
+        let records = parse(&fixture("bettergov.ph.zone"), None);
+        assert!(records.contains(&Record {
+            owner: "bettergov.ph".to_string(),
+            rtype: "SOA".to_string(),
+        }));
+        let hosts: Vec<&str> = hosts(&records).into_iter().map(|(h, _)| h).collect();
+        assert_eq!(
+            hosts,
+            [
+                "bettergov.ph",
+                "www.bettergov.ph",
+                "api.bettergov.ph",
+                "visualizations.bettergov.ph",
+                "status.bettergov.ph",
+                "budget.data.bettergov.ph",
+                "legacy.bettergov.ph",
+            ]
+        );
+    }
+
+    #[test]
+    fn axfr_dump_hosts() {
+        let found = found_in(&fixture("bettergov.ph.axfr"), None, "dump");
+        let hosts: Vec<&str> = found.iter().map(|f| f.host.as_str()).collect();
+        assert_eq!(
+            hosts,
+            ["bettergov.ph", "api.bettergov.ph", "docs.bettergov.ph"]
+        );
+        assert_eq!(found[2].detail.as_deref(), Some("CNAME record in dump"));
+    }
+
+    #[test]
+    fn relative_names_need_an_origin() {
+        let zone = "api IN A 192.0.2.1\n@ 300 IN AAAA 2001:db8::1\n";
+        assert!(parse(zone, None).is_empty());
+        let records = parse(zone, Some("BetterGov.ph."));
+        assert_eq!(records[0].owner, "api.bettergov.ph");
+        assert_eq!(records[1].owner, "bettergov.ph");
+        assert_eq!(records[1].rtype, "AAAA");
+    }
+}
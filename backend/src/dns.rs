@@ -0,0 +1,464 @@
+//! A minimal DNS client (RFC 1035): one question per message, over UDP
+//! with EDNS0, retried over TCP when the answer is truncated. Enough to
+//! watch a subdomain's records without a resolver library.
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+const CLASS_IN: u16 = 1;
+const OPT: u16 = 41;
+/// Advertised UDP payload size, as DNS Flag Day 2020 recommends.
+const UDP_PAYLOAD: u16 = 1232;
+const HEADER_LEN: usize = 12;
+/// Compression pointers followed before a name is deemed a loop.
+const MAX_POINTERS: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DnsError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("timeout")]
+    Timeout,
+    #[error("invalid name {0:?}")]
+    Name(String),
+    #[error("malformed response: {0}")]
+    Malformed(&'static str),
+    /// The server answered with an error other than NXDOMAIN.
+    #[error("{0}")]
+    Rcode(Rcode),
+}
+
+/// The record types the monitor watches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Cname,
+    Ns,
+    Mx,
+    Txt,
+    Caa,
+}
+
+impl RecordType {
+    pub const ALL: [RecordType; 7] = [
+        RecordType::A,
+        RecordType::Aaaa,
+        RecordType::Cname,
+        RecordType::Ns,
+        RecordType::Mx,
+        RecordType::Txt,
+        RecordType::Caa,
+    ];
+
+    pub fn code(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Ns => 2,
+            RecordType::Cname => 5,
+            RecordType::Mx => 15,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+            RecordType::Caa => 257,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RecordType::A => "A",
+            RecordType::Aaaa => "AAAA",
+            RecordType::Cname => "CNAME",
+            RecordType::Ns => "NS",
+            RecordType::Mx => "MX",
+            RecordType::Txt => "TXT",
+            RecordType::Caa => "CAA",
+        }
+    }
+
+    fn from_code(code: u16) -> Option<RecordType> {
+        RecordType::ALL.into_iter().find(|t| t.code() == code)
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RecordType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RecordType::ALL
+            .into_iter()
+            .find(|t| t.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unsupported record type {s:?}"))
+    }
+}
+
+impl TryFrom<String> for RecordType {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<RecordType> for String {
+    fn from(t: RecordType) -> String {
+        t.as_str().to_string()
+    }
+}
+
+/// A response code (RFC 1035, 4.1.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rcode(pub u8);
+
+impl Rcode {
+    pub const NOERROR: Rcode = Rcode(0);
+    pub const NXDOMAIN: Rcode = Rcode(3);
+}
+
+impl fmt::Display for Rcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0 => f.write_str("NOERROR"),
+            1 => f.write_str("FORMERR"),
+            2 => f.write_str("SERVFAIL"),
+            3 => f.write_str("NXDOMAIN"),
+            4 => f.write_str("NOTIMP"),
+            5 => f.write_str("REFUSED"),
+            code => write!(f, "RCODE{code}"),
+        }
+    }
+}
+
+/// An answer record of a watched type, its data in presentation form:
+/// `192.0.2.1`, `10 mail.example.com`, `0 issue "letsencrypt.org"`.
+/// Names are lower-cased and without the trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub owner: String,
+    pub rtype: RecordType,
+    pub ttl: u32,
+    pub data: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub rcode: Rcode,
+    pub answers: Vec<Record>,
+    /// Time from sending the query to having the full answer.
+    pub elapsed: Duration,
+}
+
+impl Response {
+    /// The data of the answers of type `rtype`, sorted and without
+    /// repeats. An A query answered through a CNAME keeps only the
+    /// addresses.
+    pub fn values(&self, rtype: RecordType) -> Vec<String> {
+        let mut values: Vec<String> = self
+            .answers
+            .iter()
+            .filter(|r| r.rtype == rtype)
+            .map(|r| r.data.clone())
+            .collect();
+        values.sort();
+        values.dedup();
+        values
+    }
+}
+
+/// Encodes a query for `name`/`rtype` with message id `id`, asking for
+/// recursion and advertising EDNS0.
+pub fn encode_query(id: u16, name: &str, rtype: RecordType) -> Result<Vec<u8>, DnsError> {
+    let mut message = Vec::with_capacity(64);
+    message.extend(id.to_be_bytes());
+    // RD set; one question, one additional (the OPT record).
+    message.extend([0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 1]);
+    encode_name(&mut message, name)?;
+    message.extend(rtype.code().to_be_bytes());
+    message.extend(CLASS_IN.to_be_bytes());
+    // OPT: root owner, type, UDP payload size as class, no flags or options.
+    message.push(0);
+    message.extend(OPT.to_be_bytes());
+    message.extend(UDP_PAYLOAD.to_be_bytes());
+    message.extend([0, 0, 0, 0, 0, 0]);
+    Ok(message)
+}
+
+fn encode_name(out: &mut Vec<u8>, name: &str) -> Result<(), DnsError> {
+    let trimmed = name.trim_end_matches('.');
+    if trimmed.len() > 253 {
+        return Err(DnsError::Name(name.to_string()));
+    }
+    // The root name has no labels at all.
+    let labels = trimmed.split('.').filter(|_| !trimmed.is_empty());
+    for label in labels {
+        if label.is_empty() || label.len() > 63 {
+            return Err(DnsError::Name(name.to_string()));
+        }
+        out.push(label.len() as u8);
+        out.extend(label.as_bytes());
+    }
+    out.push(0);
+    Ok(())
+}
+
+fn u16_at(message: &[u8], pos: usize) -> Result<u16, DnsError> {
+    message
+        .get(pos..pos + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or(DnsError::Malformed("message ends early"))
+}
+
+fn u32_at(message: &[u8], pos: usize) -> Result<u32, DnsError> {
+    message
+        .get(pos..pos + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(DnsError::Malformed("message ends early"))
+}
+
+/// Reads the possibly compressed name at `pos`; returns it and the
+/// position after it.
+fn read_name(message: &[u8], mut pos: usize) -> Result<(String, usize), DnsError> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *message
+            .get(pos)
+            .ok_or(DnsError::Malformed("name runs past the end"))?;
+        match len {
+            0 => {
+                end.get_or_insert(pos + 1);
+                break;
+            }
+            len if len & 0xc0 == 0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(DnsError::Malformed("compression loop"));
+                }
+                end.get_or_insert(pos + 2);
+                pos = usize::from(u16_at(message, pos)? & 0x3fff);
+            }
+            len if len & 0xc0 == 0 => {
+                let label = message
+                    .get(pos + 1..pos + 1 + usize::from(len))
+                    .ok_or(DnsError::Malformed("label runs past the end"))?;
+                labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                pos += 1 + usize::from(len);
+            }
+            _ => return Err(DnsError::Malformed("unknown label type")),
+        }
+    }
+    Ok((labels.join("."), end.unwrap_or(pos)))
+}
+
+/// Presentation form of the RDATA at `start..end` of `message`.
+fn render(message: &[u8], rtype: RecordType, start: usize, end: usize) -> Result<String, DnsError> {
+    let rdata = &message[start..end];
+    let malformed = || DnsError::Malformed("bad record data");
+    Ok(match rtype {
+        RecordType::A => {
+            let octets: [u8; 4] = rdata.try_into().map_err(|_| malformed())?;
+            Ipv4Addr::from(octets).to_string()
+        }
+        RecordType::Aaaa => {
+            let octets: [u8; 16] = rdata.try_into().map_err(|_| malformed())?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        RecordType::Cname | RecordType::Ns => read_name(message, start)?.0,
+        RecordType::Mx => {
+            let preference = u16_at(message, start)?;
+            format!("{preference} {}", read_name(message, start + 2)?.0)
+        }
+        RecordType::Txt => {
+            let mut text = Vec::new();
+            let mut rest = rdata;
+            while let Some((&len, tail)) = rest.split_first() {
+                let chunk = tail.get(..usize::from(len)).ok_or_else(malformed)?;
+                text.extend_from_slice(chunk);
+                rest = &tail[usize::from(len)..];
+            }
+            String::from_utf8_lossy(&text).into_owned()
+        }
+        RecordType::Caa => {
+            let (&flags, rest) = rdata.split_first().ok_or_else(malformed)?;
+            let (&tag_len, rest) = rest.split_first().ok_or_else(malformed)?;
+            let tag = rest.get(..usize::from(tag_len)).ok_or_else(malformed)?;
+            let value = &rest[usize::from(tag_len)..];
+            format!(
+                "{flags} {} \"{}\"",
+                String::from_utf8_lossy(tag).to_ascii_lowercase(),
+                String::from_utf8_lossy(value)
+            )
+        }
+    })
+}
+
+/// Decodes the response to query `id`. Answers of other types (DNAME,
+/// RRSIG, ...) are skipped.
+pub fn decode_response(id: u16, message: &[u8]) -> Result<(Rcode, bool, Vec<Record>), DnsError> {
+    if message.len() < HEADER_LEN {
+        return Err(DnsError::Malformed("short header"));
+    }
+    if u16_at(message, 0)? != id {
+        return Err(DnsError::Malformed("id mismatch"));
+    }
+    let flags = u16_at(message, 2)?;
+    if flags & 0x8000 == 0 {
+        return Err(DnsError::Malformed("not a response"));
+    }
+    let truncated = flags & 0x0200 != 0;
+    let rcode = Rcode((flags & 0x000f) as u8);
+    let questions = u16_at(message, 4)?;
+    let answers = u16_at(message, 6)?;
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..questions {
+        pos = read_name(message, pos)?.1 + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        let (owner, after) = read_name(message, pos)?;
+        let rtype = u16_at(message, after)?;
+        let ttl = u32_at(message, after + 4)?;
+        let len = usize::from(u16_at(message, after + 8)?);
+        let start = after + 10;
+        let end = start + len;
+        if end > message.len() {
+            return Err(DnsError::Malformed("record runs past the end"));
+        }
+        if let Some(rtype) = RecordType::from_code(rtype) {
+            records.push(Record {
+                owner,
+                rtype,
+                ttl,
+                data: render(message, rtype, start, end)?,
+            });
+        }
+        pos = end;
+    }
+    Ok((rcode, truncated, records))
+}
+
+/// Asks `server` for the `rtype` records of `name`. NXDOMAIN is an answer
+/// (with none); other error codes are errors.
+pub async fn query(
+    server: SocketAddr,
+    name: &str,
+    rtype: RecordType,
+    timeout: Duration,
+) -> Result<Response, DnsError> {
+    let started = Instant::now();
+    let exchange = async {
+        let id: u16 = rand::thread_rng().gen();
+        let query = encode_query(id, name, rtype)?;
+        let (rcode, truncated, answers) = decode_response(id, &over_udp(server, &query).await?)?;
+        if truncated {
+            return decode_response(id, &over_tcp(server, &query).await?);
+        }
+        Ok((rcode, truncated, answers))
+    };
+    let (rcode, _, answers) = tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| DnsError::Timeout)??;
+    if rcode != Rcode::NOERROR && rcode != Rcode::NXDOMAIN {
+        return Err(DnsError::Rcode(rcode));
+    }
+    Ok(Response {
+        rcode,
+        answers,
+        elapsed: started.elapsed(),
+    })
+}
+
+async fn over_udp(server: SocketAddr, query: &[u8]) -> Result<Vec<u8>, DnsError> {
+    let local: SocketAddr = if server.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+    socket.send(query).await?;
+    let mut buf = vec![0; usize::from(UDP_PAYLOAD).max(512) * 4];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        // Ignore stray datagrams that cannot be the answer.
+        if len >= 2 && buf[..2] == query[..2] {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+    }
+}
+
+async fn over_tcp(server: SocketAddr, query: &[u8]) -> Result<Vec<u8>, DnsError> {
+    let mut stream = TcpStream::connect(server).await?;
+    let len = u16::try_from(query.len()).map_err(|_| DnsError::Malformed("query too long"))?;
+    let mut framed = len.to_be_bytes().to_vec();
+    framed.extend_from_slice(query);
+    stream.write_all(&framed).await?;
+    let len = stream.read_u16().await?;
+    let mut buf = vec![0; usize::from(len)];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_names_as_labels() {
+        let query = encode_query(0xbeef, "api.BetterGov.ph.", RecordType::Caa).unwrap();
+        assert_eq!(&query[..2], &[0xbe, 0xef]);
+        assert_eq!(
+            &query[HEADER_LEN..HEADER_LEN + 20],
+            b"\x03api\x09BetterGov\x02ph\x00\x01\x01"
+        );
+        assert!(encode_query(1, "a..b", RecordType::A).is_err());
+        assert!(encode_query(1, &format!("{}.ph", "x".repeat(64)), RecordType::A).is_err());
+    }
+
+    #[test]
+    fn decodes_compressed_answers() {
+        // Query for www.bettergov.ph A, answered through a CNAME.
+        let mut message = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 3, 0, 0, 0, 0];
+        message.extend(b"\x03www\x09bettergov\x02ph\x00\x00\x01\x00\x01");
+        // CNAME to bettergov.ph, pointing into the question.
+        message.extend([0xc0, 12, 0, 5, 0, 1, 0, 0, 0x0e, 0x10, 0, 2, 0xc0, 16]);
+        // bettergov.ph A 192.0.2.10
+        message.extend([0xc0, 16, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 10]);
+        // bettergov.ph MX 10 mail.bettergov.ph
+        message.extend([0xc0, 16, 0, 15, 0, 1, 0, 0, 0, 60, 0, 9, 0, 10]);
+        message.extend(b"\x04mail\xc0\x10");
+
+        let (rcode, truncated, records) = decode_response(0x1234, &message).unwrap();
+        assert_eq!(rcode, Rcode::NOERROR);
+        assert!(!truncated);
+        assert_eq!(records[0].owner, "www.bettergov.ph");
+        assert_eq!(records[0].data, "bettergov.ph");
+        assert_eq!(records[0].ttl, 3600);
+        assert_eq!(records[1].data, "192.0.2.10");
+        assert_eq!(records[2].data, "10 mail.bettergov.ph");
+        assert!(decode_response(0x4321, &message).is_err());
+
+        // A pointer to itself must not hang.
+        let mut looped = message[..HEADER_LEN].to_vec();
+        looped[5] = 1;
+        looped[7] = 0;
+        looped.extend([0xc0, 12]);
+        assert!(decode_response(0x1234, &looped).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::json;
+
+/// One rejected field of a request. `index` locates the item within a
+/// batch body and is omitted for single objects and query parameters.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(index: Option<usize>, field: impl Into<String>, message: impl Into<String>) -> Self {
+        FieldError {
+            index,
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Errors returned by API handlers. Bodies keep the `{"status", "message"}`
+/// shape the FastAPI service used, which deployed agents already parse.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{} invalid field(s)", .0.len())]
+    Validation(Vec<FieldError>),
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = match self {
+            ApiError::Unauthorized(_) | ApiError::Forbidden(_) => "rejected",
+            _ => "error",
+        };
+        let mut body = json!({
+            "status": status,
+            "message": self.to_string(),
+        });
+        if let ApiError::Validation(errors) = self {
+            body["errors"] = json!(errors);
+        }
+        HttpResponse::build(self.status_code()).json(body)
+    }
+}
@@ -0,0 +1,323 @@
+//! Platform fingerprinting driven by the declarative rules in `rules.toml`.
+//!
+//! Rules are grouped into stack layers, and a response is matched against
+//! every layer independently, so a site behind a CDN reports the CDN, the
+//! origin server and the framework together instead of only the first hit.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+const BUILTIN_RULES: &str = include_str!("rules.toml");
+
+/// Layers of the serving stack, outermost first. The declaration order is
+/// the order detections are reported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layer {
+    Edge,
+    Hosting,
+    Server,
+    Runtime,
+    Framework,
+    Cms,
+    Frontend,
+    Application,
+}
+
+/// A response as seen by the checker. Header names must be lowercase.
+#[derive(Debug, Clone, Copy)]
+pub struct Observed<'a> {
+    pub host: &'a str,
+    pub headers: &'a BTreeMap<String, String>,
+    pub body: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Detection {
+    pub layer: Layer,
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} {version}", self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+/// Everything detected for one response, outermost layer first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Fingerprint {
+    pub layers: Vec<Detection>,
+}
+
+impl fmt::Display for Fingerprint {
+    /// Renders the form stored in `subdomains.platform`, e.g.
+    /// `Cloudflare → Nginx 1.24 → Next.js`, or `Unknown`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.layers.is_empty() {
+            return f.write_str("Unknown");
+        }
+        for (i, detection) in self.layers.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" → ")?;
+            }
+            write!(f, "{detection}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RuleError {
+    #[error("invalid fingerprint rules file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("fingerprint rule {rule:?} has an invalid regex: {source}")]
+    Regex { rule: String, source: regex::Error },
+    #[error("fingerprint rule {rule:?} {reason}")]
+    Invalid { rule: String, reason: &'static str },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    rule: Vec<RuleSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    name: String,
+    layer: Layer,
+    header: Option<String>,
+    pattern: Option<String>,
+    body: Option<String>,
+    host: Option<String>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    layer: Layer,
+    header: Option<String>,
+    pattern: Option<Regex>,
+    body: Option<Regex>,
+    host: Option<Regex>,
+}
+
+impl Rule {
+    fn compile(spec: RuleSpec) -> Result<Self, RuleError> {
+        if spec.header.is_none() && spec.body.is_none() && spec.host.is_none() {
+            return Err(RuleError::Invalid {
+                rule: spec.name,
+                reason: "needs at least one of header, body or host",
+            });
+        }
+        if spec.pattern.is_some() && spec.header.is_none() {
+            return Err(RuleError::Invalid {
+                rule: spec.name,
+                reason: "has a pattern but no header to apply it to",
+            });
+        }
+
+        let compile = |source: Option<String>| {
+            source
+                .map(|s| Regex::new(&s))
+                .transpose()
+                .map_err(|source| RuleError::Regex {
+                    rule: spec.name.clone(),
+                    source,
+                })
+        };
+        Ok(Rule {
+            pattern: compile(spec.pattern)?,
+            body: compile(spec.body)?,
+            host: compile(spec.host)?,
+            header: spec.header.map(|h| h.to_ascii_lowercase()),
+            layer: spec.layer,
+            name: spec.name,
+        })
+    }
+
+    /// `None` if the rule does not match, otherwise the captured version.
+    fn matches(&self, observed: &Observed<'_>) -> Option<Option<String>> {
+        let mut version = None;
+        let mut check = |regex: &Regex, haystack: &str| -> bool {
+            match regex.captures(haystack) {
+                Some(caps) => {
+                    if version.is_none() {
+                        version = caps.name("version").map(|m| m.as_str().to_string());
+                    }
+                    true
+                }
+                None => false,
+            }
+        };
+
+        if let Some(header) = &self.header {
+            let value = observed.headers.get(header)?;
+            if let Some(pattern) = &self.pattern {
+                if !check(pattern, value) {
+                    return None;
+                }
+            }
+        }
+        if let Some(body) = &self.body {
+            if !check(body, observed.body) {
+                return None;
+            }
+        }
+        if let Some(host) = &self.host {
+            if !check(host, observed.host) {
+                return None;
+            }
+        }
+        Some(version)
+    }
+}
+
+#[derive(Debug)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// The rules shipped in `src/fingerprint/rules.toml`.
+    pub fn builtin() -> Result<Self, RuleError> {
+        Self::from_toml(BUILTIN_RULES)
+    }
+
+    pub fn from_toml(source: &str) -> Result<Self, RuleError> {
+        let file: RulesFile = toml::from_str(source)?;
+        let rules = file
+            .rule
+            .into_iter()
+            .map(Rule::compile)
+            .collect::<Result<_, _>>()?;
+        Ok(RuleSet { rules })
+    }
+
+    pub fn detect(&self, observed: &Observed<'_>) -> Fingerprint {
+        let mut layers: Vec<Detection> = Vec::new();
+        for rule in &self.rules {
+            if layers.iter().any(|d| d.layer == rule.layer) {
+                continue;
+            }
+            if let Some(version) = rule.matches(observed) {
+                layers.push(Detection {
+                    layer: rule.layer,
+                    name: rule.name.clone(),
+                    version,
+                });
+            }
+        }
+        layers.sort_by_key(|d| d.layer);
+        Fingerprint { layers }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::fs;
+    use std::path::Path;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    /// A captured response and the platform string it should produce.
+    #[derive(Deserialize)]
+    struct Fixture {
+        host: String,
+        headers: BTreeMap<String, String>,
+        #[serde(default)]
+        body: String,
+        expected: String,
+    }
+
+    fn fixtures() -> Vec<(String, Fixture)> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fingerprint");
+        let mut fixtures: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .map(|path| {
+                let raw = fs::read_to_string(&path).unwrap();
+                let mut fixture: Fixture = serde_json::from_str(&raw)
+                    .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+                fixture.headers = fixture
+                    .headers
+                    .into_iter()
+                    .map(|(k, v)| (k.to_ascii_lowercase(), v))
+                    .collect();
+                (
+                    path.file_name().unwrap().to_string_lossy().into_owned(),
+                    fixture,
+                )
+            })
+            .collect();
+        fixtures.sort_by(|a, b| a.0.cmp(&b.0));
+        fixtures
+    }
+
+    fn detect(rules: &RuleSet, fixture: &Fixture) -> Fingerprint {
+        rules.detect(&Observed {
+            host: &fixture.host,
+            headers: &fixture.headers,
+            body: &fixture.body,
+        })
+    }
+
+    #[test]
+    fn fixtures_match_expected_platforms() {
+        let rules = RuleSet::builtin().unwrap();
+        let fixtures = fixtures();
+        assert!(!fixtures.is_empty());
+        for (name, fixture) in &fixtures {
+            assert_eq!(
+                detect(&rules, fixture).to_string(),
+                fixture.expected,
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn every_rule_name_is_covered_by_a_fixture() {
+        let rules = RuleSet::builtin().unwrap();
+        let detected: BTreeSet<String> = fixtures()
+            .iter()
+            .flat_map(|(_, fixture)| detect(&rules, fixture).layers)
+            .map(|d| d.name)
+            .collect();
+        let missing: Vec<_> = rules
+            .rules
+            .iter()
+            .map(|r| r.name.as_str())
+            .filter(|name| !detected.contains(*name))
+            .collect();
+        assert!(missing.is_empty(), "rules without a fixture: {missing:?}");
+    }
+
+    #[test]
+    fn rejects_rules_without_conditions() {
+        let err = RuleSet::from_toml("[[rule]]\nname = \"X\"\nlayer = \"server\"\n").unwrap_err();
+        assert!(matches!(err, RuleError::Invalid { .. }));
+    }
+
+    #[test]
+    fn rejects_invalid_regex() {
+        let source = "[[rule]]\nname = \"X\"\nlayer = \"server\"\nbody = \"(\"\n";
+        assert!(matches!(
+            RuleSet::from_toml(source).unwrap_err(),
+            RuleError::Regex { .. }
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+use actix_web::{web, HttpResponse, Result};
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::db::{self, subdomains};
+use crate::scheduler::JobInfo;
+use crate::state::AppState;
+
+/// Outcome of probing one dependency: its details, or the error string that
+/// made the overall status "degraded".
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Probe<T> {
+    Ok(T),
+    Error(String),
+}
+
+impl<T> Probe<T> {
+    fn from_result<E: std::fmt::Display>(result: std::result::Result<T, E>) -> Self {
+        match result {
+            Ok(value) => Probe::Ok(value),
+            Err(e) => Probe::Error(format!("error: {e}")),
+        }
+    }
+
+    fn is_error(&self) -> bool {
+        matches!(self, Probe::Error(_))
+    }
+}
+
+#[derive(Serialize)]
+pub struct SchedulerStatus {
+    pub running: bool,
+    pub jobs_count: usize,
+    pub jobs: Vec<JobInfo>,
+}
+
+#[derive(Serialize)]
+pub struct MonitoringStatus {
+    pub active_subdomains: i64,
+}
+
+#[derive(Serialize)]
+pub struct Services {
+    pub database: Probe<&'static str>,
+    pub scheduler: SchedulerStatus,
+    pub monitoring: Probe<MonitoringStatus>,
+}
+
+#[derive(Serialize)]
+pub struct HealthReport {
+    pub status: &'static str,
+    pub timestamp: String,
+    pub services: Services,
+}
+
+/// `GET /api/health`: database connectivity, scheduler jobs and the number of
+/// active subdomains. Failing probes mark the report "degraded" rather than
+/// failing the request, so load balancers always get a body to inspect.
+pub async fn health_check(state: web::Data<AppState>) -> Result<HttpResponse> {
+    let database = Probe::from_result(db::ping(&state.pool).await.map(|_| "connected"));
+
+    let jobs = if state.scheduler.is_running() {
+        state.scheduler.jobs()
+    } else {
+        Vec::new()
+    };
+    let scheduler = SchedulerStatus {
+        running: state.scheduler.is_running(),
+        jobs_count: jobs.len(),
+        jobs,
+    };
+
+    let monitoring = Probe::from_result(
+        subdomains::count_active(&state.pool)
+            .await
+            .map(|active_subdomains| MonitoringStatus { active_subdomains }),
+    );
+
+    let status = if database.is_error() || monitoring.is_error() {
+        "degraded"
+    } else {
+        "healthy"
+    };
+
+    Ok(HttpResponse::Ok().json(HealthReport {
+        status,
+        timestamp: Utc::now()
+            .naive_utc()
+            .format("%Y-%m-%dT%H:%M:%S%.6f")
+            .to_string(),
+        services: Services {
+            database,
+            scheduler,
+            monitoring,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use actix_web::{test, App};
+    use serde_json::Value;
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::*;
+
+    async fn get_health(state: AppState) -> Value {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .route("/api/health", web::get().to(health_check)),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/api/health").to_request();
+        test::call_and_read_body_json(&app, request).await
+    }
+
+    #[actix_web::test]
+    async fn reports_degraded_with_the_python_shape() {
+        // Nothing listens on port 1, so both database probes fail.
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(200))
+            .connect_lazy("postgres://monitor@127.0.0.1:1/monitoring")
+            .unwrap();
+        let state = AppState::for_tests(pool);
+        let scheduler = Arc::clone(&state.scheduler);
+        scheduler.every(
+            "uptime_check",
+            "Uptime Check",
+            Duration::from_secs(60),
+            || async {},
+        );
+
+        let body = get_health(state).await;
+        assert_eq!(body["status"], "degraded");
+        let timestamp = body["timestamp"].as_str().unwrap();
+        assert!(chrono::NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S%.6f").is_ok());
+        let services = &body["services"];
+        assert!(services["database"]
+            .as_str()
+            .unwrap()
+            .starts_with("error: "));
+        assert!(services["monitoring"]
+            .as_str()
+            .unwrap()
+            .starts_with("error: "));
+        assert_eq!(services["scheduler"]["running"], true);
+        assert_eq!(services["scheduler"]["jobs_count"], 1);
+        let job = &services["scheduler"]["jobs"][0];
+        assert_eq!(job["id"], "uptime_check");
+        assert_eq!(job["name"], "Uptime Check");
+        assert_eq!(job["trigger"], "interval[0:01:00]");
+        assert!(job["next_run_time"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn reports_healthy_with_a_database() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let body = get_health(AppState::for_tests(pool)).await;
+        assert_eq!(body["status"], "healthy");
+        assert_eq!(body["services"]["database"], "connected");
+        assert!(body["services"]["monitoring"]["active_subdomains"].is_i64());
+        assert_eq!(body["services"]["scheduler"]["running"], false);
+        assert_eq!(body["services"]["scheduler"]["jobs"], serde_json::json!([]));
+    }
+}
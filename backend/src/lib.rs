@@ -0,0 +1,18 @@
+pub mod admin;
+pub mod agents;
+pub mod alerts;
+pub mod checker;
+pub mod config;
+pub mod db;
+pub mod discovery;
+pub mod dns;
+pub mod error;
+pub mod fingerprint;
+pub mod health;
+pub mod metrics;
+pub mod notify;
+pub mod scheduler;
+pub mod state;
+pub mod status;
+pub mod subdomains;
+pub mod x509;
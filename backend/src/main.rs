@@ -1,4 +1,22 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use actix_web::{web, App, HttpResponse, HttpServer, Result};
+use bettergov_api::agents::liveness;
+use bettergov_api::checker::dns::DnsChecker;
+use bettergov_api::checker::tls::TlsChecker;
+use bettergov_api::checker::UptimeChecker;
+use bettergov_api::config::{Cli, Config};
+use bettergov_api::db::{agent_reports, report_nonces};
+use bettergov_api::discovery::{self, Discovery};
+use bettergov_api::error::ApiError;
+use bettergov_api::fingerprint::RuleSet;
+use bettergov_api::notify::Dispatcher;
+use bettergov_api::scheduler::Scheduler;
+use bettergov_api::state::AppState;
+use bettergov_api::subdomains::SubdomainCache;
+use bettergov_api::{agents, alerts, db, health, metrics, subdomains};
+use clap::Parser;
 
 async fn simple_test() -> Result<HttpResponse> {
     println!("Simple test endpoint called");
@@ -11,14 +29,326 @@ async fn root() -> Result<HttpResponse> {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    println!("🚀 Starting Rust API server on port 8000");
+    let config = match Config::load(&Cli::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+
+    let notifiers = match config.notifications.build() {
+        Ok(notifiers) => notifiers,
+        Err(e) => {
+            eprintln!("invalid configuration: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    let discovery_sources = match config.discovery.build() {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("invalid configuration: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    let tls_prober = match config.tls.prober() {
+        Ok(prober) => prober,
+        Err(e) => {
+            eprintln!("invalid configuration: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    let pool = db::connect(config.database_url(), &config.pool_settings())
+        .await
+        .map_err(std::io::Error::other)?;
+
+    let notifier = Arc::new(Dispatcher::new(
+        pool.clone(),
+        notifiers,
+        config.notifications.retry_policy(),
+    ));
+
+    let subdomain_cache = Arc::new(
+        SubdomainCache::load(&pool)
+            .await
+            .map_err(std::io::Error::other)?,
+    );
+
+    let scheduler = Arc::new(Scheduler::default());
+    let checker_settings = config.checker_settings();
+    let fingerprints = RuleSet::builtin().map_err(std::io::Error::other)?;
+    let checker = Arc::new(
+        UptimeChecker::new(
+            pool.clone(),
+            fingerprints,
+            config.status.clone(),
+            &checker_settings,
+            Arc::clone(&notifier),
+            Arc::clone(&subdomain_cache),
+        )
+        .map_err(std::io::Error::other)?,
+    );
+    scheduler.every(
+        "uptime_check",
+        "Uptime Check",
+        checker_settings.interval,
+        move || {
+            let checker = Arc::clone(&checker);
+            async move { checker.run_checks().await }
+        },
+    );
+
+    if config.tls.interval_secs > 0 {
+        let tls_checker = Arc::new(TlsChecker::new(
+            pool.clone(),
+            tls_prober,
+            Arc::clone(&notifier),
+        ));
+        scheduler.every(
+            "tls_check",
+            "TLS Certificate Check",
+            Duration::from_secs(config.tls.interval_secs),
+            move || {
+                let checker = Arc::clone(&tls_checker);
+                async move { checker.run_checks().await }
+            },
+        );
+    }
+
+    if config.dns.interval_secs > 0 {
+        let dns_checker = Arc::new(DnsChecker::new(
+            pool.clone(),
+            config.dns.clone(),
+            Arc::clone(&notifier),
+        ));
+        scheduler.every(
+            "dns_check",
+            "DNS Check",
+            Duration::from_secs(config.dns.interval_secs),
+            move || {
+                let checker = Arc::clone(&dns_checker);
+                async move { checker.run_checks().await }
+            },
+        );
+    }
+
+    if config.discovery.interval_secs > 0 {
+        let discovery = Arc::new(Discovery::new(
+            config.subdomains.domains.clone(),
+            discovery_sources,
+        ));
+        let discovery_pool = pool.clone();
+        scheduler.every(
+            "subdomain_discovery",
+            "Subdomain Discovery",
+            Duration::from_secs(config.discovery.interval_secs),
+            move || {
+                let discovery = Arc::clone(&discovery);
+                let pool = discovery_pool.clone();
+                async move { discovery.run_and_save(&pool).await }
+            },
+        );
+    }
+
+    let liveness = config.liveness_settings();
+    let liveness_pool = pool.clone();
+    let liveness_settings = liveness.clone();
+    let liveness_notifier = Arc::clone(&notifier);
+    scheduler.every(
+        "agent_liveness",
+        "Agent Liveness",
+        Duration::from_secs(60),
+        move || {
+            let pool = liveness_pool.clone();
+            let settings = liveness_settings.clone();
+            let notifier = Arc::clone(&liveness_notifier);
+            async move {
+                if let Err(e) = liveness::track(&pool, &settings, &notifier).await {
+                    eprintln!("Agent liveness check failed: {e}");
+                }
+            }
+        },
+    );
+
+    // A nonce only needs remembering while its report's timestamp is still
+    // accepted, i.e. up to twice the allowed skew after it was received.
+    // Report history only feeds the liveness tracker's recent statistics.
+    let nonce_ttl = chrono::Duration::seconds(2 * config.agents.max_clock_skew_secs as i64);
+    let report_ttl = chrono::Duration::days(7);
+    let purge_pool = pool.clone();
+    scheduler.every(
+        "report_purge",
+        "Agent Report Purge",
+        Duration::from_secs(3600),
+        move || {
+            let pool = purge_pool.clone();
+            async move {
+                let now = chrono::Utc::now();
+                if let Err(e) = report_nonces::purge(&pool, now - nonce_ttl).await {
+                    eprintln!("Report nonce purge failed: {e}");
+                }
+                if let Err(e) = agent_reports::purge(&pool, now - report_ttl).await {
+                    eprintln!("Agent report purge failed: {e}");
+                }
+            }
+        },
+    );
+
+    let state = web::Data::new(AppState {
+        pool,
+        scheduler,
+        thresholds: config.status.clone(),
+        agents: config.agents.clone(),
+        liveness,
+        subdomains: config.subdomains.clone(),
+        admin_token: config.admin_token(),
+        notifier,
+        subdomain_cache,
+    });
+
+    let listen = &config.server.listen;
+    let addresses: Vec<String> = listen.iter().map(ToString::to_string).collect();
+    println!("Starting API server on {}", addresses.join(", "));
 
-    HttpServer::new(|| {
+    let mut server = HttpServer::new(move || {
         App::new()
+            .app_data(state.clone())
+            .app_data(web::JsonConfig::default().error_handler(|e, _| {
+                ApiError::BadRequest(format!("Invalid JSON body: {e}")).into()
+            }))
+            .app_data(web::QueryConfig::default().error_handler(|e, _| {
+                ApiError::BadRequest(format!("Invalid query string: {e}")).into()
+            }))
             .route("/", web::get().to(root))
             .route("/simple-test", web::get().to(simple_test))
-    })
-    .bind("0.0.0.0:8000")?
-    .run()
-    .await
+            .route("/api/health", web::get().to(health::health_check))
+            .route("/api/metrics", web::get().to(metrics::get_metrics))
+            .route("/api/metrics", web::post().to(metrics::insert_metrics))
+            .route(
+                "/api/subdomains",
+                web::get().to(subdomains::list_subdomains),
+            )
+            .route(
+                "/api/subdomains/{subdomain}/checks",
+                web::get().to(subdomains::subdomain_checks),
+            )
+            .route(
+                "/api/subdomains/{subdomain}/tls",
+                web::get().to(subdomains::subdomain_tls),
+            )
+            .route("/api/tls", web::get().to(subdomains::list_certificates))
+            .route(
+                "/api/subdomains/{subdomain}/dns",
+                web::get().to(subdomains::subdomain_dns),
+            )
+            .route("/api/locations", web::get().to(subdomains::all_locations))
+            .route(
+                "/api/subdomains/{subdomain}/locations",
+                web::get().to(subdomains::subdomain_locations),
+            )
+            .route(
+                "/api/admin/subdomains",
+                web::post().to(subdomains::inventory::create_subdomain),
+            )
+            .route(
+                "/api/admin/subdomains/{subdomain}",
+                web::put().to(subdomains::inventory::update_subdomain),
+            )
+            .route(
+                "/api/admin/subdomains/{subdomain}",
+                web::delete().to(subdomains::inventory::delete_subdomain),
+            )
+            .route(
+                "/api/admin/subdomains/{subdomain}/activate",
+                web::post().to(subdomains::inventory::activate_subdomain),
+            )
+            .route(
+                "/api/admin/subdomains/{subdomain}/deactivate",
+                web::post().to(subdomains::inventory::deactivate_subdomain),
+            )
+            .route(
+                "/api/admin/discovery/zone",
+                web::post().to(discovery::zone::import_zone),
+            )
+            .service(
+                web::resource("/api/admin/discovery/ct")
+                    .app_data(web::PayloadConfig::new(discovery::ct::MAX_IMPORT_BYTES))
+                    .route(web::post().to(discovery::ct::import_ct)),
+            )
+            .route("/api/alerts", web::get().to(alerts::list_alerts))
+            .route("/api/alerts/{id}", web::get().to(alerts::get_alert))
+            .route(
+                "/api/alerts/{id}/acknowledge",
+                web::post().to(alerts::acknowledge_alert),
+            )
+            .route(
+                "/api/alerts/{id}/resolve",
+                web::post().to(alerts::resolve_alert),
+            )
+            .route(
+                "/api/geo-report",
+                web::post().to(agents::report::receive_geo_report),
+            )
+            .route(
+                "/api/admin/agents",
+                web::get().to(agents::registry::list_agents),
+            )
+            .route(
+                "/api/admin/agents",
+                web::post().to(agents::registry::register_agent),
+            )
+            .route(
+                "/api/admin/agents/{location}",
+                web::get().to(agents::registry::get_agent),
+            )
+            .route(
+                "/api/admin/agents/{location}",
+                web::put().to(agents::registry::update_agent),
+            )
+            .route(
+                "/api/admin/agents/{location}",
+                web::delete().to(agents::registry::delete_agent),
+            )
+            .route(
+                "/api/admin/agents/{location}/disable",
+                web::post().to(agents::registry::disable_agent),
+            )
+            .route(
+                "/api/admin/agents/{location}/enable",
+                web::post().to(agents::registry::enable_agent),
+            )
+            .route(
+                "/api/admin/agents/{location}/keys",
+                web::get().to(agents::keys::list_keys),
+            )
+            .route(
+                "/api/admin/agents/{location}/enrollment-codes",
+                web::get().to(agents::enrollment::list_codes),
+            )
+            .route(
+                "/api/admin/agents/{location}/enrollment-codes",
+                web::post().to(agents::enrollment::mint_code),
+            )
+            .route("/api/admin/audit", web::get().to(agents::audit::list_audit))
+            .route("/api/agent-status", web::get().to(liveness::agent_status))
+            .route(
+                "/api/agents/enroll",
+                web::post().to(agents::enrollment::enroll),
+            )
+            .route(
+                "/api/admin/agents/{location}/keys",
+                web::post().to(agents::keys::rotate_key),
+            )
+            .route(
+                "/api/admin/agents/{location}/keys/{key_id}",
+                web::delete().to(agents::keys::revoke_key),
+            )
+    });
+    if let Some(workers) = config.server.workers {
+        server = server.workers(workers);
+    }
+    server.bind(listen.as_slice())?.run().await
 }
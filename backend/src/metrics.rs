@@ -0,0 +1,543 @@
+//! `/api/metrics`: ingest of host/service metrics and paginated queries
+//! over them.
+
+use actix_web::{web, HttpResponse};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::db::metrics::{self, Metric, MetricFilter, MetricKey};
+use crate::error::{ApiError, FieldError};
+use crate::state::AppState;
+
+const DEFAULT_HOURS: i64 = 24;
+/// A year.
+const MAX_HOURS: i64 = 8784;
+const DEFAULT_PAGE_SIZE: i64 = 1000;
+const MAX_PAGE_SIZE: i64 = 10_000;
+/// Most metrics accepted in one POST.
+const MAX_BATCH: usize = 10_000;
+/// How far ahead of the server clock a metric timestamp may be.
+const MAX_CLOCK_AHEAD: Duration = Duration::minutes(5);
+
+/// `GET /api/metrics` parameters. The range is `from`..`to`; without `from`
+/// it covers the `hours` (default 24) before `to` or now.
+#[derive(Debug, Deserialize)]
+pub struct MetricsQuery {
+    pub host: Option<String>,
+    pub service: Option<String>,
+    pub metric_name: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub hours: Option<i64>,
+    pub limit: Option<i64>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetricsPage {
+    pub metrics: Vec<Metric>,
+    /// Pass as `cursor` to fetch the next page; `null` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+fn encode_cursor(key: &MetricKey) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(key).unwrap_or_default())
+}
+
+fn decode_cursor(cursor: &str) -> Option<MetricKey> {
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// `GET /api/metrics`: metrics matching the filters, newest first.
+pub async fn get_metrics(
+    state: web::Data<AppState>,
+    query: web::Query<MetricsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    let mut errors = Vec::new();
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        errors.push(FieldError::new(
+            None,
+            "limit",
+            format!("must be between 1 and {MAX_PAGE_SIZE}"),
+        ));
+    }
+    let from = match (query.from, query.hours) {
+        (Some(_), Some(_)) => {
+            errors.push(FieldError::new(
+                None,
+                "hours",
+                "cannot be combined with from",
+            ));
+            None
+        }
+        (Some(from), None) => Some(from),
+        (None, hours) => {
+            let hours = hours.unwrap_or(DEFAULT_HOURS);
+            if !(1..=MAX_HOURS).contains(&hours) {
+                errors.push(FieldError::new(
+                    None,
+                    "hours",
+                    format!("must be between 1 and {MAX_HOURS}"),
+                ));
+            }
+            let from = query
+                .to
+                .unwrap_or_else(Utc::now)
+                .checked_sub_signed(Duration::hours(hours.clamp(1, MAX_HOURS)));
+            if from.is_none() {
+                errors.push(FieldError::new(None, "to", "is out of range"));
+            }
+            from
+        }
+    };
+    if let (Some(from), Some(to)) = (from, query.to) {
+        if from >= to {
+            errors.push(FieldError::new(None, "to", "must be after from"));
+        }
+    }
+    let after = match query.cursor.as_deref() {
+        Some(cursor) => {
+            let key = decode_cursor(cursor);
+            if key.is_none() {
+                errors.push(FieldError::new(None, "cursor", "is not a valid cursor"));
+            }
+            key
+        }
+        None => None,
+    };
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let filter = MetricFilter {
+        host: query.host.as_deref(),
+        service: query.service.as_deref(),
+        metric_name: query.metric_name.as_deref(),
+        from,
+        to: query.to,
+    };
+    // One extra row tells whether another page follows.
+    let mut rows = metrics::list(&state.pool, &filter, after.as_ref(), limit + 1).await?;
+    let next_cursor = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last()
+            .and_then(Metric::key)
+            .map(|key| encode_cursor(&key))
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(MetricsPage {
+        metrics: rows,
+        next_cursor,
+    }))
+}
+
+/// RFC 3339, or a naive ISO 8601 timestamp taken as UTC, which is what
+/// Python's `datetime.utcnow().isoformat()` produces.
+fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f").map(|t| t.and_utc()))
+        .ok()
+}
+
+fn required_text(
+    index: Option<usize>,
+    fields: &mut Map<String, Value>,
+    name: &str,
+    errors: &mut Vec<FieldError>,
+) -> String {
+    match fields.remove(name) {
+        Some(Value::String(s)) if !s.trim().is_empty() => s.trim().to_string(),
+        Some(Value::String(_)) => {
+            errors.push(FieldError::new(index, name, "must not be empty"));
+            String::new()
+        }
+        Some(_) => {
+            errors.push(FieldError::new(index, name, "must be a string"));
+            String::new()
+        }
+        None => {
+            errors.push(FieldError::new(index, name, "is required"));
+            String::new()
+        }
+    }
+}
+
+/// Validates one submitted metric, collecting every problem with it rather
+/// than stopping at the first.
+fn parse_metric(
+    index: Option<usize>,
+    item: Value,
+    now: DateTime<Utc>,
+    errors: &mut Vec<FieldError>,
+) -> Option<Metric> {
+    let before = errors.len();
+    let Value::Object(mut fields) = item else {
+        errors.push(FieldError::new(index, "", "must be a JSON object"));
+        return None;
+    };
+
+    let host = required_text(index, &mut fields, "host", errors);
+    let service = required_text(index, &mut fields, "service", errors);
+    let metric_name = required_text(index, &mut fields, "metric_name", errors);
+
+    let value = match fields.remove("value") {
+        Some(Value::Number(n)) => n.as_f64(),
+        Some(Value::Null) => None,
+        Some(_) => {
+            errors.push(FieldError::new(index, "value", "must be a number or null"));
+            None
+        }
+        None => {
+            errors.push(FieldError::new(index, "value", "is required"));
+            None
+        }
+    };
+
+    let time = match fields.remove("time") {
+        None | Some(Value::Null) => now,
+        Some(Value::String(s)) => match parse_time(&s) {
+            Some(time) if time > now + MAX_CLOCK_AHEAD => {
+                errors.push(FieldError::new(index, "time", "is in the future"));
+                now
+            }
+            Some(time) => time,
+            None => {
+                errors.push(FieldError::new(
+                    index,
+                    "time",
+                    "is not an ISO 8601 timestamp",
+                ));
+                now
+            }
+        },
+        Some(_) => {
+            errors.push(FieldError::new(index, "time", "must be a string"));
+            now
+        }
+    };
+
+    let status = match fields.remove("status") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(s)) => Some(s),
+        Some(_) => {
+            errors.push(FieldError::new(index, "status", "must be a string"));
+            None
+        }
+    };
+
+    let metadata = match fields.remove("metadata") {
+        None | Some(Value::Null) => Value::Object(Map::new()),
+        Some(Value::Object(map)) => Value::Object(map),
+        Some(_) => {
+            errors.push(FieldError::new(index, "metadata", "must be an object"));
+            Value::Null
+        }
+    };
+
+    for unknown in fields.keys() {
+        errors.push(FieldError::new(
+            index,
+            unknown.as_str(),
+            "is not a known field",
+        ));
+    }
+
+    (errors.len() == before).then_some(Metric {
+        id: None,
+        time,
+        host,
+        service,
+        metric_name,
+        value,
+        status,
+        metadata: Some(metadata),
+    })
+}
+
+/// `POST /api/metrics`: stores one metric object or an array of them.
+///
+/// A batch is all or nothing: if any item is invalid, nothing is written and
+/// the 422 response lists every bad field with the index of its item.
+pub async fn insert_metrics(
+    state: web::Data<AppState>,
+    body: web::Json<Value>,
+) -> Result<HttpResponse, ApiError> {
+    let now = Utc::now();
+    let mut errors = Vec::new();
+    let parsed: Vec<Metric> = match body.into_inner() {
+        Value::Array(items) => {
+            let mut batch = Vec::with_capacity(items.len());
+            if items.is_empty() {
+                errors.push(FieldError::new(None, "", "batch is empty"));
+            } else if items.len() > MAX_BATCH {
+                errors.push(FieldError::new(
+                    None,
+                    "",
+                    format!("batch exceeds {MAX_BATCH} metrics"),
+                ));
+            } else {
+                for (index, item) in items.into_iter().enumerate() {
+                    if let Some(metric) = parse_metric(Some(index), item, now, &mut errors) {
+                        batch.push(metric);
+                    }
+                }
+            }
+            batch
+        }
+        item => parse_metric(None, item, now, &mut errors)
+            .into_iter()
+            .collect(),
+    };
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    metrics::insert_many(&state.pool, &parsed).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "inserted",
+        "count": parsed.len(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration as StdDuration;
+
+    use actix_web::http::StatusCode;
+    use actix_web::{test as actix_test, App};
+    use serde_json::json;
+    use sqlx::postgres::PgPoolOptions;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::db;
+
+    fn fields(errors: &[FieldError]) -> Vec<(Option<usize>, &str)> {
+        errors.iter().map(|e| (e.index, e.field.as_str())).collect()
+    }
+
+    /// A pool that never connects, for requests rejected before any query.
+    fn unreachable_pool() -> PgPool {
+        PgPoolOptions::new()
+            .acquire_timeout(StdDuration::from_millis(200))
+            .connect_lazy("postgres://monitor@127.0.0.1:1/monitoring")
+            .unwrap()
+    }
+
+    async fn call(pool: PgPool, request: actix_test::TestRequest) -> (StatusCode, Value) {
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(AppState::for_tests(pool)))
+                .route("/api/metrics", web::get().to(get_metrics))
+                .route("/api/metrics", web::post().to(insert_metrics)),
+        )
+        .await;
+        let response = actix_test::call_service(&app, request.to_request()).await;
+        let status = response.status();
+        (status, actix_test::read_body_json(response).await)
+    }
+
+    #[test]
+    fn parses_metrics_and_names_every_bad_field() {
+        let now = Utc::now();
+        let mut errors = Vec::new();
+        let metric = parse_metric(
+            None,
+            json!({
+                "host": " web-01 ",
+                "service": "nginx",
+                "metric_name": "cpu_usage",
+                "value": 45.2,
+                "time": "2025-10-01T08:30:00.123456",
+                "metadata": {"cores": 4},
+            }),
+            now,
+            &mut errors,
+        )
+        .unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(metric.host, "web-01");
+        assert_eq!(metric.value, Some(45.2));
+        assert_eq!(
+            metric.time,
+            parse_time("2025-10-01T08:30:00.123456Z").unwrap()
+        );
+
+        let metric = parse_metric(
+            Some(0),
+            json!({"host": "h", "service": "s", "metric_name": "m", "value": null}),
+            now,
+            &mut errors,
+        )
+        .unwrap();
+        assert_eq!((metric.time, metric.value), (now, None));
+        assert_eq!(metric.metadata, Some(json!({})));
+
+        let future = (now + Duration::hours(1)).to_rfc3339();
+        let rejected = parse_metric(
+            Some(3),
+            json!({
+                "host": "",
+                "service": 7,
+                "value": "high",
+                "time": future,
+                "status": false,
+                "metadata": [],
+                "unit": "%",
+            }),
+            now,
+            &mut errors,
+        );
+        assert!(rejected.is_none());
+        assert_eq!(
+            fields(&errors),
+            [
+                (Some(3), "host"),
+                (Some(3), "service"),
+                (Some(3), "metric_name"),
+                (Some(3), "value"),
+                (Some(3), "time"),
+                (Some(3), "status"),
+                (Some(3), "metadata"),
+                (Some(3), "unit"),
+            ]
+        );
+        assert_eq!(errors[2].message, "is required");
+        assert_eq!(errors[4].message, "is in the future");
+
+        errors.clear();
+        assert!(parse_metric(None, json!([1]), now, &mut errors).is_none());
+        assert_eq!(errors[0].message, "must be a JSON object");
+        errors.clear();
+        parse_metric(
+            None,
+            json!({"host": "h", "service": "s", "metric_name": "m", "value": 1, "time": "yesterday"}),
+            now,
+            &mut errors,
+        );
+        assert_eq!(errors[0].message, "is not an ISO 8601 timestamp");
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let key = MetricKey {
+            time: parse_time("2025-10-01T08:30:00.5Z").unwrap(),
+            id: 42,
+        };
+        assert_eq!(decode_cursor(&encode_cursor(&key)), Some(key));
+        assert_eq!(decode_cursor("not a cursor"), None);
+        assert_eq!(decode_cursor(&URL_SAFE_NO_PAD.encode(b"{}")), None);
+    }
+
+    #[actix_web::test]
+    async fn rejects_bad_batches_with_item_indexes() {
+        let ok = json!({"host": "h", "service": "s", "metric_name": "m", "value": 1});
+        let batch = json!([ok, {"host": "h", "service": "s", "value": 1}, ok, "cpu"]);
+        let (status, body) = call(
+            unreachable_pool(),
+            actix_test::TestRequest::post()
+                .uri("/api/metrics")
+                .set_json(batch),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body["errors"],
+            json!([
+                {"index": 1, "field": "metric_name", "message": "is required"},
+                {"index": 3, "field": "", "message": "must be a JSON object"},
+            ])
+        );
+
+        let (status, body) = call(
+            unreachable_pool(),
+            actix_test::TestRequest::post()
+                .uri("/api/metrics")
+                .set_json(json!([])),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["message"], "batch is empty");
+    }
+
+    #[actix_web::test]
+    async fn bounds_the_query_range() {
+        for uri in [
+            "/api/metrics?hours=10000000000",
+            "/api/metrics?hours=0",
+            "/api/metrics?hours=9000",
+        ] {
+            let (status, body) =
+                call(unreachable_pool(), actix_test::TestRequest::get().uri(uri)).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{uri}");
+            assert_eq!(body["errors"][0]["field"], "hours");
+        }
+        let (status, body) = call(
+            unreachable_pool(),
+            actix_test::TestRequest::get().uri("/api/metrics?cursor=bogus&limit=0"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "limit");
+        assert_eq!(body["errors"][1]["field"], "cursor");
+    }
+
+    #[actix_web::test]
+    async fn pages_through_metrics_sharing_a_timestamp() {
+        let Some(pool) = db::test_pool().await else {
+            return;
+        };
+        let host = format!("metrics-test-{}", std::process::id());
+        let item = json!({"host": host, "service": "s", "metric_name": "m", "value": 1});
+        let batch = Value::Array(vec![item; 5]);
+        let (status, _) = call(
+            pool.clone(),
+            actix_test::TestRequest::post()
+                .uri("/api/metrics")
+                .set_json(batch),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let mut ids = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let mut uri = format!("/api/metrics?host={host}&limit=2");
+            if let Some(cursor) = &cursor {
+                uri.push_str(&format!("&cursor={cursor}"));
+            }
+            let (status, page) = call(pool.clone(), actix_test::TestRequest::get().uri(&uri)).await;
+            assert_eq!(status, StatusCode::OK);
+            ids.extend(
+                page["metrics"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|m| m["id"].as_i64().unwrap()),
+            );
+            match page["next_cursor"].as_str() {
+                Some(next) => cursor = Some(next.to_string()),
+                None => break,
+            }
+        }
+        sqlx::query("DELETE FROM monitoring.metrics WHERE host = $1")
+            .bind(&host)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(ids.len(), 5);
+        assert!(ids.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+}
@@ -0,0 +1,533 @@
+//! Notifications for alerts that open, escalate or resolve.
+//!
+//! Each configured channel is a [`Notifier`]. The [`Dispatcher`] fans an
+//! event out to every channel in the background, retries failed sends with
+//! exponential backoff and records each attempt in
+//! `monitoring.notification_deliveries`.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::db::alerts::Alert;
+use crate::db::notification_deliveries;
+
+pub mod smtp;
+pub mod template;
+pub mod webhook;
+
+pub use smtp::SmtpNotifier;
+pub use template::Template;
+pub use webhook::{ChatFlavor, ChatNotifier, WebhookNotifier};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Opened,
+    /// An open alert was raised to a higher severity.
+    Escalated,
+    Resolved,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Opened => "opened",
+            EventKind::Escalated => "escalated",
+            EventKind::Resolved => "resolved",
+        }
+    }
+}
+
+/// An alert that was just opened, escalated or resolved.
+#[derive(Debug, Clone)]
+pub struct AlertEvent {
+    pub kind: EventKind,
+    pub alert: Alert,
+}
+
+impl AlertEvent {
+    pub fn opened(alert: Alert) -> Self {
+        AlertEvent {
+            kind: EventKind::Opened,
+            alert,
+        }
+    }
+
+    pub fn escalated(alert: Alert) -> Self {
+        AlertEvent {
+            kind: EventKind::Escalated,
+            alert,
+        }
+    }
+
+    pub fn resolved(alert: Alert) -> Self {
+        AlertEvent {
+            kind: EventKind::Resolved,
+            alert,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NotifyError {
+    #[error("invalid template: {0}")]
+    Template(String),
+    #[error("invalid channel {channel:?}: {reason}")]
+    Config { channel: String, reason: String },
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("endpoint answered {status}: {body}")]
+    Status { status: u16, body: String },
+    #[error("SMTP delivery failed: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("cannot build email: {0}")]
+    Email(#[from] lettre::error::Error),
+}
+
+/// A destination for alert notifications.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Channel name from the configuration, used in logs.
+    fn name(&self) -> &str;
+    /// Backend type, e.g. `webhook` or `smtp`.
+    fn kind(&self) -> &'static str;
+    /// Makes one delivery attempt.
+    async fn send(&self, event: &AlertEvent) -> Result<(), NotifyError>;
+}
+
+/// Attempts per delivery and the pause before each retry, doubling from
+/// `initial_backoff` up to `max_backoff`.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 4,
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Sends `event` through `notifier`, retrying per `policy`. `on_attempt`
+/// sees the number and outcome of every attempt, and the next attempt waits
+/// for the future it returns.
+pub async fn send_with_retry<F, Fut>(
+    notifier: &dyn Notifier,
+    event: &AlertEvent,
+    policy: RetryPolicy,
+    mut on_attempt: F,
+) -> Result<(), NotifyError>
+where
+    F: FnMut(u32, &Result<(), NotifyError>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut delay = policy.initial_backoff;
+    let mut attempt = 1;
+    loop {
+        let result = notifier.send(event).await;
+        on_attempt(attempt, &result).await;
+        if result.is_ok() || attempt >= policy.attempts {
+            return result;
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(policy.max_backoff);
+        attempt += 1;
+    }
+}
+
+/// Fans alert events out to every configured channel.
+pub struct Dispatcher {
+    pool: PgPool,
+    notifiers: Vec<Arc<dyn Notifier>>,
+    policy: RetryPolicy,
+}
+
+impl Dispatcher {
+    pub fn new(pool: PgPool, notifiers: Vec<Arc<dyn Notifier>>, policy: RetryPolicy) -> Self {
+        Dispatcher {
+            pool,
+            notifiers,
+            policy,
+        }
+    }
+
+    /// Delivers `events` in the background; never blocks the caller.
+    pub fn dispatch(&self, events: impl IntoIterator<Item = AlertEvent>) {
+        for event in events {
+            for notifier in &self.notifiers {
+                let notifier = Arc::clone(notifier);
+                let pool = self.pool.clone();
+                let policy = self.policy;
+                let event = event.clone();
+                tokio::spawn(
+                    async move { deliver(&pool, notifier.as_ref(), &event, policy).await },
+                );
+            }
+        }
+    }
+}
+
+/// Sends `event` through `notifier`, logging each attempt as it completes.
+async fn deliver(pool: &PgPool, notifier: &dyn Notifier, event: &AlertEvent, policy: RetryPolicy) {
+    let result = send_with_retry(notifier, event, policy, |attempt, result| {
+        let error = result.as_ref().err().map(ToString::to_string);
+        async move {
+            let delivery = notification_deliveries::NewDelivery {
+                alert_id: event.alert.id,
+                channel: notifier.name(),
+                kind: notifier.kind(),
+                event: event.kind.as_str(),
+                attempt: attempt as i32,
+                error: error.as_deref(),
+            };
+            if let Err(e) = notification_deliveries::insert(pool, &delivery).await {
+                eprintln!("Failed to log delivery to {}: {e}", notifier.name());
+            }
+        }
+    })
+    .await;
+
+    match result {
+        Ok(()) => println!(
+            "Notified {} of alert {} {}",
+            notifier.name(),
+            event.alert.id,
+            event.kind.as_str()
+        ),
+        Err(e) => eprintln!(
+            "Giving up notifying {} of alert {} after {} attempts: {e}",
+            notifier.name(),
+            event.alert.id,
+            policy.attempts
+        ),
+    }
+}
+
+/// `[notifications]` section of the server configuration.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationsConfig {
+    pub attempts: u32,
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    pub timeout_secs: u64,
+    pub channels: Vec<ChannelConfig>,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        let policy = RetryPolicy::default();
+        NotificationsConfig {
+            attempts: policy.attempts,
+            initial_backoff_secs: policy.initial_backoff.as_secs(),
+            max_backoff_secs: policy.max_backoff.as_secs(),
+            timeout_secs: 10,
+            channels: Vec::new(),
+        }
+    }
+}
+
+/// One `[[notifications.channels]]` entry, selected by its `type`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ChannelConfig {
+    Webhook(ChatChannel),
+    Slack(ChatChannel),
+    Discord(ChatChannel),
+    Mattermost(ChatChannel),
+    Smtp(smtp::SmtpChannel),
+}
+
+/// Settings of a webhook or chat channel.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatChannel {
+    pub name: String,
+    pub url: String,
+    pub template: Option<String>,
+}
+
+impl NotificationsConfig {
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            attempts: self.attempts,
+            initial_backoff: Duration::from_secs(self.initial_backoff_secs),
+            max_backoff: Duration::from_secs(self.max_backoff_secs),
+        }
+    }
+
+    /// Builds a notifier per channel, rejecting bad URLs, addresses and
+    /// templates.
+    pub fn build(&self) -> Result<Vec<Arc<dyn Notifier>>, NotifyError> {
+        let timeout = Duration::from_secs(self.timeout_secs);
+        let client = webhook::client(timeout)?;
+        self.channels
+            .iter()
+            .map(|channel| -> Result<Arc<dyn Notifier>, NotifyError> {
+                Ok(match channel {
+                    ChannelConfig::Webhook(hook) => {
+                        Arc::new(WebhookNotifier::from_config(client.clone(), hook)?)
+                    }
+                    ChannelConfig::Slack(chat) => Arc::new(ChatNotifier::from_config(
+                        client.clone(),
+                        ChatFlavor::Slack,
+                        chat,
+                    )?),
+                    ChannelConfig::Discord(chat) => Arc::new(ChatNotifier::from_config(
+                        client.clone(),
+                        ChatFlavor::Discord,
+                        chat,
+                    )?),
+                    ChannelConfig::Mattermost(chat) => Arc::new(ChatNotifier::from_config(
+                        client.clone(),
+                        ChatFlavor::Mattermost,
+                        chat,
+                    )?),
+                    ChannelConfig::Smtp(smtp) => {
+                        Arc::new(SmtpNotifier::from_config(smtp, timeout)?)
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    pub fn sample_event(kind: EventKind) -> AlertEvent {
+        let resolved = kind == EventKind::Resolved;
+        AlertEvent {
+            kind,
+            alert: Alert {
+                id: 42,
+                time: Utc.with_ymd_and_hms(2026, 3, 1, 8, 30, 0).unwrap(),
+                host: "api.bettergov.ph".to_string(),
+                service: "uptime".to_string(),
+                severity: "critical".to_string(),
+                message: Some("api.bettergov.ph is DOWN".to_string()),
+                acknowledged: false,
+                acknowledged_at: None,
+                acknowledged_by: None,
+                acknowledge_comment: None,
+                resolved,
+                resolved_at: None,
+                resolved_by: resolved.then(|| "system".to_string()),
+                resolve_comment: resolved.then(|| "Recovered".to_string()),
+            },
+        }
+    }
+
+    /// Fails the first `failures` sends, then succeeds.
+    struct Flaky {
+        failures: u32,
+        calls: AtomicU32,
+    }
+
+    #[async_trait]
+    impl Notifier for Flaky {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn kind(&self) -> &'static str {
+            "test"
+        }
+
+        async fn send(&self, _event: &AlertEvent) -> Result<(), NotifyError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if call <= self.failures {
+                Err(NotifyError::Status {
+                    status: 503,
+                    body: format!("call {call}"),
+                })
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn fast_policy(attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_until_a_send_succeeds() {
+        let notifier = Flaky {
+            failures: 2,
+            calls: AtomicU32::new(0),
+        };
+        let mut seen = Vec::new();
+        let result = send_with_retry(
+            &notifier,
+            &sample_event(EventKind::Opened),
+            fast_policy(4),
+            |attempt, result| {
+                seen.push((attempt, result.is_ok()));
+                async {}
+            },
+        )
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(seen, vec![(1, false), (2, false), (3, true)]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_attempt() {
+        let notifier = Flaky {
+            failures: 10,
+            calls: AtomicU32::new(0),
+        };
+        let result = send_with_retry(
+            &notifier,
+            &sample_event(EventKind::Opened),
+            fast_policy(3),
+            |_, _| async {},
+        )
+        .await;
+        assert!(matches!(result, Err(NotifyError::Status { .. })));
+        assert_eq!(notifier.calls.load(Ordering::SeqCst), 3);
+    }
+
+    /// Fails its first two sends, noting how many attempts were already
+    /// logged when each one starts.
+    struct Watching {
+        pool: PgPool,
+        logged: std::sync::Mutex<Vec<i64>>,
+    }
+
+    #[async_trait]
+    impl Notifier for Watching {
+        fn name(&self) -> &str {
+            "watching"
+        }
+
+        fn kind(&self) -> &'static str {
+            "test"
+        }
+
+        async fn send(&self, event: &AlertEvent) -> Result<(), NotifyError> {
+            let (logged,): (i64,) = sqlx::query_as(
+                "SELECT COUNT(*) FROM monitoring.notification_deliveries WHERE alert_id = $1",
+            )
+            .bind(event.alert.id)
+            .fetch_one(&self.pool)
+            .await
+            .unwrap();
+            self.logged.lock().unwrap().push(logged);
+            if logged < 2 {
+                return Err(NotifyError::Status {
+                    status: 503,
+                    body: "busy".to_string(),
+                });
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_each_attempt_as_it_completes() {
+        let Some(pool) = crate::db::test_pool().await else {
+            return;
+        };
+        let host = format!("notify-{}.bettergov.test", std::process::id());
+        let opened = crate::db::alerts::open(&pool, &host, "uptime", "critical", "down")
+            .await
+            .unwrap();
+        let event = AlertEvent::opened(opened.alert);
+        let notifier = Watching {
+            pool: pool.clone(),
+            logged: Default::default(),
+        };
+        deliver(&pool, &notifier, &event, fast_policy(4)).await;
+        assert_eq!(*notifier.logged.lock().unwrap(), [0, 1, 2]);
+
+        let deliveries = notification_deliveries::list_for_alert(&pool, event.alert.id)
+            .await
+            .unwrap();
+        let attempts: Vec<_> = deliveries
+            .iter()
+            .map(|d| (d.attempt, d.success, d.error.as_deref()))
+            .collect();
+        let failed = Some("endpoint answered 503: busy");
+        assert_eq!(
+            attempts,
+            [(1, false, failed), (2, false, failed), (3, true, None)]
+        );
+        assert!(deliveries
+            .iter()
+            .all(|d| d.channel == "watching" && d.event == "opened"));
+
+        sqlx::query("DELETE FROM monitoring.alerts WHERE host = $1")
+            .bind(&host)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn parses_channel_configs() {
+        let config: NotificationsConfig = toml::from_str(
+            r#"
+            attempts = 2
+
+            [[channels]]
+            type = "webhook"
+            name = "hook"
+            url = "http://127.0.0.1:9/alerts"
+
+            [[channels]]
+            type = "discord"
+            name = "ops"
+            url = "http://127.0.0.1:9/discord"
+            template = "{{host}} {{event}}"
+
+            [[channels]]
+            type = "smtp"
+            name = "mail"
+            host = "127.0.0.1"
+            port = 2525
+            from = "monitor@bettergov.ph"
+            to = ["oncall@bettergov.ph"]
+            "#,
+        )
+        .unwrap();
+        let notifiers = config.build().unwrap();
+        let kinds: Vec<_> = notifiers.iter().map(|n| n.kind()).collect();
+        assert_eq!(kinds, ["webhook", "discord", "smtp"]);
+    }
+
+    #[test]
+    fn rejects_channels_with_bad_templates() {
+        let config: NotificationsConfig = toml::from_str(
+            r#"
+            [[channels]]
+            type = "slack"
+            name = "ops"
+            url = "http://127.0.0.1:9/slack"
+            template = "{{nope}}"
+            "#,
+        )
+        .unwrap();
+        assert!(config.build().is_err());
+    }
+}
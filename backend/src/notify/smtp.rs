@@ -0,0 +1,247 @@
+//! Email notifications over SMTP.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Deserialize;
+
+use super::{AlertEvent, Notifier, NotifyError, Template};
+
+pub const DEFAULT_SUBJECT: &str = "[{{severity}}] {{host}} {{service}} alert {{event}}";
+pub const DEFAULT_BODY: &str = "Alert #{{id}} {{event}}\n\n\
+Host:     {{host}}\n\
+Service:  {{service}}\n\
+Severity: {{severity}}\n\
+Status:   {{status}}\n\
+Since:    {{time}}\n\n\
+{{message}}\n";
+
+/// How the connection to the relay is secured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    /// Plain text, e.g. a relay on localhost.
+    None,
+    /// Upgrade with STARTTLS (port 587).
+    #[default]
+    Starttls,
+    /// TLS from the first byte (port 465).
+    Tls,
+}
+
+/// `type = "smtp"` channel settings.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpChannel {
+    pub name: String,
+    pub host: String,
+    /// Defaults to the standard port for `security`.
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: Security,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: Option<String>,
+    pub body: Option<String>,
+}
+
+pub struct SmtpNotifier {
+    name: String,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    subject: Template,
+    body: Template,
+}
+
+impl SmtpNotifier {
+    pub fn from_config(channel: &SmtpChannel, timeout: Duration) -> Result<Self, NotifyError> {
+        let invalid = |reason: String| NotifyError::Config {
+            channel: channel.name.clone(),
+            reason,
+        };
+        let mailbox = |address: &str| {
+            address
+                .parse::<Mailbox>()
+                .map_err(|e| invalid(format!("bad address {address:?}: {e}")))
+        };
+        if channel.to.is_empty() {
+            return Err(invalid("needs at least one recipient in `to`".to_string()));
+        }
+
+        let mut builder = match channel.security {
+            Security::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&channel.host).port(25)
+            }
+            Security::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&channel.host)?
+            }
+            Security::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&channel.host)?,
+        };
+        if let Some(port) = channel.port {
+            builder = builder.port(port);
+        }
+        match (&channel.username, &channel.password) {
+            (Some(user), Some(password)) => {
+                builder = builder.credentials(Credentials::new(user.clone(), password.clone()));
+            }
+            (None, None) => {}
+            _ => return Err(invalid("username and password go together".to_string())),
+        }
+
+        Ok(SmtpNotifier {
+            name: channel.name.clone(),
+            transport: builder.timeout(Some(timeout)).build(),
+            from: mailbox(&channel.from)?,
+            to: channel
+                .to
+                .iter()
+                .map(|to| mailbox(to))
+                .collect::<Result<_, _>>()?,
+            subject: Template::parse(channel.subject.as_deref().unwrap_or(DEFAULT_SUBJECT))?,
+            body: Template::parse(channel.body.as_deref().unwrap_or(DEFAULT_BODY))?,
+        })
+    }
+
+    fn message(&self, event: &AlertEvent) -> Result<Message, NotifyError> {
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .subject(self.subject.render(event))
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+        Ok(builder.body(self.body.render(event))?)
+    }
+}
+
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(&self, event: &AlertEvent) -> Result<(), NotifyError> {
+        let message = self.message(event)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::notify::tests::sample_event;
+    use crate::notify::EventKind;
+
+    /// A one-shot SMTP sink: accepts a single message and hands back the
+    /// envelope commands and the DATA section once it is queued.
+    async fn smtp_sink() -> (u16, oneshot::Receiver<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = oneshot::channel();
+        let mut tx = Some(tx);
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            let mut commands = Vec::new();
+            let mut data = String::new();
+            write.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let verb = line.split_whitespace().next().unwrap_or("").to_uppercase();
+                let reply: &[u8] = match verb.as_str() {
+                    "EHLO" | "HELO" => b"250 sink\r\n",
+                    "DATA" => {
+                        write.write_all(b"354 go ahead\r\n").await.unwrap();
+                        while let Some(line) = lines.next_line().await.unwrap() {
+                            if line == "." {
+                                break;
+                            }
+                            data.push_str(&line);
+                            data.push('\n');
+                        }
+                        if let Some(tx) = tx.take() {
+                            let _ = tx.send((commands.clone(), data.clone()));
+                        }
+                        b"250 queued\r\n"
+                    }
+                    "QUIT" => {
+                        write.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => b"250 ok\r\n",
+                };
+                commands.push(line);
+                write.write_all(reply).await.unwrap();
+            }
+        });
+        (port, rx)
+    }
+
+    fn channel(port: u16) -> SmtpChannel {
+        SmtpChannel {
+            name: "mail".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: Security::None,
+            username: None,
+            password: None,
+            from: "Monitoring <monitor@bettergov.ph>".to_string(),
+            to: vec!["oncall@bettergov.ph".to_string()],
+            subject: None,
+            body: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_templated_mail_to_the_relay() {
+        let (port, received) = smtp_sink().await;
+        let notifier = SmtpNotifier::from_config(&channel(port), Duration::from_secs(5)).unwrap();
+        notifier
+            .send(&sample_event(EventKind::Opened))
+            .await
+            .unwrap();
+
+        let (commands, data) = received.await.unwrap();
+        assert!(commands
+            .iter()
+            .any(|c| c == "MAIL FROM:<monitor@bettergov.ph>"));
+        assert!(commands
+            .iter()
+            .any(|c| c == "RCPT TO:<oncall@bettergov.ph>"));
+        assert!(
+            data.contains("Subject: [CRITICAL] api.bettergov.ph uptime alert opened"),
+            "{data}"
+        );
+        assert!(data.contains("api.bettergov.ph is DOWN"), "{data}");
+    }
+
+    #[test]
+    fn rejects_bad_addresses() {
+        let mut config = channel(2525);
+        config.to = vec!["not an address".to_string()];
+        assert!(SmtpNotifier::from_config(&config, Duration::from_secs(5)).is_err());
+    }
+
+    #[test]
+    fn rejects_half_configured_credentials() {
+        let mut config = channel(2525);
+        config.username = Some("monitor".to_string());
+        assert!(SmtpNotifier::from_config(&config, Duration::from_secs(5)).is_err());
+    }
+}
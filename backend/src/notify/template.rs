@@ -0,0 +1,114 @@
+//! `{{placeholder}}` message templates. Placeholders are checked when a
+//! template is parsed, so a typo fails at startup instead of when an alert
+//! fires.
+
+use super::{AlertEvent, NotifyError};
+
+/// Names a template may refer to.
+pub const PLACEHOLDERS: [&str; 10] = [
+    "event", "id", "host", "service", "severity", "message", "time", "actor", "comment", "status",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, NotifyError> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let after = &rest[start + 2..];
+            let end = after.find("}}").ok_or_else(|| {
+                NotifyError::Template(format!("unclosed placeholder in {source:?}"))
+            })?;
+            let name = after[..end].trim();
+            let field = PLACEHOLDERS.iter().find(|p| **p == name).ok_or_else(|| {
+                NotifyError::Template(format!(
+                    "unknown placeholder {{{{{name}}}}}; expected one of {}",
+                    PLACEHOLDERS.join(", ")
+                ))
+            })?;
+            parts.push(Part::Field(field));
+            rest = &after[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template { parts })
+    }
+
+    pub fn render(&self, event: &AlertEvent) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Field(name) => out.push_str(&field(event, name)),
+            }
+        }
+        out
+    }
+}
+
+fn field(event: &AlertEvent, name: &str) -> String {
+    let alert = &event.alert;
+    match name {
+        "event" => event.kind.as_str().to_string(),
+        "id" => alert.id.to_string(),
+        "host" => alert.host.clone(),
+        "service" => alert.service.clone(),
+        "severity" => alert.severity.to_uppercase(),
+        "message" => alert.message.clone().unwrap_or_default(),
+        "time" => alert.time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        "actor" => alert.resolved_by.clone().unwrap_or_default(),
+        "comment" => alert.resolve_comment.clone().unwrap_or_default(),
+        "status" => if alert.resolved { "RESOLVED" } else { "OPEN" }.to_string(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notify::tests::sample_event;
+    use crate::notify::EventKind;
+
+    #[test]
+    fn renders_placeholders() {
+        let template = Template::parse("[{{severity}}] {{ host }} {{event}}: {{message}}").unwrap();
+        assert_eq!(
+            template.render(&sample_event(EventKind::Opened)),
+            "[CRITICAL] api.bettergov.ph opened: api.bettergov.ph is DOWN"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        let err = Template::parse("{{hots}} is down").unwrap_err();
+        assert!(err.to_string().contains("hots"), "{err}");
+    }
+
+    #[test]
+    fn rejects_unclosed_placeholders() {
+        assert!(Template::parse("{{host is down").is_err());
+    }
+
+    #[test]
+    fn keeps_text_without_placeholders() {
+        let template = Template::parse("plain text").unwrap();
+        assert_eq!(
+            template.render(&sample_event(EventKind::Resolved)),
+            "plain text"
+        );
+    }
+}
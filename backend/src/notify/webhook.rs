@@ -0,0 +1,355 @@
+//! HTTP notifiers: a generic JSON webhook, and Slack, Discord and
+//! Mattermost incoming webhooks.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use super::{AlertEvent, ChatChannel, Notifier, NotifyError, Template};
+
+/// Message used when a webhook or chat channel sets no `template`.
+pub const DEFAULT_CHAT_TEMPLATE: &str =
+    "[{{severity}}] {{host}} {{service}} alert {{event}}: {{message}}";
+
+/// Body bytes of an error response kept for the delivery log.
+const MAX_ERROR_BODY: usize = 512;
+
+pub fn client(timeout: Duration) -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .user_agent("BetterGov Monitoring/1.0")
+        .timeout(timeout)
+        .build()
+}
+
+fn parse_url(name: &str, url: &str) -> Result<reqwest::Url, NotifyError> {
+    reqwest::Url::parse(url).map_err(|e| NotifyError::Config {
+        channel: name.to_string(),
+        reason: format!("bad url {url:?}: {e}"),
+    })
+}
+
+async fn post_json(
+    client: &reqwest::Client,
+    url: &reqwest::Url,
+    body: &Value,
+) -> Result<(), NotifyError> {
+    let response = client.post(url.clone()).json(body).send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let mut body = response.text().await.unwrap_or_default();
+    if body.len() > MAX_ERROR_BODY {
+        let mut end = MAX_ERROR_BODY;
+        while !body.is_char_boundary(end) {
+            end -= 1;
+        }
+        body.truncate(end);
+    }
+    Err(NotifyError::Status {
+        status: status.as_u16(),
+        body,
+    })
+}
+
+/// Posts `{"event", "text", "alert"}` as JSON: the templated `text` for
+/// receivers that show it as is, and the alert itself for receivers that do
+/// their own formatting.
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    name: String,
+    url: reqwest::Url,
+    template: Template,
+}
+
+impl WebhookNotifier {
+    pub fn new(
+        client: reqwest::Client,
+        name: &str,
+        url: &str,
+        template: Template,
+    ) -> Result<Self, NotifyError> {
+        Ok(WebhookNotifier {
+            client,
+            name: name.to_string(),
+            url: parse_url(name, url)?,
+            template,
+        })
+    }
+
+    pub fn from_config(
+        client: reqwest::Client,
+        channel: &ChatChannel,
+    ) -> Result<Self, NotifyError> {
+        let template =
+            Template::parse(channel.template.as_deref().unwrap_or(DEFAULT_CHAT_TEMPLATE))?;
+        Self::new(client, &channel.name, &channel.url, template)
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn send(&self, event: &AlertEvent) -> Result<(), NotifyError> {
+        let body = json!({
+            "event": event.kind.as_str(),
+            "text": self.template.render(event),
+            "alert": event.alert,
+        });
+        post_json(&self.client, &self.url, &body).await
+    }
+}
+
+/// Payload dialect of a chat incoming webhook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatFlavor {
+    Slack,
+    Discord,
+    Mattermost,
+}
+
+impl ChatFlavor {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChatFlavor::Slack => "slack",
+            ChatFlavor::Discord => "discord",
+            ChatFlavor::Mattermost => "mattermost",
+        }
+    }
+
+    fn payload(self, text: String) -> Value {
+        match self {
+            // Mattermost accepts Slack's incoming-webhook format.
+            ChatFlavor::Slack | ChatFlavor::Mattermost => json!({ "text": text }),
+            ChatFlavor::Discord => json!({ "content": text }),
+        }
+    }
+}
+
+/// Posts a templated text message to a chat incoming webhook.
+pub struct ChatNotifier {
+    client: reqwest::Client,
+    name: String,
+    url: reqwest::Url,
+    flavor: ChatFlavor,
+    template: Template,
+}
+
+impl ChatNotifier {
+    pub fn new(
+        client: reqwest::Client,
+        flavor: ChatFlavor,
+        name: &str,
+        url: &str,
+        template: Template,
+    ) -> Result<Self, NotifyError> {
+        Ok(ChatNotifier {
+            client,
+            name: name.to_string(),
+            url: parse_url(name, url)?,
+            flavor,
+            template,
+        })
+    }
+
+    pub fn from_config(
+        client: reqwest::Client,
+        flavor: ChatFlavor,
+        channel: &ChatChannel,
+    ) -> Result<Self, NotifyError> {
+        let template =
+            Template::parse(channel.template.as_deref().unwrap_or(DEFAULT_CHAT_TEMPLATE))?;
+        Self::new(client, flavor, &channel.name, &channel.url, template)
+    }
+}
+
+#[async_trait]
+impl Notifier for ChatNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> &'static str {
+        self.flavor.as_str()
+    }
+
+    async fn send(&self, event: &AlertEvent) -> Result<(), NotifyError> {
+        let body = self.flavor.payload(self.template.render(event));
+        post_json(&self.client, &self.url, &body).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{body_json, body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::notify::tests::sample_event;
+    use crate::notify::EventKind;
+
+    fn test_client() -> reqwest::Client {
+        client(Duration::from_secs(5)).unwrap()
+    }
+
+    fn webhook(url: String, template: Option<&str>) -> Result<WebhookNotifier, NotifyError> {
+        let channel = ChatChannel {
+            name: "hook".to_string(),
+            url,
+            template: template.map(str::to_string),
+        };
+        WebhookNotifier::from_config(test_client(), &channel)
+    }
+
+    #[tokio::test]
+    async fn webhook_posts_event_text_and_alert() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/hook"))
+            .and(body_partial_json(json!({
+                "event": "opened",
+                "text": "[CRITICAL] api.bettergov.ph uptime alert opened: api.bettergov.ph is DOWN",
+                "alert": { "id": 42, "host": "api.bettergov.ph", "severity": "critical" },
+            })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = webhook(format!("{}/hook", server.uri()), None).unwrap();
+        notifier
+            .send(&sample_event(EventKind::Opened))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn webhook_renders_its_template() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "event": "resolved",
+                "text": "#42 RESOLVED by system",
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = webhook(server.uri(), Some("#{{id}} {{status}} by {{actor}}")).unwrap();
+        notifier
+            .send(&sample_event(EventKind::Resolved))
+            .await
+            .unwrap();
+        assert!(webhook(server.uri(), Some("{{nope}}")).is_err());
+    }
+
+    #[tokio::test]
+    async fn webhook_reports_error_status_and_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500).set_body_string("boom"))
+            .mount(&server)
+            .await;
+
+        let notifier = webhook(server.uri(), None).unwrap();
+        let err = notifier
+            .send(&sample_event(EventKind::Opened))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, NotifyError::Status { status: 500, body } if body == "boom"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_flavors_use_their_payload_field() {
+        let server = MockServer::start().await;
+        let text = "[CRITICAL] api.bettergov.ph uptime alert resolved: api.bettergov.ph is DOWN";
+        Mock::given(path("/slack"))
+            .and(body_json(json!({ "text": text })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/mattermost"))
+            .and(body_json(json!({ "text": text })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/discord"))
+            .and(body_json(json!({ "content": text })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let event = sample_event(EventKind::Resolved);
+        for (flavor, route) in [
+            (ChatFlavor::Slack, "slack"),
+            (ChatFlavor::Mattermost, "mattermost"),
+            (ChatFlavor::Discord, "discord"),
+        ] {
+            let channel = ChatChannel {
+                name: route.to_string(),
+                url: format!("{}/{route}", server.uri()),
+                template: None,
+            };
+            ChatNotifier::from_config(test_client(), flavor, &channel)
+                .unwrap()
+                .send(&event)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_against_a_recovering_endpoint() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let notifier = webhook(server.uri(), None).unwrap();
+        let policy = crate::notify::RetryPolicy {
+            attempts: 5,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+        };
+        let mut attempts = 0;
+        crate::notify::send_with_retry(
+            &notifier,
+            &sample_event(EventKind::Opened),
+            policy,
+            |n, _| {
+                attempts = n;
+                async {}
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn rejects_bad_urls() {
+        assert!(webhook("not a url".to_string(), None).is_err());
+    }
+}
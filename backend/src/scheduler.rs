@@ -0,0 +1,119 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::time::MissedTickBehavior;
+
+/// A periodic job as reported by `/api/health`.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub name: String,
+    pub next_run_time: Option<DateTime<Utc>>,
+    pub trigger: String,
+}
+
+/// Registry of the background jobs running inside the server process.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    running: AtomicBool,
+    jobs: RwLock<Vec<JobInfo>>,
+}
+
+impl Scheduler {
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        self.jobs
+            .read()
+            .map(|jobs| jobs.clone())
+            .unwrap_or_default()
+    }
+
+    /// Runs `job` now and then every `interval` on the tokio runtime.
+    ///
+    /// Runs never overlap: a run that overruns its slot delays the next one
+    /// instead of stacking up, like APScheduler's `max_instances=1`.
+    pub fn every<F, Fut>(self: &Arc<Self>, id: &str, name: &str, interval: Duration, job: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        if let Ok(mut jobs) = self.jobs.write() {
+            jobs.retain(|j| j.id != id);
+            jobs.push(JobInfo {
+                id: id.to_string(),
+                name: name.to_string(),
+                next_run_time: Some(Utc::now()),
+                trigger: format_trigger(interval),
+            });
+        }
+        self.running.store(true, Ordering::Relaxed);
+
+        let scheduler = Arc::clone(self);
+        let id = id.to_string();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                scheduler.set_next_run(&id, Utc::now() + interval);
+                job().await;
+            }
+        });
+    }
+
+    fn set_next_run(&self, id: &str, at: DateTime<Utc>) {
+        if let Ok(mut jobs) = self.jobs.write() {
+            if let Some(job) = jobs.iter_mut().find(|j| j.id == id) {
+                job.next_run_time = Some(at);
+            }
+        }
+    }
+}
+
+/// Formats an interval the way APScheduler prints its triggers, e.g.
+/// `interval[0:01:00]`, so dashboards reading `/api/health` see no change.
+/// Whole days come first, as Python prints a `timedelta`.
+fn format_trigger(interval: Duration) -> String {
+    let secs = interval.as_secs();
+    let days = match secs / 86_400 {
+        0 => String::new(),
+        1 => "1 day, ".to_string(),
+        n => format!("{n} days, "),
+    };
+    format!(
+        "interval[{days}{}:{:02}:{:02}]",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_triggers_like_apscheduler() {
+        assert_eq!(format_trigger(Duration::from_secs(60)), "interval[0:01:00]");
+        assert_eq!(format_trigger(Duration::from_secs(45)), "interval[0:00:45]");
+        assert_eq!(
+            format_trigger(Duration::from_secs(3600)),
+            "interval[1:00:00]"
+        );
+        assert_eq!(
+            format_trigger(Duration::from_secs(86_400 + 3_723)),
+            "interval[1 day, 1:02:03]"
+        );
+        assert_eq!(
+            format_trigger(Duration::from_secs(7 * 86_400)),
+            "interval[7 days, 0:00:00]"
+        );
+    }
+}
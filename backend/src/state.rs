@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use sqlx::PgPool;
+
+use crate::agents::liveness::LivenessSettings;
+use crate::config::{AgentsConfig, SubdomainsConfig};
+use crate::notify::Dispatcher;
+use crate::scheduler::Scheduler;
+use crate::status::Thresholds;
+use crate::subdomains::SubdomainCache;
+
+/// Shared state handed to every handler through `web::Data`.
+pub struct AppState {
+    pub pool: PgPool,
+    pub scheduler: Arc<Scheduler>,
+    pub thresholds: Thresholds,
+    /// How geo-report signatures are checked. The agents themselves are
+    /// in the registry (`db::agents`).
+    pub agents: AgentsConfig,
+    pub liveness: LivenessSettings,
+    /// Which hosts the inventory API accepts.
+    pub subdomains: SubdomainsConfig,
+    /// Bearer token of the `/api/admin` endpoints; `None` disables them.
+    pub admin_token: Option<String>,
+    pub notifier: Arc<Dispatcher>,
+    pub subdomain_cache: Arc<SubdomainCache>,
+}
+
+#[cfg(test)]
+impl AppState {
+    /// Default settings around `pool`, without notifiers or jobs.
+    pub fn for_tests(pool: PgPool) -> Self {
+        AppState {
+            pool: pool.clone(),
+            scheduler: Arc::new(Scheduler::default()),
+            thresholds: Thresholds::default(),
+            agents: AgentsConfig::default(),
+            liveness: LivenessSettings::default(),
+            subdomains: SubdomainsConfig::default(),
+            admin_token: None,
+            notifier: Arc::new(Dispatcher::new(pool, Vec::new(), Default::default())),
+            subdomain_cache: Arc::new(SubdomainCache::default()),
+        }
+    }
+}
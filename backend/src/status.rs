@@ -0,0 +1,640 @@
+//! Stable status for subdomains: a strike-counting state machine with
+//! Nagios-style flap detection, run separately for every location that
+//! checks a subdomain, and a quorum over those locations that decides the
+//! global status.
+//!
+//! [`SubdomainState::observe`] and [`aggregate`] are pure; [`record_check`]
+//! loads a location's state from add_location_status.sql, feeds one result
+//! through the machine, writes it back and re-derives the global status
+//! kept on the subdomain row.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Status {
+    Unknown,
+    Up,
+    Down,
+    Flapping,
+    /// Down in some locations, but fewer than the quorum. Only ever a
+    /// global status.
+    RegionalOutage,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Unknown => "UNKNOWN",
+            Status::Up => "UP",
+            Status::Down => "DOWN",
+            Status::Flapping => "FLAPPING",
+            Status::RegionalOutage => "REGIONAL_OUTAGE",
+        }
+    }
+
+    /// Parses the `current_status` column; anything unrecognised is UNKNOWN.
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "UP" => Status::Up,
+            "DOWN" => Status::Down,
+            "FLAPPING" => Status::Flapping,
+            "REGIONAL_OUTAGE" => Status::RegionalOutage,
+            _ => Status::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Tuning for the state machine.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Thresholds {
+    /// Consecutive up results needed to declare UP.
+    pub up_strikes: u32,
+    /// Consecutive down results needed to declare DOWN.
+    pub down_strikes: u32,
+    /// Results kept for flap detection (Nagios uses 21).
+    pub flap_window: usize,
+    /// Weighted state-change percentage at which flapping starts.
+    pub flap_high_percent: f64,
+    /// Percentage below which flapping stops again.
+    pub flap_low_percent: f64,
+    /// Locations that must be DOWN before a subdomain is DOWN globally.
+    /// When fewer locations are checking it, all of them must agree.
+    pub down_quorum: usize,
+    /// A location that has not checked a subdomain for this long no longer
+    /// counts towards its global status.
+    pub location_stale_secs: u64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            up_strikes: 3,
+            down_strikes: 3,
+            flap_window: 21,
+            flap_high_percent: 50.0,
+            flap_low_percent: 25.0,
+            down_quorum: 2,
+            location_stale_secs: 900,
+        }
+    }
+}
+
+/// A change of the stable status caused by one observation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub from: Status,
+    pub to: Status,
+}
+
+/// A change of the global status, with the locations behind it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusChange {
+    pub transition: Transition,
+    /// Locations whose own status is DOWN or FLAPPING.
+    pub failing: Vec<String>,
+    /// Locations that were counted.
+    pub counted: usize,
+}
+
+/// The location of the API server's own uptime checker. Agent locations
+/// are upper case, so it cannot clash with one.
+pub const CENTRAL_LOCATION: &str = "central";
+
+/// Everything the machine remembers about one subdomain at one location.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubdomainState {
+    pub status: Status,
+    pub consecutive_up: u32,
+    pub consecutive_down: u32,
+    pub is_flapping: bool,
+    pub flap_percent: f64,
+    /// Recent results, oldest first, at most `flap_window` long.
+    pub history: Vec<bool>,
+}
+
+impl Default for SubdomainState {
+    fn default() -> Self {
+        SubdomainState {
+            status: Status::Unknown,
+            consecutive_up: 0,
+            consecutive_down: 0,
+            is_flapping: false,
+            flap_percent: 0.0,
+            history: Vec::new(),
+        }
+    }
+}
+
+impl SubdomainState {
+    /// Feeds one check result through the machine.
+    ///
+    /// Flapping overrides everything else while it lasts. Otherwise the
+    /// status only moves once the configured number of consecutive identical
+    /// results has been seen, so after flapping ends the subdomain stays
+    /// FLAPPING until it settles one way or the other.
+    pub fn observe(&mut self, up: bool, thresholds: &Thresholds) -> Option<Transition> {
+        if up {
+            self.consecutive_up = self.consecutive_up.saturating_add(1);
+            self.consecutive_down = 0;
+        } else {
+            self.consecutive_down = self.consecutive_down.saturating_add(1);
+            self.consecutive_up = 0;
+        }
+
+        self.history.push(up);
+        let window = thresholds.flap_window.max(2);
+        if self.history.len() > window {
+            self.history.drain(..self.history.len() - window);
+        }
+        self.flap_percent = state_change_percent(&self.history, window);
+        if self.is_flapping {
+            self.is_flapping = self.flap_percent >= thresholds.flap_low_percent;
+        } else {
+            self.is_flapping = self.flap_percent >= thresholds.flap_high_percent;
+        }
+
+        let next = if self.is_flapping {
+            Status::Flapping
+        } else if self.consecutive_up >= thresholds.up_strikes {
+            Status::Up
+        } else if self.consecutive_down >= thresholds.down_strikes {
+            Status::Down
+        } else {
+            self.status
+        };
+
+        let from = self.status;
+        self.status = next;
+        (from != next).then_some(Transition { from, to: next })
+    }
+}
+
+/// Nagios' weighted state-change percentage: each change between adjacent
+/// results counts with a weight rising linearly from 0.75 (oldest) to 1.25
+/// (newest), relative to the `window - 1` changes a full window can hold.
+/// Short histories are scored against the full window, so a new subdomain
+/// cannot be declared flapping from a couple of results.
+pub fn state_change_percent(history: &[bool], window: usize) -> f64 {
+    const LOW_WEIGHT: f64 = 0.75;
+    const HIGH_WEIGHT: f64 = 1.25;
+
+    let slots = window.saturating_sub(1).max(1);
+    let increment = if slots > 1 {
+        (HIGH_WEIGHT - LOW_WEIGHT) / (slots - 1) as f64
+    } else {
+        0.0
+    };
+    // Align the newest change with the highest weight.
+    let offset = slots.saturating_sub(history.len().saturating_sub(1));
+
+    let weighted = history
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0] != pair[1])
+        .fold(0.0, |sum, (i, _)| {
+            sum + LOW_WEIGHT + (offset + i) as f64 * increment
+        });
+    weighted * 100.0 / slots as f64
+}
+
+/// Derives the global status from the current status at each location,
+/// or `None` while no location has settled on one.
+///
+/// DOWN needs `down_quorum` locations (or all of them, if fewer are
+/// counted). Locations that are flapping count towards the quorum for
+/// FLAPPING, and any down or flapping location short of that is a
+/// REGIONAL_OUTAGE.
+pub fn aggregate(statuses: &[Status], down_quorum: usize) -> Option<Status> {
+    let decided: Vec<Status> = statuses
+        .iter()
+        .copied()
+        .filter(|s| *s != Status::Unknown)
+        .collect();
+    if decided.is_empty() {
+        return None;
+    }
+    let quorum = down_quorum.clamp(1, decided.len());
+    let count = |status: Status| decided.iter().filter(|s| **s == status).count();
+    let (down, flapping) = (count(Status::Down), count(Status::Flapping));
+    Some(if down >= quorum {
+        Status::Down
+    } else if down + flapping >= quorum {
+        Status::Flapping
+    } else if down + flapping > 0 {
+        Status::RegionalOutage
+    } else {
+        Status::Up
+    })
+}
+
+/// Folds one check result from `location` into the stored state of
+/// `subdomain` and returns the change of its global status, if any.
+/// Unknown and inactive subdomains are ignored. The counters, history and
+/// flap percentage on the `subdomains` row mirror the central location.
+///
+/// Locks the subdomain row, so run it inside a transaction.
+pub async fn record_check(
+    conn: &mut PgConnection,
+    subdomain: &str,
+    location: &str,
+    up: bool,
+    thresholds: &Thresholds,
+) -> sqlx::Result<Option<StatusChange>> {
+    let global: Option<String> = sqlx::query_scalar(
+        "SELECT COALESCE(current_status, 'UNKNOWN')
+         FROM monitoring.subdomains
+         WHERE subdomain = $1 AND active = true
+         FOR UPDATE",
+    )
+    .bind(subdomain)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(global) = global else {
+        return Ok(None);
+    };
+
+    let row: Option<(String, i32, i32, bool, f64, Vec<bool>)> = sqlx::query_as(
+        "SELECT current_status, consecutive_up_count, consecutive_down_count,
+                is_flapping, flap_percent, status_history
+         FROM monitoring.subdomain_location_status
+         WHERE subdomain = $1 AND location = $2",
+    )
+    .bind(subdomain)
+    .bind(location)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let mut state = match row {
+        Some((status, up_count, down_count, is_flapping, flap_percent, history)) => {
+            SubdomainState {
+                status: Status::parse(&status),
+                consecutive_up: u32::try_from(up_count).unwrap_or(0),
+                consecutive_down: u32::try_from(down_count).unwrap_or(0),
+                is_flapping,
+                flap_percent,
+                history,
+            }
+        }
+        None => SubdomainState::default(),
+    };
+    let local = state.observe(up, thresholds);
+
+    sqlx::query(
+        "INSERT INTO monitoring.subdomain_location_status
+             (subdomain, location, current_status, consecutive_up_count,
+              consecutive_down_count, is_flapping, flap_percent, status_history,
+              last_check, last_status_change)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW(), CASE WHEN $9 THEN NOW() END)
+         ON CONFLICT (subdomain, location) DO UPDATE SET
+             current_status = EXCLUDED.current_status,
+             consecutive_up_count = EXCLUDED.consecutive_up_count,
+             consecutive_down_count = EXCLUDED.consecutive_down_count,
+             is_flapping = EXCLUDED.is_flapping,
+             flap_percent = EXCLUDED.flap_percent,
+             status_history = EXCLUDED.status_history,
+             last_check = EXCLUDED.last_check,
+             last_status_change = COALESCE(
+                 EXCLUDED.last_status_change,
+                 subdomain_location_status.last_status_change
+             )",
+    )
+    .bind(subdomain)
+    .bind(location)
+    .bind(state.status.as_str())
+    .bind(i32::try_from(state.consecutive_up).unwrap_or(i32::MAX))
+    .bind(i32::try_from(state.consecutive_down).unwrap_or(i32::MAX))
+    .bind(state.is_flapping)
+    .bind(state.flap_percent)
+    .bind(&state.history)
+    .bind(local.is_some())
+    .execute(&mut *conn)
+    .await?;
+    if location == CENTRAL_LOCATION {
+        sqlx::query(
+            "UPDATE monitoring.subdomains
+             SET consecutive_up_count = $1,
+                 consecutive_down_count = $2,
+                 status_history = $3,
+                 flap_percent = $4
+             WHERE subdomain = $5",
+        )
+        .bind(i32::try_from(state.consecutive_up).unwrap_or(i32::MAX))
+        .bind(i32::try_from(state.consecutive_down).unwrap_or(i32::MAX))
+        .bind(&state.history)
+        .bind(state.flap_percent)
+        .bind(subdomain)
+        .execute(&mut *conn)
+        .await?;
+    }
+    if let Some(t) = local {
+        println!(
+            "Status changed for {subdomain} at {location}: {} → {}",
+            t.from, t.to
+        );
+    }
+
+    let locations: Vec<(String, String)> = sqlx::query_as(
+        "SELECT location, current_status
+         FROM monitoring.subdomain_location_status
+         WHERE subdomain = $1
+           AND last_check > NOW() - make_interval(secs => $2)
+         ORDER BY location",
+    )
+    .bind(subdomain)
+    .bind(thresholds.location_stale_secs as f64)
+    .fetch_all(&mut *conn)
+    .await?;
+    let statuses: Vec<Status> = locations.iter().map(|(_, s)| Status::parse(s)).collect();
+    let from = Status::parse(&global);
+    let to = match aggregate(&statuses, thresholds.down_quorum) {
+        Some(to) if to != from => to,
+        _ => return Ok(None),
+    };
+
+    sqlx::query(
+        "UPDATE monitoring.subdomains
+         SET current_status = $1,
+             is_flapping = $2,
+             last_status_change = NOW()
+         WHERE subdomain = $3",
+    )
+    .bind(to.as_str())
+    .bind(to == Status::Flapping)
+    .bind(subdomain)
+    .execute(&mut *conn)
+    .await?;
+
+    let failing = locations
+        .iter()
+        .zip(&statuses)
+        .filter(|(_, s)| matches!(s, Status::Down | Status::Flapping))
+        .map(|((location, _), _)| location.clone())
+        .collect();
+    println!("Status changed for {subdomain}: {from} → {to}");
+    Ok(Some(StatusChange {
+        transition: Transition { from, to },
+        failing,
+        counted: statuses.iter().filter(|s| **s != Status::Unknown).count(),
+    }))
+}
+
+/// Drops the per-location state of `subdomain` and sets its global status
+/// back to UNKNOWN, as when it stops being monitored.
+pub async fn forget(conn: &mut PgConnection, subdomain: &str) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM monitoring.subdomain_location_status WHERE subdomain = $1")
+        .bind(subdomain)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(
+        "UPDATE monitoring.subdomains
+         SET current_status = 'UNKNOWN', is_flapping = false,
+             consecutive_up_count = 0, consecutive_down_count = 0,
+             status_history = '{}', flap_percent = 0
+         WHERE subdomain = $1",
+    )
+    .bind(subdomain)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(state: &mut SubdomainState, results: &[bool], t: &Thresholds) -> Vec<Transition> {
+        results
+            .iter()
+            .filter_map(|up| state.observe(*up, t))
+            .collect()
+    }
+
+    #[test]
+    fn needs_consecutive_strikes_to_leave_unknown() {
+        let t = Thresholds::default();
+        let mut state = SubdomainState::default();
+        assert_eq!(state.observe(true, &t), None);
+        assert_eq!(state.observe(true, &t), None);
+        assert_eq!(
+            state.observe(true, &t),
+            Some(Transition {
+                from: Status::Unknown,
+                to: Status::Up
+            })
+        );
+    }
+
+    #[test]
+    fn a_single_failure_does_not_mark_down() {
+        let t = Thresholds::default();
+        let mut state = SubdomainState::default();
+        feed(&mut state, &[true, true, true, false, true], &t);
+        assert_eq!(state.status, Status::Up);
+        assert_eq!(state.consecutive_up, 1);
+    }
+
+    #[test]
+    fn honours_custom_strike_thresholds() {
+        let t = Thresholds {
+            down_strikes: 5,
+            ..Thresholds::default()
+        };
+        let mut state = SubdomainState::default();
+        feed(&mut state, &[true; 3], &t);
+        feed(&mut state, &[false; 4], &t);
+        assert_eq!(state.status, Status::Up);
+        assert_eq!(
+            state.observe(false, &t),
+            Some(Transition {
+                from: Status::Up,
+                to: Status::Down
+            })
+        );
+    }
+
+    #[test]
+    fn steady_history_scores_zero() {
+        assert_eq!(state_change_percent(&[true; 21], 21), 0.0);
+        assert_eq!(state_change_percent(&[], 21), 0.0);
+    }
+
+    #[test]
+    fn alternating_full_window_scores_one_hundred() {
+        let history: Vec<bool> = (0..21).map(|i| i % 2 == 0).collect();
+        assert!((state_change_percent(&history, 21) - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn recent_changes_weigh_more_than_old_ones() {
+        let mut old_change = vec![true; 21];
+        old_change[0] = false;
+        let mut new_change = vec![true; 21];
+        new_change[20] = false;
+        let old = state_change_percent(&old_change, 21);
+        let new = state_change_percent(&new_change, 21);
+        assert!((old - 3.75).abs() < 1e-9, "{old}");
+        assert!((new - 6.25).abs() < 1e-9, "{new}");
+    }
+
+    #[test]
+    fn alternating_results_start_flapping() {
+        let t = Thresholds::default();
+        let mut state = SubdomainState::default();
+        let alternating: Vec<bool> = (0..21).map(|i| i % 2 == 0).collect();
+        let transitions = feed(&mut state, &alternating, &t);
+        assert!(state.is_flapping);
+        assert_eq!(state.status, Status::Flapping);
+        assert_eq!(transitions.last().unwrap().to, Status::Flapping);
+    }
+
+    #[test]
+    fn flapping_uses_hysteresis_before_stopping() {
+        let t = Thresholds::default();
+        let mut state = SubdomainState::default();
+        let alternating: Vec<bool> = (0..21).map(|i| i % 2 == 0).collect();
+        feed(&mut state, &alternating, &t);
+        assert!(state.is_flapping);
+
+        // Once the change rate drops below the high threshold but stays above
+        // the low one, the subdomain keeps flapping.
+        let mut steps = 0;
+        while state.flap_percent >= t.flap_high_percent {
+            state.observe(true, &t);
+            steps += 1;
+        }
+        assert!(state.flap_percent >= t.flap_low_percent);
+        assert!(
+            state.is_flapping,
+            "stopped flapping after {steps} steady results"
+        );
+
+        while state.is_flapping {
+            state.observe(true, &t);
+        }
+        assert!(state.flap_percent < t.flap_low_percent);
+        assert_eq!(state.status, Status::Up);
+    }
+
+    #[test]
+    fn history_is_bounded_by_the_window() {
+        let t = Thresholds {
+            flap_window: 5,
+            ..Thresholds::default()
+        };
+        let mut state = SubdomainState::default();
+        feed(&mut state, &[true; 12], &t);
+        assert_eq!(state.history.len(), 5);
+    }
+
+    #[test]
+    fn a_single_location_decides_alone() {
+        for status in [Status::Up, Status::Down, Status::Flapping] {
+            assert_eq!(aggregate(&[status], 2), Some(status));
+        }
+        assert_eq!(
+            aggregate(&[Status::Down, Status::Unknown], 2),
+            Some(Status::Down)
+        );
+        assert_eq!(aggregate(&[Status::Unknown], 2), None);
+        assert_eq!(aggregate(&[], 2), None);
+    }
+
+    #[test]
+    fn down_needs_a_quorum_of_locations() {
+        use Status::*;
+        assert_eq!(aggregate(&[Down, Up, Up], 2), Some(RegionalOutage));
+        assert_eq!(aggregate(&[Down, Down, Up], 2), Some(Down));
+        assert_eq!(aggregate(&[Down, Down, Up], 3), Some(RegionalOutage));
+        assert_eq!(aggregate(&[Down, Up], 2), Some(RegionalOutage));
+        assert_eq!(aggregate(&[Down, Down], 3), Some(Down));
+        assert_eq!(aggregate(&[Up, Up, Up], 2), Some(Up));
+    }
+
+    #[test]
+    fn flapping_locations_count_towards_flapping_or_an_outage() {
+        use Status::*;
+        assert_eq!(aggregate(&[Flapping, Up, Up], 2), Some(RegionalOutage));
+        assert_eq!(aggregate(&[Flapping, Up, Up, Up], 3), Some(RegionalOutage));
+        assert_eq!(aggregate(&[Flapping, Down, Up], 2), Some(Flapping));
+        assert_eq!(aggregate(&[Flapping, Flapping, Up], 2), Some(Flapping));
+    }
+
+    async fn subdomain_counters(
+        conn: &mut PgConnection,
+        subdomain: &str,
+    ) -> (i32, i32, Vec<bool>, f64) {
+        sqlx::query_as(
+            "SELECT consecutive_up_count, consecutive_down_count, status_history, flap_percent
+             FROM monitoring.subdomains WHERE subdomain = $1",
+        )
+        .bind(subdomain)
+        .fetch_one(conn)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn subdomain_counters_follow_the_central_location() {
+        let Some(pool) = crate::db::test_pool().await else {
+            return;
+        };
+        let subdomain = format!("status-{}.bettergov.test", std::process::id());
+        crate::db::subdomains::upsert_discovered(&pool, "bettergov.test", &subdomain)
+            .await
+            .unwrap();
+        let t = Thresholds::default();
+        let mut conn = pool.acquire().await.unwrap();
+        for up in [true, false, true, true] {
+            record_check(&mut conn, &subdomain, CENTRAL_LOCATION, up, &t)
+                .await
+                .unwrap();
+        }
+        let mut expected = SubdomainState::default();
+        feed(&mut expected, &[true, false, true, true], &t);
+        let central = subdomain_counters(&mut conn, &subdomain).await;
+        assert_eq!(central.0, 2);
+        assert_eq!(central.1, 0);
+        assert_eq!(central.2, [true, false, true, true]);
+        assert_eq!(central.3, expected.flap_percent);
+        assert!(central.3 > 0.0);
+
+        // Agent results have their own counters and leave these alone.
+        for _ in 0..3 {
+            record_check(&mut conn, &subdomain, "SG", false, &t)
+                .await
+                .unwrap();
+        }
+        assert_eq!(subdomain_counters(&mut conn, &subdomain).await, central);
+
+        forget(&mut conn, &subdomain).await.unwrap();
+        sqlx::query("DELETE FROM monitoring.subdomains WHERE subdomain = $1")
+            .bind(&subdomain)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn parses_stored_status_strings() {
+        for status in [
+            Status::Unknown,
+            Status::Up,
+            Status::Down,
+            Status::Flapping,
+            Status::RegionalOutage,
+        ] {
+            assert_eq!(Status::parse(status.as_str()), status);
+        }
+        assert_eq!(Status::parse("garbage"), Status::Unknown);
+    }
+}
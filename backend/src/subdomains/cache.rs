@@ -0,0 +1,228 @@
+//! The latest check and 24-hour uptime of every subdomain, kept in memory
+//! so listing subdomains does not scan `monitoring.uptime_checks`. Loaded
+//! once at startup; everything that writes checks records them here too.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::db::uptime_checks::{self, NewUptimeCheck};
+
+/// Uptime is counted in buckets of this many seconds, so the 24-hour
+/// window is exact to within one bucket.
+const BUCKET_SECS: i64 = 300;
+const WINDOW: Duration = Duration::hours(24);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatestCheck {
+    pub time: DateTime<Utc>,
+    pub location: Option<String>,
+    pub up: bool,
+    pub status_code: Option<i32>,
+    pub response_time_ms: Option<f64>,
+    pub error_message: Option<String>,
+}
+
+/// What the cache knows about one subdomain.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CachedStats {
+    /// Newest check in the last 24 hours.
+    pub latest: Option<LatestCheck>,
+    pub check_count: u64,
+    /// `None` without checks in the last 24 hours.
+    pub uptime_percent: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bucket {
+    index: i64,
+    checks: u64,
+    up: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    latest: Option<LatestCheck>,
+    /// Oldest first.
+    buckets: VecDeque<Bucket>,
+}
+
+fn bucket_index(time: DateTime<Utc>) -> i64 {
+    time.timestamp().div_euclid(BUCKET_SECS)
+}
+
+impl Entry {
+    fn count(&mut self, index: i64, checks: u64, up: u64) {
+        // Checks nearly always land in the newest bucket; reports that
+        // arrive late or out of order are placed by search.
+        match self.buckets.iter().rposition(|b| b.index <= index) {
+            Some(pos) if self.buckets[pos].index == index => {
+                self.buckets[pos].checks += checks;
+                self.buckets[pos].up += up;
+            }
+            pos => {
+                let at = pos.map_or(0, |p| p + 1);
+                self.buckets.insert(at, Bucket { index, checks, up });
+            }
+        }
+    }
+
+    fn record(&mut self, check: LatestCheck) {
+        self.count(bucket_index(check.time), 1, u64::from(check.up));
+        if self.latest.as_ref().is_none_or(|l| l.time <= check.time) {
+            self.latest = Some(check);
+        }
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let oldest = bucket_index(now - WINDOW);
+        while self.buckets.front().is_some_and(|b| b.index < oldest) {
+            self.buckets.pop_front();
+        }
+        if self.latest.as_ref().is_some_and(|l| l.time < now - WINDOW) {
+            self.latest = None;
+        }
+    }
+
+    fn stats(&self) -> CachedStats {
+        let (checks, up) = self
+            .buckets
+            .iter()
+            .fold((0, 0), |(c, u), b| (c + b.checks, u + b.up));
+        CachedStats {
+            latest: self.latest.clone(),
+            check_count: checks,
+            uptime_percent: (checks > 0).then(|| up as f64 * 100.0 / checks as f64),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SubdomainCache {
+    entries: RwLock<HashMap<String, Entry>>,
+}
+
+impl SubdomainCache {
+    /// Fills the cache from the last 24 hours of checks.
+    pub async fn load(pool: &PgPool) -> sqlx::Result<Self> {
+        let since = Utc::now() - WINDOW;
+        let mut entries: HashMap<String, Entry> = HashMap::new();
+        for (subdomain, index, checks, up) in
+            uptime_checks::bucket_counts(pool, since, BUCKET_SECS).await?
+        {
+            entries.entry(subdomain).or_default().count(
+                index,
+                u64::try_from(checks).unwrap_or(0),
+                u64::try_from(up).unwrap_or(0),
+            );
+        }
+        for check in uptime_checks::latest_per_subdomain(pool, since).await? {
+            entries.entry(check.subdomain).or_default().latest = Some(LatestCheck {
+                time: check.time,
+                location: check.location,
+                up: check.up,
+                status_code: check.status_code,
+                response_time_ms: check.response_time_ms,
+                error_message: check.error_message,
+            });
+        }
+        println!("Subdomain cache loaded for {} subdomains", entries.len());
+        Ok(SubdomainCache {
+            entries: RwLock::new(entries),
+        })
+    }
+
+    pub fn record(&self, check: &NewUptimeCheck) {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        let entry = entries.entry(check.subdomain.clone()).or_default();
+        entry.record(LatestCheck {
+            time: check.time,
+            location: check.location.clone(),
+            up: check.up,
+            status_code: check.status_code,
+            response_time_ms: check.response_time_ms,
+            error_message: check.error_message.clone(),
+        });
+        entry.prune(Utc::now());
+    }
+
+    /// Stats of every cached subdomain as of `now`.
+    pub fn snapshot(&self, now: DateTime<Utc>) -> HashMap<String, CachedStats> {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        entries
+            .iter_mut()
+            .map(|(subdomain, entry)| {
+                entry.prune(now);
+                (subdomain.clone(), entry.stats())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(subdomain: &str, time: DateTime<Utc>, up: bool) -> NewUptimeCheck {
+        NewUptimeCheck {
+            time,
+            subdomain: subdomain.to_string(),
+            up,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn counts_uptime_over_the_last_day() {
+        let cache = SubdomainCache::default();
+        let now = Utc::now();
+        for minutes in [1, 2, 3, 70] {
+            cache.record(&check("a.gov.ph", now - Duration::minutes(minutes), true));
+        }
+        cache.record(&check("a.gov.ph", now - Duration::minutes(30), false));
+        cache.record(&check("a.gov.ph", now - Duration::hours(25), false));
+
+        let stats = &cache.snapshot(now)["a.gov.ph"];
+        assert_eq!(stats.check_count, 5);
+        assert_eq!(stats.uptime_percent, Some(80.0));
+        assert_eq!(
+            stats.latest.as_ref().map(|l| l.time),
+            Some(now - Duration::minutes(1))
+        );
+    }
+
+    #[test]
+    fn late_reports_do_not_replace_the_latest_check() {
+        let cache = SubdomainCache::default();
+        let now = Utc::now();
+        cache.record(&check("a.gov.ph", now, false));
+        cache.record(&check("a.gov.ph", now - Duration::minutes(20), true));
+
+        let stats = &cache.snapshot(now)["a.gov.ph"];
+        assert_eq!(stats.latest.as_ref().map(|l| l.up), Some(false));
+        assert_eq!(stats.check_count, 2);
+    }
+
+    #[test]
+    fn keeps_buckets_in_time_order() {
+        let mut entry = Entry::default();
+        for index in [5, 2, 9, 2, 7] {
+            entry.count(index, 1, 1);
+        }
+        let indexes: Vec<i64> = entry.buckets.iter().map(|b| b.index).collect();
+        assert_eq!(indexes, [2, 5, 7, 9]);
+        assert_eq!(entry.buckets[0].checks, 2);
+    }
+
+    #[test]
+    fn forgets_subdomains_silent_for_a_day() {
+        let cache = SubdomainCache::default();
+        let now = Utc::now();
+        cache.record(&check("a.gov.ph", now - Duration::hours(23), true));
+        let stats = &cache.snapshot(now + Duration::hours(2))["a.gov.ph"];
+        assert_eq!(*stats, CachedStats::default());
+    }
+}
@@ -0,0 +1,641 @@
+//! Admin endpoints for the subdomain inventory: adding hosts by hand,
+//! tuning how each one is checked, and taking it out of monitoring. Only
+//! hosts under `subdomains.domains` are accepted.
+
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgConnection;
+
+use crate::admin::Admin;
+use crate::alerts::{SUBDOMAIN_SERVICES, SYSTEM_ACTOR};
+use crate::checker::assertions::Assertion;
+use crate::checker::socket;
+use crate::db::subdomains::{self, CheckSettings};
+use crate::db::{alerts, dns_records, subdomain_sources, tls_certificates};
+use crate::discovery;
+use crate::error::{ApiError, FieldError};
+use crate::notify::AlertEvent;
+use crate::state::AppState;
+use crate::status;
+
+const MAX_HOST_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+const MAX_PATH_LEN: usize = 512;
+/// Values of `check_kind`.
+pub const KINDS: [&str; 3] = ["http", "tcp", "udp"];
+const METHODS: [&str; 2] = ["GET", "HEAD"];
+const MAX_EXPECTED_STATUS: usize = 20;
+const MAX_TIMEOUT_SECS: i32 = 60;
+/// Targets are checked at most once per checker pass, a minute by default.
+const MIN_INTERVAL_SECS: i32 = 60;
+const MAX_INTERVAL_SECS: i32 = 86400;
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LEN: usize = 32;
+const MAX_SEND_BYTES: usize = 1024;
+const MAX_EXPECT_BYTES: usize = 256;
+const MAX_ASSERTIONS: usize = 20;
+
+/// Hosts are stored lower-cased and without a trailing dot.
+pub fn normalize_host(host: &str) -> String {
+    host.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Whether `host` is a lowercase DNS name: dot-separated labels of
+/// `[a-z0-9-]` that neither start nor end with a hyphen.
+pub fn is_hostname(host: &str) -> bool {
+    (1..=MAX_HOST_LEN).contains(&host.len())
+        && host.split('.').all(|label| {
+            (1..=MAX_LABEL_LEN).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        })
+}
+
+/// The configured domain `host` (an optional `:port` aside) belongs to,
+/// the most specific one if several match.
+pub fn domain_of<'a>(host: &str, domains: &'a [String]) -> Option<&'a str> {
+    let (name, port) = match host.rsplit_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (host, None),
+    };
+    if !is_hostname(name) || port.is_some_and(|p| !p.parse::<u16>().is_ok_and(|p| p > 0)) {
+        return None;
+    }
+    domains
+        .iter()
+        .filter(|d| {
+            name == d.as_str()
+                || name
+                    .strip_suffix(d.as_str())
+                    .is_some_and(|rest| rest.ends_with('.'))
+        })
+        .max_by_key(|d| d.len())
+        .map(String::as_str)
+}
+
+/// Body of the create and update endpoints. Update replaces every setting,
+/// so omitted ones go back to their defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubdomainBody {
+    pub subdomain: Option<String>,
+    /// `http` (the default), or `tcp` or `udp` for a subdomain named with
+    /// its port.
+    pub check_kind: Option<String>,
+    /// Defaults to `/`.
+    pub check_path: Option<String>,
+    /// `GET` (the default) or `HEAD`.
+    pub check_method: Option<String>,
+    /// Status codes that count as up; by default anything below 500 does.
+    pub expected_status: Option<Vec<i32>>,
+    /// Defaults to `checker.timeout_secs`.
+    pub check_timeout_secs: Option<i32>,
+    /// Minimum seconds between checks; by default every checker pass.
+    pub check_interval_secs: Option<i32>,
+    pub tags: Option<Vec<String>>,
+    /// What a `tcp` check sends once connected, or the datagram of a `udp`
+    /// check; `\r`, `\n`, `\t`, `\0`, `\\` and `\xHH` are unescaped.
+    pub check_send: Option<String>,
+    /// Text the banner or reply must contain, with the same escapes.
+    pub check_expect: Option<String>,
+    /// Content assertions of an `http` check, checked in order.
+    pub check_assertions: Option<Vec<Assertion>>,
+}
+
+/// Check settings that passed validation.
+#[derive(Debug, PartialEq)]
+struct Settings {
+    check_kind: String,
+    check_path: String,
+    check_method: String,
+    expected_status: Option<Vec<i32>>,
+    timeout_secs: Option<i32>,
+    interval_secs: Option<i32>,
+    tags: Vec<String>,
+    check_send: Option<String>,
+    check_expect: Option<String>,
+    check_assertions: Vec<Assertion>,
+}
+
+impl Settings {
+    fn as_check_settings(&self) -> CheckSettings<'_> {
+        CheckSettings {
+            check_kind: &self.check_kind,
+            check_path: &self.check_path,
+            check_method: &self.check_method,
+            expected_status: self.expected_status.as_deref(),
+            timeout_secs: self.timeout_secs,
+            interval_secs: self.interval_secs,
+            tags: &self.tags,
+            check_send: self.check_send.as_deref(),
+            check_expect: self.check_expect.as_deref(),
+            check_assertions: &self.check_assertions,
+        }
+    }
+}
+
+impl SubdomainBody {
+    /// The settings for checking `host`.
+    fn settings(&self, host: &str, errors: &mut Vec<FieldError>) -> Settings {
+        let check_kind = self
+            .check_kind
+            .as_deref()
+            .map(|k| k.trim().to_ascii_lowercase())
+            .unwrap_or_else(|| KINDS[0].to_string());
+        if !KINDS.contains(&check_kind.as_str()) {
+            errors.push(FieldError::new(
+                None,
+                "check_kind",
+                format!("must be one of {}", KINDS.join(", ")),
+            ));
+        }
+        let is_http = check_kind == "http";
+        if !is_http && !host.contains(':') {
+            errors.push(FieldError::new(
+                None,
+                "subdomain",
+                "must end in the port to check for tcp and udp checks, e.g. db.bettergov.ph:5432",
+            ));
+        }
+        if !is_http {
+            let http_only = [
+                ("check_path", self.check_path.is_some()),
+                ("check_method", self.check_method.is_some()),
+                ("expected_status", self.expected_status.is_some()),
+                ("check_assertions", self.check_assertions.is_some()),
+            ];
+            for (field, _) in http_only.iter().filter(|(_, set)| *set) {
+                errors.push(FieldError::new(None, *field, "only applies to http checks"));
+            }
+        }
+        let mut payload = |field: &str, value: &Option<String>, max: usize| {
+            let value = value.clone().filter(|v| !v.is_empty())?;
+            if is_http {
+                errors.push(FieldError::new(
+                    None,
+                    field,
+                    "only applies to tcp and udp checks",
+                ));
+            }
+            match socket::unescape(&value) {
+                Ok(bytes) if bytes.len() <= max => {}
+                Ok(_) => errors.push(FieldError::new(
+                    None,
+                    field,
+                    format!("must be at most {max} bytes"),
+                )),
+                Err(e) => errors.push(FieldError::new(None, field, e)),
+            }
+            Some(value)
+        };
+        let check_send = payload("check_send", &self.check_send, MAX_SEND_BYTES);
+        let check_expect = payload("check_expect", &self.check_expect, MAX_EXPECT_BYTES);
+
+        let check_path = self
+            .check_path
+            .as_deref()
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .unwrap_or("/")
+            .to_string();
+        let path_ok = check_path.starts_with('/')
+            && check_path.len() <= MAX_PATH_LEN
+            && !check_path
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control() || c == '#');
+        if !path_ok {
+            errors.push(FieldError::new(
+                None,
+                "check_path",
+                format!(
+                    "must start with / and be at most {MAX_PATH_LEN} characters, without spaces or a fragment"
+                ),
+            ));
+        }
+
+        let check_method = self
+            .check_method
+            .as_deref()
+            .map(|m| m.trim().to_ascii_uppercase())
+            .unwrap_or_else(|| METHODS[0].to_string());
+        if !METHODS.contains(&check_method.as_str()) {
+            errors.push(FieldError::new(
+                None,
+                "check_method",
+                format!("must be one of {}", METHODS.join(", ")),
+            ));
+        }
+
+        let check_assertions = self.check_assertions.clone().unwrap_or_default();
+        if check_assertions.len() > MAX_ASSERTIONS {
+            errors.push(FieldError::new(
+                None,
+                "check_assertions",
+                format!("must be at most {MAX_ASSERTIONS} assertions"),
+            ));
+        }
+        for (i, assertion) in check_assertions.iter().enumerate() {
+            if let Err(e) = assertion.validate() {
+                errors.push(FieldError::new(Some(i), "check_assertions", e));
+            } else if check_method == "HEAD" && assertion.needs_body() {
+                errors.push(FieldError::new(
+                    Some(i),
+                    "check_assertions",
+                    "needs the body, which HEAD checks do not get",
+                ));
+            }
+        }
+
+        let expected_status = self.expected_status.clone().map(|mut codes| {
+            codes.sort_unstable();
+            codes.dedup();
+            codes
+        });
+        if let Some(codes) = &expected_status {
+            if codes.is_empty() || codes.len() > MAX_EXPECTED_STATUS {
+                errors.push(FieldError::new(
+                    None,
+                    "expected_status",
+                    format!("must list 1 to {MAX_EXPECTED_STATUS} status codes, or be omitted"),
+                ));
+            }
+            if codes.iter().any(|c| !(100..=599).contains(c)) {
+                errors.push(FieldError::new(
+                    None,
+                    "expected_status",
+                    "status codes must be between 100 and 599",
+                ));
+            }
+        }
+
+        if self
+            .check_timeout_secs
+            .is_some_and(|t| !(1..=MAX_TIMEOUT_SECS).contains(&t))
+        {
+            errors.push(FieldError::new(
+                None,
+                "check_timeout_secs",
+                format!("must be between 1 and {MAX_TIMEOUT_SECS}"),
+            ));
+        }
+        if self
+            .check_interval_secs
+            .is_some_and(|i| !(MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&i))
+        {
+            errors.push(FieldError::new(
+                None,
+                "check_interval_secs",
+                format!("must be between {MIN_INTERVAL_SECS} and {MAX_INTERVAL_SECS}"),
+            ));
+        }
+
+        let mut tags: Vec<String> = self
+            .tags
+            .iter()
+            .flatten()
+            .map(|t| t.trim().to_ascii_lowercase())
+            .collect();
+        tags.sort_unstable();
+        tags.dedup();
+        let tags_ok = tags.len() <= MAX_TAGS
+            && tags.iter().all(|t| {
+                (1..=MAX_TAG_LEN).contains(&t.len())
+                    && t.bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+            });
+        if !tags_ok {
+            errors.push(FieldError::new(
+                None,
+                "tags",
+                format!(
+                    "must be at most {MAX_TAGS} tags of 1 to {MAX_TAG_LEN} characters of [a-z0-9-_.:]"
+                ),
+            ));
+        }
+
+        Settings {
+            check_kind,
+            check_path,
+            check_method,
+            expected_status,
+            timeout_secs: self.check_timeout_secs,
+            interval_secs: self.check_interval_secs,
+            tags,
+            check_send,
+            check_expect,
+            check_assertions,
+        }
+    }
+}
+
+fn not_found(subdomain: &str) -> ApiError {
+    ApiError::NotFound(format!("Subdomain {subdomain} not found"))
+}
+
+/// `POST /api/admin/subdomains`: adds a host under one of the configured
+/// domains. It is checked from the next checker pass on.
+pub async fn create_subdomain(
+    _: Admin,
+    state: web::Data<AppState>,
+    body: web::Json<SubdomainBody>,
+) -> Result<HttpResponse, ApiError> {
+    let mut errors = Vec::new();
+    let host = normalize_host(body.subdomain.as_deref().unwrap_or_default());
+    let domain = domain_of(&host, &state.subdomains.domains);
+    if domain.is_none() {
+        errors.push(FieldError::new(
+            None,
+            "subdomain",
+            format!(
+                "must be a hostname under {}",
+                state.subdomains.domains.join(", ")
+            ),
+        ));
+    }
+    let settings = body.settings(&host, &mut errors);
+    let Some(domain) = domain.filter(|_| errors.is_empty()) else {
+        return Err(ApiError::Validation(errors));
+    };
+
+    let mut tx = state.pool.begin().await?;
+    if subdomains::insert(&mut *tx, domain, &host, &settings.as_check_settings())
+        .await?
+        .is_none()
+    {
+        return Err(ApiError::Conflict(format!(
+            "Subdomain {host} is already in the inventory"
+        )));
+    }
+    subdomain_sources::record(&mut *tx, &host, discovery::MANUAL_SOURCE, None).await?;
+    let subdomain = subdomains::get(&mut *tx, &host).await?;
+    tx.commit().await?;
+
+    println!("Added subdomain {host}");
+    Ok(HttpResponse::Created().json(subdomain))
+}
+
+/// `PUT /api/admin/subdomains/{subdomain}`: replaces its check settings.
+pub async fn update_subdomain(
+    _: Admin,
+    state: web::Data<AppState>,
+    subdomain: web::Path<String>,
+    body: web::Json<SubdomainBody>,
+) -> Result<HttpResponse, ApiError> {
+    let host = normalize_host(&subdomain);
+    let mut errors = Vec::new();
+    if body
+        .subdomain
+        .as_deref()
+        .is_some_and(|s| normalize_host(s) != host)
+    {
+        errors.push(FieldError::new(None, "subdomain", "cannot be changed"));
+    }
+    let settings = body.settings(&host, &mut errors);
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+    subdomains::update_settings(&state.pool, &host, &settings.as_check_settings())
+        .await?
+        .map(|s| HttpResponse::Ok().json(s))
+        .ok_or_else(|| not_found(&host))
+}
+
+/// Forgets the status of `host` and resolves its uptime, certificate and
+/// DNS alerts, whose recovery would otherwise never be seen. Returns the
+/// resolved alerts' events for after the caller's transaction commits.
+async fn stop_monitoring(
+    conn: &mut PgConnection,
+    host: &str,
+    reason: &str,
+) -> sqlx::Result<Vec<AlertEvent>> {
+    status::forget(conn, host).await?;
+    let mut events = Vec::new();
+    for service in SUBDOMAIN_SERVICES {
+        let resolved =
+            alerts::resolve_open(&mut *conn, host, service, SYSTEM_ACTOR, reason).await?;
+        events.extend(resolved.map(AlertEvent::resolved));
+    }
+    Ok(events)
+}
+
+/// `POST /api/admin/subdomains/{subdomain}/deactivate`: it is no longer
+/// checked, and its status starts over if it is activated again.
+pub async fn deactivate_subdomain(
+    _: Admin,
+    state: web::Data<AppState>,
+    subdomain: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let host = normalize_host(&subdomain);
+    let mut tx = state.pool.begin().await?;
+    let Some(subdomain) = subdomains::set_active(&mut *tx, &host, false).await? else {
+        return Err(not_found(&host));
+    };
+    let events =
+        stop_monitoring(&mut tx, &host, &format!("Subdomain {host} was deactivated")).await?;
+    tx.commit().await?;
+
+    println!("Deactivated subdomain {host}");
+    state.notifier.dispatch(events);
+    Ok(HttpResponse::Ok().json(subdomain))
+}
+
+/// `POST /api/admin/subdomains/{subdomain}/activate`
+pub async fn activate_subdomain(
+    _: Admin,
+    state: web::Data<AppState>,
+    subdomain: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let host = normalize_host(&subdomain);
+    let subdomain = subdomains::set_active(&state.pool, &host, true)
+        .await?
+        .ok_or_else(|| not_found(&host))?;
+    println!("Activated subdomain {host}");
+    Ok(HttpResponse::Ok().json(subdomain))
+}
+
+/// `DELETE /api/admin/subdomains/{subdomain}`: removes it from the
+/// inventory with its provenance and its latest TLS and DNS checks, and
+/// resolves its alerts. Past checks and DNS changes are kept. Discovery
+/// may find it again; deactivate it instead to keep it out of monitoring.
+pub async fn delete_subdomain(
+    _: Admin,
+    state: web::Data<AppState>,
+    subdomain: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let host = normalize_host(&subdomain);
+    let mut tx = state.pool.begin().await?;
+    if !subdomains::delete(&mut *tx, &host).await? {
+        return Err(not_found(&host));
+    }
+    subdomain_sources::delete_for_subdomain(&mut *tx, &host).await?;
+    tls_certificates::delete(&mut *tx, &host).await?;
+    dns_records::delete_for_subdomain(&mut *tx, &host).await?;
+    let events = stop_monitoring(&mut tx, &host, &format!("Subdomain {host} was deleted")).await?;
+    tx.commit().await?;
+
+    println!("Deleted subdomain {host}");
+    state.notifier.dispatch(events);
+    Ok(HttpResponse::Ok().json(json!({
+        "status": "deleted",
+        "subdomain": host,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domains() -> Vec<String> {
+        vec!["bettergov.ph".to_string(), "gov.bettergov.ph".to_string()]
+    }
+
+    #[test]
+    fn accepts_only_hosts_under_configured_domains() {
+        let domains = domains();
+        assert_eq!(domain_of("bettergov.ph", &domains), Some("bettergov.ph"));
+        assert_eq!(
+            domain_of("api.bettergov.ph", &domains),
+            Some("bettergov.ph")
+        );
+        assert_eq!(
+            domain_of("x.gov.bettergov.ph", &domains),
+            Some("gov.bettergov.ph")
+        );
+        assert_eq!(
+            domain_of("api.bettergov.ph:8443", &domains),
+            Some("bettergov.ph")
+        );
+        assert_eq!(domain_of("notbettergov.ph", &domains), None);
+        assert_eq!(domain_of("bettergov.ph.evil.com", &domains), None);
+        assert_eq!(domain_of("api.bettergov.ph:0", &domains), None);
+        assert_eq!(domain_of("api.bettergov.ph:http", &domains), None);
+        assert_eq!(domain_of("-api.bettergov.ph", &domains), None);
+        assert_eq!(domain_of("a..bettergov.ph", &domains), None);
+        assert_eq!(domain_of("api_v2.bettergov.ph", &domains), None);
+        assert_eq!(domain_of("", &domains), None);
+        assert_eq!(normalize_host(" API.BetterGov.ph. "), "api.bettergov.ph");
+    }
+
+    #[test]
+    fn settings_default_and_normalize() {
+        let mut errors = Vec::new();
+        let settings = SubdomainBody::default().settings("api.bettergov.ph", &mut errors);
+        assert!(errors.is_empty());
+        assert_eq!(settings.check_path, "/");
+        assert_eq!(settings.check_method, "GET");
+        assert_eq!(settings.expected_status, None);
+        assert!(settings.tags.is_empty());
+
+        let body = SubdomainBody {
+            check_path: Some("/api/status?full=1".to_string()),
+            check_method: Some("head".to_string()),
+            expected_status: Some(vec![301, 200, 200]),
+            check_timeout_secs: Some(30),
+            check_interval_secs: Some(300),
+            tags: Some(vec![
+                "Prod".to_string(),
+                "prod".to_string(),
+                "api".to_string(),
+            ]),
+            ..Default::default()
+        };
+        let settings = body.settings("api.bettergov.ph", &mut errors);
+        assert!(errors.is_empty());
+        assert_eq!(settings.check_method, "HEAD");
+        assert_eq!(settings.expected_status, Some(vec![200, 301]));
+        assert_eq!(settings.tags, ["api", "prod"]);
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        let body = SubdomainBody {
+            check_path: Some("status page".to_string()),
+            check_method: Some("POST".to_string()),
+            expected_status: Some(vec![200, 600]),
+            check_timeout_secs: Some(0),
+            check_interval_secs: Some(10),
+            tags: Some(vec!["no spaces".to_string()]),
+            ..Default::default()
+        };
+        let mut errors = Vec::new();
+        body.settings("api.bettergov.ph", &mut errors);
+        assert_eq!(errors.len(), 6);
+
+        let mut errors = Vec::new();
+        SubdomainBody {
+            expected_status: Some(Vec::new()),
+            ..Default::default()
+        }
+        .settings("api.bettergov.ph", &mut errors);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn socket_checks_need_a_port_and_socket_settings() {
+        let tcp = SubdomainBody {
+            check_kind: Some("TCP".to_string()),
+            check_send: Some(r"EHLO monitor.bettergov.ph\r\n".to_string()),
+            check_expect: Some("250 ".to_string()),
+            ..Default::default()
+        };
+        let mut errors = Vec::new();
+        let settings = tcp.settings("relay.bettergov.ph:25", &mut errors);
+        assert!(errors.is_empty());
+        assert_eq!(settings.check_kind, "tcp");
+        assert_eq!(
+            settings.check_send.as_deref(),
+            Some(r"EHLO monitor.bettergov.ph\r\n")
+        );
+
+        let mut errors = Vec::new();
+        tcp.settings("relay.bettergov.ph", &mut errors);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "subdomain");
+
+        let mixed = SubdomainBody {
+            check_kind: Some("udp".to_string()),
+            check_path: Some("/status".to_string()),
+            expected_status: Some(vec![200]),
+            check_send: Some(r"\xZZ".to_string()),
+            ..Default::default()
+        };
+        let mut errors = Vec::new();
+        mixed.settings("ntp.bettergov.ph:123", &mut errors);
+        let fields: Vec<_> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["check_path", "expected_status", "check_send"]);
+
+        let mut errors = Vec::new();
+        SubdomainBody {
+            check_expect: Some("ok".to_string()),
+            ..Default::default()
+        }
+        .settings("api.bettergov.ph", &mut errors);
+        assert_eq!(errors[0].field, "check_expect");
+    }
+
+    #[test]
+    fn validates_assertions_by_position() {
+        let assertions = serde_json::from_value(serde_json::json!([
+            {"type": "contains", "value": "BetterGov"},
+            {"type": "regex", "pattern": "(unclosed"},
+            {"type": "json_path", "path": "status"},
+            {"type": "header", "name": "x-frame-options"},
+        ]))
+        .unwrap();
+        let body = SubdomainBody {
+            check_method: Some("HEAD".to_string()),
+            check_assertions: Some(assertions),
+            ..Default::default()
+        };
+        let mut errors = Vec::new();
+        body.settings("bettergov.ph", &mut errors);
+        let indexes: Vec<_> = errors.iter().map(|e| e.index).collect();
+        assert_eq!(indexes, [Some(0), Some(1), Some(2)]);
+        assert!(errors[0].message.contains("HEAD"));
+        assert!(errors.iter().all(|e| e.field == "check_assertions"));
+    }
+}
@@ -0,0 +1,423 @@
+//! Subdomain endpoints: the inventory with each subdomain's latest check
+//! and uptime, its check history, and how it looks from every location
+//! that checks it.
+
+use std::collections::HashMap;
+
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::checker::assertions::Assertion;
+use crate::db::subdomains::{self, Subdomain, SubdomainFilter};
+use crate::db::uptime_checks::{self, LocationStats};
+use crate::db::{dns_changes, dns_records, tls_certificates};
+use crate::error::{ApiError, FieldError};
+use crate::state::AppState;
+use crate::status::Status;
+
+pub mod cache;
+pub mod inventory;
+
+pub use cache::SubdomainCache;
+
+const DEFAULT_HOURS: i64 = 24;
+/// 90 days.
+const MAX_HOURS: i64 = 2160;
+const DEFAULT_LIST_LIMIT: i64 = 1000;
+const MAX_LIST_LIMIT: i64 = 5000;
+/// A day of checks from a handful of locations fits in one page.
+const DEFAULT_CHECKS_LIMIT: i64 = 10_000;
+const MAX_CHECKS_LIMIT: i64 = 50_000;
+
+/// Validated `limit` and `offset`, pushing any errors onto `errors`.
+fn page(
+    limit: Option<i64>,
+    offset: Option<i64>,
+    default: i64,
+    max: i64,
+    errors: &mut Vec<FieldError>,
+) -> (i64, i64) {
+    let limit = limit.unwrap_or(default);
+    if !(1..=max).contains(&limit) {
+        errors.push(FieldError::new(
+            None,
+            "limit",
+            format!("must be between 1 and {max}"),
+        ));
+    }
+    let offset = offset.unwrap_or(0);
+    if offset < 0 {
+        errors.push(FieldError::new(None, "offset", "must not be negative"));
+    }
+    (limit, offset)
+}
+
+fn hours_range(hours: Option<i64>, errors: &mut Vec<FieldError>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let hours = hours.unwrap_or(DEFAULT_HOURS);
+    if !(1..=MAX_HOURS).contains(&hours) {
+        errors.push(FieldError::new(
+            None,
+            "hours",
+            format!("must be between 1 and {MAX_HOURS}"),
+        ));
+    }
+    let to = Utc::now();
+    (to - Duration::hours(hours.clamp(1, MAX_HOURS)), to)
+}
+
+fn validated<T>(value: T, errors: Vec<FieldError>) -> Result<T, ApiError> {
+    if errors.is_empty() {
+        Ok(value)
+    } else {
+        Err(ApiError::Validation(errors))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubdomainsQuery {
+    /// Global status, any case.
+    pub status: Option<String>,
+    /// The platform or one layer of it, with or without its version.
+    pub platform: Option<String>,
+    /// Discovery source, e.g. `wordlist`, any case. `discovery_method` is
+    /// still accepted, so `DNS Enumeration` finds `dns_enumeration`.
+    #[serde(alias = "discovery_method")]
+    pub source: Option<String>,
+    pub active: Option<bool>,
+    /// Any case.
+    pub tag: Option<String>,
+    /// `http`, `tcp` or `udp`, any case.
+    pub check_kind: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// A subdomain as the dashboard lists it. `check_count`,
+/// `uptime_percentage` and `last_check` cover the last 24 hours.
+#[derive(Debug, Serialize)]
+pub struct SubdomainSummary {
+    pub id: i32,
+    pub domain: String,
+    pub subdomain: String,
+    pub discovered_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub active: bool,
+    pub sources: Vec<String>,
+    pub platform: Option<String>,
+    pub last_platform_check: Option<DateTime<Utc>>,
+    pub check_kind: String,
+    pub check_path: String,
+    pub check_method: String,
+    pub expected_status: Option<Vec<i32>>,
+    pub check_timeout_secs: Option<i32>,
+    pub check_interval_secs: Option<i32>,
+    pub tags: Vec<String>,
+    pub check_send: Option<String>,
+    pub check_expect: Option<String>,
+    pub check_assertions: Vec<Assertion>,
+    pub status: String,
+    pub is_flapping: bool,
+    pub last_status_change: Option<DateTime<Utc>>,
+    pub check_count: u64,
+    /// 0 without checks, as the dashboard expects a number.
+    pub uptime_percentage: f64,
+    pub last_check: Option<DateTime<Utc>>,
+    pub latest_check: Option<cache::LatestCheck>,
+}
+
+impl SubdomainSummary {
+    fn new(subdomain: Subdomain, stats: cache::CachedStats) -> Self {
+        SubdomainSummary {
+            id: subdomain.id,
+            domain: subdomain.domain,
+            subdomain: subdomain.subdomain,
+            discovered_at: subdomain.discovered_at,
+            last_seen: subdomain.last_seen,
+            active: subdomain.active,
+            sources: subdomain.sources,
+            platform: subdomain.platform,
+            last_platform_check: subdomain.last_platform_check,
+            check_kind: subdomain.check_kind,
+            check_path: subdomain.check_path,
+            check_method: subdomain.check_method,
+            expected_status: subdomain.expected_status,
+            check_timeout_secs: subdomain.check_timeout_secs,
+            check_interval_secs: subdomain.check_interval_secs,
+            tags: subdomain.tags,
+            check_send: subdomain.check_send,
+            check_expect: subdomain.check_expect,
+            check_assertions: subdomain.check_assertions.0,
+            status: subdomain.current_status,
+            is_flapping: subdomain.is_flapping,
+            last_status_change: subdomain.last_status_change,
+            check_count: stats.check_count,
+            uptime_percentage: stats
+                .uptime_percent
+                .map_or(0.0, |p| (p * 100.0).round() / 100.0),
+            last_check: stats.latest.as_ref().map(|l| l.time),
+            latest_check: stats.latest,
+        }
+    }
+}
+
+/// `GET /api/subdomains`: the inventory, active subdomains first, with
+/// each one's latest check and 24-hour uptime from the cache.
+pub async fn list_subdomains(
+    state: web::Data<AppState>,
+    query: web::Query<SubdomainsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let mut errors = Vec::new();
+    let (limit, offset) = page(
+        query.limit,
+        query.offset,
+        DEFAULT_LIST_LIMIT,
+        MAX_LIST_LIMIT,
+        &mut errors,
+    );
+    let status = query.status.as_deref().map(str::to_ascii_uppercase);
+    if let Some(status) = &status {
+        if Status::parse(status).as_str() != status {
+            errors.push(FieldError::new(
+                None,
+                "status",
+                "must be one of UP, DOWN, REGIONAL_OUTAGE, FLAPPING, UNKNOWN",
+            ));
+        }
+    }
+    let check_kind = query.check_kind.as_deref().map(str::to_ascii_lowercase);
+    if check_kind
+        .as_deref()
+        .is_some_and(|kind| !inventory::KINDS.contains(&kind))
+    {
+        errors.push(FieldError::new(
+            None,
+            "check_kind",
+            format!("must be one of {}", inventory::KINDS.join(", ")),
+        ));
+    }
+    let (limit, offset) = validated((limit, offset), errors)?;
+
+    let tag = query.tag.as_deref().map(|t| t.trim().to_ascii_lowercase());
+    let source = query
+        .source
+        .as_deref()
+        .map(|s| s.trim().to_ascii_lowercase().replace(' ', "_"));
+    let filter = SubdomainFilter {
+        status: status.as_deref(),
+        platform: query.platform.as_deref(),
+        source: source.as_deref(),
+        active: query.active,
+        tag: tag.as_deref(),
+        check_kind: check_kind.as_deref(),
+    };
+    let total = subdomains::count_filtered(&state.pool, &filter).await?;
+    let rows = subdomains::list_filtered(&state.pool, &filter, limit, offset).await?;
+    let mut stats = state.subdomain_cache.snapshot(Utc::now());
+    let subdomains: Vec<SubdomainSummary> = rows
+        .into_iter()
+        .map(|s| {
+            let cached = stats.remove(&s.subdomain).unwrap_or_default();
+            SubdomainSummary::new(s, cached)
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({
+        "subdomains": subdomains,
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChecksQuery {
+    pub hours: Option<i64>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// `GET /api/subdomains/{subdomain}/checks`: the checks of the last `hours`
+/// (default 24) from every location, newest first.
+pub async fn subdomain_checks(
+    state: web::Data<AppState>,
+    subdomain: web::Path<String>,
+    query: web::Query<ChecksQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let mut errors = Vec::new();
+    let (from, _) = hours_range(query.hours, &mut errors);
+    let (limit, offset) = page(
+        query.limit,
+        query.offset,
+        DEFAULT_CHECKS_LIMIT,
+        MAX_CHECKS_LIMIT,
+        &mut errors,
+    );
+    let (limit, offset) = validated((limit, offset), errors)?;
+    let name = subdomain.trim().to_ascii_lowercase();
+    if subdomains::get(&state.pool, &name).await?.is_none() {
+        return Err(ApiError::NotFound(format!("Subdomain {name} not found")));
+    }
+
+    let total = uptime_checks::count_since(&state.pool, &name, from).await?;
+    let checks = uptime_checks::list_since(&state.pool, &name, from, limit, offset).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "subdomain": name,
+        "checks": checks,
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+    })))
+}
+
+/// `GET /api/subdomains/{subdomain}/tls`: its latest TLS check.
+pub async fn subdomain_tls(
+    state: web::Data<AppState>,
+    subdomain: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let name = subdomain.trim().to_ascii_lowercase();
+    tls_certificates::get(&state.pool, &name)
+        .await?
+        .map(|certificate| HttpResponse::Ok().json(certificate))
+        .ok_or_else(|| ApiError::NotFound(format!("No TLS check of {name} yet")))
+}
+
+/// Changes shown with a subdomain's DNS records.
+const DNS_CHANGES_LIMIT: i64 = 50;
+
+/// `GET /api/subdomains/{subdomain}/dns`: the latest answer for each
+/// watched record type and the most recent changes.
+pub async fn subdomain_dns(
+    state: web::Data<AppState>,
+    subdomain: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let name = subdomain.trim().to_ascii_lowercase();
+    let records = dns_records::list_for(&state.pool, &name).await?;
+    if records.is_empty() {
+        return Err(ApiError::NotFound(format!("No DNS check of {name} yet")));
+    }
+    let changes = dns_changes::list_for(&state.pool, &name, DNS_CHANGES_LIMIT).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "subdomain": name,
+        "records": records,
+        "changes": changes,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CertificatesQuery {
+    pub expiring_within_days: Option<i32>,
+}
+
+/// `GET /api/tls?expiring_within_days=30`: the latest TLS check of every
+/// subdomain, soonest to expire first.
+pub async fn list_certificates(
+    state: web::Data<AppState>,
+    query: web::Query<CertificatesQuery>,
+) -> Result<HttpResponse, ApiError> {
+    if query
+        .expiring_within_days
+        .is_some_and(|days| !(0..=3650).contains(&days))
+    {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            None,
+            "expiring_within_days",
+            "must be between 0 and 3650",
+        )]));
+    }
+    let certificates = tls_certificates::list(&state.pool, query.expiring_within_days).await?;
+    Ok(HttpResponse::Ok().json(json!({ "certificates": certificates })))
+}
+
+/// Window of the breakdown endpoints: the `hours` (default 24) up to now.
+#[derive(Debug, Deserialize)]
+pub struct WindowQuery {
+    pub hours: Option<i64>,
+}
+
+impl WindowQuery {
+    fn range(&self) -> Result<(DateTime<Utc>, DateTime<Utc>), ApiError> {
+        let mut errors = Vec::new();
+        let range = hours_range(self.hours, &mut errors);
+        validated(range, errors)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubdomainBreakdown {
+    pub subdomain: String,
+    /// The global status, decided from the locations' own.
+    pub status: String,
+    pub locations: Vec<LocationStats>,
+}
+
+/// `GET /api/subdomains/{subdomain}/locations?hours=24`: status, uptime and
+/// latency percentiles of one subdomain from each location.
+pub async fn subdomain_locations(
+    state: web::Data<AppState>,
+    subdomain: web::Path<String>,
+    query: web::Query<WindowQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (from, to) = query.range()?;
+    let name = subdomain.trim().to_ascii_lowercase();
+    let Some(subdomain) = subdomains::get(&state.pool, &name).await? else {
+        return Err(ApiError::NotFound(format!("Subdomain {name} not found")));
+    };
+    let stats = uptime_checks::location_stats(&state.pool, Some(&name), from, to).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "from": from,
+        "to": to,
+        "subdomain": subdomain.subdomain,
+        "status": subdomain.current_status,
+        "locations": stats,
+    })))
+}
+
+/// `GET /api/locations?hours=24`: the per-location breakdown of every
+/// subdomain checked in the window.
+pub async fn all_locations(
+    state: web::Data<AppState>,
+    query: web::Query<WindowQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let (from, to) = query.range()?;
+    let global: HashMap<String, String> = subdomains::list(&state.pool)
+        .await?
+        .into_iter()
+        .map(|s| (s.subdomain, s.current_status))
+        .collect();
+    let stats = uptime_checks::location_stats(&state.pool, None, from, to).await?;
+
+    // Rows come sorted by subdomain, so each one's locations are adjacent.
+    let mut breakdowns: Vec<SubdomainBreakdown> = Vec::new();
+    for row in stats {
+        match breakdowns.last_mut() {
+            Some(last) if last.subdomain == row.subdomain => last.locations.push(row),
+            _ => breakdowns.push(SubdomainBreakdown {
+                status: global
+                    .get(&row.subdomain)
+                    .cloned()
+                    .unwrap_or_else(|| "UNKNOWN".to_string()),
+                subdomain: row.subdomain.clone(),
+                locations: vec![row],
+            }),
+        }
+    }
+    Ok(HttpResponse::Ok().json(json!({
+        "from": from,
+        "to": to,
+        "subdomains": breakdowns,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_filter_accepts_the_legacy_parameter() {
+        for query in ["source=wordlist", "discovery_method=wordlist"] {
+            let parsed = web::Query::<SubdomainsQuery>::from_query(query).unwrap();
+            assert_eq!(parsed.source.as_deref(), Some("wordlist"), "{query}");
+        }
+    }
+}
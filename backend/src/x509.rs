@@ -0,0 +1,349 @@
+//! Just enough DER to describe X.509 certificates, without verifying
+//! anything: the input is trusted to be a certificate someone else already
+//! accepted, e.g. one logged to Certificate Transparency or presented in a
+//! TLS handshake that rustls checks separately.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+/// `[0]`, the `version` field of a TBSCertificate.
+const VERSION: u8 = 0xa0;
+/// `[3]`, the `extensions` field of a TBSCertificate.
+const EXTENSIONS: u8 = 0xa3;
+/// `[2] IMPLICIT IA5String`, a `dNSName` GeneralName.
+const DNS_NAME: u8 = 0x82;
+/// 2.5.29.17, id-ce-subjectAltName.
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// Short names of the usual distinguished name attributes (2.5.4.x).
+const ATTRIBUTES: &[(&[u8], &str)] = &[
+    (&[0x55, 0x04, 0x03], "CN"),
+    (&[0x55, 0x04, 0x06], "C"),
+    (&[0x55, 0x04, 0x07], "L"),
+    (&[0x55, 0x04, 0x08], "ST"),
+    (&[0x55, 0x04, 0x0a], "O"),
+    (&[0x55, 0x04, 0x0b], "OU"),
+];
+
+/// 1.2.840.113549.1.1.1, rsaEncryption.
+const RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+/// 1.2.840.10045.2.1, id-ecPublicKey.
+const EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const CURVES: &[(&[u8], &str)] = &[
+    (&[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07], "P-256"),
+    (&[0x2b, 0x81, 0x04, 0x00, 0x22], "P-384"),
+    (&[0x2b, 0x81, 0x04, 0x00, 0x23], "P-521"),
+];
+const ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+const ED448: &[u8] = &[0x2b, 0x65, 0x71];
+
+/// One DER element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Element<'a> {
+    pub tag: u8,
+    pub value: &'a [u8],
+}
+
+/// Splits the first element off `input`. Only single-byte tags and definite
+/// lengths are understood, which is all DER certificates use.
+pub fn read(input: &[u8]) -> Option<(Element<'_>, &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (usize::from(first), rest)
+    } else {
+        let n = usize::from(first & 0x7f);
+        if n == 0 || n > std::mem::size_of::<usize>() || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |len, &b| (len << 8) | usize::from(b));
+        (len, &rest[n..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let (value, rest) = rest.split_at(len);
+    Some((Element { tag, value }, rest))
+}
+
+/// The elements of a constructed value, up to the first malformed one.
+pub fn children(mut value: &[u8]) -> impl Iterator<Item = Element<'_>> {
+    std::iter::from_fn(move || {
+        let (element, rest) = read(value)?;
+        value = rest;
+        Some(element)
+    })
+}
+
+/// The TBSCertificate of a DER certificate, with its tag and length.
+pub fn tbs_of_cert(cert: &[u8]) -> Option<&[u8]> {
+    let (certificate, _) = read(cert)?;
+    if certificate.tag != SEQUENCE {
+        return None;
+    }
+    let (tbs, rest) = read(certificate.value)?;
+    let len = certificate.value.len() - rest.len();
+    (tbs.tag == SEQUENCE).then(|| &certificate.value[..len])
+}
+
+/// The value of extension `oid` in a DER TBSCertificate.
+fn extension<'a>(tbs: &'a [u8], oid: &[u8]) -> Option<&'a [u8]> {
+    let (tbs, _) = read(tbs)?;
+    let extensions = children(tbs.value).find(|e| e.tag == EXTENSIONS)?;
+    let (list, _) = read(extensions.value)?;
+    children(list.value).find_map(|ext| {
+        let mut fields = children(ext.value);
+        let id = fields.next()?;
+        if id.tag != OID || id.value != oid {
+            return None;
+        }
+        // The optional `critical` BOOLEAN comes before the value.
+        let value = fields.find(|f| f.tag == OCTET_STRING)?;
+        Some(value.value)
+    })
+}
+
+/// The `dNSName` entries of the subjectAltName extension of a DER
+/// TBSCertificate, as written (wildcards included). Empty if there is no
+/// such extension or it is malformed.
+pub fn dns_names(tbs: &[u8]) -> Vec<String> {
+    let Some(san) = extension(tbs, SUBJECT_ALT_NAME) else {
+        return Vec::new();
+    };
+    let Some((names, _)) = read(san) else {
+        return Vec::new();
+    };
+    children(names.value)
+        .filter(|name| name.tag == DNS_NAME)
+        .filter_map(|name| std::str::from_utf8(name.value).ok())
+        .map(str::to_string)
+        .collect()
+}
+
+/// What a certificate says about itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    /// Distinguished names in encoding order, e.g. `C=US, O=Let's Encrypt, CN=R11`.
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    /// The subjectAltName `dNSName` entries.
+    pub dns_names: Vec<String>,
+    /// E.g. `RSA 2048`, `ECDSA P-256` or `Ed25519`.
+    pub key_type: String,
+}
+
+impl Certificate {
+    /// Reads a DER certificate; `None` if it is not one.
+    pub fn parse(cert: &[u8]) -> Option<Certificate> {
+        let tbs = tbs_of_cert(cert)?;
+        let (outer, _) = read(tbs)?;
+        let mut fields = children(outer.value).skip_while(|f| f.tag == VERSION);
+        let serial = fields.next()?;
+        let _signature = fields.next()?;
+        let issuer = fields.next()?;
+        let validity = fields.next()?;
+        let subject = fields.next()?;
+        let spki = fields.next()?;
+        if serial.tag != INTEGER || spki.tag != SEQUENCE {
+            return None;
+        }
+        let mut times = children(validity.value);
+        Some(Certificate {
+            subject: name(subject.value),
+            issuer: name(issuer.value),
+            not_before: time(times.next()?)?,
+            not_after: time(times.next()?)?,
+            dns_names: dns_names(tbs),
+            key_type: key_type(spki.value),
+        })
+    }
+
+    /// Whether one of the names covers `host`, a wildcard covering a single
+    /// label. Case-insensitive; the common name is not considered.
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.dns_names.iter().any(|name| {
+            let name = name.to_ascii_lowercase();
+            match name.strip_prefix("*.") {
+                Some(parent) => host
+                    .split_once('.')
+                    .is_some_and(|(label, rest)| !label.is_empty() && rest == parent),
+                None => name == host,
+            }
+        })
+    }
+}
+
+/// Dotted form of an object identifier's contents.
+fn dotted(oid: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut arc = 0u64;
+    for &b in oid {
+        arc = (arc << 7) | u64::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = arc.min(80) / 40;
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+    arcs.iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// An RDNSequence as `TYPE=value` pairs, joined with `, `.
+fn name(rdns: &[u8]) -> String {
+    children(rdns)
+        .flat_map(|set| children(set.value))
+        .filter_map(|pair| {
+            let mut parts = children(pair.value);
+            let (oid, value) = (parts.next()?, parts.next()?);
+            let label = ATTRIBUTES
+                .iter()
+                .find(|(id, _)| *id == oid.value)
+                .map(|(_, label)| label.to_string())
+                .unwrap_or_else(|| dotted(oid.value));
+            Some(format!("{label}={}", String::from_utf8_lossy(value.value)))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A UTCTime or GeneralizedTime, in the `Z` forms RFC 5280 requires.
+fn time(element: Element<'_>) -> Option<DateTime<Utc>> {
+    let text = std::str::from_utf8(element.value).ok()?;
+    let full = match element.tag {
+        // Two-digit years 50 to 99 are 19xx (RFC 5280, 4.1.2.5.1).
+        UTC_TIME => {
+            let century = if text.get(..2)? >= "50" { "19" } else { "20" };
+            format!("{century}{text}")
+        }
+        GENERALIZED_TIME => text.to_string(),
+        _ => return None,
+    };
+    NaiveDateTime::parse_from_str(&full, "%Y%m%d%H%M%SZ")
+        .ok()
+        .map(|t| t.and_utc())
+}
+
+/// Describes a SubjectPublicKeyInfo's algorithm and size.
+fn key_type(spki: &[u8]) -> String {
+    let mut fields = children(spki);
+    let (Some(algorithm), Some(key)) = (fields.next(), fields.next()) else {
+        return "unknown".to_string();
+    };
+    let mut algorithm = children(algorithm.value);
+    let Some(oid) = algorithm.next() else {
+        return "unknown".to_string();
+    };
+    match oid.value {
+        RSA => {
+            let modulus = (key.tag == BIT_STRING)
+                .then(|| read(key.value.get(1..)?))
+                .flatten()
+                .and_then(|(rsa_key, _)| children(rsa_key.value).next());
+            match modulus {
+                Some(n) => format!("RSA {}", bits(n.value)),
+                None => "RSA".to_string(),
+            }
+        }
+        EC_PUBLIC_KEY => {
+            let curve = algorithm.next().map(|c| {
+                CURVES
+                    .iter()
+                    .find(|(id, _)| *id == c.value)
+                    .map(|(_, curve)| curve.to_string())
+                    .unwrap_or_else(|| dotted(c.value))
+            });
+            match curve {
+                Some(curve) => format!("ECDSA {curve}"),
+                None => "ECDSA".to_string(),
+            }
+        }
+        ED25519 => "Ed25519".to_string(),
+        ED448 => "Ed448".to_string(),
+        other => dotted(other),
+    }
+}
+
+/// Significant bits of a non-negative INTEGER's contents.
+fn bits(integer: &[u8]) -> usize {
+    let Some(first) = integer.iter().position(|&b| b != 0) else {
+        return 0;
+    };
+    (integer.len() - first) * 8 - integer[first].leading_zeros() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn reads_short_and_long_lengths() {
+        let (element, rest) = read(&[0x04, 0x02, 0xaa, 0xbb, 0x05, 0x00]).unwrap();
+        assert_eq!(element.tag, OCTET_STRING);
+        assert_eq!(element.value, [0xaa, 0xbb]);
+        assert_eq!(rest, [0x05, 0x00]);
+
+        let mut long = vec![0x04, 0x81, 0x80];
+        long.extend([0u8; 0x80]);
+        assert_eq!(read(&long).unwrap().0.value.len(), 0x80);
+        assert!(read(&long[..100]).is_none());
+    }
+
+    #[test]
+    fn wildcards_cover_one_label() {
+        let cert = Certificate {
+            subject: String::new(),
+            issuer: String::new(),
+            not_before: Utc::now(),
+            not_after: Utc::now(),
+            dns_names: vec!["bettergov.ph".to_string(), "*.bettergov.ph".to_string()],
+            key_type: String::new(),
+        };
+        assert!(cert.matches("bettergov.ph"));
+        assert!(cert.matches("API.bettergov.ph."));
+        assert!(!cert.matches("budget.data.bettergov.ph"));
+        assert!(!cert.matches("bettergov.ph.example.org"));
+    }
+
+    #[test]
+    fn times_and_oids() {
+        let utc = Element {
+            tag: UTC_TIME,
+            value: b"491231235959Z",
+        };
+        assert_eq!(
+            time(utc),
+            Some(Utc.with_ymd_and_hms(2049, 12, 31, 23, 59, 59).unwrap())
+        );
+        let generalized = Element {
+            tag: GENERALIZED_TIME,
+            value: b"19991231000000Z",
+        };
+        assert_eq!(
+            time(generalized),
+            Some(Utc.with_ymd_and_hms(1999, 12, 31, 0, 0, 0).unwrap())
+        );
+        assert_eq!(dotted(RSA), "1.2.840.113549.1.1.1");
+        assert_eq!(bits(&[0x00, 0x80, 0x00]), 16);
+    }
+}
@@ -0,0 +1,36 @@
+-- Create one-time enrollment codes and the agent audit log
+-- An admin mints a short-lived code for a registered agent; the agent
+-- exchanges it once for a signing key. Only a hash of each code is stored.
+
+CREATE TABLE IF NOT EXISTS monitoring.agent_enrollment_codes (
+    id SERIAL PRIMARY KEY,
+    location TEXT NOT NULL REFERENCES monitoring.agents (location) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    created_by TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    key_id TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_agent_enrollment_codes_location ON monitoring.agent_enrollment_codes (location);
+
+-- Who did what to which agent's credentials, and from where
+CREATE TABLE IF NOT EXISTS monitoring.agent_audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    time TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    location TEXT,
+    event TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    key_id TEXT,
+    client_ip TEXT,
+    detail TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_agent_audit_log_location_time ON monitoring.agent_audit_log (location, time DESC);
+CREATE INDEX IF NOT EXISTS idx_agent_audit_log_time ON monitoring.agent_audit_log (time DESC);
+
+-- Add comments
+COMMENT ON COLUMN monitoring.agent_enrollment_codes.code_hash IS 'SHA-256 (hex) of the enrollment code; the code itself is shown once and never stored';
+COMMENT ON COLUMN monitoring.agent_enrollment_codes.key_id IS 'Key issued when the code was redeemed';
+COMMENT ON COLUMN monitoring.agent_audit_log.client_ip IS 'Address the request came from, as recorded for the audit trail only; never used for authorization';
@@ -0,0 +1,30 @@
+-- Create signing keys and replay protection for geo agent reports
+-- Agents sign each report with HMAC-SHA256 using a per-agent secret that
+-- survives API restarts. Rotating a key keeps the old one valid for an
+-- overlap window so running agents can be redeployed at leisure.
+
+CREATE TABLE IF NOT EXISTS monitoring.agent_keys (
+    id SERIAL PRIMARY KEY,
+    location TEXT NOT NULL,
+    key_id TEXT NOT NULL UNIQUE,
+    secret TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS idx_agent_keys_location ON monitoring.agent_keys (location);
+
+-- Nonces seen within the clock-skew window; a repeated nonce is a replay
+CREATE TABLE IF NOT EXISTS monitoring.agent_report_nonces (
+    location TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (location, nonce)
+);
+
+CREATE INDEX IF NOT EXISTS idx_agent_report_nonces_received ON monitoring.agent_report_nonces (received_at);
+
+-- Add comments
+COMMENT ON COLUMN monitoring.agent_keys.expires_at IS 'NULL while current; set to the end of the overlap window when the key is rotated';
+COMMENT ON TABLE monitoring.agent_report_nonces IS 'Report nonces kept for replay detection, purged after the clock-skew window';
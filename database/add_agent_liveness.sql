@@ -0,0 +1,23 @@
+-- Track geo agent liveness
+-- Every accepted report is logged so the server can learn each agent's
+-- report cadence and measure its clock skew; the tracker keeps the
+-- resulting online/late/offline/outdated state on the registry row.
+
+CREATE TABLE IF NOT EXISTS monitoring.agent_reports (
+    id BIGSERIAL PRIMARY KEY,
+    location TEXT NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    sent_at TIMESTAMPTZ NOT NULL,
+    results INTEGER NOT NULL,
+    accepted INTEGER NOT NULL,
+    version TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_agent_reports_location_received ON monitoring.agent_reports (location, received_at DESC);
+
+ALTER TABLE monitoring.agents ADD COLUMN IF NOT EXISTS liveness TEXT;
+ALTER TABLE monitoring.agents ADD COLUMN IF NOT EXISTS liveness_since TIMESTAMPTZ;
+
+-- Add comments
+COMMENT ON COLUMN monitoring.agent_reports.sent_at IS 'Signed X-Agent-Timestamp of the report; sent_at - received_at is the agent clock skew';
+COMMENT ON COLUMN monitoring.agents.liveness IS 'online, late, offline or outdated, as last classified by the liveness tracker; NULL while disabled';
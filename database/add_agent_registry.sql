@@ -0,0 +1,30 @@
+-- Create the geo agent registry
+-- Vantage points allowed to report are rows here instead of a hard-coded
+-- list; agents are registered, disabled and deleted through the admin API.
+
+CREATE TABLE IF NOT EXISTS monitoring.agents (
+    location TEXT PRIMARY KEY,
+    name TEXT,
+    region TEXT,
+    isp TEXT,
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    version TEXT,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (latitude BETWEEN -90 AND 90),
+    CHECK (longitude BETWEEN -180 AND 180)
+);
+
+-- Seed the original vantage points
+INSERT INTO monitoring.agents (location, name, region)
+VALUES
+    ('EU', 'Europe', 'Europe'),
+    ('PH', 'Philippines', 'Southeast Asia'),
+    ('SG', 'Singapore', 'Southeast Asia')
+ON CONFLICT (location) DO NOTHING;
+
+-- Add comments
+COMMENT ON TABLE monitoring.agents IS 'Registered geo agents; only enabled agents may submit reports';
+COMMENT ON COLUMN monitoring.agents.version IS 'Agent script version, as last reported by the agent';
@@ -0,0 +1,26 @@
+-- Add acknowledge/resolve tracking to alerts table
+-- Alerts are opened by status transitions and agent liveness checks,
+-- acknowledged or resolved by an operator, or resolved automatically on recovery
+
+ALTER TABLE monitoring.alerts
+ADD COLUMN IF NOT EXISTS acknowledged_at TIMESTAMPTZ,
+ADD COLUMN IF NOT EXISTS acknowledged_by TEXT,
+ADD COLUMN IF NOT EXISTS acknowledge_comment TEXT,
+ADD COLUMN IF NOT EXISTS resolved_at TIMESTAMPTZ,
+ADD COLUMN IF NOT EXISTS resolved_by TEXT,
+ADD COLUMN IF NOT EXISTS resolve_comment TEXT;
+
+UPDATE monitoring.alerts SET acknowledged = FALSE WHERE acknowledged IS NULL;
+UPDATE monitoring.alerts SET resolved = FALSE WHERE resolved IS NULL;
+
+-- At most one open alert per host and service, so repeated failures update
+-- the existing alert instead of piling up duplicates
+CREATE UNIQUE INDEX IF NOT EXISTS idx_alerts_open
+ON monitoring.alerts (host, service)
+WHERE NOT resolved;
+
+CREATE INDEX IF NOT EXISTS idx_alerts_time ON monitoring.alerts (time DESC);
+
+-- Add comments
+COMMENT ON COLUMN monitoring.alerts.acknowledged_by IS 'Who acknowledged the alert';
+COMMENT ON COLUMN monitoring.alerts.resolved_by IS 'Who resolved the alert, or system for automatic recovery';
@@ -0,0 +1,11 @@
+-- Add content assertions to subdomains table
+-- Conditions an HTTP response must meet besides its status code, and the
+-- last body hash of targets watched for content changes
+
+ALTER TABLE monitoring.subdomains
+ADD COLUMN IF NOT EXISTS check_assertions JSONB NOT NULL DEFAULT '[]',
+ADD COLUMN IF NOT EXISTS body_hash TEXT;
+
+-- Add comments
+COMMENT ON COLUMN monitoring.subdomains.check_assertions IS 'Content assertions of the uptime check, e.g. [{"type": "json_path", "path": "$.status", "equals": "ok"}]';
+COMMENT ON COLUMN monitoring.subdomains.body_hash IS 'SHA-256 of the last body of targets with a body_hash assertion; NULL until one is seen';
@@ -0,0 +1,13 @@
+-- Add TCP and UDP check kinds to subdomains table
+-- Non-HTTP targets carry their port in the subdomain name, e.g.
+-- db.bettergov.ph:5432, and their results go to uptime_checks like any other
+
+ALTER TABLE monitoring.subdomains
+ADD COLUMN IF NOT EXISTS check_kind TEXT NOT NULL DEFAULT 'http',
+ADD COLUMN IF NOT EXISTS check_send TEXT,
+ADD COLUMN IF NOT EXISTS check_expect TEXT;
+
+-- Add comments
+COMMENT ON COLUMN monitoring.subdomains.check_kind IS 'How the uptime check probes: http, tcp or udp';
+COMMENT ON COLUMN monitoring.subdomains.check_send IS 'Payload sent after connecting (tcp) or as the datagram (udp); may use \r, \n, \t, \0, \\ and \xHH escapes';
+COMMENT ON COLUMN monitoring.subdomains.check_expect IS 'Text the banner or reply must contain to count as up, with the same escapes; NULL accepts any';
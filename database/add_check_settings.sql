@@ -0,0 +1,19 @@
+-- Add per-target check settings to subdomains table
+-- Targets are managed through /api/admin/subdomains; NULL settings fall
+-- back to the checker's defaults
+
+ALTER TABLE monitoring.subdomains
+ADD COLUMN IF NOT EXISTS check_method TEXT NOT NULL DEFAULT 'GET',
+ADD COLUMN IF NOT EXISTS expected_status INTEGER[],
+ADD COLUMN IF NOT EXISTS check_timeout_secs INTEGER,
+ADD COLUMN IF NOT EXISTS check_interval_secs INTEGER,
+ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX IF NOT EXISTS idx_subdomains_tags ON monitoring.subdomains USING GIN (tags);
+
+-- Add comments
+COMMENT ON COLUMN monitoring.subdomains.check_method IS 'HTTP method of the uptime check: GET or HEAD';
+COMMENT ON COLUMN monitoring.subdomains.expected_status IS 'Status codes that count as up; NULL means any code below 500';
+COMMENT ON COLUMN monitoring.subdomains.check_timeout_secs IS 'Request timeout of the uptime check; NULL uses checker.timeout_secs';
+COMMENT ON COLUMN monitoring.subdomains.check_interval_secs IS 'Minimum time between uptime checks; NULL checks on every checker pass';
+COMMENT ON COLUMN monitoring.subdomains.tags IS 'Free-form labels for grouping targets';
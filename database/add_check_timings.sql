@@ -0,0 +1,17 @@
+-- Add request phase timings to uptime_checks table
+-- How long DNS, TCP connect, TLS, the wait for the first byte and the body
+-- took, for the response of HTTP checks; NULL where a phase did not happen
+
+ALTER TABLE monitoring.uptime_checks
+ADD COLUMN IF NOT EXISTS dns_ms DOUBLE PRECISION,
+ADD COLUMN IF NOT EXISTS connect_ms DOUBLE PRECISION,
+ADD COLUMN IF NOT EXISTS tls_ms DOUBLE PRECISION,
+ADD COLUMN IF NOT EXISTS ttfb_ms DOUBLE PRECISION,
+ADD COLUMN IF NOT EXISTS transfer_ms DOUBLE PRECISION;
+
+-- Add comments
+COMMENT ON COLUMN monitoring.uptime_checks.dns_ms IS 'Host name lookup; NULL for IP addresses and checks that are not HTTP';
+COMMENT ON COLUMN monitoring.uptime_checks.connect_ms IS 'TCP connect, across every address tried';
+COMMENT ON COLUMN monitoring.uptime_checks.tls_ms IS 'TLS handshake; NULL over plain HTTP';
+COMMENT ON COLUMN monitoring.uptime_checks.ttfb_ms IS 'From sending the request to the response headers';
+COMMENT ON COLUMN monitoring.uptime_checks.transfer_ms IS 'Reading the body, as far as the checker reads it';
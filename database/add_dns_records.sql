@@ -0,0 +1,38 @@
+-- Add DNS record monitoring
+-- The latest answer for each watched record type of each subdomain, and a
+-- history of every change to those answers
+
+CREATE TABLE IF NOT EXISTS monitoring.dns_records (
+    subdomain TEXT NOT NULL,
+    record_type TEXT NOT NULL,
+    checked_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    resolver TEXT,
+    resolution_ms DOUBLE PRECISION,
+    error TEXT,
+    records TEXT[] NOT NULL DEFAULT '{}',
+    asns INTEGER[] NOT NULL DEFAULT '{}',
+    changed_at TIMESTAMPTZ,
+    PRIMARY KEY (subdomain, record_type)
+);
+
+CREATE TABLE IF NOT EXISTS monitoring.dns_changes (
+    id SERIAL PRIMARY KEY,
+    time TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    subdomain TEXT NOT NULL,
+    record_type TEXT NOT NULL,
+    previous TEXT[] NOT NULL,
+    current TEXT[] NOT NULL,
+    previous_asns INTEGER[] NOT NULL DEFAULT '{}',
+    current_asns INTEGER[] NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX IF NOT EXISTS idx_dns_changes_subdomain_time ON monitoring.dns_changes (subdomain, time DESC);
+
+-- Add comments
+COMMENT ON TABLE monitoring.dns_records IS 'Latest DNS answer per subdomain and record type';
+COMMENT ON COLUMN monitoring.dns_records.resolver IS 'Resolver that answered, as address:port';
+COMMENT ON COLUMN monitoring.dns_records.error IS 'Why the last lookup failed; records keep the last answer';
+COMMENT ON COLUMN monitoring.dns_records.records IS 'Record data in presentation form, sorted';
+COMMENT ON COLUMN monitoring.dns_records.asns IS 'Origin ASNs of the addresses of A and AAAA records';
+COMMENT ON COLUMN monitoring.dns_records.changed_at IS 'When records last differed from the answer before';
+COMMENT ON TABLE monitoring.dns_changes IS 'Every change to a watched DNS answer';
@@ -0,0 +1,11 @@
+-- Add flap detection state to subdomains table
+-- The status engine keeps a window of recent results and a weighted
+-- state-change percentage (Nagios style) to decide when a subdomain is flapping
+
+ALTER TABLE monitoring.subdomains
+ADD COLUMN IF NOT EXISTS status_history BOOLEAN[] DEFAULT '{}',
+ADD COLUMN IF NOT EXISTS flap_percent DOUBLE PRECISION DEFAULT 0;
+
+-- Add comments
+COMMENT ON COLUMN monitoring.subdomains.status_history IS 'Recent check results (TRUE = up), oldest first, used for flap detection';
+COMMENT ON COLUMN monitoring.subdomains.flap_percent IS 'Weighted state-change percentage over status_history';
@@ -0,0 +1,25 @@
+-- Track subdomain status per location
+-- The API's own checker and every geo agent each run the 3-strike and flap
+-- rules on their own results; the global status on monitoring.subdomains is
+-- derived from a quorum of these rows (see status.down_quorum).
+
+CREATE TABLE IF NOT EXISTS monitoring.subdomain_location_status (
+    subdomain TEXT NOT NULL,
+    location TEXT NOT NULL,
+    current_status TEXT NOT NULL DEFAULT 'UNKNOWN',
+    consecutive_up_count INTEGER NOT NULL DEFAULT 0,
+    consecutive_down_count INTEGER NOT NULL DEFAULT 0,
+    is_flapping BOOLEAN NOT NULL DEFAULT FALSE,
+    flap_percent DOUBLE PRECISION NOT NULL DEFAULT 0,
+    status_history BOOLEAN[] NOT NULL DEFAULT '{}',
+    last_check TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_status_change TIMESTAMPTZ,
+    PRIMARY KEY (subdomain, location)
+);
+
+CREATE INDEX IF NOT EXISTS idx_subdomain_location_status_location ON monitoring.subdomain_location_status (location, current_status);
+
+-- Add comments
+COMMENT ON TABLE monitoring.subdomain_location_status IS 'Stable status of each subdomain as seen from each location; central is the API server''s own checker';
+COMMENT ON COLUMN monitoring.subdomain_location_status.last_check IS 'Rows not checked within status.location_stale_secs do not count towards the global status';
+COMMENT ON COLUMN monitoring.subdomains.current_status IS 'Global status derived from subdomain_location_status: UP, DOWN, REGIONAL_OUTAGE, FLAPPING or UNKNOWN';
@@ -0,0 +1,11 @@
+-- Add a row id to metrics table
+-- Metrics of one batch share their timestamp and may share every other
+-- column too, so the id is what orders them for cursor pagination
+
+ALTER TABLE monitoring.metrics
+ADD COLUMN IF NOT EXISTS id BIGSERIAL;
+
+CREATE INDEX IF NOT EXISTS idx_metrics_time_id ON monitoring.metrics (time DESC, id DESC);
+
+-- Add comments
+COMMENT ON COLUMN monitoring.metrics.id IS 'Insertion order; breaks ties between metrics with the same time in GET /api/metrics pages';
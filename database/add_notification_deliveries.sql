@@ -0,0 +1,22 @@
+-- Create delivery log for alert notifications
+-- One row per attempt to notify a channel (webhook, smtp, slack, ...) about an
+-- alert opening or resolving
+
+CREATE TABLE IF NOT EXISTS monitoring.notification_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    time TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    alert_id INTEGER NOT NULL REFERENCES monitoring.alerts(id) ON DELETE CASCADE,
+    channel TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    event TEXT NOT NULL,
+    attempt INTEGER NOT NULL,
+    success BOOLEAN NOT NULL,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_notification_deliveries_alert
+ON monitoring.notification_deliveries (alert_id, time);
+
+-- Add comments
+COMMENT ON TABLE monitoring.notification_deliveries IS 'Every attempt to deliver an alert notification';
+COMMENT ON COLUMN monitoring.notification_deliveries.event IS 'opened or resolved';
@@ -0,0 +1,29 @@
+-- Add per-source discovery provenance
+-- One row per (subdomain, source) that has found it, replacing the single
+-- subdomains.discovery_method string
+
+CREATE TABLE IF NOT EXISTS monitoring.subdomain_sources (
+    subdomain TEXT NOT NULL,
+    source TEXT NOT NULL,
+    first_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    detail TEXT,
+    PRIMARY KEY (subdomain, source)
+);
+
+CREATE INDEX IF NOT EXISTS idx_subdomain_sources_source ON monitoring.subdomain_sources (source, last_seen DESC);
+
+-- Carry over how existing subdomains were found
+INSERT INTO monitoring.subdomain_sources (subdomain, source, first_seen, last_seen)
+SELECT subdomain,
+       LOWER(REPLACE(discovery_method, ' ', '_')),
+       discovered_at,
+       last_seen
+FROM monitoring.subdomains
+ON CONFLICT (subdomain, source) DO NOTHING;
+
+-- Add comments
+COMMENT ON TABLE monitoring.subdomain_sources IS 'Which discovery sources have found each subdomain';
+COMMENT ON COLUMN monitoring.subdomain_sources.source IS 'Discovery source, e.g. link_crawl, wordlist, zone_file, axfr or manual';
+COMMENT ON COLUMN monitoring.subdomain_sources.detail IS 'Where the source saw it: a page URL, a zone file, a record type';
+COMMENT ON COLUMN monitoring.subdomains.discovery_method IS 'Superseded by monitoring.subdomain_sources; kept for the legacy scripts';
@@ -0,0 +1,31 @@
+-- Add TLS certificate monitoring
+-- The latest TLS handshake of each subdomain: what its certificate says,
+-- how the connection was negotiated and whether the chain and name check out
+
+CREATE TABLE IF NOT EXISTS monitoring.tls_certificates (
+    subdomain TEXT PRIMARY KEY,
+    checked_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    error TEXT,
+    protocol TEXT,
+    cipher TEXT,
+    issuer TEXT,
+    subject TEXT,
+    sans TEXT[] NOT NULL DEFAULT '{}',
+    not_before TIMESTAMPTZ,
+    not_after TIMESTAMPTZ,
+    key_type TEXT,
+    chain_valid BOOLEAN,
+    chain_error TEXT,
+    hostname_valid BOOLEAN
+);
+
+CREATE INDEX IF NOT EXISTS idx_tls_certificates_not_after ON monitoring.tls_certificates (not_after);
+
+-- Add comments
+COMMENT ON TABLE monitoring.tls_certificates IS 'Latest TLS check of each subdomain';
+COMMENT ON COLUMN monitoring.tls_certificates.error IS 'Why the last check got no handshake; the certificate columns keep the last one seen';
+COMMENT ON COLUMN monitoring.tls_certificates.protocol IS 'Negotiated protocol version, e.g. TLSv1.3';
+COMMENT ON COLUMN monitoring.tls_certificates.sans IS 'DNS names in the subjectAltName extension';
+COMMENT ON COLUMN monitoring.tls_certificates.key_type IS 'Public key algorithm and size, e.g. RSA 2048 or ECDSA P-256';
+COMMENT ON COLUMN monitoring.tls_certificates.chain_valid IS 'Whether the presented chain leads to a trusted root and is within its validity period';
+COMMENT ON COLUMN monitoring.tls_certificates.hostname_valid IS 'Whether the certificate names cover the subdomain';
@@ -1,6 +1,10 @@
 #!/bin/bash
 # Geo-Monitoring Agent Deployment Script
-# Deploys monitoring agents to remote servers without storing keys
+# Deploys monitoring agents to remote servers without handling their keys.
+# Each deployment mints a short-lived one-time enrollment code through the
+# admin API; the agent redeems it for its own signing key, and the previous
+# key keeps working for the configured overlap window.
+# Requires ADMIN_TOKEN (the server's admin.token / BETTERGOV_ADMIN_TOKEN).
 
 set -e
 
@@ -21,14 +25,14 @@ detect_location() {
     fi
 }
 
-# Get agent token from central API
-get_agent_token() {
+# Mint a one-time enrollment code for a location
+mint_enrollment_code() {
     local location=$1
-    # Fetch token from database via direct connection (requires DATABASE_URL on deployment machine)
-    # Or get from scheduler logs during deployment
-    # For now, query the API temporarily - will be replaced with DB query
-    local token=$(curl -s "http://10.27.79.2:8002/api/agent-token/${location}" 2>/dev/null | grep -o '"token":"[^"]*"' | cut -d'"' -f4)
-    echo "$token"
+    local actor=$(whoami)@$(hostname)
+    curl -s -X POST -H "Authorization: Bearer $ADMIN_TOKEN" -H "Content-Type: application/json" \
+        -d "{\"actor\":\"$actor\"}" \
+        "$CENTRAL_API/api/admin/agents/${location}/enrollment-codes" 2>/dev/null | \
+        grep -o '"code":"[^"]*"' | cut -d'"' -f4
 }
 
 # Main deployment function
@@ -39,16 +43,16 @@ deploy_to_server() {
 
     echo "🚀 Deploying to $server ($location)..."
 
-    # Get agent token from central server
-    echo "🔐 Fetching authentication token for $location..."
-    local agent_token=$(get_agent_token "$location")
-    
-    if [ -z "$agent_token" ]; then
-        echo "❌ Failed to get agent token. Ensure scheduler is running on central server."
+    # Mint an enrollment code on the central server
+    echo "🔐 Minting enrollment code for $location..."
+    local code=$(mint_enrollment_code "$location")
+
+    if [ -z "$code" ]; then
+        echo "❌ Failed to mint enrollment code. Check ADMIN_TOKEN, that the API is reachable and that $location is registered and enabled."
         return 1
     fi
-    
-    echo "✅ Token received: ${agent_token:0:16}..."
+
+    echo "✅ Enrollment code minted (valid for one use)"
 
     # Kill any existing processes
     ssh -i "$key_path" "$server" "
@@ -63,8 +67,8 @@ deploy_to_server() {
     scp -i "$key_path" "$SCRIPT_DIR/geo_monitor.sh" "$server:~/geo_monitor.sh"
     ssh -i "$key_path" "$server" "chmod +x ~/geo_monitor.sh"
 
-    # Start the agent with location and token
-    ssh -i "$key_path" "$server" "export LOCATION=\"$location\" && export AGENT_TOKEN=\"$agent_token\" && ~/geo_monitor.sh > ~/monitor.log 2>&1 &"
+    # Start the agent; it enrolls itself and stores its key on the server
+    ssh -i "$key_path" "$server" "export LOCATION=\"$location\" && export ENROLLMENT_CODE=\"$code\" && ~/geo_monitor.sh > ~/monitor.log 2>&1 &"
 
     echo "✅ Deployed authenticated monitoring agent to $server"
 }
@@ -72,6 +76,7 @@ deploy_to_server() {
 # Check if required tools are available
 command -v python3 >/dev/null 2>&1 || { echo "❌ python3 required"; exit 1; }
 command -v ssh >/dev/null 2>&1 || { echo "❌ ssh required"; exit 1; }
+[ -n "$ADMIN_TOKEN" ] || { echo "❌ ADMIN_TOKEN required to issue agent keys"; exit 1; }
 
 echo "🌍 BetterGovPH Geo-Monitoring Deployment"
 echo "========================================"
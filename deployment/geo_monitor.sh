@@ -6,16 +6,45 @@
 LOCATION="${LOCATION:-UNKNOWN}"
 CENTRAL_API="http://10.27.79.2:8002"  # API port
 INTERVAL=300
-TOKEN="${AGENT_TOKEN:-}"
+AGENT_VERSION="2.1.0"  # reported to the registry with every batch
+CREDENTIALS_FILE="${AGENT_CREDENTIALS:-$HOME/.geo_agent_credentials}"
+KEY_ID="${AGENT_KEY_ID:-}"
+SECRET="${AGENT_SECRET:-}"
 
-# Validate token is provided
-if [ -z "$TOKEN" ]; then
-    echo "❌ ERROR: AGENT_TOKEN environment variable not set"
+# Reports are signed with HMAC-SHA256, which needs openssl
+command -v openssl >/dev/null 2>&1 || { echo "❌ ERROR: openssl required to sign reports"; exit 1; }
+
+# Redeem a one-time enrollment code for a signing key and keep the key on
+# disk, so restarting the agent or the API needs no redeploy
+enroll() {
+    response=$(curl -s -X POST -H "Content-Type: application/json" -d "{\"code\":\"$ENROLLMENT_CODE\"}" --max-time 30 "$CENTRAL_API/api/agents/enroll" 2>/dev/null)
+    KEY_ID=$(echo "$response" | grep -o '"key_id":"[^"]*"' | cut -d'"' -f4)
+    SECRET=$(echo "$response" | grep -o '"secret":"[^"]*"' | cut -d'"' -f4)
+    if [ -z "$KEY_ID" ] || [ -z "$SECRET" ]; then
+        echo "❌ Enrollment failed: $(echo "$response" | sed 's/.*"message":"\([^"]*\)".*/\1/')"
+        return 1
+    fi
+    (umask 077 && printf 'KEY_ID=%s\nSECRET=%s\n' "$KEY_ID" "$SECRET" > "$CREDENTIALS_FILE")
+    echo "✅ Enrolled as $KEY_ID"
+}
+
+# A code that was already redeemed (e.g. the agent was restarted with the
+# same environment) falls back to the stored key
+if [ -n "$ENROLLMENT_CODE" ]; then
+    enroll || echo "Using stored credentials from $CREDENTIALS_FILE"
+fi
+if [ -z "$KEY_ID" ] && [ -f "$CREDENTIALS_FILE" ]; then
+    . "$CREDENTIALS_FILE"
+fi
+
+# Validate signing key is available
+if [ -z "$KEY_ID" ] || [ -z "$SECRET" ]; then
+    echo "❌ ERROR: no signing key; set ENROLLMENT_CODE (or AGENT_KEY_ID and AGENT_SECRET)"
     echo "This agent must be deployed via deploy_geo_monitor.sh"
     exit 1
 fi
 
-echo "Agent: $LOCATION started (token: $(echo "$TOKEN" | cut -c1-16)...)"
+echo "Agent: $LOCATION started (key: $KEY_ID)"
 
 # Check single subdomain
 check_subdomain() {
@@ -40,12 +69,15 @@ check_subdomain() {
     echo "{\"subdomain\":\"$subdomain\",\"status_code\":$http_code,\"response_time_ms\":${response_time:-0},\"up\":$up,\"location\":\"$LOCATION\",\"timestamp\":\"$(date -u +%Y-%m-%dT%H:%M:%S.000Z)\"}"
 }
 
-# Get subdomains with check_path from API (cross-platform compatible)
+# Get HTTP subdomains with check_path from API (cross-platform compatible).
+# TCP and UDP targets are left to the central checker; probing them over
+# HTTP would report them down.
 get_subdomains() {
     # Parse JSON using grep and simple sed - works on macOS, Linux, and BusyBox
-    curl -s --max-time 30 "$CENTRAL_API/api/subdomains" 2>/dev/null | \
+    curl -s --max-time 30 "$CENTRAL_API/api/subdomains?active=true&check_kind=http" 2>/dev/null | \
         sed 's/},{/}\n{/g' | \
         grep '"subdomain"' | \
+        grep -v -e '"check_kind":"tcp"' -e '"check_kind":"udp"' | \
         while IFS= read -r line; do
             # Extract subdomain
             subdomain=$(echo "$line" | sed 's/.*"subdomain":"\([^"]*\)".*/\1/')
@@ -56,17 +88,29 @@ get_subdomains() {
         done
 }
 
-# Report results
+# Report results, signed over "timestamp\nnonce\nbody"
 report_results() {
     results="$1"
-    payload="{\"results\":[$results],\"location\":\"$LOCATION\",\"token\":\"$TOKEN\"}"
+    payload="{\"results\":[$results],\"location\":\"$LOCATION\",\"version\":\"$AGENT_VERSION\"}"
+    timestamp=$(date +%s)
+    nonce=$(od -An -tx1 -N16 /dev/urandom | tr -d ' \n')
+    signature=$(printf '%s\n%s\n%s' "$timestamp" "$nonce" "$payload" | openssl dgst -sha256 -hmac "$SECRET" | sed 's/^.* //')
+
+    response=$(curl -s -X POST -H "Content-Type: application/json" \
+        -H "X-Agent-Key: $KEY_ID" \
+        -H "X-Agent-Timestamp: $timestamp" \
+        -H "X-Agent-Nonce: $nonce" \
+        -H "X-Agent-Signature: $signature" \
+        -d "$payload" --max-time 30 "$CENTRAL_API/api/geo-report" 2>/dev/null)
 
-    response=$(curl -s -X POST -H "Content-Type: application/json" -d "$payload" --max-time 30 "$CENTRAL_API/api/geo-report" 2>/dev/null)
-    
     # Check if report was accepted or rejected
     if echo "$response" | grep -q '"status":"rejected"'; then
-        echo "❌ Report rejected by server - token may be invalid or expired"
-        exit 1
+        echo "❌ Report rejected by server: $(echo "$response" | sed 's/.*"message":"\([^"]*\)".*/\1/')"
+        # A revoked or expired key needs a redeploy; anything else (clock
+        # skew, a lost race on a nonce) is retried next round
+        if echo "$response" | grep -q 'needs redeployment'; then
+            exit 1
+        fi
     fi
 }
 